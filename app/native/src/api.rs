@@ -1,32 +1,42 @@
 use std::collections::HashMap;
-use std::{io::Write, sync::Mutex};
+use std::{fs, io::Write, path::Path, sync::Mutex};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 
+#[cfg(target_os = "linux")]
+use bolik_sdk::key_provider::{KeyringBackend, KeyringKeyProvider};
 use bolik_sdk::{
-    account, key_from_slice, output, start_runtime,
+    account, key_from_slice,
+    key_provider::{DbKey, FileKeyProvider, KeyProvider},
+    output, start_runtime,
     timeline::{self, card::CardLabelsChange},
     DefaultSdk, MoveToBinScope, BIN_LABEL_ID,
 };
 pub use bolik_sdk::{
-    account::{AccContact, AccDevice, AccLabel},
+    account::{AccDevice, AccLabel, ChainEvent, ChainEventKind, ContactFingerprint, RemovedDevice},
+    output::SyncPhase,
     timeline::{
         acl_doc::AclRights,
         card::{CardLabel, CardTextAttrs},
     },
-    DownloadResult, ImportResult, SecretGroupStatus,
+    DownloadResult, FailedDoc, ImportResult, PendingBlob, PendingDoc, PendingOutbox, PublicLink,
+    RestoreBackupResult, SecretGroupStatus, SyncDiagnostics,
 };
 use chrono::{DateTime, Utc};
 use flutter_rust_bridge::handler::{self, ErrorHandler, ReportDartErrorHandler};
 use flutter_rust_bridge::support::WireSyncReturn;
 use flutter_rust_bridge::{frb, StreamSink, SyncReturn, ZeroCopyBuffer};
-use tokio::{runtime::Runtime, sync::oneshot};
+use tokio::{
+    runtime::Runtime,
+    sync::{broadcast::error::RecvError, oneshot},
+};
 use tracing_subscriber::{fmt::MakeWriter, EnvFilter};
 
 use crate::qr;
 
 static BOLIK_SDK: Mutex<Option<DefaultSdk>> = Mutex::new(None);
 static RUNTIME: Mutex<Option<Runtime>> = Mutex::new(None);
+static DB_KEY_STORE: Mutex<Option<DbKeyStore>> = Mutex::new(None);
 
 #[derive(Copy, Clone)]
 pub struct MyErrorHandler(ReportDartErrorHandler);
@@ -81,6 +91,79 @@ pub fn setup_logs(sink: StreamSink<String>) -> Result<()> {
     Ok(())
 }
 
+/// Databases created before per-install keys were encrypted with this key.
+const LEGACY_DB_KEY: &[u8] = b"an example very very secret key.";
+const DB_KEY_FILE: &str = "db.key";
+
+#[cfg(target_os = "linux")]
+const DB_KEY_SERVICE: &str = "bolik-db-key";
+
+/// Where database key is kept.
+enum DbKeyStore {
+    File(FileKeyProvider),
+    #[cfg(target_os = "linux")]
+    Keyring(KeyringKeyProvider),
+}
+
+impl KeyProvider for DbKeyStore {
+    fn load_or_create(&self) -> Result<DbKey> {
+        match self {
+            Self::File(provider) => provider.load_or_create(),
+            #[cfg(target_os = "linux")]
+            Self::Keyring(provider) => provider.load_or_create(),
+        }
+    }
+
+    fn store(&self, key: &DbKey) -> Result<()> {
+        match self {
+            Self::File(provider) => provider.store(key),
+            #[cfg(target_os = "linux")]
+            Self::Keyring(provider) => provider.store(key),
+        }
+    }
+}
+
+/// On Linux new installs keep database key in Secret Service when it is available.
+/// Otherwise the key is kept in a file next to the database. Existing installs are moved off
+/// the legacy key: new key is kept aside until the database is re-encrypted so that
+/// an interrupted migration is resumed on the next start.
+fn db_key_provider(app_support_dir: &str, on_progress: impl FnMut(u32, u32)) -> Result<DbKeyStore> {
+    let dir = Path::new(app_support_dir);
+    let key_path = dir.join(DB_KEY_FILE);
+    let has_db = dir.join("app.db").exists();
+
+    #[cfg(target_os = "linux")]
+    if !key_path.exists() {
+        let keyring = KeyringKeyProvider::new(
+            KeyringBackend::SecretService,
+            DB_KEY_SERVICE,
+            app_support_dir,
+        );
+        match keyring.exists() {
+            Ok(true) => return Ok(DbKeyStore::Keyring(keyring)),
+            Ok(false) if !has_db => match keyring.load_or_create() {
+                Ok(_) => return Ok(DbKeyStore::Keyring(keyring)),
+                Err(err) => {
+                    tracing::warn!("Cannot store db key in Secret Service: {}", err);
+                }
+            },
+            // Legacy database is re-encrypted with a file key below
+            Ok(false) => {}
+            Err(err) => {
+                tracing::warn!("Secret Service is unavailable: {}", err);
+            }
+        }
+    }
+
+    if !key_path.exists() && has_db {
+        let next_key_path = dir.join("db.key.next");
+        let new_key = FileKeyProvider::new(&next_key_path).load_or_create()?;
+        bolik_sdk::reencrypt_db(dir, &key_from_slice(LEGACY_DB_KEY)?, &new_key, on_progress)?;
+        fs::rename(&next_key_path, &key_path)?;
+    }
+    Ok(DbKeyStore::File(FileKeyProvider::new(key_path)))
+}
+
 /// Initialize native code and subscribe to the events that native module dispatches.
 pub fn setup(
     sink: StreamSink<OutputEvent>,
@@ -88,15 +171,17 @@ pub fn setup(
     files_dir: String,
     device_name: String,
 ) -> Result<()> {
-    // let db_encryption_key = generate_db_key();
-    let db_encryption_key = key_from_slice(b"an example very very secret key.")?;
+    // SDK is not running yet hence report legacy key rotation progress directly
+    let key_provider = db_key_provider(&app_support_dir, |done, total| {
+        sink.add(OutputEvent::DbKeyRotationProgress { done, total });
+    })?;
     let rt = start_runtime()?;
 
     let sdk = rt.block_on(bolik_sdk::run(
         app_support_dir,
         files_dir,
         device_name,
-        db_encryption_key,
+        &key_provider,
     ))?;
 
     let mut events = sdk.broadcast_subscribe();
@@ -116,6 +201,7 @@ pub fn setup(
     {
         *BOLIK_SDK.lock().expect("Set sdk") = Some(sdk);
         *RUNTIME.lock().expect("Set runtime") = Some(rt);
+        *DB_KEY_STORE.lock().expect("Set key store") = Some(key_provider);
     }
 
     let (tx, rx) = oneshot::channel();
@@ -124,8 +210,16 @@ pub fn setup(
     // Ref: https://docs.rs/tokio/latest/tokio/runtime/struct.Handle.html#method.current
     let _guard = handle.enter();
     tokio::spawn(async move {
-        while let Ok(e) = events.recv().await {
-            sink.add(e.into());
+        loop {
+            match events.recv().await {
+                Ok(e) => {
+                    sink.add(e.into());
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Skipped {} output events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
         }
         let _ = tx.send(());
     });
@@ -136,13 +230,13 @@ pub fn setup(
 
 fn with_runtime<R>(cb: impl FnOnce(&Runtime, &mut DefaultSdk) -> Result<R>) -> Result<R> {
     let mut sdk_guard = BOLIK_SDK.lock().expect("Get sdk");
-    let sdk = sdk_guard.as_mut().expect("Sdk present");
+    let sdk = sdk_guard.as_mut().ok_or(anyhow!("Sdk is not set up"))?;
 
     // We are calling async sdk methods from a thread that is not managed by
     // Tokio runtime. For this to work we need to enter the handle.
     // Ref: https://docs.rs/tokio/latest/tokio/runtime/struct.Handle.html#method.current
     let mut rt_guard = RUNTIME.lock().expect("Get runtime");
-    let rt = rt_guard.as_mut().expect("Runtime present");
+    let rt = rt_guard.as_mut().ok_or(anyhow!("Runtime is not set up"))?;
     let _guard = rt.enter();
     cb(rt, sdk)
 }
@@ -170,11 +264,45 @@ pub fn link_device(share: String) -> Result<String> {
     Ok(linked_device_name)
 }
 
-pub fn remove_device(remove_id: String) -> Result<AccView> {
-    let view = with_sdk(|sdk| sdk.remove_device(&remove_id))?;
+pub fn create_recovery_key() -> Result<String> {
+    with_runtime(|rt, sdk| rt.block_on(sdk.create_recovery_key()))
+}
+
+/// Returns the mnemonic of a new recovery key. The used one stops working.
+pub fn recover_account(mnemonic: String) -> Result<String> {
+    with_runtime(|rt, sdk| rt.block_on(sdk.recover_account(&mnemonic)))
+}
+
+pub fn enable_backups(mnemonic: String) -> Result<()> {
+    with_sdk(|sdk| sdk.enable_backups(&mnemonic))
+}
+
+pub fn disable_backups() -> Result<()> {
+    with_sdk(|sdk| sdk.disable_backups())
+}
+
+pub fn backup_now() -> Result<()> {
+    with_sdk(|sdk| sdk.backup_now())
+}
+
+pub fn restore_backup(mnemonic: String) -> Result<RestoreBackupResult> {
+    with_runtime(|rt, sdk| rt.block_on(sdk.restore_backup(&mnemonic)))
+}
+
+pub fn remove_device(remove_id: String, reason: Option<String>) -> Result<AccView> {
+    let view = with_sdk(|sdk| sdk.remove_device(&remove_id, reason.as_deref()))?;
     Ok(view.into())
 }
 
+pub fn list_devices() -> Result<Vec<DeviceSession>> {
+    let devices = with_runtime(|rt, sdk| rt.block_on(sdk.list_devices()))?;
+    Ok(devices.into_iter().map(|d| d.into()).collect())
+}
+
+pub fn account_chain_history() -> Result<Vec<ChainEvent>> {
+    with_sdk(|sdk| sdk.account_chain_history())
+}
+
 pub fn sync() {
     let _ = with_sdk(|sdk| Ok(sdk.sync()));
 }
@@ -229,6 +357,24 @@ pub fn close_card(card_id: String) -> Result<()> {
     with_sdk(|sdk| sdk.close_card(&card_id))
 }
 
+pub fn list_comments(card_id: String) -> Result<Vec<CardComment>> {
+    let comments = with_sdk(|sdk| sdk.list_comments(&card_id))?;
+    Ok(comments.into_iter().map(|c| c.into()).collect())
+}
+
+pub fn add_comment(card_id: String, text: String) -> Result<CardComment> {
+    let comment = with_sdk(|sdk| sdk.add_comment(&card_id, text))?;
+    Ok(comment.into())
+}
+
+pub fn resolve_comment(card_id: String, comment_id: String, resolved: bool) -> Result<()> {
+    with_sdk(|sdk| sdk.resolve_comment(&card_id, &comment_id, resolved))
+}
+
+pub fn delete_comment(card_id: String, comment_id: String) -> Result<()> {
+    with_sdk(|sdk| sdk.delete_comment(&card_id, &comment_id))
+}
+
 pub fn get_card(card_id: String) -> Result<CardView> {
     let card = with_sdk(|sdk| sdk.get_card(&card_id))?;
     Ok(card.into())
@@ -296,16 +442,98 @@ pub fn account_group() -> Result<SecretGroupStatus> {
     Ok(g.into())
 }
 
+pub fn rotate_group_keys() -> Result<u32> {
+    with_sdk(|sdk| sdk.rotate_group_keys())
+}
+
+pub fn upgrade_group_ciphersuites() -> Result<u32> {
+    with_runtime(|rt, sdk| rt.block_on(sdk.upgrade_group_ciphersuites()))
+}
+
+/// Re-encrypt local database with a new key. Returns how many cells were re-encrypted.
+pub fn rotate_db_key() -> Result<u32> {
+    let key_store_guard = DB_KEY_STORE.lock().expect("Get key store");
+    let key_store = key_store_guard
+        .as_ref()
+        .ok_or(anyhow!("Sdk is not set up"))?;
+    with_sdk(|sdk| sdk.rotate_db_key(key_store))
+}
+
 pub fn edit_name(name: String) -> Result<AccView> {
     let res = with_sdk(|sdk| sdk.edit_name(name))?;
     Ok(res.into())
 }
 
+pub fn edit_bio(bio: String) -> Result<AccView> {
+    let res = with_sdk(|sdk| sdk.edit_bio(bio))?;
+    Ok(res.into())
+}
+
+pub fn edit_status(status: String) -> Result<AccView> {
+    let res = with_sdk(|sdk| sdk.edit_status(status))?;
+    Ok(res.into())
+}
+
+pub fn set_avatar(path: String) -> Result<AccView> {
+    let res = with_sdk(|sdk| sdk.set_avatar(path))?;
+    Ok(res.into())
+}
+
+pub fn remove_avatar() -> Result<AccView> {
+    let res = with_sdk(|sdk| sdk.remove_avatar())?;
+    Ok(res.into())
+}
+
+pub fn download_avatar(account_id: String) -> Result<DownloadResult> {
+    let res = with_sdk(|sdk| sdk.download_avatar(&account_id))?;
+    Ok(res.into())
+}
+
 pub fn add_contact(contact: AccContact) -> Result<AccView> {
+    // Bio and avatar are read from contact's profile
+    let contact = account::AccContact {
+        account_id: contact.account_id,
+        name: contact.name,
+        bio: String::new(),
+        avatar: None,
+    };
     let view = with_runtime(|rt, sdk| rt.block_on(sdk.add_contact(contact)))?;
     Ok(view.into())
 }
 
+pub fn create_contact_invite(valid_for_days: u32) -> Result<String> {
+    with_sdk(|sdk| sdk.create_contact_invite(chrono::Duration::days(valid_for_days as i64)))
+}
+
+pub fn redeem_contact_invite(code: String) -> Result<AccView> {
+    let view = with_runtime(|rt, sdk| rt.block_on(sdk.redeem_contact_invite(&code)))?;
+    Ok(view.into())
+}
+
+pub fn remove_contact(account_id: String) -> Result<AccView> {
+    let view = with_sdk(|sdk| sdk.remove_contact(&account_id))?;
+    Ok(view.into())
+}
+
+pub fn block_account(account_id: String) -> Result<AccView> {
+    let view = with_sdk(|sdk| sdk.block_account(&account_id))?;
+    Ok(view.into())
+}
+
+pub fn unblock_account(account_id: String) -> Result<AccView> {
+    let view = with_sdk(|sdk| sdk.unblock_account(&account_id))?;
+    Ok(view.into())
+}
+
+pub fn contact_fingerprint(account_id: String) -> Result<ContactFingerprint> {
+    with_sdk(|sdk| sdk.contact_fingerprint(&account_id))
+}
+
+pub fn set_contact_verified(account_id: String, verified: bool) -> Result<AccView> {
+    let view = with_sdk(|sdk| sdk.set_contact_verified(&account_id, verified))?;
+    Ok(view.into())
+}
+
 pub fn edit_contact_name(account_id: String, name: String) -> Result<AccView> {
     let res = with_sdk(|sdk| sdk.edit_contact_name(&account_id, &name))?;
     Ok(res.into())
@@ -330,6 +558,42 @@ pub fn edit_collaborators(card_id: String, changes: Vec<CollaboratorChange>) ->
     Ok(card.into())
 }
 
+pub fn edit_collaborator_expiry(
+    card_id: String,
+    account_id: String,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<CardView> {
+    let card = with_sdk(|sdk| sdk.edit_collaborator_expiry(&card_id, &account_id, expires_at))?;
+    Ok(card.into())
+}
+
+pub fn create_shared_label(name: String) -> Result<SharedLabelView> {
+    let label = with_sdk(|sdk| sdk.create_shared_label(name))?;
+    Ok(label.into())
+}
+
+pub fn list_shared_labels() -> Result<Vec<SharedLabelView>> {
+    let labels = with_sdk(|sdk| sdk.list_shared_labels())?;
+    Ok(labels.into_iter().map(|l| l.into()).collect())
+}
+
+pub fn rename_shared_label(label_id: String, name: String) -> Result<SharedLabelView> {
+    let label = with_sdk(|sdk| sdk.rename_shared_label(&label_id, name))?;
+    Ok(label.into())
+}
+
+pub fn edit_shared_label_members(
+    label_id: String,
+    changes: Vec<CollaboratorChange>,
+) -> Result<SharedLabelView> {
+    let changed: HashMap<_, _> = changes
+        .into_iter()
+        .map(|c| (c.account_id, c.rights))
+        .collect();
+    let label = with_sdk(|sdk| sdk.edit_shared_label_members(&label_id, changed))?;
+    Ok(label.into())
+}
+
 pub fn export_data(out_dir: String) -> Result<()> {
     with_runtime(|rt, sdk| rt.block_on(sdk.export_cards_to_dir(out_dir)))?;
     Ok(())
@@ -378,6 +642,50 @@ pub fn list_notification_ids() -> Result<Vec<String>> {
     Ok(ids)
 }
 
+pub fn list_inbox() -> Result<Vec<InboxNotification>> {
+    let inbox = with_sdk(|sdk| sdk.list_inbox())?;
+    Ok(inbox.into_iter().map(|n| n.into()).collect())
+}
+
+pub fn sync_diagnostics() -> Result<SyncDiagnostics> {
+    let res = with_sdk(|sdk| sdk.sync_diagnostics())?;
+    Ok(res)
+}
+
+pub fn retry_failed_doc(doc_id: String) -> Result<()> {
+    with_sdk(|sdk| sdk.retry_failed_doc(&doc_id))
+}
+
+pub fn pending_outbox() -> Result<PendingOutbox> {
+    with_sdk(|sdk| sdk.pending_outbox())
+}
+
+pub fn cancel_blob_upload(blob_id: String) -> Result<()> {
+    with_sdk(|sdk| sdk.cancel_blob_upload(&blob_id))
+}
+
+/// Cards older than this amount of days are downloaded only when opened.
+pub fn sync_window_days() -> Result<Option<u32>> {
+    let window = with_sdk(|sdk| sdk.sync_window())?;
+    Ok(window.map(|w| w.num_days() as u32))
+}
+
+pub fn set_sync_window_days(days: Option<u32>) -> Result<()> {
+    with_sdk(|sdk| sdk.set_sync_window(days.map(|d| chrono::Duration::days(d as i64))))
+}
+
+pub fn publish_card(card_id: String) -> Result<PublicLink> {
+    with_runtime(|rt, sdk| rt.block_on(sdk.publish_card(&card_id)))
+}
+
+pub fn unpublish_card(card_id: String) -> Result<()> {
+    with_runtime(|rt, sdk| rt.block_on(sdk.unpublish_card(&card_id)))
+}
+
+pub fn list_public_links() -> Result<Vec<PublicLink>> {
+    with_sdk(|sdk| sdk.list_public_links())
+}
+
 /// Log out from the account. You must re-initialize SDK after calling this function.
 pub fn logout() {
     let mut sdk_guard = BOLIK_SDK.lock().expect("Get sdk");
@@ -396,6 +704,7 @@ pub fn logout() {
 pub enum OutputEvent {
     Synced,
     SyncFailed,
+    SyncProgress(SyncProgress),
     TimelineUpdated,
     PreAccount,
     PostAccount { acc_view: AccView },
@@ -406,6 +715,8 @@ pub enum OutputEvent {
     AccUpdated(AccView),
     Notification { id: String },
     NotificationsUpdated,
+    VerifiedContactChanged { account_id: String },
+    DbKeyRotationProgress { done: u32, total: u32 },
     LogOut,
 }
 
@@ -414,6 +725,17 @@ impl From<output::OutputEvent> for OutputEvent {
         match event {
             output::OutputEvent::Synced => Self::Synced,
             output::OutputEvent::SyncFailed => Self::SyncFailed,
+            output::OutputEvent::SyncProgress {
+                phase,
+                docs_fetched,
+                docs_total_estimate,
+                blobs_pending,
+            } => Self::SyncProgress(SyncProgress {
+                phase,
+                docs_fetched,
+                docs_total_estimate,
+                blobs_pending,
+            }),
             output::OutputEvent::TimelineUpdated => Self::TimelineUpdated,
             output::OutputEvent::DeviceAdded { device_name } => Self::DeviceAdded { device_name },
             output::OutputEvent::ConnectedToAccount { view } => Self::PostAccount {
@@ -428,10 +750,23 @@ impl From<output::OutputEvent> for OutputEvent {
             output::OutputEvent::LogOut => Self::LogOut,
             output::OutputEvent::Notification(n) => Self::Notification { id: n.id() },
             output::OutputEvent::NotificationsUpdated => Self::NotificationsUpdated,
+            output::OutputEvent::VerifiedContactChanged { account_id } => {
+                Self::VerifiedContactChanged { account_id }
+            }
+            output::OutputEvent::DbKeyRotationProgress { done, total } => {
+                Self::DbKeyRotationProgress { done, total }
+            }
         }
     }
 }
 
+pub struct SyncProgress {
+    pub phase: SyncPhase,
+    pub docs_fetched: u32,
+    pub docs_total_estimate: u32,
+    pub blobs_pending: u32,
+}
+
 pub struct TimelineDay {
     pub day: String,
     pub cards: Vec<CardView>,
@@ -453,6 +788,8 @@ pub struct AccView {
     pub contacts: Vec<AccContact>,
     pub labels: Vec<AccLabel>,
     pub devices: Vec<AccDevice>,
+    pub removed_devices: Vec<RemovedDevice>,
+    pub blocked: Vec<String>,
 }
 
 impl From<account::AccView> for AccView {
@@ -461,9 +798,11 @@ impl From<account::AccView> for AccView {
             id: view.id,
             created_at_sec: view.created_at.timestamp(),
             name: view.name,
-            contacts: view.contacts,
+            contacts: view.contacts.into_iter().map(|c| c.into()).collect(),
             labels: view.labels,
             devices: view.devices,
+            removed_devices: view.removed_devices,
+            blocked: view.blocked,
         }
     }
 }
@@ -492,6 +831,48 @@ impl From<timeline::card::CardView> for CardView {
     }
 }
 
+pub struct SharedLabelView {
+    pub id: String,
+    pub name: String,
+    pub owner_id: String,
+    pub card_ids: Vec<String>,
+    pub acl: AclDoc,
+    pub created_at_sec: i64,
+}
+
+impl From<timeline::shared_label::SharedLabelView> for SharedLabelView {
+    fn from(l: timeline::shared_label::SharedLabelView) -> Self {
+        Self {
+            id: l.id,
+            name: l.name,
+            owner_id: l.owner_id,
+            card_ids: l.card_ids,
+            acl: l.acl.into(),
+            created_at_sec: l.created_at.timestamp(),
+        }
+    }
+}
+
+pub struct CardComment {
+    pub id: String,
+    pub author_id: String,
+    pub text: String,
+    pub created_at_sec: i64,
+    pub resolved_at_sec: Option<i64>,
+}
+
+impl From<timeline::comments::CardComment> for CardComment {
+    fn from(c: timeline::comments::CardComment) -> Self {
+        Self {
+            id: c.id,
+            author_id: c.author_id,
+            text: c.text,
+            created_at_sec: c.created_at.timestamp(),
+            resolved_at_sec: c.resolved_at.map(|t| t.timestamp()),
+        }
+    }
+}
+
 pub struct CardBlock {
     pub position: u32,
     pub view: Box<ContentView>,
@@ -624,10 +1005,29 @@ pub struct _DownloadResult {
     pub download_started: bool,
 }
 
-#[frb(mirror(AccContact))]
-pub struct _AccContact {
+pub struct AccContact {
     pub account_id: String,
     pub name: String,
+    pub bio: String,
+    pub avatar: Option<ProfileAvatar>,
+}
+
+impl From<account::AccContact> for AccContact {
+    fn from(c: account::AccContact) -> Self {
+        Self {
+            account_id: c.account_id,
+            name: c.name,
+            bio: c.bio,
+            avatar: c.avatar.map(|a| a.into()),
+        }
+    }
+}
+
+#[frb(mirror(ContactFingerprint))]
+pub struct _ContactFingerprint {
+    pub account_id: String,
+    pub safety_number: String,
+    pub verified: bool,
 }
 
 #[frb(mirror(AccLabel))]
@@ -643,18 +1043,71 @@ pub struct _AccDevice {
     pub added_at: DateTime<Utc>,
 }
 
+#[frb(mirror(RemovedDevice))]
+pub struct _RemovedDevice {
+    pub id: String,
+    pub name: Option<String>,
+    pub removed_at: DateTime<Utc>,
+    pub removed_by: String,
+    pub reason: Option<String>,
+}
+
+#[frb(mirror(ChainEvent))]
+pub struct _ChainEvent {
+    pub epoch: u64,
+    pub kind: ChainEventKind,
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub authored_by: String,
+    pub authored_by_name: Option<String>,
+    pub happened_at: Option<DateTime<Utc>>,
+    pub verified: bool,
+    pub verify_error: Option<String>,
+}
+
+#[frb(mirror(ChainEventKind))]
+pub enum _ChainEventKind {
+    DeviceAdded,
+    DeviceRemoved,
+    DeviceUpdated,
+}
+
+pub struct DeviceSession {
+    pub device: AccDevice,
+    pub last_seen_at_sec: Option<i64>,
+    pub platform: Option<String>,
+    pub app_version: Option<String>,
+    pub is_current: bool,
+}
+
+impl From<account::DeviceSession> for DeviceSession {
+    fn from(s: account::DeviceSession) -> Self {
+        Self {
+            device: s.device,
+            last_seen_at_sec: s.last_seen_at.map(|at| at.timestamp()),
+            platform: s.platform,
+            app_version: s.app_version,
+            is_current: s.is_current,
+        }
+    }
+}
+
 // This custom struct is needed because frb doesn't support HashMap.
 pub struct AclDoc {
     pub accounts: Vec<AclEntry>,
 }
 
 impl From<timeline::acl_doc::AclDoc> for AclDoc {
-    fn from(a: timeline::acl_doc::AclDoc) -> Self {
+    fn from(mut a: timeline::acl_doc::AclDoc) -> Self {
         Self {
             accounts: a
                 .accounts
                 .into_iter()
-                .map(|(account_id, rights)| AclEntry { account_id, rights })
+                .map(|(account_id, rights)| AclEntry {
+                    expires_at: a.expires.remove(&account_id),
+                    account_id,
+                    rights,
+                })
                 .collect(),
         }
     }
@@ -663,6 +1116,7 @@ impl From<timeline::acl_doc::AclDoc> for AclDoc {
 pub struct AclEntry {
     pub account_id: String,
     pub rights: AclRights,
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 #[frb(mirror(AclRights))]
@@ -686,6 +1140,14 @@ impl From<bolik_sdk::CreateAccLabelResult> for CreateAccLabelResult {
     }
 }
 
+#[frb(mirror(SyncPhase))]
+pub enum _SyncPhase {
+    Mailbox,
+    FetchDocs,
+    UploadBlobs,
+    ProcessDocs,
+}
+
 #[frb(mirror(SecretGroupStatus))]
 pub struct _SecretGroupStatus {
     pub authentication_secret: Vec<u8>,
@@ -696,6 +1158,9 @@ pub struct _SecretGroupStatus {
 pub struct ProfileView {
     pub account_id: String,
     pub name: String,
+    pub bio: String,
+    pub status: String,
+    pub avatar: Option<ProfileAvatar>,
 }
 
 impl From<bolik_sdk::account::ProfileView> for ProfileView {
@@ -703,10 +1168,91 @@ impl From<bolik_sdk::account::ProfileView> for ProfileView {
         Self {
             account_id: s.account_id,
             name: s.name,
+            bio: s.bio,
+            status: s.status,
+            avatar: s.avatar.map(|a| a.into()),
+        }
+    }
+}
+
+pub struct ProfileAvatar {
+    pub blob_id: String,
+    pub device_id: String,
+    pub thumbnail: Option<FileThumbnail>,
+}
+
+impl From<bolik_sdk::account::ProfileAvatar> for ProfileAvatar {
+    fn from(a: bolik_sdk::account::ProfileAvatar) -> Self {
+        Self {
+            blob_id: a.blob_id,
+            device_id: a.device_id,
+            thumbnail: a.thumbnail.map(|t| t.into()),
         }
     }
 }
 
+pub struct InboxNotification {
+    pub id: String,
+    pub kind: NotificationKind,
+    /// Card, shared label or account id (depending on the kind)
+    pub target_id: String,
+    pub status: NotificationStatus,
+    pub created_at: DateTime<Utc>,
+    pub sender: Option<ProfileView>,
+    pub title: Option<String>,
+}
+
+impl From<account::InboxNotification> for InboxNotification {
+    fn from(n: account::InboxNotification) -> Self {
+        let (kind, target_id) = match n.notification.clone() {
+            account::AccNotification::ContactRequest { account_id } => {
+                (NotificationKind::ContactRequest, account_id)
+            }
+            account::AccNotification::CardShare { doc_id } => (NotificationKind::CardShare, doc_id),
+            account::AccNotification::LabelShare { doc_id } => {
+                (NotificationKind::LabelShare, doc_id)
+            }
+            account::AccNotification::EditorAdded { doc_id } => {
+                (NotificationKind::EditorAdded, doc_id)
+            }
+            account::AccNotification::CardEdited { doc_id } => {
+                (NotificationKind::CardEdited, doc_id)
+            }
+            account::AccNotification::LabelCardAdded { doc_id } => {
+                (NotificationKind::LabelCardAdded, doc_id)
+            }
+        };
+        Self {
+            id: n.notification.id(),
+            kind,
+            target_id,
+            status: match n.status {
+                account::NotificationStatus::Missing => NotificationStatus::Pending,
+                account::NotificationStatus::Accepted => NotificationStatus::Accepted,
+                account::NotificationStatus::Ignored => NotificationStatus::Ignored,
+            },
+            created_at: n.created_at,
+            sender: n.sender.map(|p| p.into()),
+            title: n.title,
+        }
+    }
+}
+
+pub enum NotificationKind {
+    ContactRequest,
+    CardShare,
+    LabelShare,
+    EditorAdded,
+    CardEdited,
+    LabelCardAdded,
+}
+
+pub enum NotificationStatus {
+    Pending,
+    Accepted,
+    Ignored,
+}
+
 pub struct CollaboratorChange {
     pub account_id: String,
     pub rights: Option<AclRights>,
@@ -725,3 +1271,58 @@ pub struct _ImportResult {
     pub duplicates: Vec<String>,
     pub failed: Vec<String>,
 }
+
+#[frb(mirror(SyncDiagnostics))]
+pub struct _SyncDiagnostics {
+    pub failed_docs: Vec<FailedDoc>,
+}
+
+#[frb(mirror(FailedDoc))]
+pub struct _FailedDoc {
+    pub doc_id: String,
+    pub author_device_id: String,
+    pub reason: Option<String>,
+    pub tries: u32,
+    pub next_retry_at: Option<DateTime<Utc>>,
+    pub skipped_at: Option<DateTime<Utc>>,
+}
+
+#[frb(mirror(PendingOutbox))]
+pub struct _PendingOutbox {
+    pub docs: Vec<PendingDoc>,
+    pub blobs: Vec<PendingBlob>,
+    pub mailbox_messages: u32,
+    pub mailbox_size_bytes: u64,
+    pub mailbox_acks: u32,
+}
+
+#[frb(mirror(PendingDoc))]
+pub struct _PendingDoc {
+    pub doc_id: String,
+    pub deleted: bool,
+    pub size_bytes: u64,
+}
+
+#[frb(mirror(PendingBlob))]
+pub struct _PendingBlob {
+    pub blob_id: String,
+    pub card_id: Option<String>,
+    pub path: String,
+    pub size_bytes: u64,
+}
+
+#[frb(mirror(PublicLink))]
+pub struct _PublicLink {
+    pub id: String,
+    pub card_id: String,
+    pub url: String,
+    pub published_at: DateTime<Utc>,
+}
+
+#[frb(mirror(RestoreBackupResult))]
+pub struct _RestoreBackupResult {
+    pub restored_docs: u32,
+    pub restored_secrets: u32,
+    pub missing_files: u32,
+    pub backup_created_at: DateTime<Utc>,
+}
@@ -1,7 +1,4 @@
-use std::{
-    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
-    ops::Deref,
-};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 use bolik_proto::{
     prost::Message,
@@ -201,18 +198,7 @@ impl SignatureChain {
             if block.body.epoch > epoch {
                 break;
             }
-
-            for key_package in &block.body.ops.add {
-                members.insert(key_package, block.body.epoch, crypto)?;
-            }
-
-            for removed in &block.body.ops.remove {
-                members.remove(&removed)?;
-            }
-
-            for key_package in &block.body.ops.update {
-                members.insert(key_package, block.body.epoch, crypto)?;
-            }
+            members.apply(block, crypto)?;
         }
 
         Ok(members)
@@ -247,59 +233,113 @@ impl SignatureChain {
             return Err(ChainError::Empty);
         }
 
-        let mut blocks_iter = self.blocks.iter();
-        let root = blocks_iter.next().unwrap();
-
-        if root.body.ops.add.len() != 1 {
-            return Err(ChainError::InvalidRootOps);
-        }
-
+        let root = &self.blocks[0];
         if !root.body.account_ids.is_empty() && root.body.account_ids.len() != 2 {
             return Err(ChainError::InvalidRoot);
         }
 
         let mut members = SignatureMembers::default();
+        let mut parent = None;
+        for block in &self.blocks {
+            Self::verify_block(block, parent, &mut members, backend)?;
+            parent = Some(block);
+        }
+
+        Ok(())
+    }
+
+    /// Verify each block separately. Blocks after the first invalid block are reported as invalid too.
+    pub fn audit(&self, backend: &impl OpenMlsCryptoProvider) -> Vec<BlockAudit> {
+        let mut members = SignatureMembers::default();
+        let mut parent = None;
+        let mut broken_at = None;
+        let mut audits = Vec::with_capacity(self.blocks.len());
 
-        let key_package = &root.body.ops.add[0];
-        members.insert(key_package, root.body.epoch, backend.crypto())?;
-
-        Self::assert_block_hash(root)?;
-        key_package
-            .credential()
-            .verify(backend, root.hash.as_bytes(), &root.signature)
-            .map_err(|_| ChainError::InvalidSignature(0))?;
-
-        for block in blocks_iter {
-            let author_member = members
-                .find_by_id(&block.body.authored_by.deref())
-                .ok_or(ChainError::NonMemberEdit)?;
-
-            Self::assert_block_hash(block)?;
-            author_member
-                .package
-                .credential()
-                .verify(backend, block.hash.as_bytes(), &block.signature)
-                .map_err(|_| ChainError::InvalidSignature(block.body.epoch))?;
-
-            let ops = &block.body.ops;
-            if ops.add.is_empty() && ops.remove.is_empty() && ops.update.is_empty() {
-                return Err(ChainError::EmptyOps(block.body.epoch));
-            }
+        for block in &self.blocks {
+            let device_ids = |packages: &[KeyPackage]| -> Vec<String> {
+                packages
+                    .iter()
+                    .filter_map(|p| get_device_id(p.credential()).ok())
+                    .collect()
+            };
+            // Removed devices are known only before the block is applied
+            let removed = block
+                .body
+                .ops
+                .remove
+                .iter()
+                .filter_map(|r| members.find_by_ref(&r.key_ref))
+                .filter_map(|m| get_device_id(m.package.credential()).ok())
+                .collect();
 
-            for key_package in &ops.add {
-                members.insert(key_package, block.body.epoch, backend.crypto())?;
+            let verified = match broken_at {
+                Some(epoch) => Err(ChainError::InvalidParent(epoch)),
+                None => Self::verify_block(block, parent, &mut members, backend),
+            };
+            if verified.is_err() {
+                broken_at.get_or_insert(block.body.epoch);
+                // Keep following the chain so that the rest of the history is readable
+                let _ = members.apply(block, backend.crypto());
             }
 
-            for removed in &ops.remove {
-                members.remove(&removed)?;
+            audits.push(BlockAudit {
+                block,
+                added: device_ids(&block.body.ops.add),
+                removed,
+                updated: device_ids(&block.body.ops.update),
+                verified,
+            });
+            parent = Some(block);
+        }
+
+        audits
+    }
+
+    /// Verify a block against the members before it and apply block operations to the members.
+    fn verify_block<'a>(
+        block: &'a ChainBlock,
+        parent: Option<&ChainBlock>,
+        members: &mut SignatureMembers<'a>,
+        backend: &impl OpenMlsCryptoProvider,
+    ) -> Result<(), ChainError> {
+        let epoch = block.body.epoch;
+        Self::assert_block_hash(block)?;
+
+        match parent {
+            None => {
+                if block.body.ops.add.len() != 1 {
+                    return Err(ChainError::InvalidRootOps);
+                }
+
+                block.body.ops.add[0]
+                    .credential()
+                    .verify(backend, block.hash.as_bytes(), &block.signature)
+                    .map_err(|_| ChainError::InvalidSignature(epoch))?;
             }
+            Some(parent) => {
+                if block.body.parent.as_deref() != Some(parent.hash.as_str())
+                    || epoch != parent.body.epoch + 1
+                {
+                    return Err(ChainError::InvalidParent(epoch));
+                }
 
-            for key_package in &ops.update {
-                members.insert(key_package, block.body.epoch, backend.crypto())?;
+                let author_member = members
+                    .find_by_id(&block.body.authored_by)
+                    .ok_or(ChainError::NonMemberEdit)?;
+                author_member
+                    .package
+                    .credential()
+                    .verify(backend, block.hash.as_bytes(), &block.signature)
+                    .map_err(|_| ChainError::InvalidSignature(epoch))?;
+
+                let ops = &block.body.ops;
+                if ops.add.is_empty() && ops.remove.is_empty() && ops.update.is_empty() {
+                    return Err(ChainError::EmptyOps(epoch));
+                }
             }
         }
 
-        Ok(())
+        members.apply(block, backend.crypto())
     }
 
     pub fn encode(&self) -> Result<sync::SignatureChain, ChainError> {
@@ -666,6 +706,25 @@ impl<'a> SignatureMembers<'a> {
         Ok(())
     }
 
+    fn apply(
+        &mut self,
+        block: &'a ChainBlock,
+        crypto: &impl OpenMlsCrypto,
+    ) -> Result<(), ChainError> {
+        for key_package in &block.body.ops.add {
+            self.insert(key_package, block.body.epoch, crypto)?;
+        }
+
+        for removed in &block.body.ops.remove {
+            self.remove(&removed)?;
+        }
+
+        for key_package in &block.body.ops.update {
+            self.insert(key_package, block.body.epoch, crypto)?;
+        }
+        Ok(())
+    }
+
     pub fn find_by_id(&self, device_id: &str) -> Option<&SignatureMember> {
         self.device_ids.get(device_id)
     }
@@ -683,6 +742,18 @@ impl<'a> SignatureMembers<'a> {
     }
 }
 
+/// Verification result of a single chain block.
+pub struct BlockAudit<'a> {
+    pub block: &'a ChainBlock,
+    /// Device ids added by this block.
+    pub added: Vec<String>,
+    /// Device ids removed by this block.
+    pub removed: Vec<String>,
+    /// Device ids which updated their key packages in this block.
+    pub updated: Vec<String>,
+    pub verified: Result<(), ChainError>,
+}
+
 struct AuthoredBlock<'a> {
     block: &'a ChainBlock,
     author_added_at_epoch: u64,
@@ -720,6 +791,10 @@ pub struct ChainBody {
 }
 
 impl ChainBody {
+    pub fn authored_by(&self) -> &str {
+        &self.authored_by
+    }
+
     fn encode(&self) -> Result<signature_chain::ChainBody, ChainError> {
         let mut add_packages = Vec::with_capacity(self.ops.add.len());
         let mut removals = Vec::with_capacity(self.ops.remove.len());
@@ -764,6 +839,8 @@ pub enum ChainError {
     InvalidSignature(u64),
     #[error("Hash mismatch (epoch={0})")]
     HashMismatch(u64),
+    #[error("Invalid parent block (epoch={0})")]
+    InvalidParent(u64),
     #[error("Add and remove should be in separate operations")]
     DifferentOps,
     #[error("Empty SignatureChain")]
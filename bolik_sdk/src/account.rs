@@ -1,9 +1,20 @@
 mod acc_atom;
 mod acc_view;
+mod invite;
 mod notifications;
 mod profile;
+mod recovery;
+mod safety_number;
 
 pub use acc_atom::{AccNotification, AccountAtom, AccountDevice};
-pub use acc_view::{AccContact, AccDevice, AccLabel, AccView};
-pub use notifications::{AccNotifications, NotificationStatus};
-pub use profile::ProfileView;
+pub use acc_view::{
+    AccContact, AccDevice, AccLabel, AccView, ChainEvent, ChainEventKind, DeviceSession,
+    RemovedDevice,
+};
+pub use invite::ContactInvite;
+pub use notifications::{
+    AccNotifications, AckedNotification, InboxNotification, NotificationStatus,
+};
+pub use profile::{ProfileAvatar, ProfileView};
+pub use recovery::{RecoveryKey, RecoveryKit, RECOVERY_DEVICE_NAME};
+pub use safety_number::ContactFingerprint;
@@ -1,33 +1,42 @@
 use std::collections::HashSet;
 
 use anyhow::{anyhow, bail, Context, Result};
+use bolik_chain::SignatureChain;
 use bolik_migrations::rusqlite::{params, OptionalExtension};
-use bolik_proto::sync::{doc_payload::DocSchema, request, response, DeviceShareMessage};
-use chrono::Utc;
+use bolik_proto::sync::{
+    contact_invite_message, doc_payload::DocSchema, request, response, DeviceShareMessage,
+    SignatureChain as SignatureChainMessage,
+};
+use chrono::{DateTime, Utc};
 use openmls::prelude::{Credential, KeyPackage, Signature, TlsDeserializeTrait, TlsSerializeTrait};
 use openmls_traits::OpenMlsCryptoProvider;
 use prost::Message;
 
 use crate::{
+    blobs,
     client::Client,
     device::{get_device_id, query_device_settings, DeviceShare},
     documents::{DbDocRow, DbDocRowMeta},
     mailbox,
     output::OutputEvent,
     registry::{
-        WithBackend, WithBroadcast, WithDeviceAtom, WithDocsAtom, WithInTxn, WithSecretGroupAtom,
-        WithTimelineAtom, WithTxn,
+        WithAccountAtom, WithBackend, WithBroadcast, WithDeviceAtom, WithDocsAtom, WithInTxn,
+        WithSecretGroupAtom, WithTimelineAtom, WithTxn,
     },
     secrets,
+    signature_chain::SignatureChainStorage,
     timeline::{
         self,
         acl_doc::{AclDoc, AclOperationMode},
+        card::CardView,
     },
 };
 
 use super::{
-    notifications::{AccNotifications, NotificationStatus},
-    AccContact, AccDevice, AccView, ProfileView,
+    notifications::{AccNotifications, InboxNotification, NotificationStatus},
+    recovery::{RecoveryKit, RECOVERY_DEVICE_NAME},
+    safety_number, AccContact, AccDevice, AccView, ChainEvent, ChainEventKind, ContactFingerprint,
+    ContactInvite, ProfileAvatar, ProfileView,
 };
 
 #[derive(Clone)]
@@ -58,11 +67,30 @@ impl AccountAtom {
         let doc_row = ctx.docs().find(ctx, &acc_id)?;
         let profile_row = ctx.docs().find(ctx, &format!("{}/profile", acc_id))?;
         match doc_row {
-            Some(row) => Ok(Some(AccView::from_db(row, profile_row).0)),
+            Some(row) => {
+                let mut view = AccView::from_db(row, profile_row).0;
+                self.load_contact_profiles(ctx, &mut view)?;
+                Ok(Some(view))
+            }
             None => Ok(Some(AccView::new(acc_id))),
         }
     }
 
+    /// Fill contacts with details from their profiles.
+    fn load_contact_profiles<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom),
+        view: &mut AccView,
+    ) -> Result<()> {
+        let contact_ids: Vec<_> = view.contacts.iter().map(|c| c.account_id.clone()).collect();
+        for contact_id in contact_ids {
+            if let Some(row) = ctx.docs().find(ctx, &format!("{}/profile", contact_id))? {
+                view.with_contact_profile(ProfileView::from_db(row).0);
+            }
+        }
+        Ok(())
+    }
+
     pub fn require_account<'a>(&self, ctx: &(impl WithTxn<'a> + WithDocsAtom)) -> Result<AccView> {
         self.get_account(ctx)?
             .ok_or(anyhow!("Device is not connected to account"))
@@ -170,7 +198,35 @@ impl AccountAtom {
             }
         }
 
-        let (view, _) = AccView::from_db(doc_row, profile_row);
+        let (mut view, _) = AccView::from_db(doc_row, profile_row);
+        self.load_contact_profiles(ctx, &mut view)?;
+        Ok(view)
+    }
+
+    /// Replace profile picture and remove local copy of the previous one.
+    /// Server deletes the previous blob once no doc references it.
+    pub fn set_avatar<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom),
+        avatar: Option<ProfileAvatar>,
+    ) -> Result<AccView> {
+        let acc_id = self.require_account_id(ctx)?;
+        let old_avatar = ctx
+            .docs()
+            .find(ctx, &format!("{}/profile", acc_id))?
+            .and_then(|row| ProfileView::read_avatar(&row.yrs));
+        let new_blob_id = avatar.as_ref().map(|a| a.blob_id.clone());
+
+        let view = self.edit_profile(ctx, |doc| {
+            ProfileView::set_avatar(doc, avatar);
+            Ok(())
+        })?;
+
+        if let Some(old) = old_avatar {
+            if Some(&old.blob_id) != new_blob_id.as_ref() {
+                blobs::remove_local(ctx.txn(), &old.blob_id)?;
+            }
+        }
         Ok(view)
     }
 
@@ -206,9 +262,15 @@ impl AccountAtom {
         &self,
         ctx: &(impl WithTxn<'a> + WithDeviceAtom + WithBackend),
     ) -> Result<String> {
-        // Generate Key packages
-        let mut key_packages = ctx.device().generate_key_packages(ctx, 6)?;
-        let key_package = key_packages.pop().unwrap().1;
+        // Generate Key packages and share one in the strongest ciphersuite
+        let ciphersuite = ctx.device().ciphersuites(ctx)[0];
+        let key_packages = ctx.device().generate_key_packages(ctx, 6)?;
+        let key_package = key_packages
+            .into_iter()
+            .rev()
+            .find(|(_, p)| p.ciphersuite() == ciphersuite)
+            .ok_or(anyhow!("Missing KeyPackage for {:?}", ciphersuite))?
+            .1;
 
         // Include serialized key package in QR code
         let package_bytes = key_package.tls_serialize_detached()?;
@@ -221,6 +283,252 @@ impl AccountAtom {
         Ok(share)
     }
 
+    /// Create a signed invite code. Other accounts can redeem the code to add this account as a contact.
+    pub fn create_invite<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom + WithBackend),
+        valid_for: chrono::Duration,
+    ) -> Result<String> {
+        let acc = self.require_account(ctx)?;
+        let payload = contact_invite_message::ContactInvite {
+            account_id: acc.id,
+            device_id: ctx.device().id.clone(),
+            name: acc.name,
+            expires_at_sec: (Utc::now() + valid_for).timestamp(),
+        }
+        .encode_to_vec();
+        let signature = ctx.device().sign(ctx, &payload)?;
+        Ok(ContactInvite::encode_code(payload, signature))
+    }
+
+    /// Compute safety number for this account and a contact.
+    pub fn contact_fingerprint<'a, C: Client>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithBackend + WithSecretGroupAtom<C>),
+        contact_id: &str,
+    ) -> Result<ContactFingerprint> {
+        let acc_id = self.require_account_id(ctx)?;
+        let doc_row = ctx
+            .docs()
+            .find(ctx, &acc_id)?
+            .ok_or(anyhow!("Account doc is missing"))?;
+        let contacts = AccView::read_contacts(&doc_row.yrs).unwrap_or_default();
+        if !contacts.iter().any(|c| c.account_id == contact_id) {
+            bail!("Unknown contact");
+        }
+
+        let group = ctx
+            .secret_group()
+            .load_latest_for_accounts(ctx, &mut [acc_id.clone(), contact_id.to_string()])?;
+        let (own_hash, contact_hash) =
+            self.devices_hashes(ctx, &acc_id, contact_id, &group.chain)?;
+        let verified = AccView::read_contact_verified(&doc_row.yrs, contact_id)
+            .map_or(false, |hash| hash == secrets::id_from_key(&contact_hash));
+
+        Ok(ContactFingerprint {
+            account_id: contact_id.to_string(),
+            safety_number: safety_number::safety_number(
+                (&acc_id, &own_hash),
+                (contact_id, &contact_hash),
+            ),
+            verified,
+        })
+    }
+
+    /// Mark contact as (un)verified. Verification is bound to the current contact devices.
+    pub fn set_contact_verified<'a, C: Client>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom + WithBackend + WithSecretGroupAtom<C>),
+        contact_id: &str,
+        verified: bool,
+    ) -> Result<AccView> {
+        let acc_id = self.require_account_id(ctx)?;
+        let devices_hash = if verified {
+            let group = ctx
+                .secret_group()
+                .load_latest_for_accounts(ctx, &mut [acc_id.clone(), contact_id.to_string()])?;
+            let (_, contact_hash) = self.devices_hashes(ctx, &acc_id, contact_id, &group.chain)?;
+            Some(secrets::id_from_key(&contact_hash))
+        } else {
+            None
+        };
+
+        self.edit_account(ctx, |doc| {
+            AccView::set_contact_verified(doc, contact_id, devices_hash)
+        })
+    }
+
+    /// Remove verification from contacts whose devices in the chain no longer match.
+    /// Returns ids of affected contacts.
+    pub fn check_verified_contacts<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom + WithBackend),
+        chain: &SignatureChain,
+    ) -> Result<Vec<String>> {
+        let Some(acc_id) = self.get_account_id(ctx) else {
+            return Ok(vec![]);
+        };
+        let Some(doc_row) = ctx.docs().find(ctx, &acc_id)? else {
+            return Ok(vec![]);
+        };
+
+        let mut changed = vec![];
+        for contact_id in chain.account_ids() {
+            if *contact_id == acc_id {
+                continue;
+            }
+            let Some(verified_hash) = AccView::read_contact_verified(&doc_row.yrs, contact_id)
+            else {
+                continue;
+            };
+            let (_, contact_hash) = self.devices_hashes(ctx, &acc_id, contact_id, chain)?;
+            if verified_hash != secrets::id_from_key(&contact_hash) {
+                changed.push(contact_id.clone());
+            }
+        }
+
+        if !changed.is_empty() {
+            tracing::info!(contacts = ?changed, "Verified contacts changed devices");
+            self.edit_account(ctx, |doc| {
+                for contact_id in &changed {
+                    AccView::set_contact_verified(doc, contact_id, None)?;
+                }
+                Ok(())
+            })?;
+        }
+        Ok(changed)
+    }
+
+    /// Check if this device was removed from the account according to the remote chain.
+    ///
+    /// Remote chain is trusted only when it is valid and continues the local chain.
+    /// Diverged chains are left to the group merge (MLS commit will follow).
+    pub fn removed_in_chain<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithBackend + WithDeviceAtom),
+        remote_chain: SignatureChainMessage,
+    ) -> Result<bool> {
+        let Some(acc_id) = self.get_account_id(ctx) else {
+            return Ok(false);
+        };
+
+        let backend = ctx.backend();
+        let remote_chain = SignatureChain::decode(remote_chain)?;
+        if remote_chain.root() != acc_id {
+            bail!("Remote chain belongs to another account");
+        }
+        remote_chain.verify(&backend)?;
+
+        let local_chain = SignatureChainStorage::load(ctx.txn(), &acc_id)?
+            .ok_or(anyhow!("Account chain is missing"))?;
+        if remote_chain.hash_at(local_chain.epoch()) != Some(local_chain.head()) {
+            return Ok(false);
+        }
+
+        let members = remote_chain.members(backend.crypto())?;
+        Ok(members.removed.contains_key(&ctx.device().id))
+    }
+
+    /// Decode account chain into device changes. Each change carries verification result of its block.
+    pub fn chain_history<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithBackend),
+    ) -> Result<Vec<ChainEvent>> {
+        let acc = self.require_account(ctx)?;
+        let chain = SignatureChainStorage::load(ctx.txn(), &acc.id)?
+            .ok_or(anyhow!("Account chain is missing"))?;
+        let device_name = |id: &str| -> Option<String> {
+            if let Some(device) = acc.devices.iter().find(|d| d.id == id) {
+                return Some(device.name.clone());
+            }
+            acc.removed_devices
+                .iter()
+                .find(|d| d.id == id)
+                .and_then(|d| d.name.clone())
+        };
+
+        let mut events = vec![];
+        for audit in chain.audit(&ctx.backend()) {
+            let epoch = audit.block.body.epoch;
+            let authored_by = audit.block.body.authored_by().to_string();
+            let verify_error = audit.verified.err().map(|err| err.to_string());
+            let changes = audit
+                .added
+                .into_iter()
+                .map(|id| (ChainEventKind::DeviceAdded, id))
+                .chain(
+                    audit
+                        .removed
+                        .into_iter()
+                        .map(|id| (ChainEventKind::DeviceRemoved, id)),
+                )
+                .chain(
+                    audit
+                        .updated
+                        .into_iter()
+                        .map(|id| (ChainEventKind::DeviceUpdated, id)),
+                );
+
+            for (kind, device_id) in changes {
+                let happened_at = match kind {
+                    ChainEventKind::DeviceAdded => acc
+                        .devices
+                        .iter()
+                        .find(|d| d.id == device_id)
+                        .map(|d| d.added_at),
+                    ChainEventKind::DeviceRemoved => acc
+                        .removed_devices
+                        .iter()
+                        .find(|d| d.id == device_id)
+                        .map(|d| d.removed_at),
+                    ChainEventKind::DeviceUpdated => None,
+                };
+                events.push(ChainEvent {
+                    epoch,
+                    kind,
+                    device_name: device_name(&device_id),
+                    device_id,
+                    authored_by_name: device_name(&authored_by),
+                    authored_by: authored_by.clone(),
+                    happened_at,
+                    verified: verify_error.is_none(),
+                    verify_error: verify_error.clone(),
+                });
+            }
+        }
+        Ok(events)
+    }
+
+    /// Hash devices of this account and the contact. Contact devices are read from the
+    /// contact group chain: all devices that don't belong to this account.
+    fn devices_hashes<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithBackend),
+        acc_id: &str,
+        contact_id: &str,
+        contact_chain: &SignatureChain,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let backend = ctx.backend();
+        let own_chain = SignatureChainStorage::load(ctx.txn(), acc_id)?
+            .ok_or(anyhow!("Account chain is missing"))?;
+        let own_members = own_chain.members(backend.crypto())?;
+        let contact_members = contact_chain.members(backend.crypto())?;
+
+        let mut own_ids: Vec<String> = own_members.device_ids.keys().cloned().collect();
+        let mut contact_ids: Vec<String> = contact_members
+            .device_ids
+            .into_keys()
+            .filter(|id| {
+                !own_members.device_ids.contains_key(id) && !own_members.removed.contains_key(id)
+            })
+            .collect();
+
+        Ok((
+            safety_number::devices_hash(acc_id, &mut own_ids),
+            safety_number::devices_hash(contact_id, &mut contact_ids),
+        ))
+    }
+
     pub fn link_device<'a, C: Client>(
         &self,
         ctx: &(impl WithTxn<'a> + WithSecretGroupAtom<C> + WithDocsAtom + WithBackend + WithDeviceAtom),
@@ -228,17 +536,71 @@ impl AccountAtom {
         other_device: response::DevicePackages,
     ) -> Result<String> {
         let acc = self.require_account(ctx)?;
+        let other_device_id = get_device_id(share.key_package.credential())?;
+        let other_device_name = share.device_name;
+
+        let mut other_key_packages = Vec::new();
+        for message in other_device.key_packages {
+            let package = KeyPackage::tls_deserialize(&mut message.data.as_slice())?;
+            let device_id = get_device_id(package.credential())?;
+            if other_device_id == device_id {
+                other_key_packages.push(package);
+            }
+        }
+
+        // Account group could have been created with another ciphersuite than the shared package
+        let account_suite = ctx
+            .secret_group()
+            .load_latest(ctx, &acc.id)?
+            .mls
+            .ciphersuite();
+        let member_package = if share.key_package.ciphersuite() == account_suite {
+            share.key_package
+        } else {
+            let index = other_key_packages
+                .iter()
+                .position(|p| p.ciphersuite() == account_suite)
+                .ok_or(anyhow!("Device has no KeyPackage for {:?}", account_suite))?;
+            other_key_packages.remove(index)
+        };
+        self.add_account_member(ctx, &acc, member_package, &other_device_name)?;
+
+        // Add device to each contact group
+        ctx.secret_group()
+            .add_to_all_groups(ctx, other_key_packages)?;
+
+        Ok(other_device_name)
+    }
+
+    /// Add recovery member to the account group. Unlike devices recovery member
+    /// doesn't join contact groups: it is only used to link a new device.
+    pub fn add_recovery_member<'a, C: Client>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithSecretGroupAtom<C> + WithDocsAtom + WithBackend + WithDeviceAtom),
+        kit: &RecoveryKit,
+    ) -> Result<AccView> {
+        let acc = self.require_account(ctx)?;
+        self.add_account_member(ctx, &acc, kit.key_package().clone(), RECOVERY_DEVICE_NAME)?;
+        let acc = self.require_account(ctx)?;
+        Ok(acc)
+    }
 
+    fn add_account_member<'a, C: Client>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithSecretGroupAtom<C> + WithDocsAtom + WithBackend + WithDeviceAtom),
+        acc: &AccView,
+        key_package: KeyPackage,
+        name: &str,
+    ) -> Result<()> {
         // Add device to account group
         tracing::debug!(?acc.id, "Loading group");
-        let other_device_id = get_device_id(share.key_package.credential())?;
-        let other_device_name = share.device_name;
+        let other_device_id = get_device_id(key_package.credential())?;
         tracing::debug!(?other_device_id, "Adding new account member");
 
         let mut group = ctx.secret_group().load_latest(ctx, &acc.id)?;
         let commit = ctx
             .secret_group()
-            .add(ctx, &mut group, vec![share.key_package])?
+            .add(ctx, &mut group, vec![key_package])?
             .ok_or(anyhow!("Device is already in the group"))?;
         mailbox::queue_mls_commit(ctx, commit)?;
 
@@ -246,43 +608,29 @@ impl AccountAtom {
         let secrets = ctx.docs().list_secrets(ctx)?;
         if !secrets.is_empty() {
             ctx.secret_group()
-                .queue_secrets(ctx, &acc, secrets, &mut [acc.id.clone()])?;
-        }
-
-        // Add device to each contact group
-        let mut other_key_packages = Vec::new();
-        for message in other_device.key_packages {
-            let package = KeyPackage::tls_deserialize(&mut message.data.as_slice())?;
-            let device_id = get_device_id(package.credential())?;
-            if other_device_id == device_id {
-                other_key_packages.push(package);
-            }
+                .queue_secrets(ctx, acc, secrets, &mut [acc.id.clone()])?;
         }
 
-        // Add to all remaining groups
-        ctx.secret_group()
-            .add_to_all_groups(ctx, other_key_packages)?;
-
         // Add device to account document
         self.edit_account(ctx, |yrs_doc| {
             AccView::add_device(
                 yrs_doc,
                 AccDevice {
                     id: other_device_id,
-                    name: other_device_name.clone(),
+                    name: name.to_string(),
                     added_at: Utc::now(),
                 },
             );
             Ok(())
         })?;
-
-        Ok(other_device_name)
+        Ok(())
     }
 
     pub fn remove_device<'a, C: Client>(
         &self,
         ctx: &(impl WithTxn<'a> + WithSecretGroupAtom<C> + WithDeviceAtom + WithBackend + WithDocsAtom),
         remove_id: &str,
+        reason: Option<&str>,
     ) -> Result<AccView> {
         let acc = self.require_account(ctx)?;
         ctx.secret_group().remove_from_all_groups(ctx, remove_id)?;
@@ -290,7 +638,7 @@ impl AccountAtom {
 
         // Remove device from account document
         let acc = self.edit_account(ctx, |yrs_doc| {
-            AccView::remove_device(yrs_doc, remove_id);
+            AccView::remove_device(yrs_doc, remove_id, &ctx.device().id, reason);
             Ok(())
         })?;
 
@@ -320,19 +668,56 @@ impl AccountAtom {
             }
 
             let updated_acc = self.edit_account(tx_ctx, |yrs_doc| {
-                AccView::add_contact(
-                    yrs_doc,
-                    AccContact {
-                        account_id: contact.account_id,
-                        name: contact.name,
-                    },
-                );
+                AccView::add_contact(yrs_doc, contact);
                 Ok(())
             })?;
             Ok(updated_acc)
         })
     }
 
+    /// Remove contact from the account doc, card ACLs and contact MLS group.
+    /// When `block` is set future contact requests and shares from the account are ignored.
+    pub fn remove_contact<'a, C: Client>(
+        &self,
+        ctx: &(impl WithTxn<'a>
+              + WithDocsAtom
+              + WithDeviceAtom
+              + WithBackend
+              + WithAccountAtom
+              + WithTimelineAtom
+              + WithSecretGroupAtom<C>),
+        account_id: &str,
+        block: bool,
+    ) -> Result<AccView> {
+        let acc = self.require_account(ctx)?;
+        if acc.id == account_id {
+            bail!("Cannot remove own account");
+        }
+        let is_contact = acc.contacts.iter().any(|c| c.account_id == account_id);
+        if !is_contact && !block {
+            bail!("Unknown contact");
+        }
+
+        ctx.timeline().remove_account_access(ctx, account_id)?;
+        ctx.secret_group()
+            .remove_account_devices(ctx, &acc.id, account_id)?;
+
+        if block {
+            let request = AccNotification::ContactRequest {
+                account_id: account_id.to_string(),
+            };
+            self.delete_local_notification(ctx, &request.id())?;
+        }
+
+        self.edit_account(ctx, |doc| {
+            AccView::remove_contact(doc, account_id);
+            if block {
+                AccView::block_account(doc, account_id);
+            }
+            Ok(())
+        })
+    }
+
     /// Find account id using device id. Note that this could return a device that was already removed from account.
     pub fn find_account_device<'a>(
         &self,
@@ -431,14 +816,15 @@ SELECT credential, last_counter, json_each.value
         &self,
         ctx: &impl WithTxn<'a>,
         notification: &AccNotification,
+        from_account_id: &str,
     ) -> Result<()> {
         let id = notification.id();
         let now = Utc::now();
         ctx.txn().execute(
             r#"
-INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
+INSERT INTO local_notifications (id, created_at, from_account_id) VALUES (?1, ?2, ?3)
     ON CONFLICT (id) DO NOTHING"#,
-            params![id, now],
+            params![id, now, from_account_id],
         )?;
         Ok(())
     }
@@ -448,6 +834,7 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
         &self,
         ctx: &(impl WithTxn<'a> + WithDocsAtom),
         notification: &AccNotification,
+        from_account_id: &str,
     ) -> Result<bool> {
         let id = notification.id();
         // Check if this notification has already been handled by other device
@@ -461,7 +848,7 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
                 )
                 .optional()?;
             if local.is_none() {
-                self.create_notification(ctx, notification)?;
+                self.create_notification(ctx, notification, from_account_id)?;
                 Ok(true)
             } else {
                 Ok(false)
@@ -471,6 +858,117 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
         }
     }
 
+    /// Create or refresh informational notification. Unlike requests these notifications
+    /// are shown again even if a previous one with the same id was acknowledged.
+    pub fn create_info_notification<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        notification: &AccNotification,
+        from_account_id: &str,
+    ) -> Result<()> {
+        let id = notification.id();
+        let now = Utc::now();
+        ctx.txn().execute(
+            r#"
+INSERT INTO local_notifications (id, created_at, from_account_id) VALUES (?1, ?2, ?3)
+    ON CONFLICT (id) DO UPDATE
+       SET created_at = excluded.created_at,
+           from_account_id = excluded.from_account_id"#,
+            params![id, now, from_account_id],
+        )?;
+        Ok(())
+    }
+
+    /// List pending and acknowledged notifications. Newest first.
+    pub fn list_inbox<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithTimelineAtom),
+    ) -> Result<Vec<InboxNotification>> {
+        let acc_id = self.require_account_id(ctx)?;
+        let mut entries: Vec<(String, NotificationStatus, DateTime<Utc>, Option<String>)> = vec![];
+
+        // Pending
+        {
+            let mut stmt = ctx
+                .txn()
+                .prepare("SELECT id, created_at, from_account_id FROM local_notifications")?;
+            let mut rows = stmt.query([])?;
+            while let Some(row) = rows.next()? {
+                entries.push((
+                    row.get(0)?,
+                    NotificationStatus::Missing,
+                    row.get(1)?,
+                    row.get(2)?,
+                ));
+            }
+        }
+
+        // History
+        if let Some(row) = ctx.docs().find(ctx, &format!("{}/notifications", acc_id))? {
+            for acked in AccNotifications::history(&row.yrs) {
+                // Informational notifications could be pending again
+                if entries.iter().any(|(id, ..)| id == &acked.id) {
+                    continue;
+                }
+                entries.push((
+                    acked.id,
+                    acked.status,
+                    acked.acked_at.unwrap_or(row.meta.edited_at),
+                    acked.from_account_id,
+                ));
+            }
+        }
+
+        let mut inbox = vec![];
+        for (id, status, created_at, from_account_id) in entries {
+            let notification = match AccNotification::from_id(&id) {
+                Some(n) => n,
+                None => {
+                    tracing::debug!(%id, "Skipping unknown notification");
+                    continue;
+                }
+            };
+
+            let sender_id = match &notification {
+                AccNotification::ContactRequest { account_id } => Some(account_id.clone()),
+                _ => from_account_id,
+            };
+            let sender = match sender_id {
+                Some(sender_id) => ctx
+                    .docs()
+                    .find(ctx, &format!("{}/profile", sender_id))?
+                    .map(|row| ProfileView::from_db(row).0),
+                None => None,
+            };
+
+            let title = match &notification {
+                AccNotification::ContactRequest { .. } => None,
+                AccNotification::LabelShare { doc_id } => ctx
+                    .timeline()
+                    .find_shared_label(ctx, doc_id)?
+                    .map(|label| label.name),
+                AccNotification::CardShare { doc_id }
+                | AccNotification::EditorAdded { doc_id }
+                | AccNotification::CardEdited { doc_id }
+                | AccNotification::LabelCardAdded { doc_id } => ctx
+                    .docs()
+                    .find(ctx, doc_id)?
+                    .and_then(|row| CardView::from_db(row, None).0.title()),
+            };
+
+            inbox.push(InboxNotification {
+                notification,
+                status,
+                created_at,
+                sender,
+                title,
+            });
+        }
+
+        inbox.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+        Ok(inbox)
+    }
+
     pub fn list_notifications<'a>(&self, ctx: &impl WithTxn<'a>) -> Result<Vec<String>> {
         let mut stmt = ctx
             .txn()
@@ -491,12 +989,46 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
         Ok(())
     }
 
+    /// Delete local notification if it was created before it was acknowledged.
+    pub fn delete_local_notification_acked<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        id: &str,
+        acked_at: Option<DateTime<Utc>>,
+    ) -> Result<()> {
+        let created_at: Option<DateTime<Utc>> = ctx
+            .txn()
+            .query_row(
+                "SELECT created_at FROM local_notifications WHERE id = ?",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match (created_at, acked_at) {
+            // Acknowledgement time is stored with a second precision
+            (Some(created_at), Some(acked_at)) if created_at.timestamp() > acked_at.timestamp() => {
+                Ok(())
+            }
+            (Some(_), _) => self.delete_local_notification(ctx, id),
+            (None, _) => Ok(()),
+        }
+    }
+
     fn ack_notification<'a>(
         &self,
         ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom),
         id: &str,
         accepted: bool,
     ) -> Result<()> {
+        let from_account_id: Option<String> = ctx
+            .txn()
+            .query_row(
+                "SELECT from_account_id FROM local_notifications WHERE id = ?",
+                [id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
         self.delete_local_notification(ctx, id)?;
 
         // Mark notification as acked in shared doc
@@ -509,9 +1041,9 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
 
         // Apply changes to it
         if accepted {
-            AccNotifications::accept(&row.yrs, id.into());
+            AccNotifications::accept(&row.yrs, id.into(), from_account_id);
         } else {
-            AccNotifications::ignore(&row.yrs, id.into());
+            AccNotifications::ignore(&row.yrs, id.into(), from_account_id);
         }
 
         row.meta.author_device_id = ctx.device().id.clone();
@@ -549,6 +1081,8 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
                     AccContact {
                         account_id: contact_id.into(),
                         name: "".into(),
+                        bio: String::new(),
+                        avatar: None,
                     },
                 )
                 .await?;
@@ -563,6 +1097,19 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
             ctx.broadcast(OutputEvent::DocUpdated {
                 doc_id: card_id.into(),
             });
+        } else if let Some(label_id) = id.strip_prefix(AccNotification::LABEL_SHARE) {
+            // Add label cards to timeline
+            ctx.in_txn(|tx_ctx| {
+                let label = tx_ctx
+                    .timeline()
+                    .find_shared_label(tx_ctx, label_id)?
+                    .ok_or(anyhow!("Shared label not found"))?;
+                tx_ctx
+                    .timeline()
+                    .accept_shared_label_cards(tx_ctx, &label)?;
+                Ok(())
+            })?;
+            ctx.broadcast(OutputEvent::TimelineUpdated);
         }
 
         ctx.in_txn(|tx_ctx| self.ack_notification(tx_ctx, id, true))?;
@@ -577,6 +1124,8 @@ INSERT INTO local_notifications (id, created_at) VALUES (?1, ?2)
         if let Some(card_id) = id.strip_prefix(AccNotification::CARD_SHARE) {
             ctx.docs().remove_external(ctx, card_id)?;
             // TODO: ideally, we would schedule an upload to remove ourselves from collaborators
+        } else if let Some(label_id) = id.strip_prefix(AccNotification::LABEL_SHARE) {
+            ctx.docs().remove_external(ctx, label_id)?;
         }
         self.ack_notification(ctx, id, false)
     }
@@ -610,13 +1159,50 @@ impl AccountDevice {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AccNotification {
-    ContactRequest { account_id: String },
-    CardShare { doc_id: String },
+    ContactRequest {
+        account_id: String,
+    },
+    CardShare {
+        doc_id: String,
+    },
+    LabelShare {
+        doc_id: String,
+    },
+    /// Collaborator allowed this account to edit a card (informational)
+    EditorAdded {
+        doc_id: String,
+    },
+    /// Collaborator edited a card that this account shared (informational)
+    CardEdited {
+        doc_id: String,
+    },
+    /// Card was added to the timeline via an accepted shared label (informational)
+    LabelCardAdded {
+        doc_id: String,
+    },
 }
 
 impl AccNotification {
     const CONTACT_REQUEST: &'static str = "contact-request/";
     pub const CARD_SHARE: &'static str = "card-share/";
+    pub const LABEL_SHARE: &'static str = "label-share/";
+    const EDITOR_ADDED: &'static str = "editor-added/";
+    const CARD_EDITED: &'static str = "card-edited/";
+    const LABEL_CARD_ADDED: &'static str = "label-card-added/";
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        let (prefix, rest) = id.split_at(id.find('/')? + 1);
+        let rest = rest.to_string();
+        match prefix {
+            Self::CONTACT_REQUEST => Some(Self::ContactRequest { account_id: rest }),
+            Self::CARD_SHARE => Some(Self::CardShare { doc_id: rest }),
+            Self::LABEL_SHARE => Some(Self::LabelShare { doc_id: rest }),
+            Self::EDITOR_ADDED => Some(Self::EditorAdded { doc_id: rest }),
+            Self::CARD_EDITED => Some(Self::CardEdited { doc_id: rest }),
+            Self::LABEL_CARD_ADDED => Some(Self::LabelCardAdded { doc_id: rest }),
+            _ => None,
+        }
+    }
 
     pub fn id(&self) -> String {
         match self {
@@ -624,6 +1210,10 @@ impl AccNotification {
                 format!("{}{}", Self::CONTACT_REQUEST, account_id)
             }
             Self::CardShare { doc_id, .. } => format!("{}{}", Self::CARD_SHARE, doc_id),
+            Self::LabelShare { doc_id } => format!("{}{}", Self::LABEL_SHARE, doc_id),
+            Self::EditorAdded { doc_id } => format!("{}{}", Self::EDITOR_ADDED, doc_id),
+            Self::CardEdited { doc_id } => format!("{}{}", Self::CARD_EDITED, doc_id),
+            Self::LabelCardAdded { doc_id } => format!("{}{}", Self::LABEL_CARD_ADDED, doc_id),
         }
     }
 }
@@ -8,7 +8,7 @@ use yrs::{Map, MapPrelim, ReadTxn, Transact};
 
 use crate::documents::{yrs_util::int64_from_yrs, DbDocRow};
 
-use super::ProfileView;
+use super::{ProfileAvatar, ProfileView};
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct AccView {
@@ -19,12 +19,18 @@ pub struct AccView {
     pub contacts: Vec<AccContact>,
     pub labels: Vec<AccLabel>,
     pub devices: Vec<AccDevice>,
+    /// Devices that were removed from the account (most recent first)
+    pub removed_devices: Vec<RemovedDevice>,
+    /// Accounts which contact requests and shares are ignored
+    pub blocked: Vec<String>,
 }
 
 impl AccView {
     const CONTACTS: &'static str = "contacts";
     const LABELS: &'static str = "labels";
     const DEVICES: &'static str = "devices";
+    const REMOVED_DEVICES: &'static str = "removed_devices";
+    const BLOCKED: &'static str = "blocked";
 
     pub fn new(id: impl Into<String>) -> Self {
         Self {
@@ -34,6 +40,8 @@ impl AccView {
             contacts: vec![],
             labels: vec![],
             devices: vec![],
+            removed_devices: vec![],
+            blocked: vec![],
         }
     }
 
@@ -59,6 +67,8 @@ impl AccView {
         let contacts = Self::read_contacts(&doc);
         let labels = Self::read_labels(&doc);
         let devices = Self::read_devices(&doc);
+        let removed_devices = Self::read_removed_devices(&doc);
+        let blocked = Self::read_blocked(&doc);
 
         (
             Self {
@@ -68,6 +78,8 @@ impl AccView {
                 contacts: contacts.unwrap_or_default(),
                 labels: labels.unwrap_or_default(),
                 devices: devices.unwrap_or_default(),
+                removed_devices,
+                blocked,
             },
             doc,
         )
@@ -77,6 +89,18 @@ impl AccView {
         self.name = profile.name;
     }
 
+    /// Fill contact details from contact's profile.
+    pub fn with_contact_profile(&mut self, profile: ProfileView) {
+        if let Some(contact) = self
+            .contacts
+            .iter_mut()
+            .find(|c| c.account_id == profile.account_id)
+        {
+            contact.bio = profile.bio;
+            contact.avatar = profile.avatar;
+        }
+    }
+
     pub fn add_contact(doc: &yrs::Doc, contact: AccContact) {
         let contact_prelim: MapPrelim<Any> = MapPrelim::from(HashMap::from([(
             AccContact::NAME.to_string(),
@@ -98,6 +122,62 @@ impl AccView {
         }
     }
 
+    pub fn remove_contact(doc: &yrs::Doc, account_id: &str) {
+        let contacts = doc.get_or_insert_map(Self::CONTACTS);
+        let txn = &mut doc.transact_mut();
+        contacts.remove(txn, account_id);
+    }
+
+    pub fn block_account(doc: &yrs::Doc, account_id: &str) {
+        let blocked = doc.get_or_insert_map(Self::BLOCKED);
+        let txn = &mut doc.transact_mut();
+        blocked.insert(txn, account_id, Utc::now().timestamp());
+    }
+
+    pub fn unblock_account(doc: &yrs::Doc, account_id: &str) {
+        let blocked = doc.get_or_insert_map(Self::BLOCKED);
+        let txn = &mut doc.transact_mut();
+        blocked.remove(txn, account_id);
+    }
+
+    pub fn is_blocked(&self, account_id: &str) -> bool {
+        self.blocked.iter().any(|id| id == account_id)
+    }
+
+    /// Mark contact as verified. Value is a hash of contact devices at the time of verification.
+    pub fn set_contact_verified(
+        doc: &yrs::Doc,
+        account_id: &str,
+        devices_hash: Option<String>,
+    ) -> Result<()> {
+        let contacts = doc.get_or_insert_map(Self::CONTACTS);
+        let txn = &mut doc.transact_mut();
+        if let Some(contact) = contacts.get(txn, account_id).and_then(|v| v.to_ymap()) {
+            match devices_hash {
+                Some(hash) => {
+                    contact.insert(txn, AccContact::VERIFIED, hash);
+                }
+                None => {
+                    contact.remove(txn, AccContact::VERIFIED);
+                }
+            }
+            Ok(())
+        } else {
+            bail!("Unknown contact")
+        }
+    }
+
+    pub fn read_contact_verified(doc: &yrs::Doc, account_id: &str) -> Option<String> {
+        let txn = &doc.transact();
+        let contact = txn
+            .get_map(Self::CONTACTS)?
+            .get(txn, account_id)?
+            .to_ymap()?;
+        contact
+            .get(txn, AccContact::VERIFIED)
+            .map(|v| v.to_string(txn))
+    }
+
     pub fn create_label(doc: &yrs::Doc, label: AccLabel) {
         let label_prelim: MapPrelim<Any> = MapPrelim::from(HashMap::from([(
             AccLabel::NAME.to_string(),
@@ -127,10 +207,35 @@ impl AccView {
         devices.insert(txn, device.id, device_prelim);
     }
 
-    pub fn remove_device(doc: &yrs::Doc, device_id: &str) {
+    /// Remove device from the account and remember who removed it and why.
+    pub fn remove_device(doc: &yrs::Doc, device_id: &str, removed_by: &str, reason: Option<&str>) {
         let devices = doc.get_or_insert_map(Self::DEVICES);
+        let removed_devices = doc.get_or_insert_map(Self::REMOVED_DEVICES);
         let txn = &mut doc.transact_mut();
+        let name = devices
+            .get(txn, device_id)
+            .and_then(|v| v.to_ymap())
+            .and_then(|m| m.get(txn, AccDevice::NAME))
+            .map(|v| v.to_string(txn));
         devices.remove(txn, device_id);
+
+        let mut fields = HashMap::from([
+            (
+                RemovedDevice::REMOVED_AT.to_string(),
+                Utc::now().timestamp().into(),
+            ),
+            (
+                RemovedDevice::REMOVED_BY.to_string(),
+                removed_by.to_string().into(),
+            ),
+        ]);
+        if let Some(name) = name {
+            fields.insert(RemovedDevice::NAME.to_string(), name.into());
+        }
+        if let Some(reason) = reason {
+            fields.insert(RemovedDevice::REASON.to_string(), reason.to_string().into());
+        }
+        removed_devices.insert(txn, device_id, MapPrelim::<Any>::from(fields));
     }
 
     pub fn read_contacts(doc: &yrs::Doc) -> Option<Vec<AccContact>> {
@@ -143,6 +248,16 @@ impl AccView {
         })
     }
 
+    fn read_blocked(doc: &yrs::Doc) -> Vec<String> {
+        let txn = &doc.transact();
+        let mut blocked: Vec<String> = txn
+            .get_map(Self::BLOCKED)
+            .map(|m| m.iter(txn).map(|(id, _)| id.to_string()).collect())
+            .unwrap_or_default();
+        blocked.sort();
+        blocked
+    }
+
     fn read_labels(doc: &yrs::Doc) -> Option<Vec<AccLabel>> {
         let txn = &doc.transact();
         txn.get_map(Self::LABELS).and_then(|m| {
@@ -153,6 +268,20 @@ impl AccView {
         })
     }
 
+    fn read_removed_devices(doc: &yrs::Doc) -> Vec<RemovedDevice> {
+        let txn = &doc.transact();
+        let mut removed: Vec<RemovedDevice> = txn
+            .get_map(Self::REMOVED_DEVICES)
+            .map(|m| {
+                m.iter(txn)
+                    .filter_map(|(id, v)| RemovedDevice::from_map_entry(txn, id.to_string(), v))
+                    .collect()
+            })
+            .unwrap_or_default();
+        removed.sort_by(|a, b| b.removed_at.cmp(&a.removed_at));
+        removed
+    }
+
     fn read_devices(doc: &yrs::Doc) -> Option<Vec<AccDevice>> {
         let txn = &doc.transact();
         txn.get_map(Self::DEVICES).and_then(|m| {
@@ -168,10 +297,15 @@ impl AccView {
 pub struct AccContact {
     pub account_id: String,
     pub name: String,
+    /// Bio from contact's profile
+    pub bio: String,
+    /// Picture from contact's profile
+    pub avatar: Option<ProfileAvatar>,
 }
 
 impl AccContact {
     const NAME: &'static str = "name";
+    const VERIFIED: &'static str = "verified";
 
     fn from_map_entry(
         txn: &impl ReadTxn,
@@ -185,6 +319,8 @@ impl AccContact {
                     .map(|v| v.to_string(txn))
                     .unwrap_or_else(|| ProfileView::default_name(&account_id)),
                 account_id,
+                bio: String::new(),
+                avatar: None,
             })
         })
     }
@@ -250,3 +386,72 @@ impl AccDevice {
         })
     }
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemovedDevice {
+    pub id: String,
+    pub name: Option<String>,
+    pub removed_at: DateTime<Utc>,
+    /// Device that removed this one
+    pub removed_by: String,
+    pub reason: Option<String>,
+}
+
+impl RemovedDevice {
+    const NAME: &'static str = "name";
+    const REMOVED_AT: &'static str = "removed_at";
+    const REMOVED_BY: &'static str = "removed_by";
+    const REASON: &'static str = "reason";
+
+    fn from_map_entry(txn: &impl ReadTxn, id: String, value: yrs::types::Value) -> Option<Self> {
+        value.to_ymap().and_then(|ymap| {
+            let removed_at = ymap
+                .get(txn, Self::REMOVED_AT)
+                .and_then(int64_from_yrs)
+                .and_then(|secs| Utc.timestamp_opt(secs, 0).earliest())?;
+            Some(Self {
+                id,
+                name: ymap.get(txn, Self::NAME).map(|v| v.to_string(txn)),
+                removed_at,
+                removed_by: ymap.get(txn, Self::REMOVED_BY)?.to_string(txn),
+                reason: ymap.get(txn, Self::REASON).map(|v| v.to_string(txn)),
+            })
+        })
+    }
+}
+
+/// Account device together with its activity as seen by the server.
+#[derive(Debug, Clone)]
+pub struct DeviceSession {
+    pub device: AccDevice,
+    /// Time of the last request to the server
+    pub last_seen_at: Option<DateTime<Utc>>,
+    pub platform: Option<String>,
+    pub app_version: Option<String>,
+    /// True for this device
+    pub is_current: bool,
+}
+
+/// Change of account devices recorded in the account signature chain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainEvent {
+    pub epoch: u64,
+    pub kind: ChainEventKind,
+    pub device_id: String,
+    pub device_name: Option<String>,
+    pub authored_by: String,
+    pub authored_by_name: Option<String>,
+    /// Chain blocks are not timestamped. Time is taken from the account when known.
+    pub happened_at: Option<DateTime<Utc>>,
+    /// True when block hash, parent and author signature are valid
+    pub verified: bool,
+    pub verify_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChainEventKind {
+    DeviceAdded,
+    DeviceRemoved,
+    /// Device has rotated its key package
+    DeviceUpdated,
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, bail, Result};
+use bolik_chain::SignatureChain;
+use bolik_proto::sync::{contact_invite_message, response, ContactInviteMessage};
+use chrono::{DateTime, TimeZone, Utc};
+use openmls_traits::OpenMlsCryptoProvider;
+use prost::Message;
+
+use crate::secrets;
+
+use super::AccountDevice;
+
+/// Invite code contents. Invite is signed by one of the inviting account's devices.
+#[derive(Debug)]
+pub struct ContactInvite {
+    pub account_id: String,
+    pub device_id: String,
+    /// Account name at the time the invite was created
+    pub name: String,
+    pub expires_at: DateTime<Utc>,
+    payload: Vec<u8>,
+    signature: String,
+}
+
+impl ContactInvite {
+    /// Encode signed invite payload into an invite code.
+    pub fn encode_code(payload: Vec<u8>, signature: String) -> String {
+        let message = ContactInviteMessage { payload, signature }.encode_to_vec();
+        secrets::id_from_key(&message)
+    }
+
+    pub fn parse(code: &str) -> Result<Self> {
+        let bytes = secrets::key_from_id(code.trim())?;
+        let message = ContactInviteMessage::decode(bytes.as_slice())?;
+        let invite = contact_invite_message::ContactInvite::decode(message.payload.as_slice())?;
+        let expires_at = Utc
+            .timestamp_opt(invite.expires_at_sec, 0)
+            .earliest()
+            .ok_or(anyhow!("Invalid expires_at {}", invite.expires_at_sec))?;
+
+        Ok(Self {
+            account_id: invite.account_id,
+            device_id: invite.device_id,
+            name: invite.name,
+            expires_at,
+            payload: message.payload,
+            signature: message.signature,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+
+    /// Verify that invite was signed by a device from the account's signature chain.
+    pub fn verify(
+        &self,
+        backend: &impl OpenMlsCryptoProvider,
+        devices: response::AccountDevices,
+    ) -> Result<()> {
+        let chain = SignatureChain::decode(
+            devices
+                .chain
+                .ok_or(anyhow!("AccountDevices is missing the chain"))?,
+        )?;
+        chain.verify(backend)?;
+        if chain.root() != self.account_id {
+            bail!(
+                "Chain root doesn't match account id ({} != {})",
+                chain.root(),
+                self.account_id
+            );
+        }
+
+        let members = chain.members(backend.crypto())?;
+        let member = members
+            .find_by_id(&self.device_id)
+            .ok_or(anyhow!("Invite device is not a member of the account"))?;
+        let device = AccountDevice {
+            device_id: self.device_id.clone(),
+            account_id: self.account_id.clone(),
+            credential: member.package.credential().clone(),
+            last_counter: None,
+        };
+        device.verify(backend, &self.payload, &self.signature)
+    }
+}
@@ -1,11 +1,19 @@
-use anyhow::Result;
+use chrono::{DateTime, TimeZone, Utc};
 use lib0::any::Any;
 use yrs::{Map, ReadTxn, Transact};
 
+use crate::documents::yrs_util::int64_from_yrs;
+
+use super::{AccNotification, ProfileView};
+
 pub struct AccNotifications {}
 
 impl AccNotifications {
     const IDS: &'static str = "ids";
+    /// Notification id to the time when it was accepted or ignored
+    const ACKED_AT: &'static str = "acked_at";
+    /// Notification id to the account that caused the notification
+    const SENDERS: &'static str = "senders";
 
     pub fn init(client_id: yrs::block::ClientID) -> yrs::Doc {
         yrs::Doc::with_options(yrs::Options {
@@ -16,17 +24,25 @@ impl AccNotifications {
     }
 
     /// Accept notification.
-    pub fn accept(doc: &yrs::Doc, id: String) {
-        let ids = doc.get_or_insert_map(Self::IDS);
-        let txn = &mut doc.transact_mut();
-        ids.insert(txn, id, true);
+    pub fn accept(doc: &yrs::Doc, id: String, from_account_id: Option<String>) {
+        Self::ack(doc, id, true, from_account_id);
     }
 
     /// Ignore notification.
-    pub fn ignore(doc: &yrs::Doc, id: String) {
+    pub fn ignore(doc: &yrs::Doc, id: String, from_account_id: Option<String>) {
+        Self::ack(doc, id, false, from_account_id);
+    }
+
+    fn ack(doc: &yrs::Doc, id: String, accepted: bool, from_account_id: Option<String>) {
         let ids = doc.get_or_insert_map(Self::IDS);
+        let acked_at = doc.get_or_insert_map(Self::ACKED_AT);
+        let senders = doc.get_or_insert_map(Self::SENDERS);
         let txn = &mut doc.transact_mut();
-        ids.insert(txn, id, false);
+        ids.insert(txn, id.as_str(), accepted);
+        acked_at.insert(txn, id.as_str(), Utc::now().timestamp());
+        if let Some(from_account_id) = from_account_id {
+            senders.insert(txn, id, from_account_id);
+        }
     }
 
     pub fn status(doc: &yrs::Doc, id: &str) -> NotificationStatus {
@@ -48,12 +64,13 @@ impl AccNotifications {
         }
     }
 
-    /// Iterate over notification ids and apply a function to each entry.
-    pub fn iter_ids(
-        doc: &yrs::Doc,
-        f: impl Fn(&str, NotificationStatus) -> Result<()>,
-    ) -> Result<()> {
+    /// List acknowledged notifications.
+    pub fn history(doc: &yrs::Doc) -> Vec<AckedNotification> {
         let txn = &doc.transact();
+        let acked_at = txn.get_map(Self::ACKED_AT);
+        let senders = txn.get_map(Self::SENDERS);
+        let mut entries = vec![];
+
         if let Some(map) = txn.get_map(Self::IDS) {
             for (notification_id, v) in map.iter(txn) {
                 let status = match v {
@@ -64,11 +81,24 @@ impl AccNotifications {
                     }
                 };
 
-                f(notification_id, status)?;
+                entries.push(AckedNotification {
+                    id: notification_id.to_string(),
+                    status,
+                    // Notifications acknowledged by older versions don't have a timestamp
+                    acked_at: acked_at
+                        .as_ref()
+                        .and_then(|m| m.get(txn, notification_id))
+                        .and_then(int64_from_yrs)
+                        .map(|sec| Utc.timestamp(sec, 0)),
+                    from_account_id: senders
+                        .as_ref()
+                        .and_then(|m| m.get(txn, notification_id))
+                        .map(|v| v.to_string(txn)),
+                });
             }
         }
 
-        Ok(())
+        entries
     }
 }
 
@@ -78,3 +108,23 @@ pub enum NotificationStatus {
     Accepted,
     Ignored,
 }
+
+pub struct AckedNotification {
+    pub id: String,
+    pub status: NotificationStatus,
+    pub acked_at: Option<DateTime<Utc>>,
+    pub from_account_id: Option<String>,
+}
+
+/// Notification as displayed in the inbox.
+pub struct InboxNotification {
+    pub notification: AccNotification,
+    /// Missing for pending notifications
+    pub status: NotificationStatus,
+    /// When notification was received (pending) or acknowledged (history)
+    pub created_at: DateTime<Utc>,
+    /// Profile of the account that caused the notification
+    pub sender: Option<ProfileView>,
+    /// Card title or shared label name
+    pub title: Option<String>,
+}
@@ -1,16 +1,30 @@
 use yrs::{Map, ReadTxn, Transact};
 
-use crate::documents::{DbDocRow, DbDocRowMeta};
+use crate::{
+    documents::{
+        yrs_util::{bytes_from_yrs, uint_from_yrs},
+        DbDocRow, DbDocRowMeta,
+    },
+    timeline::card::{CardFile, FileThumbnail},
+};
 
 /// Profile is a public part of account
 pub struct ProfileView {
     pub account_id: String,
     pub name: String,
+    pub bio: String,
+    /// Short status message
+    pub status: String,
+    pub avatar: Option<ProfileAvatar>,
 }
 
 impl ProfileView {
     const FIELDS: &'static str = "fields";
     const NAME: &'static str = "name";
+    const BIO: &'static str = "bio";
+    const STATUS: &'static str = "status";
+    const AVATAR: &'static str = "avatar";
+    const AVATAR_THUMBNAIL: &'static str = "avatar_thumbnail";
 
     pub fn init(client_id: yrs::block::ClientID) -> yrs::Doc {
         yrs::Doc::with_options(yrs::Options {
@@ -22,22 +36,82 @@ impl ProfileView {
 
     pub fn from_db(row: DbDocRow) -> (Self, yrs::Doc) {
         let name = Self::get_name(&row);
+        let mut bio = String::new();
+        let mut status = String::new();
+        let avatar = Self::read_avatar(&row.yrs);
+
+        {
+            let txn = &row.yrs.transact();
+            if let Some(fields) = txn.get_map(Self::FIELDS) {
+                if let Some(v) = fields.get(txn, Self::BIO) {
+                    bio = v.to_string(txn);
+                }
+                if let Some(v) = fields.get(txn, Self::STATUS) {
+                    status = v.to_string(txn);
+                }
+            }
+        }
 
         (
             Self {
                 account_id: row.meta.id.split('/').next().unwrap().into(),
                 name,
+                bio,
+                status,
+                avatar,
             },
             row.yrs,
         )
     }
 
+    pub fn read_avatar(doc: &yrs::Doc) -> Option<ProfileAvatar> {
+        let txn = &doc.transact();
+        let avatar_map = txn.get_map(Self::AVATAR)?;
+        ProfileAvatar::from_map(txn, avatar_map).map(|mut a| {
+            a.thumbnail = txn
+                .get_map(Self::AVATAR_THUMBNAIL)
+                .and_then(|m| FileThumbnail::from_map(txn, m));
+            a
+        })
+    }
+
     pub fn set_name(doc: &yrs::Doc, name: String) {
         let fields = doc.get_or_insert_map(Self::FIELDS);
         let txn = &mut doc.transact_mut();
         fields.insert(txn, Self::NAME, name);
     }
 
+    pub fn set_bio(doc: &yrs::Doc, bio: String) {
+        let fields = doc.get_or_insert_map(Self::FIELDS);
+        let txn = &mut doc.transact_mut();
+        fields.insert(txn, Self::BIO, bio);
+    }
+
+    pub fn set_status(doc: &yrs::Doc, status: String) {
+        let fields = doc.get_or_insert_map(Self::FIELDS);
+        let txn = &mut doc.transact_mut();
+        fields.insert(txn, Self::STATUS, status);
+    }
+
+    pub fn set_avatar(doc: &yrs::Doc, avatar: Option<ProfileAvatar>) {
+        let avatar_map = doc.get_or_insert_map(Self::AVATAR);
+        let thumb_map = doc.get_or_insert_map(Self::AVATAR_THUMBNAIL);
+        let txn = &mut doc.transact_mut();
+        avatar_map.clear(txn);
+        thumb_map.clear(txn);
+
+        if let Some(avatar) = avatar {
+            avatar_map.insert(txn, ProfileAvatar::BLOB_ID, avatar.blob_id);
+            avatar_map.insert(txn, ProfileAvatar::DEVICE_ID, avatar.device_id);
+            avatar_map.insert(txn, ProfileAvatar::CHECKSUM, avatar.checksum);
+            avatar_map.insert(txn, ProfileAvatar::SIZE_BYTES, avatar.size_bytes);
+            avatar_map.insert(txn, ProfileAvatar::SECRET, avatar.secret);
+            if let Some(thumb) = avatar.thumbnail {
+                thumb.insert_into(txn, &thumb_map);
+            }
+        }
+    }
+
     pub fn default_name(account_id: &str) -> String {
         let short_id: String = account_id.chars().take(6).collect();
         format!("Account #{}", short_id.to_lowercase())
@@ -58,3 +132,60 @@ impl ProfileView {
         }
     }
 }
+
+/// Profile picture. Image is stored as an encrypted blob and a small thumbnail is kept inline.
+#[derive(Clone, PartialEq)]
+pub struct ProfileAvatar {
+    pub blob_id: String,
+    pub device_id: String,
+    pub checksum: String,
+    pub size_bytes: u32,
+    pub thumbnail: Option<FileThumbnail>,
+    /// Key the blob is encrypted with
+    pub secret: Vec<u8>,
+}
+
+impl ProfileAvatar {
+    /// Max width and height of avatar thumbnails.
+    pub const THUMBNAIL_SIZE: u32 = 128;
+
+    const BLOB_ID: &'static str = "blob_id";
+    const DEVICE_ID: &'static str = "device_id";
+    const CHECKSUM: &'static str = "checksum";
+    const SIZE_BYTES: &'static str = "size_bytes";
+    const SECRET: &'static str = "secret";
+
+    fn from_map(txn: &yrs::Transaction, map: impl yrs::Map) -> Option<Self> {
+        Some(Self {
+            blob_id: map.get(txn, Self::BLOB_ID)?.to_string(txn),
+            device_id: map.get(txn, Self::DEVICE_ID)?.to_string(txn),
+            checksum: map.get(txn, Self::CHECKSUM)?.to_string(txn),
+            size_bytes: uint_from_yrs(map.get(txn, Self::SIZE_BYTES)?)?,
+            thumbnail: None,
+            secret: bytes_from_yrs(map.get(txn, Self::SECRET)?)?,
+        })
+    }
+
+    /// Describe avatar blob as a file (to reuse blob transfers).
+    pub fn file(&self) -> CardFile {
+        CardFile {
+            blob_id: self.blob_id.clone(),
+            device_id: self.device_id.clone(),
+            checksum: self.checksum.clone(),
+            size_bytes: self.size_bytes,
+            name: None,
+            dimensions: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ProfileAvatar {
+    // Skip the secret and thumbnail bytes
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProfileAvatar")
+            .field("blob_id", &self.blob_id)
+            .field("device_id", &self.device_id)
+            .field("checksum", &self.checksum)
+            .finish_non_exhaustive()
+    }
+}
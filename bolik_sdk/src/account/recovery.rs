@@ -0,0 +1,174 @@
+use anyhow::{anyhow, bail, Result};
+use bolik_migrations::rusqlite::params;
+use bolik_proto::sync::RecoveryKitMessage;
+use openmls::prelude::{
+    CredentialBundle, CredentialType, KeyPackage, KeyPackageBundle, OpenMlsKeyStore,
+};
+use openmls_rust_crypto::{OpenMlsRustCrypto, RustCrypto};
+use openmls_traits::{
+    crypto::OpenMlsCrypto,
+    key_store::{FromKeyStoreValue, ToKeyStoreValue},
+    types::HashType,
+    OpenMlsCryptoProvider,
+};
+use prost::Message;
+use rand::RngCore;
+use uuid::Uuid;
+
+use crate::{
+    device::{get_credential_id_bytes, get_device_id},
+    secrets::{self, DbCipher, SqliteCryptoProvider, CIPHERSUITES, DEFAULT_CIPHERSUITE},
+};
+
+/// 256 bits of entropy give a 24 word mnemonic.
+const SECRET_SIZE: usize = 32;
+const KIT_ID_SIZE: usize = 16;
+const KIT_KEY_SIZE: usize = 32;
+
+/// Name under which recovery member is listed among account devices.
+pub const RECOVERY_DEVICE_NAME: &str = "Recovery key";
+
+/// Secret behind the recovery mnemonic.
+///
+/// Both the ID of the recovery kit on the server and the kit encryption key are derived from it
+/// hence the server never learns the keys of the recovery member.
+pub struct RecoveryKey {
+    secret: Vec<u8>,
+}
+
+impl RecoveryKey {
+    pub fn generate() -> Self {
+        let mut secret = vec![0; SECRET_SIZE];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self { secret }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Result<Self> {
+        let mnemonic = bip39::Mnemonic::parse_normalized(mnemonic.trim())
+            .map_err(|err| anyhow!("Invalid recovery key: {}", err))?;
+        let secret = mnemonic.to_entropy();
+        if secret.len() != SECRET_SIZE {
+            bail!(
+                "Invalid recovery key: expected {} words",
+                SECRET_SIZE * 3 / 4
+            );
+        }
+        Ok(Self { secret })
+    }
+
+    pub fn to_mnemonic(&self) -> Result<String> {
+        let mnemonic = bip39::Mnemonic::from_entropy(&self.secret)?;
+        Ok(mnemonic.to_string())
+    }
+
+    /// ID under which the encrypted kit is stored on the server.
+    pub fn kit_id(&self) -> Result<String> {
+        let id = self.derive(b"bolik recovery kit id", KIT_ID_SIZE)?;
+        Ok(secrets::id_from_key(&id))
+    }
+
+    pub fn encrypt_kit(&self, kit: &RecoveryKit) -> Result<Vec<u8>> {
+        let message = RecoveryKitMessage {
+            credential_bundle: kit
+                .credential_bundle
+                .to_key_store_value()
+                .map_err(|err| anyhow!("Serialize CredentialBundle: {:?}", err))?,
+            key_package_bundle: kit
+                .key_package_bundle
+                .to_key_store_value()
+                .map_err(|err| anyhow!("Serialize KeyPackageBundle: {:?}", err))?,
+        };
+        self.cipher()?.encrypt(&message.encode_to_vec())
+    }
+
+    pub fn decrypt_kit(&self, payload: &[u8]) -> Result<RecoveryKit> {
+        let bytes = self
+            .cipher()?
+            .decrypt(payload)
+            .map_err(|_| anyhow!("Recovery kit doesn't match the recovery key"))?;
+        let message = RecoveryKitMessage::decode(bytes.as_slice())?;
+        Ok(RecoveryKit {
+            credential_bundle: CredentialBundle::from_key_store_value(&message.credential_bundle)
+                .map_err(|err| anyhow!("Deserialize CredentialBundle: {:?}", err))?,
+            key_package_bundle: KeyPackageBundle::from_key_store_value(&message.key_package_bundle)
+                .map_err(|err| anyhow!("Deserialize KeyPackageBundle: {:?}", err))?,
+        })
+    }
+
+    /// Key that encrypts account backups.
+    pub fn backup_key(&self) -> Result<Vec<u8>> {
+        self.derive(b"bolik backup key", KIT_KEY_SIZE)
+    }
+
+    fn cipher(&self) -> Result<DbCipher> {
+        let key = self.derive(b"bolik recovery kit key", KIT_KEY_SIZE)?;
+        Ok(DbCipher::new(chacha20poly1305::Key::from_slice(&key)))
+    }
+
+    fn derive(&self, info: &[u8], len: usize) -> Result<Vec<u8>> {
+        let crypto = RustCrypto::default();
+        let prk = crypto
+            .hkdf_extract(HashType::Sha2_256, &[], &self.secret)
+            .map_err(|err| anyhow!("Derive recovery secret: {:?}", err))?;
+        let okm = crypto
+            .hkdf_expand(HashType::Sha2_256, &prk, info, len)
+            .map_err(|err| anyhow!("Derive recovery secret: {:?}", err))?;
+        Ok(okm)
+    }
+}
+
+/// Keys of the recovery member.
+///
+/// Recovery member joins the account group like any other device
+/// but its keys never stay on the device that generated them.
+pub struct RecoveryKit {
+    pub credential_bundle: CredentialBundle,
+    pub key_package_bundle: KeyPackageBundle,
+}
+
+impl RecoveryKit {
+    pub fn generate() -> Result<Self> {
+        let backend = &OpenMlsRustCrypto::default();
+        let identity = Uuid::new_v4().to_string();
+        let credential_bundle = CredentialBundle::new(
+            identity.as_bytes().to_vec(),
+            CredentialType::Basic,
+            DEFAULT_CIPHERSUITE.signature_algorithm(),
+            backend,
+        )?;
+        let key_package_bundle =
+            KeyPackageBundle::new(&CIPHERSUITES, &credential_bundle, backend, vec![])?;
+        Ok(Self {
+            credential_bundle,
+            key_package_bundle,
+        })
+    }
+
+    pub fn key_package(&self) -> &KeyPackage {
+        self.key_package_bundle.key_package()
+    }
+
+    pub fn device_id(&self) -> Result<String> {
+        get_device_id(self.credential_bundle.credential())
+    }
+
+    /// Set up an empty database as the recovery member device.
+    pub fn restore(&self, backend: &SqliteCryptoProvider) -> Result<String> {
+        let credential_id_bytes = get_credential_id_bytes(self.credential_bundle.credential())?;
+        backend
+            .key_store()
+            .store(&credential_id_bytes, &self.credential_bundle)?;
+
+        let key_id = self.key_package().hash_ref(backend.crypto())?;
+        backend
+            .key_store()
+            .store(key_id.value(), &self.key_package_bundle)?;
+
+        let device_id = self.device_id()?;
+        backend.conn.execute(
+            "INSERT INTO device_settings (device_id, device_name) VALUES (?, ?)",
+            params![device_id, RECOVERY_DEVICE_NAME],
+        )?;
+        Ok(device_id)
+    }
+}
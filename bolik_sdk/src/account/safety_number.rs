@@ -0,0 +1,68 @@
+use multihash::{Blake3_256, Hasher};
+
+/// Safety number of this account and a contact. Both accounts see the same number.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContactFingerprint {
+    pub account_id: String,
+    /// 60 digits split into groups of five
+    pub safety_number: String,
+    /// Whether the contact was verified and its devices haven't changed since
+    pub verified: bool,
+}
+
+/// Hash account id together with its devices. Device ids are derived from device signature keys.
+pub fn devices_hash(account_id: &str, device_ids: &mut [String]) -> Vec<u8> {
+    device_ids.sort();
+    let mut hasher = Blake3_256::default();
+    hasher.update(account_id.as_bytes());
+    for device_id in device_ids {
+        hasher.update(b",");
+        hasher.update(device_id.as_bytes());
+    }
+    hasher.finalize().to_vec()
+}
+
+/// Build a safety number from (account id, devices hash) pairs.
+pub fn safety_number(a: (&str, &[u8]), b: (&str, &[u8])) -> String {
+    // Order accounts so that both sides compute the same number
+    let (first, second) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+    [first.1, second.1]
+        .into_iter()
+        .flat_map(|hash| hash[..30].chunks(5))
+        .map(|chunk| {
+            let n = chunk
+                .iter()
+                .fold(0u64, |acc, byte| (acc << 8) | *byte as u64);
+            format!("{:05}", n % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{devices_hash, safety_number};
+
+    #[test]
+    fn test_safety_number_is_symmetric() {
+        let hash_a = devices_hash("acc-a", &mut ["dev-2".to_string(), "dev-1".to_string()]);
+        let hash_b = devices_hash("acc-b", &mut ["dev-3".to_string()]);
+
+        let number = safety_number(("acc-a", &hash_a), ("acc-b", &hash_b));
+        assert_eq!(
+            number,
+            safety_number(("acc-b", &hash_b), ("acc-a", &hash_a))
+        );
+        assert_eq!(number.split(' ').count(), 12);
+        assert!(number.split(' ').all(|g| g.len() == 5));
+    }
+
+    #[test]
+    fn test_devices_hash_ignores_order() {
+        let hash_1 = devices_hash("acc", &mut ["dev-1".to_string(), "dev-2".to_string()]);
+        let hash_2 = devices_hash("acc", &mut ["dev-2".to_string(), "dev-1".to_string()]);
+        let hash_3 = devices_hash("acc", &mut ["dev-1".to_string()]);
+        assert_eq!(hash_1, hash_2);
+        assert_ne!(hash_1, hash_3);
+    }
+}
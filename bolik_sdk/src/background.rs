@@ -1,13 +1,21 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use tracing::instrument;
 
 use crate::{
+    account::ProfileView,
+    backup,
     client::Client,
     output::OutputEvent,
-    registry::{Registry, WithInTxn, WithTimelineAtom},
+    public_links,
+    registry::{Registry, WithDocsAtom, WithInTxn, WithTimelineAtom},
+    secret_group,
     timeline::card::{CardFile, CardView},
+    BIN_LABEL_ID,
 };
 
 pub enum BackgroundInput {
@@ -18,6 +26,12 @@ pub enum BackgroundInput {
         card_id: String,
         card_file: CardFile,
     },
+    FetchDeferredDoc {
+        doc_id: String,
+    },
+    DownloadAvatar {
+        account_id: String,
+    },
 }
 
 impl Display for BackgroundInput {
@@ -32,13 +46,27 @@ impl Display for BackgroundInput {
                 "DownloadFile(blob_id={} device_id={} doc_id={})",
                 card_file.blob_id, card_file.device_id, card_id
             )),
+            Self::FetchDeferredDoc { doc_id } => {
+                f.write_fmt(format_args!("FetchDeferredDoc(doc_id={})", doc_id))
+            }
+            Self::DownloadAvatar { account_id } => {
+                f.write_fmt(format_args!("DownloadAvatar(account_id={})", account_id))
+            }
         }
     }
 }
 
+/// How often to look for collaborators which rights have expired.
+const EXPIRED_COLLABORATORS_INTERVAL: Duration = Duration::from_secs(60);
+/// How often to look for secret groups that need a self-update.
+const KEY_ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 pub struct BackgroundTask<C: Clone> {
     debug_name: String,
     registry: Registry<C>,
+    client: C,
+    expired_collaborators_checked_at: Option<Instant>,
+    key_rotation_checked_at: Option<Instant>,
 }
 
 #[instrument(name = "bg", skip_all, fields(d = task.debug_name))]
@@ -59,24 +87,39 @@ impl<C> BackgroundTask<C>
 where
     C: Client,
 {
-    pub fn new(registry: Registry<C>, debug_name: String) -> Self {
+    pub fn new(registry: Registry<C>, client: C, debug_name: String) -> Self {
         Self {
             registry,
+            client,
             debug_name,
+            expired_collaborators_checked_at: None,
+            key_rotation_checked_at: None,
         }
     }
 
     async fn process(&mut self, input: BackgroundInput) -> Result<()> {
         match input {
-            BackgroundInput::Sync => match self.sync().await {
-                Ok(_) => {
-                    self.broadcast(OutputEvent::Synced)?;
+            BackgroundInput::Sync => {
+                if let Err(err) = self.remove_expired_collaborators() {
+                    tracing::warn!("Failed to remove expired collaborators: {:?}", err);
+                }
+                if let Err(err) = self.backup_if_due() {
+                    tracing::warn!("Failed to create scheduled backup: {:?}", err);
                 }
-                Err(err) => {
-                    self.broadcast(OutputEvent::SyncFailed)?;
-                    return Err(err);
+                if let Err(err) = self.rotate_keys() {
+                    tracing::warn!("Failed to rotate group keys: {:?}", err);
                 }
-            },
+
+                match self.sync().await {
+                    Ok(_) => {
+                        self.broadcast(OutputEvent::Synced)?;
+                    }
+                    Err(err) => {
+                        self.broadcast(OutputEvent::SyncFailed)?;
+                        return Err(err);
+                    }
+                }
+            }
             BackgroundInput::EmptyBin => self.empty_bin()?,
             BackgroundInput::ProcessFiles(card, sender) => {
                 self.process_files(card).await?;
@@ -101,6 +144,44 @@ where
                     }
                 }
             }
+            BackgroundInput::FetchDeferredDoc { doc_id } => {
+                let ctx = self.registry.db_ctx();
+                self.registry
+                    .sync_docs
+                    .fetch_deferred(&ctx, &doc_id)
+                    .await?;
+                self.broadcast(OutputEvent::DocUpdated { doc_id })?;
+            }
+            BackgroundInput::DownloadAvatar { account_id } => {
+                let ctx = self.registry.db_ctx();
+                let profile_id = format!("{}/profile", account_id);
+                let profile = ctx
+                    .in_txn(|ctx_tx| ctx_tx.docs().find(ctx_tx, &profile_id))?
+                    .map(|row| ProfileView::from_db(row).0)
+                    .ok_or(anyhow!("Profile not found"))?;
+                let avatar = profile.avatar.ok_or(anyhow!("Profile has no avatar"))?;
+
+                match self
+                    .registry
+                    .blobs
+                    .download_avatar(&ctx, &profile_id, &avatar)
+                    .await
+                {
+                    Ok(path) => {
+                        self.broadcast(OutputEvent::DownloadCompleted {
+                            blob_id: avatar.blob_id,
+                            device_id: avatar.device_id,
+                            path,
+                        })?;
+                    }
+                    Err(err) => {
+                        self.broadcast(OutputEvent::DownloadFailed {
+                            blob_id: avatar.blob_id,
+                        })?;
+                        return Err(err);
+                    }
+                }
+            }
         };
         Ok(())
     }
@@ -123,6 +204,35 @@ where
             .await
             .context("Sync docs")?;
 
+        if let Err(err) = self.unpublish_removed_cards().await {
+            tracing::warn!("Failed to unpublish removed cards: {:?}", err);
+        }
+
+        Ok(())
+    }
+
+    /// Remove public links of cards that were moved to the bin or deleted.
+    async fn unpublish_removed_cards(&self) -> Result<()> {
+        let link_ids = self.registry.in_txn(|ctx, r| {
+            let mut link_ids = vec![];
+            for link in public_links::list(ctx)? {
+                let removed = match r.timeline.find_card(ctx, &link.card_id)? {
+                    Some(card) => card.labels.iter().any(|l| l.id == BIN_LABEL_ID),
+                    None => true,
+                };
+                if removed {
+                    link_ids.push(link.id);
+                }
+            }
+            Ok(link_ids)
+        })?;
+
+        for link_id in link_ids {
+            tracing::info!(link_id, "Unpublishing removed card");
+            self.client.unpublish_link(&link_id).await?;
+            self.registry
+                .in_txn(|ctx, _r| public_links::delete(ctx, &link_id))?;
+        }
         Ok(())
     }
 
@@ -136,6 +246,64 @@ where
         Ok(())
     }
 
+    fn remove_expired_collaborators(&mut self) -> Result<()> {
+        if let Some(checked_at) = self.expired_collaborators_checked_at {
+            if checked_at.elapsed() < EXPIRED_COLLABORATORS_INTERVAL {
+                return Ok(());
+            }
+        }
+        self.expired_collaborators_checked_at = Some(Instant::now());
+
+        let card_ids = self.registry.in_txn(|ctx, r| {
+            if r.account.get_account_id(ctx).is_some() {
+                r.timeline.remove_expired_collaborators(ctx)
+            } else {
+                Ok(vec![])
+            }
+        })?;
+        for doc_id in card_ids {
+            self.broadcast(OutputEvent::DocUpdated { doc_id })?;
+        }
+        Ok(())
+    }
+
+    /// Update own leaf keys in groups where they haven't changed for a while (post-compromise security).
+    /// Commits are sent during the sync that follows.
+    fn rotate_keys(&mut self) -> Result<()> {
+        if let Some(checked_at) = self.key_rotation_checked_at {
+            if checked_at.elapsed() < KEY_ROTATION_CHECK_INTERVAL {
+                return Ok(());
+            }
+        }
+        self.key_rotation_checked_at = Some(Instant::now());
+
+        self.registry.in_txn(|ctx, r| {
+            if r.account.get_account_id(ctx).is_some() {
+                r.secret_group
+                    .update_own_leaves(ctx, Some(secret_group::leaf_update_interval()))?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Create a backup when backups are enabled and the last one is too old.
+    /// Backup archive is uploaded during the sync that follows.
+    fn backup_if_due(&self) -> Result<()> {
+        self.registry.in_txn(|ctx, r| {
+            let Some(acc_id) = r.account.get_account_id(ctx) else {
+                return Ok(());
+            };
+            match backup::find_settings(ctx)? {
+                Some(settings) if settings.is_due() => {
+                    backup::create_backup(ctx, &acc_id, &settings.key)?;
+                    tracing::info!("Created scheduled backup");
+                }
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
     async fn process_files(&self, card: CardView) -> Result<()> {
         let ctx = self.registry.db_ctx();
         let res = ctx.in_txn(|ctx_tx| self.registry.timeline.generate_thumbnail(ctx_tx, &card))?;
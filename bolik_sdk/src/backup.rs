@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use bolik_migrations::rusqlite::{params, OptionalExtension};
+use bolik_proto::sync::{backup_snapshot, doc_payload::DocSchema, BackupSnapshot};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use prost::Message;
+use uuid::Uuid;
+use yrs::{Map, ReadTxn, Transact};
+
+use crate::{
+    blobs::{self, BlobRef},
+    db::StringListReadColumn,
+    documents::{
+        self,
+        yrs_util::{bytes_from_yrs, int64_from_yrs, uint_from_yrs},
+        DbDocRow, DbDocRowMeta, DocSecretRow,
+    },
+    registry::{WithDeviceAtom, WithDocsAtom, WithTxn},
+    secrets::{self, DbCipher},
+    timeline::{
+        self,
+        acl_doc::AclDoc,
+        card::{CardFile, CardView},
+    },
+};
+
+/// How often scheduled backups are created.
+pub(crate) fn backup_interval() -> Duration {
+    Duration::days(1)
+}
+
+/// Local backup configuration. Backups are opt-in.
+pub(crate) struct BackupSettings {
+    /// Key the archive is encrypted with (derived from the recovery mnemonic)
+    pub key: Vec<u8>,
+    pub last_backup_at: Option<DateTime<Utc>>,
+}
+
+impl BackupSettings {
+    pub fn is_due(&self) -> bool {
+        match self.last_backup_at {
+            Some(at) => at + backup_interval() <= Utc::now(),
+            None => true,
+        }
+    }
+}
+
+pub(crate) fn find_settings<'a>(ctx: &impl WithTxn<'a>) -> Result<Option<BackupSettings>> {
+    let row: Option<(Vec<u8>, Option<DateTime<Utc>>)> = ctx
+        .txn()
+        .query_row(
+            "SELECT encrypted_key, last_backup_at FROM backup_settings",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    match row {
+        Some((encrypted_key, last_backup_at)) => Ok(Some(BackupSettings {
+            key: ctx.db_cipher().decrypt(&encrypted_key)?,
+            last_backup_at,
+        })),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn enable<'a>(ctx: &impl WithTxn<'a>, key: &[u8]) -> Result<()> {
+    let encrypted_key = ctx.db_cipher().encrypt(key)?;
+    ctx.txn().execute("DELETE FROM backup_settings", [])?;
+    ctx.txn().execute(
+        "INSERT INTO backup_settings (encrypted_key, enabled_at) VALUES (?, ?)",
+        params![encrypted_key, Utc::now()],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn disable<'a>(ctx: &impl WithTxn<'a>) -> Result<()> {
+    ctx.txn().execute("DELETE FROM backup_settings", [])?;
+    Ok(())
+}
+
+pub(crate) fn mark_backed_up<'a>(ctx: &impl WithTxn<'a>, at: DateTime<Utc>) -> Result<()> {
+    ctx.txn()
+        .execute("UPDATE backup_settings SET last_backup_at = ?", params![at])?;
+    Ok(())
+}
+
+/// Write an encrypted snapshot into a local blob and point the manifest doc to it.
+/// Archive is uploaded and manifest is pushed during the next sync.
+pub(crate) fn create_backup<'a>(
+    ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom),
+    account_id: &str,
+    key: &[u8],
+) -> Result<BackupArchive> {
+    let snapshot = build_snapshot(ctx, account_id)?;
+    let created_at = timestamp(snapshot.created_at_sec)?;
+    let payload = encrypt_snapshot(key, &snapshot)?;
+
+    let manifest_id = BackupManifest::doc_id(account_id);
+    let blob_id = Uuid::new_v4().to_string();
+    let blob_path = ctx
+        .device()
+        .blobs_dir
+        .join(format!("backup-{}.bin", blob_id));
+    std::fs::write(&blob_path, payload)?;
+    let (checksum, size_bytes) = blobs::hash_file(&blob_path)?;
+
+    let blob_ref = BlobRef {
+        id: blob_id,
+        device_id: ctx.device().id.clone(),
+        checksum,
+        path: format!("{}", blob_path.display()),
+        synced: false,
+        card_id: Some(manifest_id.clone()),
+    };
+    blobs::save(ctx.txn(), &blob_ref)?;
+
+    let archive = BackupArchive {
+        blob_id: blob_ref.id,
+        device_id: blob_ref.device_id,
+        checksum: blob_ref.checksum,
+        size_bytes,
+        secret: secrets::generate_key().to_vec(),
+        created_at,
+    };
+
+    let mut row = match ctx.docs().find(ctx, &manifest_id)? {
+        Some(row) => row,
+        None => {
+            let client_id = ctx.device().yrs_client_id;
+            DbDocRow {
+                meta: DbDocRowMeta {
+                    id: manifest_id,
+                    author_device_id: String::new(),
+                    counter: 0,
+                    schema: DocSchema::BackupV1 as i32,
+                    created_at,
+                    edited_at: created_at,
+                },
+                yrs: BackupManifest::init(client_id),
+                acl: AclDoc::init(client_id, account_id),
+            }
+        }
+    };
+
+    // Only the latest archive is kept locally
+    if let Some(previous) = BackupManifest::archive(&row.yrs) {
+        if let Some(path) = blobs::get_file_path(ctx.txn(), &previous.blob_id)? {
+            if let Err(err) = std::fs::remove_file(&path) {
+                tracing::warn!("Failed to remove previous backup archive: {}", err);
+            }
+        }
+        blobs::rm_row(ctx.txn(), &previous.blob_id)?;
+    }
+
+    BackupManifest::set_archive(&row.yrs, archive.clone());
+    row.meta.author_device_id = ctx.device().id.clone();
+    row.meta.counter = ctx.device().increment_clock(ctx)?;
+    row.meta.edited_at = created_at;
+    ctx.docs().save(ctx, &row)?;
+
+    mark_backed_up(ctx, created_at)?;
+    Ok(archive)
+}
+
+/// Collect all docs (with CRDT history and ACLs), doc secrets and blob references.
+/// Placeholders are kept together with remote versions that were not downloaded yet.
+pub(crate) fn build_snapshot<'a>(
+    ctx: &impl WithTxn<'a>,
+    account_id: &str,
+) -> Result<BackupSnapshot> {
+    let mut deferred: HashMap<String, Vec<backup_snapshot::DeferredVersion>> = HashMap::new();
+    {
+        let mut stmt = ctx
+            .txn()
+            .prepare("SELECT doc_id, author_device_id, counter, size_bytes FROM deferred_docs")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            deferred
+                .entry(row.get(0)?)
+                .or_default()
+                .push(backup_snapshot::DeferredVersion {
+                    author_device_id: row.get(1)?,
+                    counter: row.get(2)?,
+                    size_bytes: row.get(3)?,
+                });
+        }
+    }
+
+    let mut docs = vec![];
+    {
+        let mut stmt = ctx.txn().prepare(
+            r#"
+SELECT id, data, acl_data, schema, created_at, edited_at, author_device_id, counter
+  FROM documents
+ WHERE schema != ?"#,
+        )?;
+        let mut rows = stmt.query([DocSchema::BackupV1 as i32])?;
+        while let Some(row) = rows.next()? {
+            let id: String = row.get(0)?;
+            let data: Option<Vec<u8>> = row.get(1)?;
+            let created_at: DateTime<Utc> = row.get(4)?;
+            let edited_at: DateTime<Utc> = row.get(5)?;
+            docs.push(backup_snapshot::Doc {
+                deferred: deferred.remove(&id).unwrap_or_default(),
+                id,
+                data: data.unwrap_or_default(),
+                acl_data: row.get(2)?,
+                schema: row.get(3)?,
+                created_at_sec: created_at.timestamp(),
+                edited_at_sec: edited_at.timestamp(),
+                author_device_id: row.get(6)?,
+                counter: row.get(7)?,
+            });
+        }
+    }
+
+    let mut secrets = vec![];
+    {
+        let mut stmt = ctx.txn().prepare(
+            r#"
+SELECT id, encrypted_secret, account_ids, doc_id, algorithm, created_at, obsolete_at
+  FROM doc_secrets"#,
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let encrypted_secret: Vec<u8> = row.get(1)?;
+            let created_at: DateTime<Utc> = row.get(5)?;
+            let obsolete_at: DateTime<Utc> = row.get(6)?;
+            secrets.push(backup_snapshot::Secret {
+                id: row.get(0)?,
+                key: ctx.db_cipher().decrypt(&encrypted_secret)?,
+                account_ids: row.get::<_, StringListReadColumn>(2)?.0,
+                doc_id: row.get(3)?,
+                algorithm: row.get(4)?,
+                created_at_sec: created_at.timestamp(),
+                obsolete_at_sec: obsolete_at.timestamp(),
+            });
+        }
+    }
+
+    let mut blobs = vec![];
+    {
+        let mut stmt = ctx
+            .txn()
+            .prepare("SELECT id, device_id, checksum, card_id FROM blobs")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            blobs.push(backup_snapshot::Blob {
+                id: row.get(0)?,
+                device_id: row.get(1)?,
+                checksum: row.get(2)?,
+                card_id: row.get(3)?,
+            });
+        }
+    }
+
+    Ok(BackupSnapshot {
+        account_id: account_id.to_string(),
+        created_at_sec: Utc::now().timestamp(),
+        docs,
+        secrets,
+        blobs,
+    })
+}
+
+pub(crate) fn encrypt_snapshot(key: &[u8], snapshot: &BackupSnapshot) -> Result<Vec<u8>> {
+    let cipher = DbCipher::new(chacha20poly1305::Key::from_slice(key));
+    cipher.encrypt(&snapshot.encode_to_vec())
+}
+
+pub(crate) fn decrypt_snapshot(key: &[u8], payload: &[u8]) -> Result<BackupSnapshot> {
+    let cipher = DbCipher::new(chacha20poly1305::Key::from_slice(key));
+    let bytes = cipher
+        .decrypt(payload)
+        .map_err(|_| anyhow!("Backup doesn't match the recovery key"))?;
+    Ok(BackupSnapshot::decode(bytes.as_slice())?)
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoreBackupResult {
+    /// Docs that were missing or had changes missing locally
+    pub restored_docs: u32,
+    pub restored_secrets: u32,
+    /// Files that are referenced in the backup but not present locally (they can be downloaded)
+    pub missing_files: u32,
+    pub backup_created_at: DateTime<Utc>,
+}
+
+/// A doc that was changed by restoring a backup.
+pub(crate) struct RestoredDoc {
+    pub doc_id: String,
+    pub schema: i32,
+    /// True, when no doc was present locally
+    pub is_new: bool,
+}
+
+/// Merge backed up docs and secrets into local database. Restored docs that this account
+/// can edit are saved as if they were edited by this device so that they are pushed on
+/// the next sync. Other docs (e.g. read-only cards) are only kept locally.
+pub(crate) fn restore_snapshot<'a>(
+    ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom),
+    account_id: &str,
+    snapshot: BackupSnapshot,
+) -> Result<(RestoreBackupResult, Vec<RestoredDoc>)> {
+    if snapshot.account_id != account_id {
+        bail!("Backup belongs to another account");
+    }
+
+    let mut result = RestoreBackupResult {
+        restored_docs: 0,
+        restored_secrets: 0,
+        missing_files: 0,
+        backup_created_at: timestamp(snapshot.created_at_sec)?,
+    };
+
+    let known_secrets = ctx.docs().list_secrets(ctx)?;
+    for secret in snapshot.secrets {
+        if known_secrets.iter().any(|s| s.id == secret.id) {
+            continue;
+        }
+        ctx.docs().save_secret(
+            ctx,
+            DocSecretRow {
+                id: secret.id,
+                key: secret.key,
+                account_ids: secret.account_ids,
+                doc_id: secret.doc_id,
+                algorithm: secret.algorithm,
+                created_at: timestamp(secret.created_at_sec)?,
+                obsolete_at: timestamp(secret.obsolete_at_sec)?,
+            },
+        )?;
+        result.restored_secrets += 1;
+    }
+
+    let mut restored = vec![];
+    for doc in snapshot.docs {
+        if doc.data.is_empty() {
+            if restore_placeholder(ctx, &doc)? {
+                result.restored_docs += 1;
+            }
+            continue;
+        }
+
+        let (mut row, is_new) = match ctx.docs().find(ctx, &doc.id)? {
+            Some(row) => {
+                if row.meta.schema != doc.schema {
+                    tracing::warn!(doc_id = doc.id, "Skipping backed up doc with other schema");
+                    continue;
+                }
+
+                let before = (state_vector(&row.yrs), state_vector(&row.acl));
+                documents::merge_yrs_docs(&row.yrs, &doc.data)?;
+                documents::merge_yrs_docs(&row.acl, &doc.acl_data)?;
+                if before == (state_vector(&row.yrs), state_vector(&row.acl)) {
+                    // Nothing new in the backup
+                    continue;
+                }
+                (row, false)
+            }
+            None => {
+                let client_id = ctx.device().yrs_client_id;
+                let row = DbDocRow {
+                    meta: DbDocRowMeta {
+                        id: doc.id,
+                        author_device_id: doc.author_device_id,
+                        counter: doc.counter,
+                        schema: doc.schema,
+                        created_at: timestamp(doc.created_at_sec)?,
+                        edited_at: timestamp(doc.edited_at_sec)?,
+                    },
+                    yrs: documents::build_yrs_doc(client_id, &doc.data)?,
+                    acl: documents::build_yrs_doc(client_id, &doc.acl_data)?,
+                };
+                (row, true)
+            }
+        };
+
+        // Other devices would reject changes from this account
+        if AclDoc::from_doc(&row.acl).allowed_to_edit(account_id) {
+            row.meta.author_device_id = ctx.device().id.clone();
+            row.meta.counter = ctx.device().increment_clock(ctx)?;
+        }
+        ctx.docs().save(ctx, &row)?;
+        restored.push(RestoredDoc {
+            doc_id: row.meta.id,
+            schema: row.meta.schema,
+            is_new,
+        });
+        result.restored_docs += 1;
+    }
+
+    for blob in snapshot.blobs {
+        let conn = ctx.txn();
+        if blobs::find_by_id(conn, &blob.id, &blob.device_id)?.is_none() {
+            result.missing_files += 1;
+        }
+    }
+
+    Ok((result, restored))
+}
+
+/// Save a placeholder together with its remote versions so that the doc body could be
+/// downloaded later. Returns true if the placeholder was missing locally.
+fn restore_placeholder<'a>(
+    ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom),
+    doc: &backup_snapshot::Doc,
+) -> Result<bool> {
+    if ctx.docs().find(ctx, &doc.id)?.is_some() {
+        return Ok(false);
+    }
+
+    for version in &doc.deferred {
+        ctx.txn().execute(
+            r#"
+INSERT INTO deferred_docs (doc_id, author_device_id, counter, size_bytes) VALUES (?1, ?2, ?3, ?4)
+       ON CONFLICT (doc_id, author_device_id) DO NOTHING"#,
+            params![
+                doc.id,
+                version.author_device_id,
+                version.counter,
+                version.size_bytes
+            ],
+        )?;
+    }
+    if ctx.docs().find_placeholder(ctx, &doc.id)?.is_some() {
+        return Ok(false);
+    }
+
+    let meta = DbDocRowMeta {
+        id: doc.id.clone(),
+        author_device_id: doc.author_device_id.clone(),
+        counter: doc.counter,
+        schema: doc.schema,
+        created_at: timestamp(doc.created_at_sec)?,
+        edited_at: timestamp(doc.edited_at_sec)?,
+    };
+    ctx.docs().save_placeholder(ctx, &meta)?;
+
+    // Show the card in the timeline
+    if let Some(row) = ctx.docs().find_placeholder(ctx, &doc.id)? {
+        if row.meta.schema == DocSchema::CardV1 as i32 {
+            let labels_row = ctx.docs().find(ctx, &format!("{}/labels", doc.id))?;
+            let view = CardView::from_db(row, labels_row).0;
+            timeline::index_card(ctx.txn(), &view)?;
+        }
+    }
+    Ok(true)
+}
+
+fn state_vector(doc: &yrs::Doc) -> yrs::StateVector {
+    doc.transact().state_vector()
+}
+
+fn timestamp(sec: i64) -> Result<DateTime<Utc>> {
+    Utc.timestamp_opt(sec, 0)
+        .earliest()
+        .ok_or(anyhow!("Invalid timestamp {}", sec))
+}
+
+/// Manifest points to the latest backup archive. It is synced like other account docs
+/// so that a recovered device could find and download the archive.
+pub struct BackupManifest {
+    pub archive: Option<BackupArchive>,
+}
+
+impl BackupManifest {
+    const ARCHIVE: &'static str = "archive";
+
+    pub fn init(client_id: yrs::block::ClientID) -> yrs::Doc {
+        yrs::Doc::with_options(yrs::Options {
+            client_id,
+            offset_kind: yrs::OffsetKind::Utf32,
+            ..Default::default()
+        })
+    }
+
+    pub fn doc_id(account_id: &str) -> String {
+        format!("{}/backup", account_id)
+    }
+
+    pub fn from_db(row: DbDocRow) -> (Self, yrs::Doc) {
+        let archive = Self::archive(&row.yrs);
+        (Self { archive }, row.yrs)
+    }
+
+    fn archive(doc: &yrs::Doc) -> Option<BackupArchive> {
+        let txn = &doc.transact();
+        txn.get_map(Self::ARCHIVE)
+            .and_then(|map| BackupArchive::from_map(txn, map))
+    }
+
+    pub fn set_archive(doc: &yrs::Doc, archive: BackupArchive) {
+        let map = doc.get_or_insert_map(Self::ARCHIVE);
+        let txn = &mut doc.transact_mut();
+        map.clear(txn);
+        map.insert(txn, BackupArchive::BLOB_ID, archive.blob_id);
+        map.insert(txn, BackupArchive::DEVICE_ID, archive.device_id);
+        map.insert(txn, BackupArchive::CHECKSUM, archive.checksum);
+        map.insert(txn, BackupArchive::SIZE_BYTES, archive.size_bytes);
+        map.insert(txn, BackupArchive::SECRET, archive.secret);
+        map.insert(
+            txn,
+            BackupArchive::CREATED_AT,
+            archive.created_at.timestamp(),
+        );
+    }
+}
+
+/// Encrypted backup archive. Archive is stored as a regular blob.
+#[derive(Clone)]
+pub struct BackupArchive {
+    pub blob_id: String,
+    pub device_id: String,
+    pub checksum: String,
+    pub size_bytes: u32,
+    /// Key the blob is encrypted with
+    pub secret: Vec<u8>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl BackupArchive {
+    const BLOB_ID: &'static str = "blob_id";
+    const DEVICE_ID: &'static str = "device_id";
+    const CHECKSUM: &'static str = "checksum";
+    const SIZE_BYTES: &'static str = "size_bytes";
+    const SECRET: &'static str = "secret";
+    const CREATED_AT: &'static str = "created_at";
+
+    fn from_map(txn: &yrs::Transaction, map: impl yrs::Map) -> Option<Self> {
+        let created_at_sec = int64_from_yrs(map.get(txn, Self::CREATED_AT)?)?;
+        Some(Self {
+            blob_id: map.get(txn, Self::BLOB_ID)?.to_string(txn),
+            device_id: map.get(txn, Self::DEVICE_ID)?.to_string(txn),
+            checksum: map.get(txn, Self::CHECKSUM)?.to_string(txn),
+            size_bytes: uint_from_yrs(map.get(txn, Self::SIZE_BYTES)?)?,
+            secret: bytes_from_yrs(map.get(txn, Self::SECRET)?)?,
+            created_at: Utc.timestamp_opt(created_at_sec, 0).earliest()?,
+        })
+    }
+
+    /// Describe archive blob as a file (to reuse blob transfers).
+    pub fn file(&self) -> CardFile {
+        CardFile {
+            blob_id: self.blob_id.clone(),
+            device_id: self.device_id.clone(),
+            checksum: self.checksum.clone(),
+            size_bytes: self.size_bytes,
+            name: Some("backup.bin".into()),
+            dimensions: None,
+        }
+    }
+}
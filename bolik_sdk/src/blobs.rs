@@ -15,6 +15,16 @@ pub struct BlobRef {
     pub checksum: String,
     pub path: String,
     pub synced: bool,
+    pub card_id: Option<String>,
+}
+
+/// A blob that hasn't been uploaded yet.
+#[derive(Clone, Debug)]
+pub struct PendingBlob {
+    pub blob_id: String,
+    pub card_id: Option<String>,
+    pub path: String,
+    pub size_bytes: u64,
 }
 
 mod blobs_atom;
@@ -32,6 +42,7 @@ where
                 checksum: row.get(2)?,
                 path: row.get(3)?,
                 synced: row.get(4)?,
+                card_id: row.get(5)?,
             })
         })
         .optional()?;
@@ -47,7 +58,8 @@ SELECT
   device_id,
   checksum,
   path,
-  synced
+  synced,
+  card_id
  FROM blobs
 WHERE id = ? AND device_id = ?"#,
         params![blob_id, device_id],
@@ -90,6 +102,7 @@ pub fn save_file(conn: &Connection, params: SaveFileParams<'_>) -> Result<CardFi
         checksum,
         path: blob_path_str,
         synced: false,
+        card_id: Some(params.card_id.to_string()),
     };
     save(conn, &blob_ref)?;
 
@@ -109,12 +122,53 @@ pub fn mark_synced(conn: &Connection, blob_id: &str) -> Result<()> {
     Ok(())
 }
 
+/// Count blobs that haven't been uploaded yet
+pub fn count_unsynced(conn: &Connection) -> Result<u32> {
+    let count = conn.query_row("SELECT COUNT(*) FROM blobs WHERE synced = 0", [], |row| {
+        row.get(0)
+    })?;
+    Ok(count)
+}
+
+/// List blobs that haven't been uploaded yet. File sizes are not read here (see [read_sizes]).
+pub fn list_unsynced(conn: &Connection) -> Result<Vec<PendingBlob>> {
+    let mut stmt = conn.prepare("SELECT id, card_id, path FROM blobs WHERE synced = 0")?;
+    let mut rows = stmt.query([])?;
+    let mut blobs = vec![];
+    while let Some(row) = rows.next()? {
+        blobs.push(PendingBlob {
+            blob_id: row.get(0)?,
+            card_id: row.get(1)?,
+            path: row.get(2)?,
+            size_bytes: 0,
+        });
+    }
+    Ok(blobs)
+}
+
+/// Read sizes of pending files. Call this outside of a transaction.
+pub fn read_sizes(blobs: &mut [PendingBlob]) {
+    for blob in blobs {
+        blob.size_bytes = std::fs::metadata(&blob.path).map(|m| m.len()).unwrap_or(0);
+    }
+}
+
 /// Remove row from blobs table
 pub fn rm_row(conn: &Connection, blob_id: &str) -> Result<()> {
     conn.execute("DELETE FROM blobs WHERE id = ?", [blob_id])?;
     Ok(())
 }
 
+/// Remove local file of the blob and its row from blobs table
+pub fn remove_local(conn: &Connection, blob_id: &str) -> Result<()> {
+    if let Some(path) = get_file_path(conn, blob_id)? {
+        if let Err(err) = std::fs::remove_file(&path) {
+            tracing::warn!("Failed to remove a file: {}", err);
+        }
+    }
+    rm_row(conn, blob_id)
+}
+
 /// Get local file by id and return path
 pub fn get_file_path(conn: &Connection, blob_id: &str) -> Result<Option<String>> {
     let path = conn
@@ -131,17 +185,19 @@ pub fn get_file_path(conn: &Connection, blob_id: &str) -> Result<Option<String>>
 pub fn save(conn: &Connection, blob: &BlobRef) -> Result<()> {
     conn.execute(
         r#"
-INSERT INTO blobs (id, device_id, checksum, path, synced) VALUES (?1, ?2, ?3, ?4, ?5)
+INSERT INTO blobs (id, device_id, checksum, path, synced, card_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
   ON CONFLICT (id, device_id) DO UPDATE
     SET checksum = excluded.checksum,
         path = excluded.path,
-        synced = excluded.synced"#,
+        synced = excluded.synced,
+        card_id = excluded.card_id"#,
         params![
             blob.id,
             blob.device_id,
             blob.checksum,
             blob.path,
             blob.synced,
+            blob.card_id,
         ],
     )?;
     Ok(())
@@ -19,6 +19,8 @@ use tokio_util::{
 use tracing::instrument;
 
 use crate::{
+    account::ProfileAvatar,
+    backup::BackupArchive,
     blobs::FixedBytesCodec,
     client::Client,
     documents::DocSecret,
@@ -73,6 +75,59 @@ impl<C: Client> BlobsAtom<C> {
             checksum: file.checksum.clone(),
             path,
             synced: true,
+            card_id: Some(card.id.clone()),
+        };
+        ctx.in_txn(|tx_ctx| super::save(tx_ctx.txn(), &blob_ref))?;
+        Ok(blob_ref.path)
+    }
+
+    /// Download profile picture from remote
+    #[instrument(skip_all, fields(blob_id = avatar.blob_id))]
+    pub async fn download_avatar(
+        &self,
+        ctx: &impl BlobsCtx<C>,
+        profile_id: &str,
+        avatar: &ProfileAvatar,
+    ) -> Result<String> {
+        let secret = DocSecret::new(profile_id, &avatar.secret);
+        let file = avatar.file();
+        let path = self
+            .download_blob(&ctx.device().blobs_dir, profile_id, &file, secret)
+            .await?;
+
+        let blob_ref = BlobRef {
+            id: file.blob_id,
+            device_id: file.device_id,
+            checksum: file.checksum,
+            path,
+            synced: true,
+            card_id: Some(profile_id.to_string()),
+        };
+        ctx.in_txn(|tx_ctx| super::save(tx_ctx.txn(), &blob_ref))?;
+        Ok(blob_ref.path)
+    }
+
+    /// Download backup archive from remote
+    #[instrument(skip_all, fields(blob_id = archive.blob_id))]
+    pub async fn download_backup(
+        &self,
+        ctx: &impl BlobsCtx<C>,
+        manifest_id: &str,
+        archive: &BackupArchive,
+    ) -> Result<String> {
+        let secret = DocSecret::new(manifest_id, &archive.secret);
+        let file = archive.file();
+        let path = self
+            .download_blob(&ctx.device().blobs_dir, manifest_id, &file, secret)
+            .await?;
+
+        let blob_ref = BlobRef {
+            id: file.blob_id,
+            device_id: file.device_id,
+            checksum: file.checksum,
+            path,
+            synced: true,
+            card_id: Some(manifest_id.to_string()),
         };
         ctx.in_txn(|tx_ctx| super::save(tx_ctx.txn(), &blob_ref))?;
         Ok(blob_ref.path)
@@ -7,9 +7,11 @@ use bolik_proto::{
     sync::{request, response, DeviceVectorClock, KeyPackageMessage},
 };
 use bytes::Bytes;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use multihash::{Blake3_256, Hasher};
 use openmls::{ciphersuite::signature::SignaturePrivateKey, prelude::TlsSerializeTrait};
 use openmls_rust_crypto::OpenMlsRustCrypto;
+use rand::RngCore;
 use reqwest::{header::HeaderMap, Body, RequestBuilder, Response};
 use tokio_stream::Stream;
 
@@ -27,6 +29,11 @@ impl ClientConfig {
         self.host = host.into();
         self
     }
+
+    /// Public (non-API) address of the server.
+    pub fn public_host(&self) -> &str {
+        self.host.strip_suffix("/api").unwrap_or(&self.host)
+    }
 }
 
 #[derive(Clone)]
@@ -55,14 +62,25 @@ impl HttpClient {
     ) -> Result<impl Future<Output = Result<Response, reqwest::Error>>> {
         let mut req = builder.build()?;
         let timestamp = format!("{}", Utc::now().timestamp());
+        // Nonce makes identical requests sent within the same second distinguishable
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = secrets::id_from_key(&nonce);
+
+        // Streaming bodies are not signed (only blob uploads to S3 use them)
+        let body = req.body().and_then(|b| b.as_bytes()).unwrap_or_default();
+        let mut hasher = Blake3_256::default();
+        hasher.update(body);
 
         let mut payload = vec![];
         payload.extend(timestamp.as_bytes());
+        payload.extend(nonce.as_bytes());
         payload.extend(req.method().as_str().as_bytes());
         payload.extend(req.url().path().as_bytes());
         if let Some(query) = req.url().query() {
             payload.extend(query.as_bytes());
         }
+        payload.extend(hasher.finalize());
 
         let backend = &OpenMlsRustCrypto::default();
         let signature = self.signature_key.sign(backend, payload.as_ref())?;
@@ -72,6 +90,7 @@ impl HttpClient {
         let headers = req.headers_mut();
         headers.insert("signature", signature_str.parse()?);
         headers.insert("timestamp", timestamp.parse()?);
+        headers.insert("nonce", nonce.parse()?);
 
         Ok(self.client.execute(req))
     }
@@ -137,14 +156,34 @@ pub trait Client: Clone + Send + Sync {
 
     async fn get_account_devices(&self, account_id: &str) -> Result<response::AccountDevices>;
     async fn get_device_packages(&self, device_id: &str) -> Result<response::DevicePackages>;
+    /// List when account devices were last seen by the server.
+    async fn get_device_sessions(&self, account_id: &str) -> Result<response::DeviceSessions>;
 
-    async fn fetch_docs(&self, clock: &DeviceVectorClock) -> Result<response::AccountDocs>;
+    /// Fetch docs that are newer than the clock. Cards created before `headers_before`
+    /// are returned without the payload (see [response::doc_version::HeaderBody]).
+    async fn fetch_docs(
+        &self,
+        clock: &DeviceVectorClock,
+        headers_before: Option<DateTime<Utc>>,
+    ) -> Result<response::AccountDocs>;
     async fn get_doc_version(
         &self,
         doc_id: &str,
         author_device_id: &str,
     ) -> Result<response::DocVersion>;
     async fn push_doc(&self, doc: request::DocMessage) -> Result<()>;
+    /// Tell the server which doc versions this device has processed.
+    async fn ack_docs(&self, clock: &DeviceVectorClock) -> Result<()>;
+
+    /// Upload (or replace) an encrypted snapshot of a public link.
+    async fn publish_link(&self, link_id: &str, payload: Vec<u8>) -> Result<()>;
+    async fn unpublish_link(&self, link_id: &str) -> Result<()>;
+    /// Build a URL of the public link page (without the key).
+    fn public_link_url(&self, link_id: &str) -> String;
+
+    async fn upload_recovery_kit(&self, kit_id: &str, payload: Vec<u8>) -> Result<()>;
+    /// Fetch encrypted recovery kit. Kits are public hence this request is not signed.
+    async fn fetch_recovery_kit(&self, kit_id: &str) -> Result<Vec<u8>>;
 }
 
 #[async_trait]
@@ -162,6 +201,8 @@ impl Client for HttpClient {
         let sdk_version = std::env!("CARGO_PKG_VERSION");
         let user_agent = format!("bolik-rust-sdk ({})", sdk_version);
         headers.insert("user-agent", user_agent.parse()?);
+        headers.insert("platform", std::env::consts::OS.parse()?);
+        headers.insert("app-version", sdk_version.parse()?);
 
         let client = reqwest::ClientBuilder::new()
             .user_agent("bolik-timeline")
@@ -321,15 +362,34 @@ impl Client for HttpClient {
         Ok(devices)
     }
 
-    async fn fetch_docs(&self, clock: &DeviceVectorClock) -> Result<response::AccountDocs> {
-        let data = clock.encode_to_vec();
+    async fn get_device_sessions(&self, account_id: &str) -> Result<response::DeviceSessions> {
         let res = self
-            .send_signed(
-                self.client
-                    .post(format!("{}/docs/list", self.conf.host))
-                    .body(data),
-            )?
+            .send_signed(self.client.get(format!(
+                "{}/account/{}/sessions",
+                self.conf.host, account_id
+            )))?
             .await?;
+        let res = Self::expect_success("get_device_sessions", res).await?;
+        let mut body = res.bytes().await?;
+        let sessions = response::DeviceSessions::decode(&mut body)?;
+        Ok(sessions)
+    }
+
+    async fn fetch_docs(
+        &self,
+        clock: &DeviceVectorClock,
+        headers_before: Option<DateTime<Utc>>,
+    ) -> Result<response::AccountDocs> {
+        let data = clock.encode_to_vec();
+        let url = match headers_before {
+            Some(before) => format!(
+                "{}/docs/list?headers_before_sec={}",
+                self.conf.host,
+                before.timestamp()
+            ),
+            None => format!("{}/docs/list", self.conf.host),
+        };
+        let res = self.send_signed(self.client.post(url).body(data))?.await?;
         let res = Self::expect_success("fetch_docs", res).await?;
         let mut body = res.bytes().await?;
         let docs = response::AccountDocs::decode(&mut body)?;
@@ -372,6 +432,74 @@ impl Client for HttpClient {
         Self::expect_success("push_doc", res).await?;
         Ok(())
     }
+
+    async fn ack_docs(&self, clock: &DeviceVectorClock) -> Result<()> {
+        let res = self
+            .send_signed(
+                self.client
+                    .post(format!("{}/docs/ack", self.conf.host))
+                    .body(clock.encode_to_vec()),
+            )?
+            .await?;
+        Self::expect_success("ack_docs", res).await?;
+        Ok(())
+    }
+
+    async fn publish_link(&self, link_id: &str, payload: Vec<u8>) -> Result<()> {
+        let message = request::PublishLink { payload };
+        let res = self
+            .send_signed(
+                self.client
+                    .put(format!("{}/public-links/{}", self.conf.host, link_id))
+                    .body(message.encode_to_vec()),
+            )?
+            .await?;
+        Self::expect_success("publish_link", res).await?;
+        Ok(())
+    }
+
+    async fn unpublish_link(&self, link_id: &str) -> Result<()> {
+        let res = self
+            .send_signed(
+                self.client
+                    .delete(format!("{}/public-links/{}", self.conf.host, link_id)),
+            )?
+            .await?;
+        Self::expect_success("unpublish_link", res).await?;
+        Ok(())
+    }
+
+    fn public_link_url(&self, link_id: &str) -> String {
+        format!("{}/p/{}", self.conf.public_host(), link_id)
+    }
+
+    async fn upload_recovery_kit(&self, kit_id: &str, payload: Vec<u8>) -> Result<()> {
+        let message = request::UploadRecoveryKit { payload };
+        let res = self
+            .send_signed(
+                self.client
+                    .put(format!("{}/recovery-kits/{}", self.conf.host, kit_id))
+                    .body(message.encode_to_vec()),
+            )?
+            .await?;
+        Self::expect_success("upload_recovery_kit", res).await?;
+        Ok(())
+    }
+
+    async fn fetch_recovery_kit(&self, kit_id: &str) -> Result<Vec<u8>> {
+        let res = self
+            .client
+            .get(format!(
+                "{}/recovery-kits/{}",
+                self.conf.public_host(),
+                kit_id
+            ))
+            .send()
+            .await?;
+        let res = Self::expect_success("fetch_recovery_kit", res).await?;
+        let bytes = res.bytes().await?;
+        Ok(bytes.to_vec())
+    }
 }
 
 #[cfg(test)]
@@ -423,6 +551,10 @@ pub(crate) mod mock {
         documents: HashMap<String, Vec<response::DocVersion>>,
         /// Blobs per blob id
         blobs: HashMap<String, Vec<u8>>,
+        /// Public link snapshots per link id
+        public_links: HashMap<String, Vec<u8>>,
+        /// Encrypted recovery kits (account id and payload) per kit id
+        recovery_kits: HashMap<String, (String, Vec<u8>)>,
     }
 
     struct MailboxEntry {
@@ -688,6 +820,27 @@ pub(crate) mod mock {
             Ok(res)
         }
 
+        fn get_doc_version(
+            &self,
+            device_id: &str,
+            doc_id: &str,
+            author_device_id: &str,
+        ) -> Result<response::DocVersion> {
+            let acc_id = self
+                .find_account_id(device_id)
+                .ok_or(anyhow!("Device not connected to account"))?;
+
+            self.documents
+                .get(&acc_id)
+                .and_then(|docs| {
+                    docs.iter().find(|doc| {
+                        doc.doc_id == doc_id && doc.author_device_id == author_device_id
+                    })
+                })
+                .cloned()
+                .ok_or(anyhow!("Doc not found"))
+        }
+
         fn get_account_devices(&self, account_id: &str) -> Result<response::AccountDevices> {
             // Find signature chain
             let group = self
@@ -779,11 +932,24 @@ pub(crate) mod mock {
             self.data.lock().unwrap().downloaded_blobs.clone()
         }
 
+        pub fn published_links(&self) -> HashMap<String, Vec<u8>> {
+            let server = self.conf.mock_server.lock().unwrap();
+            server.public_links.clone()
+        }
+
         pub fn mock_blob_download(&self, blob_id: &str, bytes: Vec<u8>) {
             let mut server = self.conf.mock_server.lock().unwrap();
             server.blobs.insert(blob_id.to_string(), bytes);
         }
 
+        /// Remove doc versions uploaded by given device as if they haven't reached the server yet.
+        pub fn remove_doc_versions(&self, author_device_id: &str) {
+            let mut server = self.conf.mock_server.lock().unwrap();
+            for docs in server.documents.values_mut() {
+                docs.retain(|doc| doc.author_device_id != author_device_id);
+            }
+        }
+
         pub fn clear(&self) {
             let mut data = self.data.lock().unwrap();
             *data = MockClientData::default();
@@ -918,7 +1084,17 @@ pub(crate) mod mock {
                 .get_device_packages(device_id)
         }
 
-        async fn fetch_docs(&self, clock: &DeviceVectorClock) -> Result<response::AccountDocs> {
+        async fn get_device_sessions(&self, _account_id: &str) -> Result<response::DeviceSessions> {
+            // Mock server doesn't track sessions
+            Ok(response::DeviceSessions::default())
+        }
+
+        async fn fetch_docs(
+            &self,
+            clock: &DeviceVectorClock,
+            _headers_before: Option<DateTime<Utc>>,
+        ) -> Result<response::AccountDocs> {
+            // Mock server always returns full docs
             let res = self
                 .conf
                 .mock_server
@@ -930,10 +1106,14 @@ pub(crate) mod mock {
 
         async fn get_doc_version(
             &self,
-            _doc_id: &str,
-            _author_device_id: &str,
+            doc_id: &str,
+            author_device_id: &str,
         ) -> Result<response::DocVersion> {
-            todo!("get_doc_version: Not implemented")
+            self.conf.mock_server.lock().unwrap().get_doc_version(
+                &self.device_id,
+                doc_id,
+                author_device_id,
+            )
         }
 
         async fn push_doc(&self, message: request::DocMessage) -> Result<()> {
@@ -949,5 +1129,60 @@ pub(crate) mod mock {
                 .push_doc(&self.device_id, message)?;
             Ok(())
         }
+
+        async fn ack_docs(&self, _clock: &DeviceVectorClock) -> Result<()> {
+            // Mock server doesn't compact docs
+            Ok(())
+        }
+
+        async fn publish_link(&self, link_id: &str, payload: Vec<u8>) -> Result<()> {
+            self.conf
+                .mock_server
+                .lock()
+                .unwrap()
+                .public_links
+                .insert(link_id.to_string(), payload);
+            Ok(())
+        }
+
+        async fn unpublish_link(&self, link_id: &str) -> Result<()> {
+            self.conf
+                .mock_server
+                .lock()
+                .unwrap()
+                .public_links
+                .remove(link_id);
+            Ok(())
+        }
+
+        fn public_link_url(&self, link_id: &str) -> String {
+            format!("{}/p/{}", self.conf.public_host(), link_id)
+        }
+
+        async fn upload_recovery_kit(&self, kit_id: &str, payload: Vec<u8>) -> Result<()> {
+            let mut server = self.conf.mock_server.lock().unwrap();
+            let acc_id = server
+                .find_account_id(&self.device_id)
+                .ok_or(anyhow!("Device not connected to account"))?;
+            // New kit supersedes previous ones
+            server
+                .recovery_kits
+                .retain(|_, (kit_acc_id, _)| kit_acc_id != &acc_id);
+            server
+                .recovery_kits
+                .insert(kit_id.to_string(), (acc_id, payload));
+            Ok(())
+        }
+
+        async fn fetch_recovery_kit(&self, kit_id: &str) -> Result<Vec<u8>> {
+            self.conf
+                .mock_server
+                .lock()
+                .unwrap()
+                .recovery_kits
+                .get(kit_id)
+                .map(|(_, payload)| payload.clone())
+                .ok_or(anyhow!("Recovery kit not found"))
+        }
     }
 }
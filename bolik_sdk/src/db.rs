@@ -1,12 +1,13 @@
 use std::sync::{Arc, Mutex};
 
+use anyhow::{bail, Context, Result};
 use bolik_migrations::rusqlite::{
-    self,
+    self, params,
     types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef},
-    Connection, ToSql,
+    Connection, OptionalExtension, ToSql,
 };
 
-use crate::secrets::DbCipher;
+use crate::{key_from_slice, secrets::DbCipher};
 
 pub mod migrations;
 
@@ -41,3 +42,171 @@ impl<'a> ToSql for StringListWriteColumn<'a> {
         Ok(ToSqlOutput::Owned(Value::Text(t)))
     }
 }
+
+/// Known value encrypted with the database key. Lets us detect a wrong key on startup.
+const KEY_CHECK_VALUE: &[u8] = b"bolik-db-key-check";
+
+/// Columns that are encrypted with the database key.
+const ENCRYPTED_COLUMNS: &[(&str, &str)] = &[
+    ("mls_keys", "encrypted_value"),
+    ("mls_groups", "encrypted_state"),
+    ("doc_secrets", "encrypted_secret"),
+    ("public_links", "encrypted_key"),
+    ("backup_settings", "encrypted_key"),
+];
+
+/// Report rotation progress every N cells.
+const ROTATION_PROGRESS_STEP: u32 = 100;
+
+/// Verify that the cipher can decrypt the database.
+///
+/// If key rotation wasn't confirmed and the cipher holds the previous key then the cipher
+/// is switched to the new key. Returns the current key while rotation is not confirmed.
+pub fn check_key(
+    conn: &Connection,
+    cipher: &DbCipher,
+    key: &chacha20poly1305::Key,
+) -> Result<Option<chacha20poly1305::Key>> {
+    let row: Option<(Vec<u8>, Option<Vec<u8>>)> = conn
+        .query_row(
+            "SELECT encrypted_check, encrypted_next_key FROM db_key_check",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((encrypted_check, encrypted_next_key)) = row else {
+        // New database or the one created before key checks. Don't lock in a wrong key.
+        if !key_opens(conn, cipher)? {
+            bail!("Database key doesn't match");
+        }
+        conn.execute(
+            "INSERT INTO db_key_check (encrypted_check) VALUES (?)",
+            params![cipher.encrypt(KEY_CHECK_VALUE)?],
+        )?;
+        return Ok(None);
+    };
+
+    if key_matches(cipher, &encrypted_check) {
+        return Ok(encrypted_next_key.map(|_| *key));
+    }
+
+    // App could have crashed before storing the rotated key
+    if let Some(encrypted_next_key) = encrypted_next_key {
+        let next_key = key_from_slice(&cipher.decrypt(&encrypted_next_key)?)?;
+        if key_matches(&DbCipher::new(&next_key), &encrypted_check) {
+            tracing::warn!("Database key rotation is not confirmed. Switching to the new key.");
+            cipher.replace_key(&next_key);
+            return Ok(Some(next_key));
+        }
+    }
+    bail!("Database key doesn't match")
+}
+
+fn key_matches(cipher: &DbCipher, encrypted_check: &[u8]) -> bool {
+    matches!(cipher.decrypt(encrypted_check), Ok(value) if value == KEY_CHECK_VALUE)
+}
+
+/// Check if the cipher can decrypt the database. Empty database opens with any key.
+pub fn key_opens(conn: &Connection, cipher: &DbCipher) -> Result<bool> {
+    for (table, column) in ENCRYPTED_COLUMNS {
+        let cell: Option<Vec<u8>> = conn
+            .query_row(
+                &format!("SELECT {column} FROM {table} LIMIT 1"),
+                [],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(cell) = cell {
+            return Ok(cipher.decrypt(&cell).is_ok());
+        }
+    }
+    Ok(true)
+}
+
+/// Re-encrypt every encrypted cell with the new key. Until the rotation is confirmed
+/// the new key is also stored encrypted with the previous one, so that the previous key
+/// can still open the database. Run this inside a transaction. Returns how many cells
+/// were re-encrypted.
+pub fn rotate_key(
+    conn: &Connection,
+    cipher: &DbCipher,
+    new_key: &chacha20poly1305::Key,
+    on_progress: impl FnMut(u32, u32),
+) -> Result<u32> {
+    let new_cipher = DbCipher::new(new_key);
+    let done = reencrypt_cells(conn, cipher, &new_cipher, on_progress)?;
+    conn.execute(
+        "UPDATE db_key_check SET encrypted_check = ?1, encrypted_next_key = ?2",
+        params![
+            new_cipher.encrypt(KEY_CHECK_VALUE)?,
+            cipher.encrypt(new_key)?
+        ],
+    )?;
+    Ok(done)
+}
+
+/// Re-encrypt every encrypted cell with the new cipher. Run this inside a transaction.
+/// Progress is reported as (done, total) cells. Returns how many cells were re-encrypted.
+pub fn reencrypt_cells(
+    conn: &Connection,
+    cipher: &DbCipher,
+    new_cipher: &DbCipher,
+    mut on_progress: impl FnMut(u32, u32),
+) -> Result<u32> {
+    let mut total = 0;
+    for (table, _) in ENCRYPTED_COLUMNS {
+        let count: u32 = conn.query_row(&format!("SELECT count(*) FROM {table}"), [], |row| {
+            row.get(0)
+        })?;
+        total += count;
+    }
+    on_progress(0, total);
+
+    let mut done = 0;
+    for (table, column) in ENCRYPTED_COLUMNS {
+        let cells: Vec<Vec<u8>> = {
+            let mut stmt = conn.prepare(&format!("SELECT {column} FROM {table}"))?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<Result<_, _>>()?
+        };
+
+        // Nonce makes each ciphertext unique hence we can match cells by their value
+        let mut update = conn.prepare(&format!(
+            "UPDATE {table} SET {column} = ?1 WHERE {column} = ?2"
+        ))?;
+        for cell in cells {
+            let value = cipher
+                .decrypt(&cell)
+                .with_context(|| format!("Decrypt {table}.{column}"))?;
+            update.execute(params![new_cipher.encrypt(&value)?, cell])?;
+
+            done += 1;
+            if done % ROTATION_PROGRESS_STEP == 0 {
+                on_progress(done, total);
+            }
+        }
+    }
+    on_progress(done, total);
+    Ok(done)
+}
+
+/// Forget the previous key. Returns false if there was no rotation to confirm.
+pub fn confirm_key_rotation(conn: &Connection) -> Result<bool> {
+    let updated = conn.execute(
+        "UPDATE db_key_check SET encrypted_next_key = NULL WHERE encrypted_next_key IS NOT NULL",
+        [],
+    )?;
+    if updated == 0 {
+        return Ok(false);
+    }
+
+    purge_stale_pages(conn)?;
+    Ok(true)
+}
+
+/// Drop free pages and WAL frames that still hold cells encrypted with the previous key.
+pub fn purge_stale_pages(conn: &Connection) -> Result<()> {
+    conn.execute("VACUUM", [])?;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_row| Ok(()))?;
+    Ok(())
+}
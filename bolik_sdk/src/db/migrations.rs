@@ -7,7 +7,10 @@ const _FUTURE_CHANGELOG: &[(&str, &str)] = &[(
 CREATE TABLE device_settings (
   device_id TEXT REFERENCES mls_keys(id) PRIMARY KEY,
   device_name TEXT NOT NULL,
-  account_id TEXT
+  account_id TEXT,
+  sync_window_sec INT,
+  pushed_doc_counter INT NOT NULL DEFAULT 0,
+  membership_checked_at TEXT
 ) WITHOUT ROWID;
 
 CREATE TABLE device_vector_clock (
@@ -41,6 +44,7 @@ CREATE TABLE mls_groups (
   epoch INT NOT NULL,
   encrypted_state BLOB NOT NULL,
   accounts_hash TEXT,
+  ciphersuite INT,
   PRIMARY KEY (id, chain_hash)
 ) WITHOUT ROWID;
 
@@ -72,6 +76,7 @@ CREATE TABLE blobs (
   checksum TEXT NOT NULL,
   path TEXT NOT NULL,
   synced INT,
+  card_id TEXT,
   PRIMARY KEY (id, device_id)
 ) WITHOUT ROWID;
 
@@ -94,7 +99,8 @@ CREATE VIRTUAL TABLE card_index USING fts5 (id UNINDEXED, text, label_ids);
 CREATE TABLE local_notifications (
   id TEXT PRIMARY KEY,
   body BLOB,
-  created_at TEXT NOT NULL
+  created_at TEXT NOT NULL,
+  from_account_id TEXT
 ) WITHOUT ROWID;
 
 -- Contains a complete doc message to be sent
@@ -108,6 +114,8 @@ CREATE TABLE failed_docs (
   author_device_id TEXT NOT NULL,
   tries INT DEFAULT 1,
   retry_after TEXT NOT NULL,
+  reason TEXT,
+  skipped_at TEXT,
   PRIMARY KEY (doc_id, author_device_id)
 );
 
@@ -117,6 +125,43 @@ CREATE TABLE process_fetched_docs_queue (
   from_account_id TEXT NOT NULL,
   priority INT NOT NULL
 );
+
+CREATE TABLE deferred_docs (
+  doc_id TEXT NOT NULL,
+  author_device_id TEXT NOT NULL,
+  counter INT NOT NULL,
+  size_bytes INT NOT NULL,
+  PRIMARY KEY (doc_id, author_device_id)
+) WITHOUT ROWID;
+
+CREATE TABLE public_links (
+  id TEXT PRIMARY KEY,
+  card_id TEXT NOT NULL UNIQUE,
+  encrypted_key BLOB NOT NULL,
+  published_at TEXT NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE backup_settings (
+  encrypted_key BLOB NOT NULL,
+  enabled_at TEXT NOT NULL,
+  last_backup_at TEXT
+);
+
+CREATE TABLE mls_group_updates (
+  id TEXT PRIMARY KEY,
+  updated_at TEXT NOT NULL
+) WITHOUT ROWID;
+
+CREATE TABLE db_key_check (
+  encrypted_check BLOB NOT NULL,
+  encrypted_next_key BLOB
+);
+
+CREATE TABLE postponed_acl_removals (
+  doc_id TEXT NOT NULL REFERENCES documents (id) ON DELETE CASCADE,
+  account_id TEXT NOT NULL,
+  PRIMARY KEY (doc_id, account_id)
+) WITHOUT ROWID;
 "#,
 )];
 
@@ -248,6 +293,122 @@ CREATE TABLE process_fetched_docs_queue (
   from_account_id TEXT NOT NULL,
   priority INT NOT NULL
 );
+"#,
+    ),
+    (
+        "20230220",
+        r#"
+ALTER TABLE failed_docs ADD COLUMN reason TEXT;
+-- Skipped docs are kept for diagnostics and are not retried automatically
+ALTER TABLE failed_docs ADD COLUMN skipped_at TEXT;
+"#,
+    ),
+    (
+        "20230301",
+        r#"
+-- Cards created earlier than this window are not downloaded during sync (only headers are)
+ALTER TABLE device_settings ADD COLUMN sync_window_sec INT;
+
+-- Remote doc versions that were not downloaded yet.
+-- Such docs are saved as placeholders in documents table (with NULL data).
+CREATE TABLE deferred_docs (
+  doc_id TEXT NOT NULL,
+  author_device_id TEXT NOT NULL,
+  counter INT NOT NULL,
+  size_bytes INT NOT NULL,
+  PRIMARY KEY (doc_id, author_device_id)
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230305",
+        r#"
+-- Card that the blob is attached to
+ALTER TABLE blobs ADD COLUMN card_id TEXT;
+"#,
+    ),
+    (
+        "20230312",
+        r#"
+-- Published card snapshots. Key is needed to build the link again.
+CREATE TABLE public_links (
+  id TEXT PRIMARY KEY,
+  card_id TEXT NOT NULL UNIQUE,
+  encrypted_key BLOB NOT NULL,
+  published_at TEXT NOT NULL
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230325",
+        r#"
+-- Account that caused the notification (e.g shared a card)
+ALTER TABLE local_notifications ADD COLUMN from_account_id TEXT;
+"#,
+    ),
+    (
+        "20230401",
+        r#"
+-- Scheduled backups (at most one row). Key is derived from the recovery mnemonic.
+CREATE TABLE backup_settings (
+  encrypted_key BLOB NOT NULL,
+  enabled_at TEXT NOT NULL,
+  last_backup_at TEXT
+);
+"#,
+    ),
+    (
+        "20230408",
+        r#"
+-- When this device last updated its own leaf in a group
+CREATE TABLE mls_group_updates (
+  id TEXT PRIMARY KEY,
+  updated_at TEXT NOT NULL
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230422",
+        r#"
+-- Known value encrypted with the database key (at most one row).
+-- Next key is encrypted with the previous key until key rotation is confirmed.
+CREATE TABLE db_key_check (
+  encrypted_check BLOB NOT NULL,
+  encrypted_next_key BLOB
+);
+"#,
+    ),
+    (
+        "20230423",
+        r#"
+-- Counter of the last own doc version that the server has
+ALTER TABLE device_settings ADD COLUMN pushed_doc_counter INT NOT NULL DEFAULT 0;
+"#,
+    ),
+    (
+        "20230424",
+        r#"
+-- Accounts to remove from cards which bodies haven't been downloaded yet (placeholders).
+-- Removal is applied once the body is fetched and the ACL is known.
+CREATE TABLE postponed_acl_removals (
+  doc_id TEXT NOT NULL REFERENCES documents (id) ON DELETE CASCADE,
+  account_id TEXT NOT NULL,
+  PRIMARY KEY (doc_id, account_id)
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230426",
+        r#"
+-- When the server was last asked whether this device still belongs to the account
+ALTER TABLE device_settings ADD COLUMN membership_checked_at TEXT;
+"#,
+    ),
+    (
+        "20230427",
+        r#"
+-- MLS ciphersuite id of the group. Groups created before have the default ciphersuite.
+ALTER TABLE mls_groups ADD COLUMN ciphersuite INT;
 "#,
     ),
 ];
@@ -4,8 +4,8 @@ use anyhow::{anyhow, Result};
 use bolik_migrations::rusqlite::{config::DbConfig, params, Connection, OptionalExtension};
 use bolik_proto::sync::{DeviceVectorClock, KeyPackageMessage};
 use openmls::prelude::{
-    CredentialBundle, CredentialType, KeyPackage, KeyPackageBundle, KeyPackageRef, OpenMlsKeyStore,
-    TlsSerializeTrait,
+    Ciphersuite, CredentialBundle, CredentialType, KeyPackage, KeyPackageBundle, KeyPackageRef,
+    OpenMlsKeyStore, TlsSerializeTrait,
 };
 use openmls_traits::OpenMlsCryptoProvider;
 use prost::Message;
@@ -15,7 +15,7 @@ use crate::{
     blobs,
     device::{get_credential_id_bytes, get_device_id, yrs_client_id},
     registry::{WithBackend, WithTxn},
-    secrets::{self, SqliteCryptoProvider, CIPHERSUITES, DEFAULT_CIPHERSUITE},
+    secrets::{self, SqliteCryptoProvider, DEFAULT_CIPHERSUITE},
 };
 
 pub trait DeviceCtx<'a>: WithTxn<'a> + WithBackend {}
@@ -96,30 +96,62 @@ impl DeviceAtom {
         Ok(credential_bundle)
     }
 
-    /// Generate several KeyPackageBundles and save them in opemls' key store.
+    /// Ciphersuites this device supports, from the strongest to the weakest.
+    pub fn ciphersuites<'a>(&self, ctx: &impl DeviceCtx<'a>) -> Vec<Ciphersuite> {
+        secrets::negotiable_ciphersuites(ctx.backend().crypto())
+    }
+
+    /// Generate several KeyPackageBundles and save them in opemls' key store. The amount is split
+    /// across supported ciphersuites (stronger suites get the remainder) and each suite gets
+    /// at least one package.
     pub fn generate_key_packages<'a>(
         &self,
         ctx: &impl DeviceCtx<'a>,
         amount: u8,
     ) -> Result<Vec<(KeyPackageRef, KeyPackage)>> {
         let bundle = self.get_credential_bundle(ctx)?;
+        let ciphersuites = self.ciphersuites(ctx);
+        let per_suite = amount as usize / ciphersuites.len();
+        let remainder = amount as usize % ciphersuites.len();
         let mut packages = vec![];
-        for _ in 0..amount {
-            packages.push(Self::generate_key_package(
-                ctx.txn(),
-                &bundle,
-                &ctx.backend(),
-            )?);
+        for (i, ciphersuite) in ciphersuites.into_iter().enumerate() {
+            let suite_amount = (per_suite + usize::from(i < remainder)).max(1);
+            for _ in 0..suite_amount {
+                packages.push(Self::generate_key_package(
+                    ctx.txn(),
+                    &bundle,
+                    ciphersuite,
+                    &ctx.backend(),
+                )?);
+            }
         }
         Ok(packages)
     }
 
+    /// Generate a KeyPackageBundle for updating own leaf in secret groups.
+    pub fn generate_update_bundle<'a>(
+        &self,
+        ctx: &impl DeviceCtx<'a>,
+        ciphersuite: Ciphersuite,
+    ) -> Result<KeyPackageBundle> {
+        let backend = &ctx.backend();
+        let bundle = self.get_credential_bundle(ctx)?;
+        let key_bundle = secrets::new_key_package_bundle(ciphersuite, &bundle, backend)?;
+        let key_id = key_bundle.key_package().hash_ref(backend.crypto())?;
+        backend
+            .key_store()
+            .store(key_id.value(), &key_bundle)
+            .map_err(|err| anyhow!("{:?}", err))?;
+        Ok(key_bundle)
+    }
+
     /// Generate a KeyPackageBundle and save it in opemls' key store.
     fn generate_offline_key_package(
         credential_bundle: &CredentialBundle,
+        ciphersuite: Ciphersuite,
         backend: &impl OpenMlsCryptoProvider,
     ) -> Result<(KeyPackageRef, KeyPackage)> {
-        let key_bundle = KeyPackageBundle::new(&CIPHERSUITES, credential_bundle, backend, vec![])?;
+        let key_bundle = secrets::new_key_package_bundle(ciphersuite, credential_bundle, backend)?;
         let key_id = key_bundle.key_package().hash_ref(backend.crypto())?;
         backend
             .key_store()
@@ -132,9 +164,11 @@ impl DeviceAtom {
     fn generate_key_package(
         conn: &Connection,
         credential_bundle: &CredentialBundle,
+        ciphersuite: Ciphersuite,
         backend: &impl OpenMlsCryptoProvider,
     ) -> Result<(KeyPackageRef, KeyPackage)> {
-        let (key_ref, package) = Self::generate_offline_key_package(credential_bundle, backend)?;
+        let (key_ref, package) =
+            Self::generate_offline_key_package(credential_bundle, ciphersuite, backend)?;
         let data = package.tls_serialize_detached()?;
 
         let message = KeyPackageMessage { data }.encode_to_vec();
@@ -218,6 +252,8 @@ INSERT INTO device_vector_clock (device_id, counter) VALUES (?, ?)
         conn.set_db_config(DbConfig::SQLITE_DBCONFIG_RESET_DATABASE, true)?;
         conn.execute("VACUUM", [])?;
         conn.set_db_config(DbConfig::SQLITE_DBCONFIG_RESET_DATABASE, false)?;
+        // Old pages could still be present in the write-ahead log
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_row| Ok(()))?;
 
         Ok(())
     }
@@ -1,15 +1,15 @@
 use anyhow::Result;
 use bolik_migrations::rusqlite::{params, Connection};
 use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Days, Utc};
 use yrs::{updates::decoder::Decode, ReadTxn, StateVector, Transact, Update};
 
 mod docs_atom;
 mod sync_docs_atom;
 pub mod yrs_util;
 
-pub use docs_atom::{DocsAtom, DocsCtx};
-pub use sync_docs_atom::SyncDocsAtom;
+pub use docs_atom::{DocsAtom, DocsCtx, PendingDoc};
+pub use sync_docs_atom::{FailedDoc, SyncDocsAtom};
 
 /// Mark moved to bin cards
 pub const BIN_LABEL_ID: &str = "bolik-bin";
@@ -87,9 +87,14 @@ INSERT INTO documents (id, data, acl_data, author_device_id, counter, created_at
 pub(crate) fn delete_row(conn: &Connection, doc_id: &str) -> Result<()> {
     conn.execute("DELETE FROM documents WHERE id = ?", [doc_id])?;
     conn.execute("DELETE FROM card_index WHERE id = ?", [doc_id])?;
+    conn.execute("DELETE FROM deferred_docs WHERE doc_id = ?", [doc_id])?;
     Ok(())
 }
 
+/// How long a doc secret is used for encryption. A new secret is generated for the same
+/// accounts once it becomes obsolete.
+pub(crate) const SECRET_ROTATION_PERIOD: Days = Days::new(7);
+
 #[derive(Clone)]
 pub struct DocSecretRow {
     pub id: String,
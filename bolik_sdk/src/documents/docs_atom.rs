@@ -1,7 +1,7 @@
 use anyhow::Result;
 use bolik_migrations::rusqlite::{params, OptionalExtension, Params, Row};
 use bolik_proto::sync::{doc_payload::DocSchema, request, SecretAlgorithm};
-use chrono::{DateTime, Days, Utc};
+use chrono::{DateTime, Utc};
 use prost::Message;
 use uuid::Uuid;
 
@@ -12,7 +12,7 @@ use crate::{
     secrets::{build_accounts_hash, generate_key},
 };
 
-use super::{save, DbDocRow, DbDocRowMeta, DocSecretRow, ALL_LABEL_ID};
+use super::{save, DbDocRow, DbDocRowMeta, DocSecretRow, ALL_LABEL_ID, SECRET_ROTATION_PERIOD};
 
 pub trait DocsCtx<'a>: WithTxn<'a> {}
 impl<'a, T> DocsCtx<'a> for T where T: WithTxn<'a> {}
@@ -31,14 +31,31 @@ impl DocsAtom {
         }
     }
 
-    /// Find a document by ID. Never returns a deleted document
+    /// Find a document by ID. Never returns a deleted document or a placeholder
     pub fn find<'a>(&self, ctx: &impl DocsCtx<'a>, doc_id: &str) -> Result<Option<DbDocRow>> {
         self.query_row(
             ctx,
             r#"
 SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, schema
   FROM documents
- WHERE id = ?"#,
+ WHERE id = ? AND data IS NOT NULL"#,
+            [doc_id],
+        )
+    }
+
+    /// Find a placeholder of a document which body hasn't been downloaded yet.
+    /// Returned row has empty data and ACL.
+    pub fn find_placeholder<'a>(
+        &self,
+        ctx: &impl DocsCtx<'a>,
+        doc_id: &str,
+    ) -> Result<Option<DbDocRow>> {
+        self.query_row(
+            ctx,
+            r#"
+SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, schema
+  FROM documents
+ WHERE id = ? AND data IS NULL"#,
             [doc_id],
         )
     }
@@ -57,7 +74,7 @@ SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, sch
 SELECT d.id, author_device_id, counter, data, acl_data, created_at, edited_at, schema
   FROM documents d
   JOIN card_index i ON d.id = i.id
- WHERE schema = ?1 AND i.label_ids MATCH ?2
+ WHERE schema = ?1 AND i.label_ids MATCH ?2 AND data IS NOT NULL
  ORDER BY created_at DESC
  LIMIT ?, 1"#,
             params![schema as i32, not_deleted, offset],
@@ -75,17 +92,67 @@ SELECT d.id, author_device_id, counter, data, acl_data, created_at, edited_at, s
             r#"
 SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, schema
   FROM documents
- WHERE author_device_id = ?1 AND counter > ?2
+ WHERE author_device_id = ?1 AND counter > ?2 AND data IS NOT NULL
  ORDER BY counter
  LIMIT 1"#,
             params![self.device_id, counter],
         )
     }
 
+    /// List locally modified documents with clock higher than provided.
+    pub fn list_local_after<'a>(
+        &self,
+        ctx: &impl DocsCtx<'a>,
+        counter: u64,
+    ) -> Result<Vec<PendingDoc>> {
+        let mut stmt = ctx.txn().prepare(
+            r#"
+SELECT id, length(data) + length(acl_data)
+  FROM documents
+ WHERE author_device_id = ?1 AND counter > ?2 AND data IS NOT NULL
+ ORDER BY counter"#,
+        )?;
+        let mut rows = stmt.query(params![self.device_id, counter])?;
+        let mut docs = vec![];
+        while let Some(row) = rows.next()? {
+            docs.push(PendingDoc {
+                doc_id: row.get(0)?,
+                deleted: false,
+                size_bytes: row.get(1)?,
+            });
+        }
+        Ok(docs)
+    }
+
     pub fn save<'a>(&self, ctx: &impl DocsCtx<'a>, row: &DbDocRow) -> Result<()> {
         save(ctx.txn(), row)
     }
 
+    /// Save a placeholder for a document which body will be downloaded later.
+    /// Existing documents are not modified.
+    pub fn save_placeholder<'a>(&self, ctx: &impl DocsCtx<'a>, meta: &DbDocRowMeta) -> Result<()> {
+        let acl_data = super::encode_yrs_doc(&yrs::Doc::new());
+        ctx.txn().execute(
+            r#"
+INSERT INTO documents (id, data, acl_data, author_device_id, counter, created_at, edited_at, schema)
+     VALUES (?1, NULL, ?2, ?3, ?4, ?5, ?6, ?7)
+  ON CONFLICT (id) DO UPDATE
+     SET author_device_id = excluded.author_device_id,
+         counter = excluded.counter
+   WHERE data IS NULL"#,
+            params![
+                meta.id,
+                acl_data,
+                meta.author_device_id,
+                meta.counter,
+                meta.created_at,
+                meta.edited_at,
+                meta.schema,
+            ],
+        )?;
+        Ok(())
+    }
+
     pub fn queue_doc_push<'a>(
         &self,
         ctx: &impl WithTxn<'a>,
@@ -119,6 +186,7 @@ SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, sch
             body: Some(request::doc_message::Body::Deleted(
                 request::doc_message::DeletionBody { deleted_at_sec },
             )),
+            schema: None,
         };
 
         self.queue_doc_push(ctx, &message)
@@ -145,6 +213,25 @@ SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, sch
         }
     }
 
+    /// List doc messages that are waiting to be sent.
+    pub fn list_queued_docs<'a>(&self, ctx: &impl WithTxn<'a>) -> Result<Vec<PendingDoc>> {
+        let mut stmt = ctx
+            .txn()
+            .prepare("SELECT message FROM push_docs_queue ORDER BY rowid")?;
+        let mut rows = stmt.query([])?;
+        let mut docs = vec![];
+        while let Some(row) = rows.next()? {
+            let bytes: Vec<u8> = row.get(0)?;
+            let message = request::DocMessage::decode(bytes.as_slice())?;
+            docs.push(PendingDoc {
+                doc_id: message.id,
+                deleted: matches!(message.body, Some(request::doc_message::Body::Deleted(_))),
+                size_bytes: bytes.len() as u64,
+            });
+        }
+        Ok(docs)
+    }
+
     pub fn remove_queued_doc<'a>(&self, ctx: &impl WithTxn<'a>, rowid: u32) -> Result<()> {
         ctx.txn()
             .execute("DELETE FROM push_docs_queue WHERE rowid = ?", [rowid])?;
@@ -161,7 +248,7 @@ SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, sch
             r#"
     SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, schema
       FROM documents
-     WHERE schema = ?"#,
+     WHERE schema = ? AND data IS NOT NULL"#,
         )?;
         let mut rows = stmt.query([schema as i32])?;
         let mut docs = vec![];
@@ -193,7 +280,7 @@ SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, sch
     }
 
     fn read_row(&self, row: &Row) -> Result<DbDocRow> {
-        let data: Vec<u8> = row.get(3)?;
+        let data: Option<Vec<u8>> = row.get(3)?;
         let acl_data: Vec<u8> = row.get(4)?;
 
         let meta = DbDocRowMeta {
@@ -205,7 +292,15 @@ SELECT id, author_device_id, counter, data, acl_data, created_at, edited_at, sch
             schema: row.get(7)?,
         };
 
-        let doc = super::build_yrs_doc(self.client_id, &data)?;
+        let doc = match data {
+            Some(data) => super::build_yrs_doc(self.client_id, &data)?,
+            // Placeholders have no data
+            None => yrs::Doc::with_options(yrs::Options {
+                client_id: self.client_id,
+                offset_kind: yrs::OffsetKind::Utf32,
+                ..Default::default()
+            }),
+        };
         let acl = super::build_yrs_doc(self.client_id, &acl_data)?;
 
         Ok(DbDocRow {
@@ -302,7 +397,7 @@ SELECT id, encrypted_secret, account_ids, doc_id, algorithm, created_at, obsolet
             doc_id,
             algorithm: SecretAlgorithm::ChaCha20Poly1305.into(),
             created_at: now,
-            obsolete_at: now.checked_add_days(Days::new(30)).unwrap_or(now),
+            obsolete_at: now.checked_add_days(SECRET_ROTATION_PERIOD).unwrap_or(now),
         };
         self.save_secret(ctx, secret)
     }
@@ -369,3 +464,12 @@ UPDATE doc_secrets
         Ok(())
     }
 }
+
+/// A doc message that is waiting to be sent.
+#[derive(Clone, Debug)]
+pub struct PendingDoc {
+    pub doc_id: String,
+    /// Doc is permanently deleted
+    pub deleted: bool,
+    pub size_bytes: u64,
+}
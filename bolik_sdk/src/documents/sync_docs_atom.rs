@@ -2,10 +2,11 @@ use crate::{
     account::{
         AccNotification, AccNotifications, AccView, AccountDevice, NotificationStatus, ProfileView,
     },
+    backup::BackupManifest,
     blobs::{self, BlobRef},
     client::Client,
-    documents::{self, DbDocRow, DbDocRowMeta},
-    output::OutputEvent,
+    documents::{self, DbDocRow, DbDocRowMeta, PendingDoc},
+    output::{OutputEvent, SyncPhase},
     registry::{
         WithAccountAtom, WithBackend, WithBackendConn, WithBlobsAtom, WithBroadcast, WithDb,
         WithDeviceAtom, WithDocsAtom, WithEvents, WithInTxn, WithMailboxAtom, WithSecretGroupAtom,
@@ -16,6 +17,8 @@ use crate::{
         self,
         acl_doc::{AclDoc, AclOperationMode},
         card::{CardChange, CardView, ContentView},
+        comments::{CardComment, CardComments},
+        shared_label::SharedLabelView,
         EditCardOpts, PermanentDeleteOpts,
     },
 };
@@ -33,6 +36,7 @@ use bolik_proto::{
 use chacha20poly1305::aead::Aead;
 use chrono::{DateTime, Duration, TimeZone, Utc};
 use tracing::instrument;
+use yrs::{ReadTxn, StateVector, Transact};
 
 use super::DocSecret;
 
@@ -72,6 +76,16 @@ impl<C: Client> SyncDocsAtom<C> {
     }
 
     pub async fn sync(&self, ctx: &impl SyncDocsCtx<'_, C>) -> Result<()> {
+        self.sync_docs(ctx, false).await
+    }
+
+    /// Sync only the account doc. Other remote docs are ignored. This is meant for short-lived
+    /// devices (like the recovery member) that don't need the rest of account data.
+    pub async fn sync_account_doc(&self, ctx: &impl SyncDocsCtx<'_, C>) -> Result<()> {
+        self.sync_docs(ctx, true).await
+    }
+
+    async fn sync_docs(&self, ctx: &impl SyncDocsCtx<'_, C>, only_account_doc: bool) -> Result<()> {
         if let None = ctx.in_txn(|tx_ctx| Ok(tx_ctx.account().get_account_id(tx_ctx)))? {
             tracing::info!("Device not connected to account, skipping doc sync...");
             return Ok(());
@@ -80,8 +94,9 @@ impl<C: Client> SyncDocsAtom<C> {
         // Continuously sync until server responds with less docs than the limit.
         // Also limit the amount of rounds to prevent infinite loops in case of an error.
         let mut sync_err = None;
+        let mut stats = SyncStats::default();
         for _ in 0..20 {
-            match self.sync_roundtrip(ctx).await {
+            match self.sync_roundtrip(ctx, &mut stats, only_account_doc).await {
                 Ok(res) if res.docs < res.limit => {
                     break;
                 }
@@ -95,8 +110,17 @@ impl<C: Client> SyncDocsAtom<C> {
 
         self.process_failed_docs(ctx).await?;
 
+        self.broadcast_progress(ctx, SyncPhase::ProcessDocs, &stats);
         ctx.in_txn(|tx_ctx| self.process_fetched_docs(tx_ctx))?;
 
+        if stats.docs_fetched > 0 {
+            // Let the server know which versions could be compacted
+            let clock = ctx.in_txn(|tx_ctx| tx_ctx.device().get_vector_clock(tx_ctx))?;
+            if let Err(err) = self.client.ack_docs(&clock).await {
+                tracing::warn!("Failed to ack docs: {:?}", err);
+            }
+        }
+
         if let Some(err) = sync_err {
             Err(err)
         } else {
@@ -104,22 +128,40 @@ impl<C: Client> SyncDocsAtom<C> {
         }
     }
 
-    async fn sync_roundtrip(&self, ctx: &impl SyncDocsCtx<'_, C>) -> Result<FetchResult> {
+    async fn sync_roundtrip(
+        &self,
+        ctx: &impl SyncDocsCtx<'_, C>,
+        stats: &mut SyncStats,
+        only_account_doc: bool,
+    ) -> Result<FetchResult> {
         let device_id = &ctx.device().id;
-        let (local_clock, acc_id) = ctx.in_txn(|tx_ctx| {
+        let (local_clock, acc_id, sync_window) = ctx.in_txn(|tx_ctx| {
             let clock = tx_ctx.device().get_vector_clock(tx_ctx)?;
             let acc_id = tx_ctx.account().require_account_id(tx_ctx)?;
-            Ok((clock, acc_id))
+            let sync_window = self.sync_window(tx_ctx)?;
+            Ok((clock, acc_id, sync_window))
         })?;
 
         // Fetch remote changes
         tracing::debug!(?local_clock, "Fetching remote docs");
-        let res = self.client.fetch_docs(&local_clock).await?;
+        let headers_before = sync_window.map(|window| Utc::now() - window);
+        let res = self.client.fetch_docs(&local_clock, headers_before).await?;
         let fetch_res = FetchResult {
             docs: res.docs.len() as u32,
             limit: res.limit,
         };
         tracing::debug!("Remote has {} new docs", res.docs.len());
+        stats.docs_fetched += fetch_res.docs;
+        stats.docs_total_estimate = if fetch_res.docs < fetch_res.limit {
+            stats.docs_fetched
+        } else {
+            // There are more docs to fetch
+            stats.docs_fetched + fetch_res.limit
+        };
+        self.broadcast_progress(ctx, SyncPhase::FetchDocs, stats);
+
+        let mut fetch_now = vec![];
+        let mut deferred_docs = 0;
         for remote_doc in res.docs {
             if &remote_doc.author_device_id == device_id {
                 continue;
@@ -131,11 +173,38 @@ impl<C: Client> SyncDocsAtom<C> {
                     &remote_doc.author_device_id,
                     remote_doc.counter,
                 )?;
-                self.process_remote_doc(tx_ctx, &acc_id, remote_doc)?;
+
+                if only_account_doc && remote_doc.doc_id != acc_id {
+                    return Ok(());
+                }
+
+                match &remote_doc.body {
+                    Some(response::doc_version::Body::Header(header)) => {
+                        if self.defer_remote_doc(tx_ctx, &acc_id, &remote_doc, header)? {
+                            deferred_docs += 1;
+                        } else {
+                            fetch_now.push((remote_doc.doc_id, remote_doc.author_device_id));
+                        }
+                    }
+                    _ => {
+                        self.process_remote_doc(tx_ctx, &acc_id, remote_doc)?;
+                    }
+                }
                 Ok(())
             })?;
         }
 
+        // Server sent only headers but we need the whole doc
+        for (doc_id, author_device_id) in fetch_now {
+            self.fetch_doc_version(ctx, &acc_id, &doc_id, &author_device_id)
+                .await?;
+        }
+
+        if deferred_docs > 0 {
+            tracing::debug!("Deferred {} docs", deferred_docs);
+            ctx.broadcast(OutputEvent::TimelineUpdated);
+        }
+
         let (acc, mut local_clock) = ctx.in_txn(|ctx_tx| {
             let acc = ctx_tx.account().require_account(ctx_tx)?;
             let clock = ctx.device().get_vector_clock(ctx_tx)?;
@@ -144,12 +213,14 @@ impl<C: Client> SyncDocsAtom<C> {
 
         // Find and upload locally modified docs
         let mut last_seen_counter = res.last_seen_counter;
+        ctx.in_txn(|ctx_tx| self.set_pushed_doc_counter(ctx_tx, last_seen_counter))?;
         while let Some(modified_doc) =
             ctx.in_txn(|ctx_tx| ctx_tx.docs().find_local_after(ctx_tx, last_seen_counter))?
         {
             last_seen_counter = modified_doc.meta.counter;
-            self.upload_encrypted_doc(ctx, modified_doc, local_clock.clone(), &acc)
+            self.upload_encrypted_doc(ctx, modified_doc, local_clock.clone(), &acc, stats)
                 .await?;
+            ctx.in_txn(|ctx_tx| self.set_pushed_doc_counter(ctx_tx, last_seen_counter))?;
         }
 
         // Upload other queued docs
@@ -180,6 +251,10 @@ impl<C: Client> SyncDocsAtom<C> {
         acc_id: &str,
         remote_doc: response::DocVersion,
     ) -> Result<()> {
+        if self.defer_until_body_fetched(ctx, acc_id, &remote_doc)? {
+            return Ok(());
+        }
+
         match self.merge_remote_doc(ctx, acc_id, remote_doc)? {
             MergeResult::Merged(merged) => {
                 self.complete_doc_fetching(ctx, &acc_id, merged)?;
@@ -188,19 +263,267 @@ impl<C: Client> SyncDocsAtom<C> {
             MergeResult::Retry {
                 doc_id,
                 author_device_id,
+                reason,
             } => {
-                self.mark_for_retry(ctx, &doc_id, &author_device_id)?;
+                self.mark_for_retry(ctx, &doc_id, &author_device_id, &reason)?;
             }
             MergeResult::Skip {
                 doc_id,
                 author_device_id,
+                reason,
             } => {
-                self.mark_doc_skipped(ctx, &doc_id, &author_device_id)?;
+                self.mark_doc_skipped(ctx, &doc_id, &author_device_id, &reason)?;
             }
         }
         Ok(())
     }
 
+    /// Save a placeholder for a doc that server sent without a body.
+    /// Returns false if the doc cannot be deferred and should be fetched right away.
+    fn defer_remote_doc<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithAccountAtom),
+        acc_id: &str,
+        remote_doc: &response::DocVersion,
+        header: &response::doc_version::HeaderBody,
+    ) -> Result<bool> {
+        // Only cards from own account could be deferred.
+        // Cards shared by other accounts need to be processed (e.g. to create a notification).
+        if header.schema != DocSchema::CardV1 as i32 {
+            return Ok(false);
+        }
+        match ctx
+            .account()
+            .find_account_device(ctx, &remote_doc.author_device_id)
+        {
+            Ok(device) if device.account_id == acc_id => {}
+            _ => return Ok(false),
+        }
+
+        // Keep downloaded docs up to date
+        if ctx.docs().find(ctx, &remote_doc.doc_id)?.is_some() {
+            return Ok(false);
+        }
+
+        let created_at = Utc.timestamp(remote_doc.created_at_sec, 0);
+        let meta = DbDocRowMeta {
+            id: remote_doc.doc_id.clone(),
+            author_device_id: remote_doc.author_device_id.clone(),
+            counter: remote_doc.counter,
+            schema: header.schema,
+            created_at,
+            edited_at: created_at,
+        };
+        ctx.docs().save_placeholder(ctx, &meta)?;
+        self.save_deferred(ctx, remote_doc, header.size_bytes)?;
+        tracing::debug!(
+            doc_id = remote_doc.doc_id,
+            size_bytes = header.size_bytes,
+            "Deferred doc"
+        );
+
+        // Show the card in the timeline
+        if let Some(row) = ctx.docs().find_placeholder(ctx, &remote_doc.doc_id)? {
+            let labels_row = ctx
+                .docs()
+                .find(ctx, &format!("{}/labels", remote_doc.doc_id))?;
+            let view = CardView::from_db(row, labels_row).0;
+            timeline::index_card(ctx.txn(), &view)?;
+        }
+        Ok(true)
+    }
+
+    /// Defer a version from another account when the doc body hasn't been downloaded yet.
+    /// Placeholder has no ACL to verify the version against, hence the version is merged
+    /// once the versions from own account are fetched.
+    fn defer_until_body_fetched<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithAccountAtom),
+        acc_id: &str,
+        remote_doc: &response::DocVersion,
+    ) -> Result<bool> {
+        let Some(response::doc_version::Body::Encrypted(body)) = &remote_doc.body else {
+            return Ok(false);
+        };
+        if ctx
+            .docs()
+            .find_placeholder(ctx, &remote_doc.doc_id)?
+            .is_none()
+        {
+            return Ok(false);
+        }
+        match ctx
+            .account()
+            .find_account_device(ctx, &remote_doc.author_device_id)
+        {
+            Ok(device) if device.account_id == acc_id => return Ok(false),
+            _ => {}
+        }
+
+        self.save_deferred(ctx, remote_doc, body.payload.len() as u64)?;
+        tracing::debug!(
+            doc_id = remote_doc.doc_id,
+            author_device_id = remote_doc.author_device_id,
+            "Deferred doc version from another account"
+        );
+        Ok(true)
+    }
+
+    fn save_deferred<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        remote_doc: &response::DocVersion,
+        size_bytes: u64,
+    ) -> Result<()> {
+        ctx.txn().execute(
+            r#"
+INSERT INTO deferred_docs (doc_id, author_device_id, counter, size_bytes) VALUES (?1, ?2, ?3, ?4)
+       ON CONFLICT (doc_id, author_device_id)
+          DO UPDATE SET counter = excluded.counter, size_bytes = excluded.size_bytes"#,
+            params![
+                remote_doc.doc_id,
+                remote_doc.author_device_id,
+                remote_doc.counter,
+                size_bytes,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Fetch a single doc version and merge it. Failed requests are retried later.
+    async fn fetch_doc_version(
+        &self,
+        ctx: &impl SyncDocsCtx<'_, C>,
+        acc_id: &str,
+        doc_id: &str,
+        author_device_id: &str,
+    ) -> Result<()> {
+        match self.client.get_doc_version(doc_id, author_device_id).await {
+            Ok(remote_doc) => ctx.in_txn(|tx_ctx| {
+                self.remove_deferred(tx_ctx, doc_id, author_device_id)?;
+                self.process_remote_doc(tx_ctx, acc_id, remote_doc)
+            }),
+            Err(err) => {
+                tracing::warn!(
+                    doc_id,
+                    author_device_id,
+                    "Failed to fetch doc version: {}",
+                    err
+                );
+                let reason = format!("Failed to fetch doc version: {}", err);
+                ctx.in_txn(|tx_ctx| {
+                    self.remove_deferred(tx_ctx, doc_id, author_device_id)?;
+                    self.mark_for_retry(tx_ctx, doc_id, author_device_id, &reason)
+                })
+            }
+        }
+    }
+
+    /// Download doc versions that were deferred during sync.
+    #[instrument(skip(self, ctx))]
+    pub async fn fetch_deferred(&self, ctx: &impl SyncDocsCtx<'_, C>, doc_id: &str) -> Result<()> {
+        let (acc_id, author_ids) = ctx.in_txn(|tx_ctx| {
+            let acc_id = tx_ctx.account().require_account_id(tx_ctx)?;
+            let mut stmt = tx_ctx
+                .txn()
+                .prepare("SELECT author_device_id FROM deferred_docs WHERE doc_id = ?")?;
+            let mut rows = stmt.query([doc_id])?;
+            let mut ids: Vec<String> = vec![];
+            while let Some(row) = rows.next()? {
+                ids.push(row.get(0)?);
+            }
+            // Versions from own account go first. They bring the ACL that versions
+            // from other accounts are verified against.
+            ids.sort_by_key(|id| {
+                !matches!(
+                    tx_ctx.account().find_account_device(tx_ctx, id),
+                    Ok(device) if device.account_id == acc_id
+                )
+            });
+            Ok((acc_id, ids))
+        })?;
+
+        for author_device_id in author_ids {
+            tracing::debug!(author_device_id, "Fetching deferred doc");
+            self.fetch_doc_version(ctx, &acc_id, doc_id, &author_device_id)
+                .await?;
+        }
+
+        ctx.in_txn(|tx_ctx| self.process_fetched_docs(tx_ctx))?;
+        Ok(())
+    }
+
+    /// Check if doc has versions that haven't been downloaded yet.
+    pub fn has_deferred<'a>(&self, ctx: &impl WithTxn<'a>, doc_id: &str) -> Result<bool> {
+        let found: Option<u32> = ctx
+            .txn()
+            .query_row(
+                "SELECT 1 FROM deferred_docs WHERE doc_id = ? LIMIT 1",
+                [doc_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    fn remove_deferred<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        doc_id: &str,
+        author_device_id: &str,
+    ) -> Result<()> {
+        ctx.txn().execute(
+            "DELETE FROM deferred_docs WHERE doc_id = ?1 AND author_device_id = ?2",
+            [doc_id, author_device_id],
+        )?;
+        Ok(())
+    }
+
+    /// Cards created earlier than this window are not downloaded during sync.
+    /// They are fetched once opened.
+    pub fn sync_window<'a>(&self, ctx: &impl WithTxn<'a>) -> Result<Option<Duration>> {
+        let window_sec: Option<i64> =
+            ctx.txn()
+                .query_row("SELECT sync_window_sec FROM device_settings", [], |row| {
+                    row.get(0)
+                })?;
+        Ok(window_sec.map(Duration::seconds))
+    }
+
+    pub fn set_sync_window<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        window: Option<Duration>,
+    ) -> Result<()> {
+        let window_sec = window.map(|w| w.num_seconds().max(0));
+        ctx.txn().execute(
+            "UPDATE device_settings SET sync_window_sec = ?",
+            [window_sec],
+        )?;
+        Ok(())
+    }
+
+    /// List locally modified docs that haven't been uploaded yet.
+    pub fn list_modified_docs<'a>(
+        &self,
+        ctx: &(impl WithTxn<'a> + WithDocsAtom),
+    ) -> Result<Vec<PendingDoc>> {
+        let pushed_counter: u64 = ctx.txn().query_row(
+            "SELECT pushed_doc_counter FROM device_settings",
+            [],
+            |row| row.get(0),
+        )?;
+        ctx.docs().list_local_after(ctx, pushed_counter)
+    }
+
+    fn set_pushed_doc_counter<'a>(&self, ctx: &impl WithTxn<'a>, counter: u64) -> Result<()> {
+        ctx.txn().execute(
+            "UPDATE device_settings SET pushed_doc_counter = ?",
+            [counter],
+        )?;
+        Ok(())
+    }
+
     #[instrument(skip_all, fields(doc_id = remote_doc.doc_id, author = remote_doc.author_device_id, counter = remote_doc.counter))]
     fn merge_remote_doc<'a>(
         &self,
@@ -224,11 +547,17 @@ impl<C: Client> SyncDocsAtom<C> {
                 // There could have been a confict in Signature Chain and we couldn't find an account for author device.
                 if self.can_retry_doc(ctx, doc_id, &remote_doc.author_device_id)? {
                     tracing::warn!("Doc payload is invalid (will retry): {:?}", err);
-                    return Ok(MergeResult::retry(remote_doc));
+                    return Ok(MergeResult::retry(
+                        remote_doc,
+                        format!("Doc payload is invalid: {}", err),
+                    ));
                 }
 
                 tracing::error!("Doc payload is invalid: {:?}", err);
-                return Ok(MergeResult::skip(remote_doc));
+                return Ok(MergeResult::skip(
+                    remote_doc,
+                    format!("Doc payload is invalid: {}", err),
+                ));
             }
         };
         let from_account_id = &from_account_device.account_id;
@@ -241,18 +570,27 @@ impl<C: Client> SyncDocsAtom<C> {
                 // This error might be transient, so retry later.
                 if self.can_retry_doc(ctx, doc_id, &remote_doc.author_device_id)? {
                     tracing::warn!("Failed to read remote doc (will retry): {:?}", err);
-                    return Ok(MergeResult::retry(remote_doc));
+                    return Ok(MergeResult::retry(
+                        remote_doc,
+                        format!("Failed to read remote doc: {}", err),
+                    ));
                 }
 
                 tracing::error!("Failed to read remote doc: {:?}", err);
-                return Ok(MergeResult::skip(remote_doc));
+                return Ok(MergeResult::skip(
+                    remote_doc,
+                    format!("Failed to read remote doc: {}", err),
+                ));
             }
         };
         match remote_body {
             DocBody::Deleted(_deleted_at) => {
                 // Allow permanent deletions only from own account
                 if from_account_id != &acc_id {
-                    return Ok(MergeResult::skip(remote_doc));
+                    return Ok(MergeResult::skip(
+                        remote_doc,
+                        format!("Deletion from another account={}", from_account_id),
+                    ));
                 }
 
                 tracing::info!(doc_id, "Permanently deleting");
@@ -262,22 +600,29 @@ impl<C: Client> SyncDocsAtom<C> {
             }
             DocBody::Payload(payload) => {
                 let author_device_id = remote_doc.author_device_id.clone();
+                let mut card_before = None;
+                let mut avatar_before = None;
                 let (local_row, is_new) = match existing {
                     Some(mut local_row) => {
                         // Apply remote version
                         if payload.schema != local_row.meta.schema {
-                            tracing::warn!(
+                            let reason = format!(
                                 "Remote schema={} doesn't match local={}",
-                                payload.schema,
-                                local_row.meta.schema
+                                payload.schema, local_row.meta.schema
                             );
-                            return Ok(MergeResult::skip(remote_doc));
+                            tracing::warn!("{}", reason);
+                            return Ok(MergeResult::skip(remote_doc, reason));
                         }
 
                         // Filter schemas from other accounts
                         if from_account_id != &acc_id {
                             match DocSchema::from_i32(payload.schema) {
-                                Some(DocSchema::CardV1 | DocSchema::ProfileV1) => {
+                                Some(
+                                    DocSchema::CardV1
+                                    | DocSchema::CardCommentsV1
+                                    | DocSchema::ProfileV1
+                                    | DocSchema::SharedLabelV1,
+                                ) => {
                                     // Allowed
                                 }
                                 _ => {
@@ -286,35 +631,93 @@ impl<C: Client> SyncDocsAtom<C> {
                                         from_account_id,
                                         "Skipping doc for disallowed schema from another account"
                                     );
-                                    return Ok(MergeResult::skip(remote_doc));
+                                    return Ok(MergeResult::skip(
+                                        remote_doc,
+                                        format!(
+                                            "Schema={} is not allowed from another account={}",
+                                            payload.schema, from_account_id
+                                        ),
+                                    ));
                                 }
                             }
                         }
 
+                        if payload.schema == DocSchema::ProfileV1 as i32 {
+                            avatar_before = ProfileView::read_avatar(&local_row.yrs);
+                        }
+
+                        // Remember card state to notify about changes from collaborators
+                        if from_account_id != &acc_id && payload.schema == DocSchema::CardV1 as i32
+                        {
+                            card_before = Some((
+                                AclDoc::from_doc(&local_row.acl),
+                                local_row.yrs.transact().state_vector(),
+                            ));
+                        }
+
                         // ACL
-                        match Self::merge_acls(from_account_id, &local_row.acl, &payload.acl) {
+                        let acl_result = if payload.schema == DocSchema::CardCommentsV1 as i32 {
+                            Self::card_comments_acl(
+                                ctx,
+                                from_account_id,
+                                doc_id,
+                                &mut local_row.acl,
+                            )?
+                        } else {
+                            Self::merge_acls(
+                                ctx.device().yrs_client_id,
+                                from_account_id,
+                                &local_row.acl,
+                                &payload.acl,
+                            )?
+                        };
+                        match acl_result {
                             MergeAclResult::Applied => {}
-                            MergeAclResult::Unauthorized => {
+                            MergeAclResult::Unauthorized(reason) => {
                                 // This error might be transient, so retry later.
                                 // This could happen when doc ACL changed, a new member edited the doc and new member's change
                                 // came first. In this case we just skip this doc and refetch during next sync.
                                 if self.can_retry_doc(ctx, doc_id, &remote_doc.author_device_id)? {
-                                    tracing::warn!(
-                                        from_account_id,
-                                        "not allowed to edit this doc (will retry)",
-                                    );
-                                    return Ok(MergeResult::retry(remote_doc));
+                                    tracing::warn!(from_account_id, "{} (will retry)", reason);
+                                    return Ok(MergeResult::retry(remote_doc, reason));
                                 }
 
-                                tracing::warn!(from_account_id, "not allowed to edit this doc",);
-                                return Ok(MergeResult::skip(remote_doc));
+                                tracing::warn!(from_account_id, "{}", reason);
+                                return Ok(MergeResult::skip(remote_doc, reason));
                             }
                         }
 
                         // Merge docs
+                        let comments_before = if payload.schema == DocSchema::CardCommentsV1 as i32
+                        {
+                            Some(CardComments::read_comments(&local_row.yrs))
+                        } else {
+                            None
+                        };
                         if let Err(err) = documents::merge_yrs_docs(&local_row.yrs, &payload.data) {
                             tracing::warn!("Failed to merge remote doc: {}", err);
-                            return Ok(MergeResult::skip(remote_doc));
+                            return Ok(MergeResult::skip(
+                                remote_doc,
+                                format!("Failed to merge remote doc: {}", err),
+                            ));
+                        }
+
+                        if let Some(before) = comments_before {
+                            if let Some(reason) = Self::verify_comment_changes(
+                                from_account_id,
+                                &local_row.acl,
+                                &before,
+                                &local_row.yrs,
+                            ) {
+                                // Version could include comments of another account that haven't arrived yet.
+                                if self.can_retry_doc(ctx, doc_id, &remote_doc.author_device_id)? {
+                                    tracing::warn!(from_account_id, "{} (will retry)", reason);
+                                    return Ok(MergeResult::retry(remote_doc, reason));
+                                }
+
+                                tracing::warn!(from_account_id, "{}", reason);
+                                return Ok(MergeResult::skip(remote_doc, reason));
+                            }
                         }
 
                         let remote_edited_at = Utc.timestamp(payload.edited_at_sec, 0);
@@ -334,6 +737,49 @@ impl<C: Client> SyncDocsAtom<C> {
                     None => {
                         // Create new doc row
                         let yrs_client_id = ctx.device().yrs_client_id;
+                        let yrs_doc = documents::build_yrs_doc(yrs_client_id, &payload.data)?;
+                        let mut acl = if let Some(acl) = payload.acl {
+                            let acl_doc = documents::build_yrs_doc(yrs_client_id, &acl.data)?;
+                            acl_doc
+                        } else {
+                            AclDoc::init(yrs_client_id, &from_account_device.account_id)
+                        };
+
+                        if payload.schema == DocSchema::CardCommentsV1 as i32 {
+                            if let MergeAclResult::Unauthorized(reason) =
+                                Self::card_comments_acl(ctx, from_account_id, doc_id, &mut acl)?
+                            {
+                                // Card might not have arrived yet, so retry later.
+                                if self.can_retry_doc(ctx, doc_id, &remote_doc.author_device_id)? {
+                                    tracing::warn!(from_account_id, "{} (will retry)", reason);
+                                    return Ok(MergeResult::retry(remote_doc, reason));
+                                }
+
+                                tracing::warn!(from_account_id, "{}", reason);
+                                return Ok(MergeResult::skip(remote_doc, reason));
+                            }
+
+                            if let Some(reason) =
+                                Self::verify_comment_changes(from_account_id, &acl, &[], &yrs_doc)
+                            {
+                                // Version could include comments of another account that haven't arrived yet.
+                                if self.can_retry_doc(ctx, doc_id, &remote_doc.author_device_id)? {
+                                    tracing::warn!(from_account_id, "{} (will retry)", reason);
+                                    return Ok(MergeResult::retry(remote_doc, reason));
+                                }
+
+                                tracing::warn!(from_account_id, "{}", reason);
+                                return Ok(MergeResult::skip(remote_doc, reason));
+                            }
+                        } else if !AclDoc::from_doc(&acl).allowed_to_edit(from_account_id) {
+                            // Sender must be allowed to edit according to the ACL it sent
+                            tracing::warn!(from_account_id, "not allowed to edit new doc");
+                            return Ok(MergeResult::skip(
+                                remote_doc,
+                                format!("Account={} is not allowed to edit", from_account_id),
+                            ));
+                        }
+
                         let meta = DbDocRowMeta {
                             id: remote_doc.doc_id,
                             created_at: Utc.timestamp(remote_doc.created_at_sec, 0),
@@ -343,14 +789,6 @@ impl<C: Client> SyncDocsAtom<C> {
                             counter: remote_doc.counter,
                         };
 
-                        let yrs_doc = documents::build_yrs_doc(yrs_client_id, &payload.data)?;
-                        let acl = if let Some(acl) = payload.acl {
-                            let acl_doc = documents::build_yrs_doc(yrs_client_id, &acl.data)?;
-                            acl_doc
-                        } else {
-                            AclDoc::init(yrs_client_id, &from_account_device.account_id)
-                        };
-
                         (
                             DbDocRow {
                                 meta,
@@ -369,6 +807,25 @@ impl<C: Client> SyncDocsAtom<C> {
                     local_row.meta.schema,
                 );
 
+                // Remove local copy of a replaced profile picture
+                if let Some(old) = avatar_before {
+                    let avatar = ProfileView::read_avatar(&local_row.yrs);
+                    if avatar.map(|a| a.blob_id) != Some(old.blob_id.clone()) {
+                        blobs::remove_local(ctx.txn(), &old.blob_id)?;
+                    }
+                }
+
+                if let Some((acl_before, state_before)) = card_before {
+                    Self::notify_card_changes(
+                        ctx,
+                        acc_id,
+                        from_account_id,
+                        &local_row,
+                        acl_before,
+                        state_before,
+                    )?;
+                }
+
                 Ok(MergeResult::Merged(MergedDoc {
                     doc_id: local_row.meta.id,
                     is_new,
@@ -380,6 +837,58 @@ impl<C: Client> SyncDocsAtom<C> {
         }
     }
 
+    /// Create informational notifications about collaborator changes to a card.
+    fn notify_card_changes<'a>(
+        ctx: &(impl WithTxn<'a> + WithAccountAtom),
+        acc_id: &str,
+        from_account_id: &str,
+        row: &DbDocRow,
+        acl_before: AclDoc,
+        state_before: StateVector,
+    ) -> Result<()> {
+        let acl = AclDoc::from_doc(&row.acl);
+
+        // Collaborator allowed us to edit the card
+        if acl_before.accounts.contains_key(acc_id)
+            && !acl_before.allowed_to_edit(acc_id)
+            && acl.allowed_to_edit(acc_id)
+        {
+            let notification = AccNotification::EditorAdded {
+                doc_id: row.meta.id.clone(),
+            };
+            ctx.account()
+                .create_info_notification(ctx, &notification, from_account_id)?;
+        }
+
+        // Collaborator edited the card we shared
+        let content_changed = row.yrs.transact().state_vector() != state_before;
+        if acl_before.allowed_to_admin(acc_id) && content_changed {
+            let notification = AccNotification::CardEdited {
+                doc_id: row.meta.id.clone(),
+            };
+            ctx.account()
+                .create_info_notification(ctx, &notification, from_account_id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Let the user know that a card from a shared label was added to the timeline
+    /// without asking for a confirmation.
+    fn notify_label_card_added<'a>(
+        ctx: &(impl WithTxn<'a> + WithAccountAtom + WithEvents),
+        card_id: &str,
+        from_account_id: &str,
+    ) -> Result<()> {
+        let notification = AccNotification::LabelCardAdded {
+            doc_id: card_id.to_string(),
+        };
+        ctx.account()
+            .create_info_notification(ctx, &notification, from_account_id)?;
+        ctx.queue_event(OutputEvent::NotificationsUpdated);
+        Ok(())
+    }
+
     fn verify_doc_payload<'a>(
         ctx: &(impl WithTxn<'a> + WithAccountAtom + WithBackend),
         remote_doc: &response::DocVersion,
@@ -456,26 +965,127 @@ impl<C: Client> SyncDocsAtom<C> {
         }
     }
 
+    /// Comments docs use a copy of card ACL. Any card member (including readers) is allowed to comment.
+    fn card_comments_acl<'a>(
+        ctx: &(impl WithTxn<'a> + WithDocsAtom + WithDeviceAtom),
+        from_account_id: &str,
+        doc_id: &str,
+        acl_doc: &mut yrs::Doc,
+    ) -> Result<MergeAclResult> {
+        let card_row = match CardComments::card_id(doc_id) {
+            Some(card_id) => ctx.docs().find(ctx, card_id)?,
+            None => None,
+        };
+        let Some(card_row) = card_row else {
+            return Ok(MergeAclResult::Unauthorized(format!(
+                "Card for comments={} is missing",
+                doc_id
+            )));
+        };
+
+        if !AclDoc::from_doc(&card_row.acl).allowed_to_read(from_account_id) {
+            return Ok(MergeAclResult::Unauthorized(format!(
+                "Account={} is not a card member",
+                from_account_id
+            )));
+        }
+
+        *acl_doc = documents::build_yrs_doc(
+            ctx.device().yrs_client_id,
+            &documents::encode_yrs_doc(&card_row.acl),
+        )?;
+        Ok(MergeAclResult::Applied)
+    }
+
+    /// Comments can be edited and removed only by their authors. Card admins are also allowed
+    /// to resolve comments. New comments must be authored by card members (version could
+    /// include comments of other members that we haven't received yet).
+    /// Returns a reason if changes are not allowed.
+    fn verify_comment_changes(
+        from_account_id: &str,
+        acl_doc: &yrs::Doc,
+        before: &[CardComment],
+        doc: &yrs::Doc,
+    ) -> Option<String> {
+        let acl = AclDoc::from_doc(acl_doc);
+        let changes = CardComments::changes(before, doc);
+        if let Some(author_id) = changes.added.iter().find(|id| !acl.allowed_to_read(id)) {
+            return Some(format!(
+                "Account={} added comments of non-member={}",
+                from_account_id, author_id
+            ));
+        }
+
+        let edited_other = changes.edited.iter().any(|id| id != from_account_id);
+        let resolved_other = changes.resolved.iter().any(|id| id != from_account_id)
+            && !acl.allowed_to_admin(from_account_id);
+        if edited_other || resolved_other {
+            Some(format!(
+                "Account={} changed comments of another account",
+                from_account_id
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Merge remote ACL into local one. Only admins are allowed to change the ACL
+    /// and only writers are allowed to change the content.
     fn merge_acls(
+        yrs_client_id: yrs::block::ClientID,
         from_account_id: &str,
         acl_doc: &yrs::Doc,
         remote_acl: &Option<AclPayload>,
-    ) -> MergeAclResult {
+    ) -> Result<MergeAclResult> {
         let local_acl = AclDoc::from_doc(acl_doc);
 
+        if !local_acl.accounts.contains_key(from_account_id) {
+            return Ok(MergeAclResult::Unauthorized(format!(
+                "Account={} is not allowed to edit",
+                from_account_id
+            )));
+        }
+
         if !local_acl.allowed_to_edit(from_account_id) {
-            return MergeAclResult::Unauthorized;
+            return Ok(MergeAclResult::Unauthorized(format!(
+                "Account={} has read-only access",
+                from_account_id
+            )));
         }
 
+        let remote_acl = match remote_acl {
+            Some(acl) => acl,
+            None => return Ok(MergeAclResult::Applied),
+        };
+
         if local_acl.allowed_to_admin(from_account_id) {
-            if let Some(remote_acl) = remote_acl {
-                // Merge ACLs
-                if let Err(err) = documents::merge_yrs_docs(acl_doc, &remote_acl.data) {
-                    tracing::warn!("Failed to apply remote acl: {}", err);
-                }
+            // Merge ACLs
+            if let Err(err) = documents::merge_yrs_docs(acl_doc, &remote_acl.data) {
+                tracing::warn!("Failed to apply remote acl: {}", err);
             }
+            return Ok(MergeAclResult::Applied);
         }
-        MergeAclResult::Applied
+
+        // Non-admins are not allowed to modify the ACL. Remote ACL carries the whole ACL history,
+        // hence merge it into a copy and accept it only if the result matches the local ACL.
+        // If remote ACL has admin changes that we haven't received yet, the version is rejected
+        // and retried later.
+        let merged_acl =
+            documents::build_yrs_doc(yrs_client_id, &documents::encode_yrs_doc(acl_doc))?;
+        if let Err(err) = documents::merge_yrs_docs(&merged_acl, &remote_acl.data) {
+            return Ok(MergeAclResult::Unauthorized(format!(
+                "Account={} sent invalid ACL: {}",
+                from_account_id, err
+            )));
+        }
+
+        if AclDoc::from_doc(&merged_acl) != local_acl {
+            return Ok(MergeAclResult::Unauthorized(format!(
+                "Account={} is not allowed to change ACL",
+                from_account_id
+            )));
+        }
+        Ok(MergeAclResult::Applied)
     }
 
     async fn upload_encrypted_doc<'a>(
@@ -484,6 +1094,7 @@ impl<C: Client> SyncDocsAtom<C> {
         local_doc: DbDocRow,
         local_clock: DeviceVectorClock,
         acc: &AccView,
+        stats: &SyncStats,
     ) -> Result<DbDocRowMeta> {
         // Build a list of participants
         let mut participants: Vec<String> = {
@@ -540,7 +1151,8 @@ impl<C: Client> SyncDocsAtom<C> {
         };
 
         // Build a list of blob refences (upload new blobs)
-        let UploadBlobsResult { blob_refs, doc } = self.upload_card_blobs(ctx, local_doc).await?;
+        let UploadBlobsResult { blob_refs, doc } =
+            self.upload_card_blobs(ctx, local_doc, stats).await?;
 
         // Prepare doc payload
         let doc_payload = DocPayload {
@@ -580,6 +1192,7 @@ impl<C: Client> SyncDocsAtom<C> {
                         blob_refs,
                     },
                 )),
+                schema: Some(meta.schema),
             })
             .await?;
         Ok(meta)
@@ -610,7 +1223,16 @@ impl<C: Client> SyncDocsAtom<C> {
         &self,
         ctx: &impl SyncDocsCtx<'_, C>,
         local_row: DbDocRow,
+        stats: &SyncStats,
     ) -> Result<UploadBlobsResult> {
+        if local_row.meta.schema == DocSchema::ProfileV1 as i32 {
+            return self.upload_profile_blobs(ctx, local_row, stats).await;
+        }
+
+        if local_row.meta.schema == DocSchema::BackupV1 as i32 {
+            return self.upload_backup_blobs(ctx, local_row, stats).await;
+        }
+
         if local_row.meta.schema != DocSchema::CardV1 as i32 {
             return Ok(UploadBlobsResult {
                 doc: local_row.yrs,
@@ -690,6 +1312,67 @@ impl<C: Client> SyncDocsAtom<C> {
         // Upload blobs
         for (blob, secret) in queue {
             self.upload_blob(ctx, &blob, &secret).await?;
+            self.broadcast_progress(ctx, SyncPhase::UploadBlobs, stats);
+        }
+
+        Ok(UploadBlobsResult { doc, blob_refs })
+    }
+
+    async fn upload_profile_blobs(
+        &self,
+        ctx: &impl SyncDocsCtx<'_, C>,
+        local_row: DbDocRow,
+        stats: &SyncStats,
+    ) -> Result<UploadBlobsResult> {
+        let profile_id = local_row.meta.id.clone();
+        let (profile, doc) = ProfileView::from_db(local_row);
+
+        let mut blob_refs = vec![];
+        if let Some(avatar) = profile.avatar {
+            let blob_ref = {
+                let conn = ctx.db().conn.lock().unwrap();
+                blobs::find_by_id(&conn, &avatar.blob_id, &avatar.device_id)?
+            };
+            if let Some(blob) = blob_ref.filter(|b| !b.synced) {
+                let secret = DocSecret::new(&profile_id, &avatar.secret);
+                self.upload_blob(ctx, &blob, &secret).await?;
+                self.broadcast_progress(ctx, SyncPhase::UploadBlobs, stats);
+            }
+
+            blob_refs.push(request::BlobRefMessage {
+                id: avatar.blob_id,
+                device_id: avatar.device_id,
+            });
+        }
+
+        Ok(UploadBlobsResult { doc, blob_refs })
+    }
+
+    async fn upload_backup_blobs(
+        &self,
+        ctx: &impl SyncDocsCtx<'_, C>,
+        local_row: DbDocRow,
+        stats: &SyncStats,
+    ) -> Result<UploadBlobsResult> {
+        let manifest_id = local_row.meta.id.clone();
+        let (manifest, doc) = BackupManifest::from_db(local_row);
+
+        let mut blob_refs = vec![];
+        if let Some(archive) = manifest.archive {
+            let blob_ref = {
+                let conn = ctx.db().conn.lock().unwrap();
+                blobs::find_by_id(&conn, &archive.blob_id, &archive.device_id)?
+            };
+            if let Some(blob) = blob_ref.filter(|b| !b.synced) {
+                let secret = DocSecret::new(&manifest_id, &archive.secret);
+                self.upload_blob(ctx, &blob, &secret).await?;
+                self.broadcast_progress(ctx, SyncPhase::UploadBlobs, stats);
+            }
+
+            blob_refs.push(request::BlobRefMessage {
+                id: archive.blob_id,
+                device_id: archive.device_id,
+            });
         }
 
         Ok(UploadBlobsResult { doc, blob_refs })
@@ -708,6 +1391,30 @@ impl<C: Client> SyncDocsAtom<C> {
         Ok(())
     }
 
+    fn broadcast_progress(
+        &self,
+        ctx: &impl SyncDocsCtx<'_, C>,
+        phase: SyncPhase,
+        stats: &SyncStats,
+    ) {
+        let blobs_pending = {
+            let conn = ctx.db().conn.lock().unwrap();
+            match blobs::count_unsynced(&conn) {
+                Ok(count) => count,
+                Err(err) => {
+                    tracing::warn!("Failed to count unsynced blobs: {}", err);
+                    0
+                }
+            }
+        };
+        ctx.broadcast(OutputEvent::SyncProgress {
+            phase,
+            docs_fetched: stats.docs_fetched,
+            docs_total_estimate: stats.docs_total_estimate,
+            blobs_pending,
+        });
+    }
+
     fn process_fetched_docs<'a>(
         &self,
         ctx: &(impl WithTxn<'a>
@@ -732,6 +1439,12 @@ impl<C: Client> SyncDocsAtom<C> {
                 priority: 0,
             };
 
+            // Placeholder got its body: now the ACL is known
+            if merged.is_new {
+                ctx.timeline()
+                    .apply_postponed_acl_changes(ctx, &merged.doc_id)?;
+            }
+
             let row = ctx.docs().find(ctx, &merged.doc_id)?;
             if let Some(row) = row {
                 self.process_fetched_doc(ctx, &acc, row, &merged)?;
@@ -760,19 +1473,22 @@ impl<C: Client> SyncDocsAtom<C> {
     ) -> Result<()> {
         match DocSchema::from_i32(row.meta.schema) {
             Some(DocSchema::AccountV1) => {
-                let profile_row = ctx.docs().find(ctx, &format!("{}/profile", row.meta.id))?;
-                let view = AccView::from_db(row, profile_row).0;
+                let view = ctx.account().require_account(ctx)?;
                 ctx.queue_event(OutputEvent::AccUpdated { view });
             }
             Some(DocSchema::AccountNotificationsV1) => {
                 // Another device could have acknowledged a notification.
-                AccNotifications::iter_ids(&row.yrs, |notification_id, status| {
-                    // Remove local notification
-                    ctx.account()
-                        .delete_local_notification(ctx, &notification_id)?;
-
-                    if status != NotificationStatus::Accepted {
-                        return Ok(());
+                for acked in AccNotifications::history(&row.yrs) {
+                    let notification_id = acked.id.as_str();
+                    // Remove local notification (unless it was created after acknowledgement)
+                    ctx.account().delete_local_notification_acked(
+                        ctx,
+                        notification_id,
+                        acked.acked_at,
+                    )?;
+
+                    if acked.status != NotificationStatus::Accepted {
+                        continue;
                     }
 
                     // Add missing card to timeline
@@ -783,8 +1499,18 @@ impl<C: Client> SyncDocsAtom<C> {
                         }
                     }
 
-                    Ok(())
-                })?;
+                    // Add missing shared label cards to timeline
+                    if let Some(label_id) =
+                        notification_id.strip_prefix(AccNotification::LABEL_SHARE)
+                    {
+                        if let Some(label) = ctx.timeline().find_shared_label(ctx, label_id)? {
+                            let added = ctx.timeline().accept_shared_label_cards(ctx, &label)?;
+                            if !added.is_empty() {
+                                ctx.queue_event(OutputEvent::TimelineUpdated);
+                            }
+                        }
+                    }
+                }
 
                 ctx.queue_event(OutputEvent::NotificationsUpdated);
             }
@@ -798,6 +1524,16 @@ impl<C: Client> SyncDocsAtom<C> {
                     return Ok(());
                 }
 
+                if acc.is_blocked(&merged.from_account_id) {
+                    tracing::debug!(
+                        doc_id = row.meta.id,
+                        account_id = merged.from_account_id,
+                        "Ignoring card from blocked account"
+                    );
+                    ctx.docs().remove_external(ctx, &row.meta.id)?;
+                    return Ok(());
+                }
+
                 // External card rules:
                 // If not in notifications --> add notification, save to docs
                 // If was accepted --> save to docs, index
@@ -811,9 +1547,31 @@ impl<C: Client> SyncDocsAtom<C> {
                 let status = ctx.account().notification_status(ctx, &notification_id)?;
                 match status {
                     NotificationStatus::Missing => {
-                        let is_new = ctx
-                            .account()
-                            .create_notification_if_new(ctx, &notification)?;
+                        // Cards of accepted shared labels don't need a confirmation
+                        if let Some(label) = ctx
+                            .timeline()
+                            .find_shared_label_for_card(ctx, &row.meta.id)?
+                        {
+                            if ctx.timeline().accept_shared_label_card(
+                                ctx,
+                                &label.id,
+                                &row.meta.id,
+                            )? {
+                                Self::notify_label_card_added(
+                                    ctx,
+                                    &row.meta.id,
+                                    &merged.from_account_id,
+                                )?;
+                            }
+                            ctx.queue_event(OutputEvent::TimelineUpdated);
+                            return Ok(());
+                        }
+
+                        let is_new = ctx.account().create_notification_if_new(
+                            ctx,
+                            &notification,
+                            &merged.from_account_id,
+                        )?;
                         if is_new {
                             ctx.queue_event(OutputEvent::Notification(notification));
                         }
@@ -845,7 +1603,10 @@ impl<C: Client> SyncDocsAtom<C> {
                 // Index the card
                 // First we need to find the parent id.
                 if let Some(parent_id) = row.meta.id.split('/').next() {
-                    let card_row = ctx.docs().find(ctx, parent_id)?;
+                    let card_row = match ctx.docs().find(ctx, parent_id)? {
+                        Some(card_row) => Some(card_row),
+                        None => ctx.docs().find_placeholder(ctx, parent_id)?,
+                    };
                     if let Some(card_row) = card_row {
                         let view = CardView::from_db(card_row, Some(row)).0;
                         timeline::index_card(ctx.txn(), &view)?;
@@ -853,6 +1614,56 @@ impl<C: Client> SyncDocsAtom<C> {
                 }
                 ctx.queue_event(OutputEvent::TimelineUpdated);
             }
+            Some(DocSchema::SharedLabelV1) => {
+                let label = SharedLabelView::from_db(row);
+                if label.owner_id == acc.id {
+                    return Ok(());
+                }
+                if acc.is_blocked(&label.owner_id) {
+                    ctx.docs().remove_external(ctx, &label.id)?;
+                    return Ok(());
+                }
+
+                // Shared labels from other accounts:
+                // If not in notifications --> add notification
+                // If was accepted --> add label cards to timeline
+                // If was ignored --> ignore
+                let notification = AccNotification::LabelShare {
+                    doc_id: label.id.clone(),
+                };
+                let status = ctx.account().notification_status(ctx, &notification.id())?;
+                match status {
+                    NotificationStatus::Missing => {
+                        let is_new = ctx.account().create_notification_if_new(
+                            ctx,
+                            &notification,
+                            &label.owner_id,
+                        )?;
+                        if is_new {
+                            ctx.queue_event(OutputEvent::Notification(notification));
+                        }
+                    }
+                    NotificationStatus::Accepted => {
+                        let added = ctx.timeline().accept_shared_label_cards(ctx, &label)?;
+                        for card_id in &added {
+                            Self::notify_label_card_added(ctx, card_id, &label.owner_id)?;
+                        }
+                        if !added.is_empty() {
+                            ctx.queue_event(OutputEvent::TimelineUpdated);
+                        }
+                    }
+                    NotificationStatus::Ignored => {
+                        ctx.docs().remove_external(ctx, &label.id)?;
+                    }
+                }
+            }
+            Some(DocSchema::CardCommentsV1) => {
+                if let Some(card_id) = CardComments::card_id(&row.meta.id) {
+                    ctx.queue_event(OutputEvent::DocUpdated {
+                        doc_id: card_id.to_string(),
+                    });
+                }
+            }
             Some(DocSchema::ProfileV1) => {
                 let profile = ProfileView::from_db(row).0;
 
@@ -867,17 +1678,19 @@ impl<C: Client> SyncDocsAtom<C> {
                     .iter()
                     .find(|c| c.account_id == profile.account_id)
                     .is_some();
-                if contact_exists {
+                if contact_exists || acc.is_blocked(&profile.account_id) {
                     return Ok(());
                 }
 
                 // Create new notification
                 let notification = AccNotification::ContactRequest {
-                    account_id: profile.account_id,
+                    account_id: profile.account_id.clone(),
                 };
-                let is_new = ctx
-                    .account()
-                    .create_notification_if_new(ctx, &notification)?;
+                let is_new = ctx.account().create_notification_if_new(
+                    ctx,
+                    &notification,
+                    &profile.account_id,
+                )?;
                 if is_new {
                     ctx.queue_event(OutputEvent::Notification(notification));
                 }
@@ -911,24 +1724,47 @@ impl<C: Client> SyncDocsAtom<C> {
         ctx: &impl WithTxn<'a>,
         doc_id: &str,
         author_device_id: &str,
+        reason: &str,
     ) -> Result<()> {
         let now = Utc::now();
         let future = now + Duration::seconds(60);
         ctx.txn().execute(
             r#"
-INSERT INTO failed_docs (doc_id, author_device_id, retry_after) VALUES (?1, ?2, ?3)
+INSERT INTO failed_docs (doc_id, author_device_id, retry_after, reason) VALUES (?1, ?2, ?3, ?5)
        ON CONFLICT (doc_id, author_device_id)
-          DO UPDATE SET tries = excluded.tries + 1, retry_after = ?4"#,
-            params![doc_id, author_device_id, now, future],
+          DO UPDATE SET tries = tries + 1, retry_after = ?4, reason = ?5, skipped_at = NULL"#,
+            params![doc_id, author_device_id, now, future, reason],
         )?;
         Ok(())
     }
 
+    /// Mark this doc as skipped. Skipped docs are not retried automatically
+    /// but are kept for diagnostics.
     fn mark_doc_skipped<'a>(
         &self,
         ctx: &impl WithTxn<'a>,
         doc_id: &str,
         author_device_id: &str,
+        reason: &str,
+    ) -> Result<()> {
+        let now = Utc::now();
+        ctx.txn().execute(
+            r#"
+INSERT INTO failed_docs (doc_id, author_device_id, retry_after, reason, skipped_at)
+       VALUES (?1, ?2, ?3, ?4, ?3)
+       ON CONFLICT (doc_id, author_device_id)
+          DO UPDATE SET reason = ?4, skipped_at = ?3"#,
+            params![doc_id, author_device_id, now, reason],
+        )?;
+        Ok(())
+    }
+
+    /// Remove the doc from failed docs table.
+    fn clear_failed_doc<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        doc_id: &str,
+        author_device_id: &str,
     ) -> Result<()> {
         ctx.txn().execute(
             "DELETE FROM failed_docs WHERE doc_id = ?1 AND author_device_id = ?2",
@@ -944,7 +1780,8 @@ INSERT INTO failed_docs (doc_id, author_device_id, retry_after) VALUES (?1, ?2,
         acc_id: &str,
         merged: MergedDoc,
     ) -> Result<()> {
-        self.mark_doc_skipped(ctx, &merged.doc_id, &merged.author_device_id)?;
+        self.clear_failed_doc(ctx, &merged.doc_id, &merged.author_device_id)?;
+        self.remove_deferred(ctx, &merged.doc_id, &merged.author_device_id)?;
         ctx.txn().execute(
                 r#"
 INSERT INTO process_fetched_docs_queue (doc_id, is_new, from_account_id, priority) VALUES (?1, ?2, ?3, ?4)
@@ -963,12 +1800,79 @@ INSERT INTO process_fetched_docs_queue (doc_id, is_new, from_account_id, priorit
         Ok(())
     }
 
+    /// Queue a doc that was changed outside of sync (e.g restored from a backup)
+    /// to be processed like a fetched one.
+    pub fn queue_processing<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        acc_id: &str,
+        doc_id: &str,
+        schema: i32,
+        is_new: bool,
+    ) -> Result<()> {
+        ctx.txn().execute(
+            r#"
+INSERT INTO process_fetched_docs_queue (doc_id, is_new, from_account_id, priority) VALUES (?1, ?2, ?3, ?4)
+       ON CONFLICT (doc_id) DO NOTHING"#,
+            params![doc_id, is_new, acc_id, MergedDoc::build_priority(schema)],
+        )?;
+        Ok(())
+    }
+
+    /// List docs that failed to sync (both scheduled for retry and skipped).
+    pub fn list_failed_docs<'a>(&self, ctx: &impl WithTxn<'a>) -> Result<Vec<FailedDoc>> {
+        let mut stmt = ctx.txn().prepare(
+            r#"
+SELECT doc_id, author_device_id, reason, tries, retry_after, skipped_at
+  FROM failed_docs
+ ORDER BY retry_after"#,
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut docs = vec![];
+        while let Some(row) = rows.next()? {
+            let retry_after: DateTime<Utc> = row.get(4)?;
+            let skipped_at: Option<DateTime<Utc>> = row.get(5)?;
+            docs.push(FailedDoc {
+                doc_id: row.get(0)?,
+                author_device_id: row.get(1)?,
+                reason: row.get(2)?,
+                tries: row.get(3)?,
+                next_retry_at: if skipped_at.is_some() {
+                    None
+                } else {
+                    Some(retry_after)
+                },
+                skipped_at,
+            });
+        }
+        Ok(docs)
+    }
+
+    /// Reset retry counter of a failed doc so that it is fetched again during next sync.
+    pub fn retry_failed_doc<'a>(&self, ctx: &impl WithTxn<'a>, doc_id: &str) -> Result<()> {
+        let updated = ctx.txn().execute(
+            r#"
+UPDATE failed_docs
+   SET tries = 0, retry_after = ?1, skipped_at = NULL
+ WHERE doc_id = ?2"#,
+            params![Utc::now(), doc_id],
+        )?;
+        if updated == 0 {
+            bail!("Failed doc not found id={}", doc_id);
+        }
+        Ok(())
+    }
+
     async fn process_failed_docs(&self, ctx: &impl SyncDocsCtx<'_, C>) -> Result<()> {
         loop {
             let row: Option<(String, String)> = {
                 let conn = ctx.db().conn.lock().unwrap();
                 conn.query_row(
-                    "SELECT doc_id, author_device_id FROM failed_docs WHERE retry_after < ? LIMIT 1",
+                    r#"
+SELECT doc_id, author_device_id
+  FROM failed_docs
+ WHERE skipped_at IS NULL AND retry_after < ?
+ LIMIT 1"#,
                     [Utc::now()],
                     |row| Ok((row.get(0)?, row.get(1)?)),
                 )
@@ -986,8 +1890,9 @@ INSERT INTO process_fetched_docs_queue (doc_id, is_new, from_account_id, priorit
                         Ok(d) => d,
                         Err(err) => {
                             tracing::warn!("Failed to fetch doc version: {}", err);
+                            let reason = format!("Failed to fetch doc version: {}", err);
                             ctx.in_txn(|tx_ctx| {
-                                self.mark_for_retry(tx_ctx, &doc_id, &author_device_id)?;
+                                self.mark_for_retry(tx_ctx, &doc_id, &author_device_id, &reason)?;
                                 Ok(())
                             })?;
                             continue;
@@ -1011,7 +1916,7 @@ INSERT INTO process_fetched_docs_queue (doc_id, is_new, from_account_id, priorit
 }
 
 enum MergeAclResult {
-    Unauthorized,
+    Unauthorized(String),
     Applied,
 }
 
@@ -1030,6 +1935,25 @@ struct FetchResult {
     limit: u32,
 }
 
+/// A remote doc version that couldn't be merged.
+#[derive(Clone, Debug)]
+pub struct FailedDoc {
+    pub doc_id: String,
+    pub author_device_id: String,
+    pub reason: Option<String>,
+    pub tries: u32,
+    /// When the doc will be fetched again. Skipped docs are not retried automatically.
+    pub next_retry_at: Option<DateTime<Utc>>,
+    pub skipped_at: Option<DateTime<Utc>>,
+}
+
+/// Progress of a single sync run
+#[derive(Default)]
+struct SyncStats {
+    docs_fetched: u32,
+    docs_total_estimate: u32,
+}
+
 struct MergedDoc {
     doc_id: String,
     /// True, when no doc was present locally
@@ -1046,6 +1970,10 @@ impl MergedDoc {
             Some(DocSchema::AccountV1) => 1,
             Some(DocSchema::AccountNotificationsV1) => 2,
             Some(DocSchema::ProfileV1) => 6,
+            // Process shared labels before cards so that label cards are accepted right away
+            Some(DocSchema::SharedLabelV1) => 8,
+            // Process comments after their cards
+            Some(DocSchema::CardCommentsV1) => 12,
             _ => 10,
         }
     }
@@ -1060,26 +1988,89 @@ enum MergeResult {
     Retry {
         doc_id: String,
         author_device_id: String,
+        reason: String,
     },
     /// Doc is invalid.
     Skip {
         doc_id: String,
         author_device_id: String,
+        reason: String,
     },
 }
 
 impl MergeResult {
-    fn retry(doc: response::DocVersion) -> Self {
+    fn retry(doc: response::DocVersion, reason: String) -> Self {
         Self::Retry {
             doc_id: doc.doc_id,
             author_device_id: doc.author_device_id,
+            reason,
         }
     }
 
-    fn skip(doc: response::DocVersion) -> Self {
+    fn skip(doc: response::DocVersion, reason: String) -> Self {
         Self::Skip {
             doc_id: doc.doc_id,
             author_device_id: doc.author_device_id,
+            reason,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bolik_proto::sync::{acl_payload::AclSchema, AclPayload};
+    use chrono::Utc;
+
+    use crate::{
+        client::mock::MockClient,
+        timeline::{
+            acl_doc::{AclDoc, AclRights},
+            comments::{CardComment, CardComments},
+        },
+    };
+
+    use super::{MergeAclResult, SyncDocsAtom};
+
+    #[test]
+    fn test_merge_acls_rejects_invalid_acl_from_non_admin() {
+        let acl_doc = AclDoc::init(1, "acc-A");
+        AclDoc::add(&acl_doc, "acc-B".into(), AclRights::Write);
+        let remote_acl = Some(AclPayload {
+            data: vec![0xff],
+            schema: AclSchema::YrsV1.into(),
+        });
+
+        let result =
+            SyncDocsAtom::<MockClient>::merge_acls(2, "acc-B", &acl_doc, &remote_acl).unwrap();
+        assert!(matches!(result, MergeAclResult::Unauthorized(_)));
+    }
+
+    #[test]
+    fn test_verify_comment_changes_checks_authors_membership() {
+        let acl_doc = AclDoc::init(1, "acc-A");
+        AclDoc::add(&acl_doc, "acc-B".into(), AclRights::Read);
+        AclDoc::add(&acl_doc, "acc-C".into(), AclRights::Read);
+
+        let comment = |id: &str, author_id: &str| CardComment {
+            id: id.into(),
+            author_id: author_id.into(),
+            text: "Hello".into(),
+            created_at: Utc::now(),
+            resolved_at: None,
+        };
+
+        // Comments of other members are accepted
+        let doc = yrs::Doc::new();
+        CardComments::add(&doc, &comment("c-1", "acc-C"));
+        CardComments::add(&doc, &comment("c-2", "acc-B"));
+        let reason =
+            SyncDocsAtom::<MockClient>::verify_comment_changes("acc-B", &acl_doc, &[], &doc);
+        assert_eq!(None, reason);
+
+        // Comments of non-members are rejected
+        CardComments::add(&doc, &comment("c-3", "acc-D"));
+        let reason =
+            SyncDocsAtom::<MockClient>::verify_comment_changes("acc-B", &acl_doc, &[], &doc);
+        assert!(reason.is_some());
+    }
+}
@@ -6,6 +6,7 @@ use anyhow::Result;
 use async_stream::try_stream;
 use chrono::{DateTime, Local, Utc};
 use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
+use pulldown_cmark::{CowStr, Event, Parser, Tag};
 use tokio_stream::{Stream, StreamExt};
 
 use crate::account::AccLabel;
@@ -48,6 +49,21 @@ impl ExportAtom {
         Ok(exported)
     }
 
+    /// Export single card as HTML. Attached files are listed by name only.
+    pub async fn export_card_html<C: Client>(
+        &self,
+        ctx: &impl ExportCtx<C>,
+        card_id: &str,
+    ) -> Result<String> {
+        let card = ctx.in_txn(|tx_ctx| tx_ctx.timeline().get_card(tx_ctx, card_id))?;
+
+        // Labels are private to the account hence we don't include them
+        let acc_labels = HashMap::new();
+        let exporter = CardExporter::new(card, &acc_labels).without_files();
+        let (card_export, _attachments) = exporter.prepare(ctx).await?;
+        HtmlWriter::serialize_card(card_export)
+    }
+
     async fn build_exported_card<C: Client>(
         ctx: &impl ExportCtx<C>,
         card: CardView,
@@ -236,6 +252,8 @@ struct CardExporter<'a> {
     sections: Vec<ContentSection>,
     /// Lines of current paragraph.
     paragraph: Vec<Vec<TextSpan>>,
+    /// List files without downloading them.
+    skip_files: bool,
 }
 
 impl<'a> CardExporter<'a> {
@@ -245,9 +263,15 @@ impl<'a> CardExporter<'a> {
             acc_labels,
             sections: vec![],
             paragraph: vec![],
+            skip_files: false,
         }
     }
 
+    pub fn without_files(mut self) -> Self {
+        self.skip_files = true;
+        self
+    }
+
     pub async fn prepare<C: Client>(
         mut self,
         ctx: &impl ExportCtx<C>,
@@ -325,6 +349,12 @@ impl<'a> CardExporter<'a> {
 
                     self.push_text(t.value, styles);
                 }
+                ContentView::File(f) if self.skip_files => {
+                    self.push_file(FileItem {
+                        name: f.name.unwrap_or(f.blob_id),
+                        path: String::new(),
+                    });
+                }
                 ContentView::File(f) => {
                     // Use local path or download the blob from remote
                     let blob_path = self.download_blob(ctx, &f).await?;
@@ -529,12 +559,29 @@ impl MarkdownWriter {
         output.push_str("\n-------------------------------\n\n");
 
         // Write content sections
-        for section in card.content {
+        Self::write_sections(&mut output, card.content)?;
+
+        let file_name = format!(
+            "{} ({}).md",
+            card.metadata.created_at.format("%Y-%m-%dT%H:%M:%S"),
+            card.metadata.id.chars().take(6).collect::<String>()
+        );
+        Ok(ExportedCard {
+            id: card.metadata.id,
+            created_at: card.metadata.created_at,
+            file_name,
+            content: output,
+            files: attachments,
+        })
+    }
+
+    fn write_sections(output: &mut String, content: Vec<ContentSection>) -> Result<()> {
+        for section in content {
             tracing::trace!("Writing section: {:?}", section);
             match section {
                 ContentSection::Paragraph(lines) => {
                     for spans in lines {
-                        write_spans(&mut output, spans);
+                        write_spans(output, spans);
                         output.push_str("\n");
                     }
                 }
@@ -551,14 +598,20 @@ impl MarkdownWriter {
                         };
                         output.push_str(prefix);
                         output.push_str(" ");
-                        write_spans(&mut output, item.text);
+                        write_spans(output, item.text);
                         output.push_str("\n");
                     }
                 }
                 ContentSection::Files(files) => {
                     for file in files.files {
-                        output
-                            .write_fmt(format_args!("* [File:{}]({})\n", file.name, file.path))?;
+                        if file.path.is_empty() {
+                            output.write_fmt(format_args!("* File: {}\n", file.name))?;
+                        } else {
+                            output.write_fmt(format_args!(
+                                "* [File:{}]({})\n",
+                                file.name, file.path
+                            ))?;
+                        }
                     }
                 }
                 ContentSection::Heading(text, level) => {
@@ -569,19 +622,55 @@ impl MarkdownWriter {
 
             output.push_str("\n");
         }
+        Ok(())
+    }
+}
 
-        let file_name = format!(
-            "{} ({}).md",
-            card.metadata.created_at.format("%Y-%m-%dT%H:%M:%S"),
-            card.metadata.id.chars().take(6).collect::<String>()
-        );
-        Ok(ExportedCard {
-            id: card.metadata.id,
-            created_at: card.metadata.created_at,
-            file_name,
-            content: output,
-            files: attachments,
-        })
+struct HtmlWriter {}
+
+impl HtmlWriter {
+    fn serialize_card(card: CardExport) -> Result<String> {
+        let mut markdown = String::new();
+        MarkdownWriter::write_sections(&mut markdown, card.content)?;
+
+        let mut opts = pulldown_cmark::Options::empty();
+        opts.insert(pulldown_cmark::Options::ENABLE_TASKLISTS);
+        opts.insert(pulldown_cmark::Options::ENABLE_STRIKETHROUGH);
+        let parser = Parser::new_ext(&markdown, opts).map(|event| match event {
+            // We write only <ins> tags ourselves. Any other HTML comes from card text.
+            Event::Html(html) if html.as_ref() == "<ins>" || html.as_ref() == "</ins>" => {
+                Event::Html(html)
+            }
+            Event::Html(html) => Event::Text(html),
+            Event::Start(Tag::Link(link_type, url, title)) => {
+                Event::Start(Tag::Link(link_type, safe_url(url), title))
+            }
+            Event::End(Tag::Link(link_type, url, title)) => {
+                Event::End(Tag::Link(link_type, safe_url(url), title))
+            }
+            e => e,
+        });
+
+        let created_at = card.metadata.created_at;
+        let mut output = String::new();
+        output.write_fmt(format_args!(
+            "<time datetime=\"{}\">{}</time>\n",
+            created_at.to_rfc3339(),
+            created_at.format("%Y-%m-%d")
+        ))?;
+        pulldown_cmark::html::push_html(&mut output, parser);
+        Ok(output)
+    }
+}
+
+/// Allow only web and email links.
+fn safe_url(url: CowStr) -> CowStr {
+    let lower = url.to_lowercase();
+    if lower.starts_with("https://") || lower.starts_with("http://") || lower.starts_with("mailto:")
+    {
+        url
+    } else {
+        CowStr::Borrowed("")
     }
 }
 
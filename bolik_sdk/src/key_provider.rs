@@ -0,0 +1,268 @@
+//! Sources of the local database encryption key.
+//!
+//! SDK only needs the key when starting up. Embedding apps pick a provider that suits
+//! the platform or implement [`KeyProvider`] for hardware-backed stores (Keychain, Keystore).
+//! Note that the key stays in memory (inside the database cipher) while SDK is running.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, bail, Result};
+use argon2::Argon2;
+use rand::RngCore;
+
+use crate::{key_from_slice, secrets};
+
+/// Local database encryption key.
+pub type DbKey = chacha20poly1305::Key;
+
+/// Provide the key for encrypting local database.
+pub trait KeyProvider {
+    /// Load the key or create and persist a new one if it doesn't exist yet.
+    fn load_or_create(&self) -> Result<chacha20poly1305::Key>;
+
+    /// Replace stored key. Called when the database key is rotated.
+    fn store(&self, key: &chacha20poly1305::Key) -> Result<()>;
+}
+
+/// Store the key in a file readable only by the current user.
+pub struct FileKeyProvider {
+    path: PathBuf,
+}
+
+impl FileKeyProvider {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn load_or_create(&self) -> Result<chacha20poly1305::Key> {
+        if self.path.exists() {
+            let bytes = fs::read(&self.path)?;
+            return key_from_slice(&bytes);
+        }
+
+        let key = secrets::generate_key();
+        self.store(&key)?;
+        Ok(key)
+    }
+
+    fn store(&self, key: &chacha20poly1305::Key) -> Result<()> {
+        write_private_file(&self.path, key)
+    }
+}
+
+const SALT_SIZE: usize = 16;
+const PASSPHRASE_CHECK: &[u8] = b"bolik-db-key";
+
+/// Derive the key from user passphrase with Argon2id. Random salt and a value
+/// for detecting a wrong passphrase are kept in a file.
+pub struct PassphraseKeyProvider {
+    passphrase: String,
+    salt_path: PathBuf,
+}
+
+impl PassphraseKeyProvider {
+    pub fn new(passphrase: impl Into<String>, salt_path: impl Into<PathBuf>) -> Self {
+        Self {
+            passphrase: passphrase.into(),
+            salt_path: salt_path.into(),
+        }
+    }
+
+    fn derive(&self, salt: &[u8]) -> Result<chacha20poly1305::Key> {
+        let mut key = chacha20poly1305::Key::default();
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, &mut key)
+            .map_err(|err| anyhow!("Derive key: {}", err))?;
+        Ok(key)
+    }
+}
+
+impl KeyProvider for PassphraseKeyProvider {
+    fn load_or_create(&self) -> Result<chacha20poly1305::Key> {
+        if self.salt_path.exists() {
+            let bytes = fs::read(&self.salt_path)?;
+            if bytes.len() <= SALT_SIZE {
+                bail!("Salt file is corrupted");
+            }
+            let (salt, check) = bytes.split_at(SALT_SIZE);
+            let key = self.derive(salt)?;
+            match secrets::DbCipher::new(&key).decrypt(check) {
+                Ok(value) if value == PASSPHRASE_CHECK => Ok(key),
+                _ => Err(anyhow!("Wrong passphrase")),
+            }
+        } else {
+            let mut salt = [0; SALT_SIZE];
+            rand::thread_rng().fill_bytes(&mut salt);
+            let key = self.derive(&salt)?;
+
+            let mut content = salt.to_vec();
+            content.extend(secrets::DbCipher::new(&key).encrypt(PASSPHRASE_CHECK)?);
+            write_private_file(&self.salt_path, &content)?;
+            Ok(key)
+        }
+    }
+
+    fn store(&self, _key: &chacha20poly1305::Key) -> Result<()> {
+        bail!("Key derived from a passphrase cannot be replaced")
+    }
+}
+
+/// Linux keyring backends.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug)]
+pub enum KeyringBackend {
+    /// Secret Service over D-Bus (GNOME Keyring, KWallet). Keys survive reboots.
+    SecretService,
+    /// Kernel keyring (keyutils). Keys are lost on reboot, use only when the device
+    /// is restored from the server or a backup anyway.
+    KernelKeyring,
+}
+
+/// Store the key in Linux keyring.
+#[cfg(target_os = "linux")]
+pub struct KeyringKeyProvider {
+    backend: KeyringBackend,
+    service: String,
+    user: String,
+}
+
+#[cfg(target_os = "linux")]
+impl KeyringKeyProvider {
+    pub fn new(
+        backend: KeyringBackend,
+        service: impl Into<String>,
+        user: impl Into<String>,
+    ) -> Self {
+        Self {
+            backend,
+            service: service.into(),
+            user: user.into(),
+        }
+    }
+
+    fn entry(&self) -> Result<keyring::Entry> {
+        let credential: Box<keyring::credential::Credential> = match self.backend {
+            KeyringBackend::SecretService => {
+                Box::new(keyring::secret_service::SsCredential::new_with_target(
+                    None,
+                    &self.service,
+                    &self.user,
+                )?)
+            }
+            KeyringBackend::KernelKeyring => {
+                Box::new(keyring::keyutils::KeyutilsCredential::new_with_target(
+                    None,
+                    &self.service,
+                    &self.user,
+                )?)
+            }
+        };
+        Ok(keyring::Entry::new_with_credential(credential))
+    }
+
+    /// Check if the key was stored already.
+    pub fn exists(&self) -> Result<bool> {
+        match self.entry()?.get_password() {
+            Ok(_) => Ok(true),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl KeyProvider for KeyringKeyProvider {
+    fn load_or_create(&self) -> Result<chacha20poly1305::Key> {
+        let entry = self.entry()?;
+        match entry.get_password() {
+            Ok(encoded) => key_from_slice(&secrets::key_from_id(&encoded)?),
+            Err(keyring::Error::NoEntry) => {
+                let key = secrets::generate_key();
+                self.store(&key)?;
+                Ok(key)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn store(&self, key: &chacha20poly1305::Key) -> Result<()> {
+        self.entry()?.set_password(&secrets::id_from_key(key))?;
+        Ok(())
+    }
+}
+
+/// Write file atomically and restrict its permissions to the current user.
+fn write_private_file(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileKeyProvider, KeyProvider, PassphraseKeyProvider};
+
+    #[test]
+    fn test_file_key_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = FileKeyProvider::new(dir.path().join("db.key"));
+        let key = provider.load_or_create().unwrap();
+        assert_eq!(provider.load_or_create().unwrap(), key);
+
+        let other = FileKeyProvider::new(dir.path().join("other.key"));
+        assert_ne!(other.load_or_create().unwrap(), key);
+    }
+
+    #[test]
+    fn test_passphrase_key_provider() {
+        let dir = tempfile::tempdir().unwrap();
+        let salt_path = dir.path().join("db.salt");
+        let key = PassphraseKeyProvider::new("correct horse", &salt_path)
+            .load_or_create()
+            .unwrap();
+        let same = PassphraseKeyProvider::new("correct horse", &salt_path)
+            .load_or_create()
+            .unwrap();
+        assert_eq!(key, same);
+
+        let wrong = PassphraseKeyProvider::new("battery staple", &salt_path).load_or_create();
+        assert!(wrong.is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "requires kernel keyring (unavailable in most sandboxes and containers)"]
+    fn test_keyring_key_provider() {
+        use super::{KeyringBackend, KeyringKeyProvider};
+
+        let service = format!("bolik-test-{}", uuid::Uuid::new_v4());
+        let provider = KeyringKeyProvider::new(KeyringBackend::KernelKeyring, &service, "db");
+        assert!(!provider.exists().unwrap());
+        let key = provider.load_or_create().unwrap();
+        assert!(provider.exists().unwrap());
+        assert_eq!(provider.load_or_create().unwrap(), key);
+
+        let other = KeyringKeyProvider::new(KeyringBackend::KernelKeyring, &service, "other");
+        assert_ne!(other.load_or_create().unwrap(), key);
+
+        provider.entry().unwrap().delete_password().unwrap();
+        other.entry().unwrap().delete_password().unwrap();
+    }
+}
@@ -6,14 +6,17 @@ use std::{
 };
 
 use anyhow::{anyhow, Result};
+use bolik_migrations::rusqlite::Connection;
 use chacha20poly1305::{ChaCha20Poly1305, KeySizeUser};
 use client::{Client, ClientConfig};
+use key_provider::KeyProvider;
 pub use sdk::Sdk;
 pub use tokio::runtime::Handle;
 use tokio::runtime::Runtime;
 
 pub mod account;
 mod background;
+mod backup;
 mod blobs;
 pub mod client;
 mod db;
@@ -22,8 +25,10 @@ mod documents;
 mod export;
 mod import;
 mod input;
+pub mod key_provider;
 mod mailbox;
 pub mod output;
+mod public_links;
 mod registry;
 mod sdk;
 mod secret_group;
@@ -31,9 +36,14 @@ mod secrets;
 mod signature_chain;
 pub mod timeline;
 
-pub use documents::BIN_LABEL_ID;
+pub use backup::RestoreBackupResult;
+pub use blobs::PendingBlob;
+pub use documents::{FailedDoc, PendingDoc, BIN_LABEL_ID};
 pub use import::ImportResult;
-pub use sdk::{CreateAccLabelResult, DownloadResult, MoveToBinScope};
+pub use public_links::PublicLink;
+pub use sdk::{
+    CreateAccLabelResult, DownloadResult, MoveToBinScope, PendingOutbox, SyncDiagnostics,
+};
 pub use secret_group::SecretGroupStatus;
 
 use crate::client::HttpClient;
@@ -52,13 +62,15 @@ pub fn start_runtime() -> Result<Runtime> {
 }
 
 /// Run this SDK. Tokio Runtime should be started prior to calling this function.
+/// Database encryption key is read from the key provider.
 pub async fn run(
     app_support_dir: impl Into<PathBuf>,
     files_dir: impl Into<PathBuf>,
     device_name: impl Into<String>,
-    db_encryption_key: chacha20poly1305::Key,
+    key_provider: &dyn KeyProvider,
 ) -> Result<Sdk<HttpClient>> {
-    run_with(
+    let db_encryption_key = key_provider.load_or_create()?;
+    let sdk = run_with(
         app_support_dir,
         files_dir,
         device_name,
@@ -66,7 +78,11 @@ pub async fn run(
         Duration::from_millis(100),
         ClientConfig::default().with_host(DEFAULT_HOST),
     )
-    .await
+    .await?;
+
+    // Finish key rotation that was interrupted before the new key was stored
+    sdk.finish_db_key_rotation(key_provider)?;
+    Ok(sdk)
 }
 
 pub async fn run_with<C>(
@@ -106,6 +122,36 @@ fn get_db_path(app_support_dir: &Path) -> String {
     format!("file:{}", db_file.display())
 }
 
+/// Re-encrypt database with a new key before starting the SDK. Does nothing if the
+/// database can't be opened with the old key (e.g. it was re-encrypted already).
+/// Progress is reported as (done, total) cells. Returns how many cells were re-encrypted.
+pub fn reencrypt_db(
+    app_support_dir: impl Into<PathBuf>,
+    old_key: &chacha20poly1305::Key,
+    new_key: &chacha20poly1305::Key,
+    on_progress: impl FnMut(u32, u32),
+) -> Result<u32> {
+    let db_path = get_db_path(&app_support_dir.into());
+    let mut conn = Connection::open(db_path)?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    db::migrations::apply(&conn)?;
+
+    let cipher = secrets::DbCipher::new(old_key);
+    if !db::key_opens(&conn, &cipher)? {
+        return Ok(0);
+    }
+
+    let txn = conn.transaction()?;
+    let rotated =
+        db::reencrypt_cells(&txn, &cipher, &secrets::DbCipher::new(new_key), on_progress)?;
+    // Key check is created again with the new key on startup
+    txn.execute("DELETE FROM db_key_check", [])?;
+    txn.commit()?;
+    db::purge_stale_pages(&conn)?;
+    tracing::info!(cells = rotated, "Re-encrypted database");
+    Ok(rotated)
+}
+
 pub fn generate_db_key() -> chacha20poly1305::Key {
     secrets::generate_key()
 }
@@ -143,12 +189,16 @@ mod tests {
     use crate::client::mock::{MockClient, MockServerArc};
     use crate::client::ClientConfig;
     use crate::documents::build_yrs_doc;
+    use crate::key_provider::{FileKeyProvider, KeyProvider};
     use crate::output::OutputEvent;
+    use crate::registry::WithDeviceAtom;
     use crate::timeline::acl_doc::{AclDoc, AclRights};
     use crate::timeline::card::{
         CardBlock, CardChange, CardLabelsChange, CardText, CardTextAttrs, CardView, ContentView,
     };
-    use crate::{blobs, run_with, timeline, CreateAccLabelResult, Sdk, BIN_LABEL_ID};
+    use crate::timeline::comments::CardComments;
+    use crate::timeline::PermanentDeleteOpts;
+    use crate::{blobs, public_links, run_with, timeline, CreateAccLabelResult, Sdk, BIN_LABEL_ID};
     use crate::{secrets, MoveToBinScope};
 
     static LOGGER_INIT: Once = Once::new();
@@ -195,8 +245,13 @@ mod tests {
     async fn recv_output(
         rx: &mut tokio::sync::broadcast::Receiver<OutputEvent>,
     ) -> Result<OutputEvent> {
-        let event = timeout(rx.recv()).await??;
-        Ok(event)
+        loop {
+            let event = timeout(rx.recv()).await??;
+            // Progress events are not deterministic, skip them
+            if !matches!(event, OutputEvent::SyncProgress { .. }) {
+                return Ok(event);
+            }
+        }
     }
 
     struct RunConfig {
@@ -273,6 +328,15 @@ mod tests {
             }
         }
 
+        /// Skip other events until sync completes.
+        async fn wait_synced(&mut self) -> Result<()> {
+            loop {
+                if let OutputEvent::Synced = self.output().await? {
+                    return Ok(());
+                }
+            }
+        }
+
         async fn expect_sync_failed(&mut self) -> Result<()> {
             let event = self.output().await?;
             if let OutputEvent::SyncFailed = event {
@@ -515,7 +579,7 @@ mod tests {
         let days = timeline::timeline_days(&db, vec![]).unwrap();
         assert_eq!(days.len(), 1);
 
-        let timeline_day = timeline::timeline_by_day(&db, &days[0], vec![]).unwrap();
+        let timeline_day = d.timeline_by_day(&days[0], vec![]).unwrap();
         assert_eq!(timeline_day.cards.len(), 1);
         assert_eq!(timeline_day.cards[0].id, card.id);
 
@@ -1003,6 +1067,8 @@ mod tests {
             .add_contact(AccContact {
                 name: "John".into(),
                 account_id: acc_b.id.clone(),
+                bio: String::new(),
+                avatar: None,
             })
             .await
             .unwrap();
@@ -1068,6 +1134,166 @@ mod tests {
         assert_eq!(Some(&AclRights::Read), acl_b.accounts.get(&acc_b.id));
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sdk_reject_foreign_comment_changes() {
+        setup_tracing();
+
+        let mut d_a = {
+            let conf = RunConfig::new().with_name("A");
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            d.create_sample_account().await.unwrap();
+            d
+        };
+
+        let (mut d_b, acc_b) = {
+            let conf = RunConfig::new()
+                .with_name("B")
+                .with_server(d_a.client.conf.mock_server.clone());
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            let acc_b = d.create_sample_account().await.unwrap();
+            (d, acc_b)
+        };
+
+        // Share a card and comment on it
+        let card = d_a.create_sample_card().unwrap();
+        d_a.add_contact(AccContact {
+            name: "John".into(),
+            account_id: acc_b.id.clone(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+        d_a.wait_synced().await.unwrap();
+        d_a.edit_collaborators(
+            &card.id,
+            HashMap::from([(acc_b.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+        d_a.wait_synced().await.unwrap();
+        let comment = d_a.add_comment(&card.id, "Hello".into()).unwrap();
+        d_a.wait_synced().await.unwrap();
+
+        d_b.sync();
+        d_b.wait_synced().await.unwrap();
+        assert_eq!(1, d_b.list_comments(&card.id).unwrap().len());
+
+        // Remove comment of another account bypassing the checks
+        d_b.registry
+            .in_txn(|ctx, r| {
+                let mut row = r
+                    .docs
+                    .find(ctx, &CardComments::doc_id(&card.id))?
+                    .ok_or(anyhow!("Comments not found"))?;
+                CardComments::remove(&row.yrs, &comment.id);
+                row.meta.author_device_id = ctx.device().id.clone();
+                row.meta.counter = ctx.device().increment_clock(ctx)?;
+                r.docs.save(ctx, &row)
+            })
+            .unwrap();
+        d_b.sync();
+        d_b.wait_synced().await.unwrap();
+
+        // Version is rejected
+        d_a.sync();
+        d_a.wait_synced().await.unwrap();
+        assert_eq!(vec![comment], d_a.list_comments(&card.id).unwrap());
+        let failed = d_a.sync_diagnostics().unwrap().failed_docs;
+        assert_eq!(1, failed.len());
+        assert_eq!(CardComments::doc_id(&card.id), failed[0].doc_id);
+        assert_eq!(
+            Some(format!(
+                "Account={} changed comments of another account",
+                acc_b.id
+            )),
+            failed[0].reason
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sdk_accept_comments_of_other_members() {
+        setup_tracing();
+
+        let mut d_a = {
+            let conf = RunConfig::new().with_name("A");
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            d.create_sample_account().await.unwrap();
+            d
+        };
+
+        let (mut d_b, acc_b) = {
+            let conf = RunConfig::new()
+                .with_name("B")
+                .with_server(d_a.client.conf.mock_server.clone());
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            let acc_b = d.create_sample_account().await.unwrap();
+            (d, acc_b)
+        };
+
+        let (mut d_c, acc_c) = {
+            let conf = RunConfig::new()
+                .with_name("C")
+                .with_server(d_a.client.conf.mock_server.clone());
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            let acc_c = d.create_sample_account().await.unwrap();
+            (d, acc_c)
+        };
+
+        // Share a card with both accounts
+        let card = d_a.create_sample_card().unwrap();
+        for (name, acc) in [("John", &acc_b), ("Jane", &acc_c)] {
+            d_a.add_contact(AccContact {
+                name: name.into(),
+                account_id: acc.id.clone(),
+                bio: String::new(),
+                avatar: None,
+            })
+            .await
+            .unwrap();
+            d_a.wait_synced().await.unwrap();
+        }
+        d_a.edit_collaborators(
+            &card.id,
+            HashMap::from([
+                (acc_b.id.clone(), Some(AclRights::Read)),
+                (acc_c.id.clone(), Some(AclRights::Read)),
+            ]),
+        )
+        .unwrap();
+        d_a.wait_synced().await.unwrap();
+
+        // C comments
+        d_c.sync();
+        d_c.wait_synced().await.unwrap();
+        d_c.add_comment(&card.id, "From C".into()).unwrap();
+        d_c.wait_synced().await.unwrap();
+
+        // B receives the comment and comments too
+        d_b.sync();
+        d_b.wait_synced().await.unwrap();
+        assert_eq!(1, d_b.list_comments(&card.id).unwrap().len());
+        d_b.add_comment(&card.id, "From B".into()).unwrap();
+        d_b.wait_synced().await.unwrap();
+
+        // A receives B's version before C's version
+        let device_c_id = d_c
+            .registry
+            .in_txn(|ctx, _r| Ok(ctx.device().id.clone()))
+            .unwrap();
+        d_a.client.remove_doc_versions(&device_c_id);
+        d_a.sync();
+        d_a.wait_synced().await.unwrap();
+
+        let authors: HashSet<_> = d_a
+            .list_comments(&card.id)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.author_id)
+            .collect();
+        assert_eq!(HashSet::from([acc_b.id.clone(), acc_c.id.clone()]), authors);
+        assert!(d_a.sync_diagnostics().unwrap().failed_docs.is_empty());
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_client_filter_labels() {
         setup_tracing();
@@ -1100,7 +1326,7 @@ mod tests {
         let db = Connection::open(&d.db_path).unwrap();
         let days = timeline::timeline_days(&db, vec![]).unwrap();
         assert_eq!(days.len(), 1);
-        let timeline_day = timeline::timeline_by_day(&db, &days[0], vec![]).unwrap();
+        let timeline_day = d.timeline_by_day(&days[0], vec![]).unwrap();
         assert_eq!(timeline_day.cards.len(), 2);
         assert_eq!(timeline_day.cards[0].id, card_2.id);
         assert_eq!(timeline_day.cards[1].id, card_1.id);
@@ -1108,8 +1334,7 @@ mod tests {
         // Verify timeline (label filter)
         let days = timeline::timeline_days(&db, vec![label.id.clone()]).unwrap();
         assert_eq!(days.len(), 1);
-        let timeline_day =
-            timeline::timeline_by_day(&db, &days[0], vec![label.id.clone()]).unwrap();
+        let timeline_day = d.timeline_by_day(&days[0], vec![label.id.clone()]).unwrap();
         assert_eq!(timeline_day.cards.len(), 1);
         assert_eq!(timeline_day.cards[0].id, card_1.id);
         assert_eq!(timeline_day.cards[0].labels.len(), 1);
@@ -1268,6 +1493,214 @@ New paragraph with <https://bolik.tech> **bold** _italic_ <ins>underline</ins> ~
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_client_publish_card() {
+        setup_tracing();
+        let (mut d, _c) = run_test_device().await.unwrap();
+        let _acc = d.create_sample_account().await.unwrap();
+
+        let card = d.create_card().unwrap();
+        let mut changes = vec![CardChange::append_text(
+            "Pancakes <script>alert(1)</script>\n",
+        )];
+        changes.extend(CardChange::append_task("Flour", true));
+        let card = d.edit_card(&card.id, changes).unwrap();
+
+        // Publish
+        let link = d.publish_card(&card.id).await.unwrap();
+        assert_eq!(link.card_id, card.id);
+        let (page_url, fragment) = link.url.split_once('#').unwrap();
+        assert_eq!(page_url, format!("http://mock.local/p/{}", link.id));
+
+        let published = d.client.published_links();
+        let html = public_links::decrypt_snapshot(fragment, &published[&link.id]).unwrap();
+        assert!(html.contains("Pancakes &lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!html.contains("<script>"));
+        assert!(html.contains("checkbox"));
+        assert!(html.contains("Flour"));
+
+        // Publishing again keeps the link
+        let link_2 = d.publish_card(&card.id).await.unwrap();
+        assert_eq!(link_2.url, link.url);
+        assert_eq!(d.list_public_links().unwrap().len(), 1);
+
+        // Unpublish
+        d.unpublish_card(&card.id).await.unwrap();
+        assert!(d.client.published_links().is_empty());
+        assert!(d.list_public_links().unwrap().is_empty());
+
+        // Moving to bin unpublishes the card
+        d.publish_card(&card.id).await.unwrap();
+        d.move_card_to_bin(&card.id, MoveToBinScope::ThisAccount)
+            .unwrap();
+        d.expect_synced().await.unwrap();
+        assert!(d.client.published_links().is_empty());
+        assert!(d.list_public_links().unwrap().is_empty());
+        assert!(d.publish_card(&card.id).await.is_err());
+
+        // Permanently deleting unpublishes the card
+        let card_2 = d.create_card().unwrap();
+        let card_2 = d
+            .edit_card(&card_2.id, vec![CardChange::append_text("Waffles")])
+            .unwrap();
+        d.publish_card(&card_2.id).await.unwrap();
+        d.registry
+            .in_txn(|ctx, r| {
+                r.timeline
+                    .permanently_delete(ctx, &card_2.id, PermanentDeleteOpts::default())
+            })
+            .unwrap();
+        d.sync();
+        d.expect_synced().await.unwrap();
+        assert!(d.client.published_links().is_empty());
+        assert!(d.list_public_links().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_sdk_publish_card_requires_admin() {
+        setup_tracing();
+
+        let mut d_a = {
+            let conf = RunConfig::new().with_name("A");
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            d.create_sample_account().await.unwrap();
+            d
+        };
+
+        let (mut d_b, acc_b) = {
+            let conf = RunConfig::new()
+                .with_name("B")
+                .with_server(d_a.client.conf.mock_server.clone());
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            let acc_b = d.create_sample_account().await.unwrap();
+            (d, acc_b)
+        };
+
+        // Share a card as read-only
+        let card = d_a.create_sample_card().unwrap();
+        d_a.add_contact(AccContact {
+            name: "John".into(),
+            account_id: acc_b.id.clone(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+        d_a.wait_synced().await.unwrap();
+        d_a.edit_collaborators(
+            &card.id,
+            HashMap::from([(acc_b.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+        d_a.wait_synced().await.unwrap();
+
+        d_b.sync();
+        d_b.wait_synced().await.unwrap();
+        assert!(d_b.get_card(&card.id).is_ok());
+
+        // Collaborator cannot publish the card
+        let res = d_b.publish_card(&card.id).await;
+        assert_eq!(
+            "Only card admin can publish the card",
+            res.unwrap_err().to_string()
+        );
+        assert!(d_b.client.published_links().is_empty());
+        assert!(d_b.list_public_links().unwrap().is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_client_reencrypt_db() {
+        setup_tracing();
+        let mut conf = RunConfig::new();
+        let new_key = secrets::generate_key();
+
+        let card_id = {
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            let _acc = d.create_sample_account().await.unwrap();
+            let card = d.create_card().unwrap();
+            d.edit_card(&card.id, vec![CardChange::append_text("Secret")])
+                .unwrap();
+            card.id
+        };
+
+        let mut progress = vec![];
+        let rotated = reencrypt_db(
+            &conf.app_support_dir,
+            &conf.db_key,
+            &new_key,
+            |done, total| progress.push((done, total)),
+        )
+        .unwrap();
+        assert!(rotated > 0);
+        assert_eq!(progress.first(), Some(&(0, rotated)));
+        assert_eq!(progress.last(), Some(&(rotated, rotated)));
+        // Database is already encrypted with the new key
+        assert_eq!(
+            reencrypt_db(&conf.app_support_dir, &conf.db_key, &new_key, |_, _| {}).unwrap(),
+            0
+        );
+
+        // Old key is not accepted even though key check was reset
+        assert!(run_test_device_with(&conf).await.is_err());
+
+        conf.db_key = new_key;
+        let d = run_test_device_with(&conf).await.unwrap();
+        assert!(d.get_account().is_some());
+        assert!(d.get_card(&card_id).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_client_rotate_db_key() {
+        setup_tracing();
+        let mut conf = RunConfig::new();
+        let key_dir = tempfile::tempdir().unwrap();
+        let key_provider = FileKeyProvider::new(key_dir.path().join("db.key"));
+        key_provider.store(&conf.db_key).unwrap();
+
+        let card_id = {
+            let mut d = run_test_device_with(&conf).await.unwrap();
+            let _acc = d.create_sample_account().await.unwrap();
+            let card = d.create_card().unwrap();
+            d.edit_card(&card.id, vec![CardChange::append_text("Secret")])
+                .unwrap();
+            d.publish_card(&card.id).await.unwrap();
+
+            // New key is stored and rotation is confirmed right away
+            let rotated = d.rotate_db_key(&key_provider).unwrap();
+            assert!(rotated > 0);
+            assert_eq!(d.pending_db_key(), None);
+            card.id
+        };
+
+        // Previous key no longer works
+        assert!(run_test_device_with(&conf).await.is_err());
+        conf.db_key = key_provider.load_or_create().unwrap();
+
+        {
+            // Rotation is interrupted before the new key is stored
+            let d = run_test_device_with(&conf).await.unwrap();
+            let next_key = secrets::generate_key();
+            d.reencrypt_with_key(next_key).unwrap();
+            drop(d);
+
+            // Previous key opens the database and the new key is stored on the next start
+            let d = run_test_device_with(&conf).await.unwrap();
+            assert_eq!(d.pending_db_key(), Some(next_key));
+            d.finish_db_key_rotation(&key_provider).unwrap();
+            assert_eq!(d.pending_db_key(), None);
+            assert_eq!(key_provider.load_or_create().unwrap(), next_key);
+        }
+
+        // Previous key no longer works
+        assert!(run_test_device_with(&conf).await.is_err());
+
+        conf.db_key = key_provider.load_or_create().unwrap();
+        let d = run_test_device_with(&conf).await.unwrap();
+        assert!(d.get_account().is_some());
+        assert!(d.get_card(&card_id).is_ok());
+        assert_eq!(d.list_public_links().unwrap().len(), 1);
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
     async fn test_client_export_data() {
         setup_tracing();
@@ -1444,7 +1877,7 @@ New paragraph with <https://bolik.tech> **bold** _italic_ <ins>underline</ins> ~
         assert_eq!(days.len(), 1);
         assert_eq!(days[0], "2022-10-07");
 
-        let timeline_day = timeline::timeline_by_day(&db, &days[0], vec![]).unwrap();
+        let timeline_day = d.timeline_by_day(&days[0], vec![]).unwrap();
         let card = &timeline_day.cards[0];
         assert_eq!(card.id, "45941d0a-7836-443b-a430-a9518eca56b9");
 
@@ -1515,8 +1948,9 @@ Share this card with Sam"#
 
         // Should be able to find by label
         let testing_label = acc.labels.iter().find(|l| l.name == "Testing").unwrap();
-        let timeline_day =
-            timeline::timeline_by_day(&db, &days[0], vec![testing_label.id.clone()]).unwrap();
+        let timeline_day = d
+            .timeline_by_day(&days[0], vec![testing_label.id.clone()])
+            .unwrap();
         assert_eq!(timeline_day.cards.len(), 1);
         assert_eq!(
             timeline_day.cards[0].id,
@@ -1547,7 +1981,7 @@ Share this card with Sam"#
         assert_eq!(days.len(), 1);
         assert_eq!(days[0], "2022-12-26");
 
-        let timeline_day = timeline::timeline_by_day(&db, &days[0], vec![]).unwrap();
+        let timeline_day = d.timeline_by_day(&days[0], vec![]).unwrap();
         let card = &timeline_day.cards[0];
         assert_eq!(card.id, "9aa6b40a-d8c8-4bf0-8b36-a93436b14487");
 
@@ -1715,8 +2149,9 @@ Share this card with Sam"#
 
         // Should be able to find by label
         let testing_label = acc.labels.iter().find(|l| l.name == "Bolik").unwrap();
-        let timeline_day =
-            timeline::timeline_by_day(&db, &days[0], vec![testing_label.id.clone()]).unwrap();
+        let timeline_day = d
+            .timeline_by_day(&days[0], vec![testing_label.id.clone()])
+            .unwrap();
         assert_eq!(timeline_day.cards.len(), 1);
         assert_eq!(
             timeline_day.cards[0].id,
@@ -1790,16 +2225,13 @@ Share this card with Sam"#
             (d, conf, acc)
         };
 
-        let db_a = Connection::open(&sdk_a.db_path).unwrap();
-        let db_b = Connection::open(&sdk_b.db_path).unwrap();
-        let db_c = Connection::open(&sdk_c.db_path).unwrap();
-        let db_d = Connection::open(&sdk_d.db_path).unwrap();
-
         // Add account 2 and 3 as contacts
         let _acc_1 = sdk_a
             .add_contact(AccContact {
                 account_id: acc_2.id.clone(),
                 name: "".into(),
+                bio: String::new(),
+                avatar: None,
             })
             .await
             .unwrap();
@@ -1808,6 +2240,8 @@ Share this card with Sam"#
             .add_contact(AccContact {
                 account_id: acc_3.id.clone(),
                 name: "".into(),
+                bio: String::new(),
+                avatar: None,
             })
             .await
             .unwrap();
@@ -1825,23 +2259,24 @@ Share this card with Sam"#
         let _ = sdk_d.expect_notification().await.unwrap(); // Contact request notification
         sdk_d.expect_synced().await.unwrap();
 
-        fn query_profile_acl(conn: &Connection, profile_id: &str) -> Result<AclDoc> {
+        fn query_profile_acl(d: &TestDevice, profile_id: &str) -> Result<AclDoc> {
+            let conn = Connection::open(&d.db_path)?;
             let acl_data: Vec<u8> = conn.query_row(
                 "SELECT acl_data FROM documents WHERE id = ?",
                 [&profile_id],
                 |row| row.get(0),
             )?;
-            let doc = build_yrs_doc(1, &acl_data)?;
+            let doc = build_yrs_doc(d.registry.device.yrs_client_id, &acl_data)?;
             let acl_doc = AclDoc::from_doc(&doc);
             Ok(acl_doc)
         }
 
         let profile_id = format!("{}/profile", acc_1.id);
 
-        let acl_a = query_profile_acl(&db_a, &profile_id).unwrap();
-        let acl_b = query_profile_acl(&db_b, &profile_id).unwrap();
-        let acl_c = query_profile_acl(&db_c, &profile_id).unwrap();
-        let acl_d = query_profile_acl(&db_d, &profile_id).unwrap();
+        let acl_a = query_profile_acl(&sdk_a, &profile_id).unwrap();
+        let acl_b = query_profile_acl(&sdk_b, &profile_id).unwrap();
+        let acl_c = query_profile_acl(&sdk_c, &profile_id).unwrap();
+        let acl_d = query_profile_acl(&sdk_d, &profile_id).unwrap();
 
         // Verify that profile doc ACL is the same on all devices and includes only admin account.
         let expected_accounts = HashMap::from([(acc_1.id.clone(), AclRights::Admin)]);
@@ -26,6 +26,26 @@ pub fn queue_mls_commit<'a>(
     queue_mailbox(ctx, request::push_mailbox::Value::Commit(message))
 }
 
+/// Count messages in push mailbox queue and their total size in bytes
+pub fn count_queued<'a>(ctx: &impl WithTxn<'a>) -> Result<(u32, u64)> {
+    let res = ctx.txn().query_row(
+        "SELECT COUNT(*), COALESCE(SUM(LENGTH(message)), 0) FROM push_mailbox_queue",
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    Ok(res)
+}
+
+/// Count acknowledgements that haven't been sent yet
+pub fn count_queued_acks<'a>(ctx: &impl WithTxn<'a>) -> Result<u32> {
+    let count = ctx
+        .txn()
+        .query_row("SELECT COUNT(*) FROM ack_mailbox_queue", [], |row| {
+            row.get(0)
+        })?;
+    Ok(count)
+}
+
 /// Add mailbox message to push mailbox queue
 pub fn queue_mailbox<'a>(
     ctx: &impl WithTxn<'a>,
@@ -1,8 +1,9 @@
 use crate::{
     account::AccView,
+    blobs,
     client::Client,
-    documents::DocSecretRow,
-    output::OutputEvent,
+    documents::{DocSecretRow, SECRET_ROTATION_PERIOD},
+    output::{OutputEvent, SyncPhase},
     registry::{
         WithAccountAtom, WithBackend, WithBackendConn, WithBroadcast, WithDb, WithDeviceAtom,
         WithDocsAtom, WithEvents, WithInTxn, WithSecretGroupAtom, WithTxn,
@@ -13,7 +14,7 @@ use anyhow::{bail, Context, Result};
 use bolik_chain::DeviceRemovedOp;
 use bolik_migrations::rusqlite::{params, OptionalExtension};
 use bolik_proto::sync::{app_message, request, response, AppMessage, KeyPackageMessage};
-use chrono::{Days, TimeZone, Utc};
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use openmls::prelude::{KeyPackageRef, Sender};
 use openmls_traits::OpenMlsCryptoProvider;
 use prost::Message;
@@ -54,6 +55,17 @@ where
     pub async fn sync(&self, ctx: &impl MailboxCtx<'_, C>) -> Result<()> {
         let mut events = SyncEvents::default();
 
+        let blobs_pending = {
+            let conn = ctx.db().conn.lock().unwrap();
+            blobs::count_unsynced(&conn).unwrap_or_default()
+        };
+        ctx.broadcast(OutputEvent::SyncProgress {
+            phase: SyncPhase::Mailbox,
+            docs_fetched: 0,
+            docs_total_estimate: 0,
+            blobs_pending,
+        });
+
         self.push_key_packages(ctx)
             .await
             .context("Push KeyPackages")?;
@@ -62,6 +74,16 @@ where
             .await
             .context("Fetch mailbox")?;
 
+        let check_membership = ctx
+            .in_txn(|tx_ctx| self.membership_check_due(tx_ctx, &events))
+            .unwrap_or(true);
+        if !events.logged_out && check_membership {
+            match self.removed_from_account(ctx).await {
+                Ok(removed) => events.logged_out = removed,
+                Err(err) => tracing::warn!("Failed to check account membership: {:?}", err),
+            }
+        }
+
         if events.logged_out {
             tracing::info!("Logging out (removed by another device)!");
             let conn = ctx.db().conn.lock().unwrap();
@@ -82,11 +104,57 @@ where
             ctx.broadcast(OutputEvent::AccUpdated { view });
         }
 
+        for account_id in events.unverified_contacts {
+            ctx.broadcast(OutputEvent::VerifiedContactChanged { account_id });
+        }
+
         self.push_mailbox(ctx).await.context("Push mailbox")?;
 
         Ok(())
     }
 
+    /// Detect removal from the account even if the removal commit never reached this device.
+    async fn removed_from_account(&self, ctx: &impl MailboxCtx<'_, C>) -> Result<bool> {
+        let account_id = ctx.in_txn(|tx_ctx| Ok(tx_ctx.account().get_account_id(tx_ctx)))?;
+        let Some(account_id) = account_id else {
+            return Ok(false);
+        };
+
+        let devices = self.client.get_account_devices(&account_id).await?;
+        ctx.in_txn(|tx_ctx| {
+            tx_ctx.txn().execute(
+                "UPDATE device_settings SET membership_checked_at = ?",
+                params![Utc::now()],
+            )?;
+            match devices.chain {
+                Some(chain) => tx_ctx.account().removed_in_chain(tx_ctx, chain),
+                None => Ok(false),
+            }
+        })
+    }
+
+    /// Account membership is checked on the server when the mailbox brought changes to account
+    /// devices. Otherwise it is checked periodically in case the removal commit never arrived.
+    fn membership_check_due<'a>(
+        &self,
+        ctx: &impl WithTxn<'a>,
+        events: &SyncEvents,
+    ) -> Result<bool> {
+        if events.account_devices_changed || !events.remove_members.is_empty() {
+            return Ok(true);
+        }
+
+        let checked_at: Option<DateTime<Utc>> = ctx.txn().query_row(
+            "SELECT membership_checked_at FROM device_settings",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(match checked_at {
+            Some(at) => Utc::now() - at >= membership_check_interval(),
+            None => true,
+        })
+    }
+
     /// Go through key packages
     async fn push_key_packages(&self, ctx: &impl MailboxCtx<'_, C>) -> Result<()> {
         loop {
@@ -279,7 +347,7 @@ INSERT INTO ack_mailbox_queue (message_id, error) VALUES (?, ?)
                                     .earliest()
                                     .unwrap_or_else(|| Utc::now());
                                 let obsolete_at = created_at
-                                    .checked_add_days(Days::new(30))
+                                    .checked_add_days(SECRET_ROTATION_PERIOD)
                                     .unwrap_or(created_at);
 
                                 tx_ctx.docs().save_secret(
@@ -332,6 +400,25 @@ INSERT INTO ack_mailbox_queue (message_id, error) VALUES (?, ?)
                             self.rotate_doc_secrets(tx_ctx, &group)?;
                         }
 
+                        if (stats.added > 0 || stats.removed > 0)
+                            && group.chain.account_ids().is_empty()
+                        {
+                            events.account_devices_changed = true;
+                        }
+
+                        if (stats.added > 0 || stats.removed > 0)
+                            && !group.chain.account_ids().is_empty()
+                        {
+                            let changed = tx_ctx
+                                .account()
+                                .check_verified_contacts(tx_ctx, &group.chain)?;
+                            if !changed.is_empty() {
+                                let acc = tx_ctx.account().require_account(tx_ctx)?;
+                                events.updated_acc = Some(acc);
+                                events.unverified_contacts.extend(changed);
+                            }
+                        }
+
                         for message in messages_out {
                             super::queue_mls_commit(tx_ctx, message)?;
                         }
@@ -451,7 +538,8 @@ INSERT INTO ack_mailbox_queue (message_id, error) VALUES (?, ?)
                 match ctx.account().get_account_id(ctx) {
                     Some(account_id) if account_id == group.id() => {
                         let acc = ctx.account().edit_account(ctx, |doc| {
-                            AccView::remove_device(doc, &device_id);
+                            // Device asked to be removed
+                            AccView::remove_device(doc, &device_id, &device_id, None);
                             Ok(())
                         })?;
                         events.updated_acc = Some(acc);
@@ -507,11 +595,19 @@ impl<'a> std::fmt::Display for DisplayPushMailbox<'a> {
     }
 }
 
+/// How often to check account membership on the server when nothing else hints at a removal.
+fn membership_check_interval() -> Duration {
+    Duration::hours(1)
+}
+
 #[derive(Default)]
 struct SyncEvents {
     updated_acc: Option<AccView>,
     logged_out: bool,
+    /// Devices were added to or removed from this account group
+    account_devices_changed: bool,
     remove_members: Vec<MemberRef>,
+    unverified_contacts: Vec<String>,
 }
 
 struct MemberRef {
@@ -4,6 +4,13 @@ use crate::account::{AccNotification, AccView};
 pub enum OutputEvent {
     Synced,
     SyncFailed,
+    SyncProgress {
+        phase: SyncPhase,
+        docs_fetched: u32,
+        /// Server doesn't report the total, so this is a lower bound that grows while fetching.
+        docs_total_estimate: u32,
+        blobs_pending: u32,
+    },
     TimelineUpdated,
     DeviceAdded {
         device_name: String,
@@ -27,5 +34,23 @@ pub enum OutputEvent {
     },
     Notification(AccNotification),
     NotificationsUpdated,
+    /// Verified contact has added or removed devices. Contact is no longer verified.
+    VerifiedContactChanged {
+        account_id: String,
+    },
+    /// Database key rotation progress (re-encrypted cells).
+    DbKeyRotationProgress {
+        done: u32,
+        total: u32,
+    },
+    /// Device is no longer connected to the account and local data was cleared.
     LogOut,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncPhase {
+    Mailbox,
+    FetchDocs,
+    UploadBlobs,
+    ProcessDocs,
+}
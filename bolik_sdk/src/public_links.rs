@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use bolik_migrations::rusqlite::{params, Row};
+use chacha20poly1305::aead::OsRng;
+use chrono::{DateTime, Utc};
+use openmls::prelude::OpenMlsCrypto;
+use openmls_rust_crypto::RustCrypto;
+use openmls_traits::types::AeadType;
+use rand::RngCore;
+use uuid::Uuid;
+
+use crate::registry::WithTxn;
+
+// Snapshots are decrypted in the browser hence we use AES-GCM which is available in WebCrypto.
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
+
+/// A read-only snapshot of a card that is accessible to anyone with the link.
+#[derive(Clone, Debug)]
+pub struct PublicLink {
+    pub id: String,
+    pub card_id: String,
+    /// Full link including the decryption key
+    pub url: String,
+    pub published_at: DateTime<Utc>,
+}
+
+pub(crate) struct PublicLinkRow {
+    pub id: String,
+    pub card_id: String,
+    pub key: Vec<u8>,
+    pub published_at: DateTime<Utc>,
+}
+
+impl PublicLinkRow {
+    pub fn new(card_id: &str) -> Self {
+        let mut key = vec![0; KEY_SIZE];
+        OsRng.fill_bytes(&mut key);
+        Self {
+            id: Uuid::new_v4().to_string(),
+            card_id: card_id.to_string(),
+            key,
+            published_at: Utc::now(),
+        }
+    }
+
+    /// Key is passed in URL fragment so that it never reaches the server.
+    pub fn url_fragment(&self) -> String {
+        self.key.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// Encrypt snapshot HTML and return ciphertext with nonce prefix.
+pub(crate) fn encrypt_snapshot(key: &[u8], html: &str) -> Result<Vec<u8>> {
+    let mut nonce = [0; NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = RustCrypto::default()
+        .aead_encrypt(AeadType::Aes256Gcm, key, html.as_bytes(), &nonce, &[])
+        .map_err(|err| anyhow!("Encrypt snapshot: {:?}", err))?;
+
+    let mut payload = Vec::with_capacity(nonce.len() + ciphertext.len());
+    payload.extend(nonce);
+    payload.extend(ciphertext);
+    Ok(payload)
+}
+
+/// Decrypt snapshot with a key from URL fragment (the same way public link page does).
+#[cfg(test)]
+pub(crate) fn decrypt_snapshot(url_fragment: &str, payload: &[u8]) -> Result<String> {
+    if url_fragment.len() != KEY_SIZE * 2 || payload.len() <= NONCE_SIZE {
+        anyhow::bail!("Invalid public link");
+    }
+
+    let key = (0..url_fragment.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&url_fragment[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()?;
+    let (nonce, ciphertext) = payload.split_at(NONCE_SIZE);
+    let plaintext = RustCrypto::default()
+        .aead_decrypt(AeadType::Aes256Gcm, &key, ciphertext, nonce, &[])
+        .map_err(|err| anyhow!("Decrypt snapshot: {:?}", err))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+fn read_row(ctx: &impl WithTxn<'_>, row: &Row) -> Result<PublicLinkRow> {
+    let encrypted_key: Vec<u8> = row.get(2)?;
+    Ok(PublicLinkRow {
+        id: row.get(0)?,
+        card_id: row.get(1)?,
+        key: ctx.db_cipher().decrypt(&encrypted_key)?,
+        published_at: row.get(3)?,
+    })
+}
+
+pub(crate) fn find_by_card_id<'a>(
+    ctx: &impl WithTxn<'a>,
+    card_id: &str,
+) -> Result<Option<PublicLinkRow>> {
+    let mut stmt = ctx.txn().prepare(
+        "SELECT id, card_id, encrypted_key, published_at FROM public_links WHERE card_id = ?",
+    )?;
+    let mut rows = stmt.query([card_id])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(read_row(ctx, row)?)),
+        None => Ok(None),
+    }
+}
+
+pub(crate) fn list<'a>(ctx: &impl WithTxn<'a>) -> Result<Vec<PublicLinkRow>> {
+    let mut stmt = ctx.txn().prepare(
+        r#"
+SELECT id, card_id, encrypted_key, published_at
+  FROM public_links
+ ORDER BY published_at DESC"#,
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut links = vec![];
+    while let Some(row) = rows.next()? {
+        links.push(read_row(ctx, row)?);
+    }
+    Ok(links)
+}
+
+pub(crate) fn save<'a>(ctx: &impl WithTxn<'a>, link: &PublicLinkRow) -> Result<()> {
+    let encrypted_key = ctx.db_cipher().encrypt(&link.key)?;
+    ctx.txn().execute(
+        r#"
+INSERT INTO public_links (id, card_id, encrypted_key, published_at) VALUES (?1, ?2, ?3, ?4)
+  ON CONFLICT (id) DO UPDATE
+     SET published_at = excluded.published_at"#,
+        params![link.id, link.card_id, encrypted_key, link.published_at],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn delete<'a>(ctx: &impl WithTxn<'a>, link_id: &str) -> Result<()> {
+    ctx.txn()
+        .execute("DELETE FROM public_links WHERE id = ?", [link_id])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_encryption() {
+        let link = PublicLinkRow::new("card-1");
+        let payload = encrypt_snapshot(&link.key, "<p>Hello</p>").unwrap();
+        assert!(payload.len() > NONCE_SIZE);
+
+        let html = decrypt_snapshot(&link.url_fragment(), &payload).unwrap();
+        assert_eq!(html, "<p>Hello</p>");
+
+        let other = PublicLinkRow::new("card-1");
+        assert!(decrypt_snapshot(&other.url_fragment(), &payload).is_err());
+    }
+}
@@ -51,7 +51,7 @@ where
     C: Client,
 {
     pub fn new(db: Db, device: DeviceAtom, client: C) -> Self {
-        let (broadcaster, _) = tokio::sync::broadcast::channel(20);
+        let (broadcaster, _) = tokio::sync::broadcast::channel(64);
         let docs = DocsAtom::new(&device);
         let sync_docs = SyncDocsAtom::new(client.clone());
         let account = AccountAtom::new();
@@ -8,37 +8,51 @@ use std::{
 use anyhow::{anyhow, bail, Context, Result};
 use bolik_migrations::rusqlite::Connection;
 use bolik_proto::sync::doc_payload::DocSchema;
-use chrono::Utc;
+use chrono::{DateTime, TimeZone, Utc};
+use openmls_rust_crypto::RustCrypto;
 use tokio_stream::Stream;
 use tracing::instrument;
 
 use crate::{
-    account::{AccContact, AccLabel, AccView, ProfileView},
+    account::{
+        AccContact, AccLabel, AccView, ChainEvent, ContactFingerprint, ContactInvite,
+        DeviceSession, InboxNotification, ProfileAvatar, ProfileView, RecoveryKey, RecoveryKit,
+        RECOVERY_DEVICE_NAME,
+    },
     background::{BackgroundInput, BackgroundTask},
-    blobs::{self, SaveFileParams},
+    backup::{self, BackupManifest},
+    blobs::{self, PendingBlob, SaveFileParams},
     client::{Client, ClientConfig},
-    db::{migrations, Db},
+    db::{self, migrations, Db},
     device::{get_device_id, DeviceAtom, DeviceShare},
+    documents::{FailedDoc, PendingDoc},
     export::ExportedCard,
+    key_provider::KeyProvider,
+    mailbox,
     output::OutputEvent,
-    registry::{Registry, SetupTxnCtx, WithTxn},
-    secrets::DbCipher,
+    public_links::{self, PublicLink, PublicLinkRow},
+    registry::{Registry, SetupTxnCtx, WithBackend, WithDeviceAtom, WithTxn},
+    secrets::{self, DbCipher, SqliteCryptoProvider},
     timeline::{
         self,
         acl_doc::{AclChange, AclRights},
         card::{CardChange, CardFile, CardLabelsChange, CardView, CleanupResult},
+        comments::CardComment,
+        shared_label::SharedLabelView,
         TimelineDay,
     },
-    SecretGroupStatus, BIN_LABEL_ID, import::ImportResult,
+    RestoreBackupResult, SecretGroupStatus, BIN_LABEL_ID, import::ImportResult,
 };
 
 pub struct Sdk<C: Clone> {
-    #[allow(unused)]
     pub(crate) db_path: String,
     debug_name: String,
     pub(crate) registry: Registry<C>,
     background_tx: tokio::sync::mpsc::Sender<BackgroundInput>,
+    client_conf: ClientConfig,
     pub client: C,
+    /// Database key while key rotation is not confirmed
+    pending_db_key: Mutex<Option<chacha20poly1305::Key>>,
 }
 
 impl<C> Sdk<C>
@@ -62,6 +76,7 @@ where
         migrations::apply(&conn)?;
 
         let db_cipher = DbCipher::new(&db_encryption_key);
+        let pending_db_key = db::check_key(&conn, &db_cipher, &db_encryption_key)?;
         let conn = Arc::new(Mutex::new(conn));
         let db = Db {
             conn: conn.clone(),
@@ -76,7 +91,11 @@ where
             (device, bundle)
         };
         let debug_name = device.name.chars().take(3).collect();
-        let client = C::new(client_conf, device.id.clone(), cred_bundle.into_parts().1)?;
+        let client = C::new(
+            client_conf.clone(),
+            device.id.clone(),
+            cred_bundle.into_parts().1,
+        )?;
         let registry = Registry::new(db, device, client.clone());
 
         Ok(Self {
@@ -84,12 +103,18 @@ where
             debug_name,
             registry,
             background_tx,
+            client_conf,
             client,
+            pending_db_key: Mutex::new(pending_db_key),
         })
     }
 
     pub(crate) fn bg_task(&self) -> BackgroundTask<C> {
-        BackgroundTask::new(self.registry.clone(), self.debug_name.clone())
+        BackgroundTask::new(
+            self.registry.clone(),
+            self.client.clone(),
+            self.debug_name.clone(),
+        )
     }
 
     pub fn broadcast_subscribe(&self) -> tokio::sync::broadcast::Receiver<OutputEvent> {
@@ -121,8 +146,9 @@ where
     }
 
     pub fn timeline_by_day(&self, day: &str, label_ids: Vec<String>) -> Result<TimelineDay> {
-        self.registry
-            .in_txn(|ctx, _| timeline::timeline_by_day(ctx.txn(), day, label_ids))
+        self.registry.in_txn(|ctx, _| {
+            timeline::timeline_by_day(ctx.txn(), ctx.device().yrs_client_id, day, label_ids)
+        })
     }
 
     pub fn sync(&self) {
@@ -162,15 +188,270 @@ where
         Ok(other_device_name)
     }
 
+    /// Add a recovery member to the account and return its mnemonic. The mnemonic
+    /// is the only way to use the recovery member hence it must be shown to the user.
+    ///
+    /// Unlike other account setup this is not done in [`Sdk::create_account`]: the encrypted
+    /// recovery kit has to be uploaded to the server while accounts can be created offline.
+    /// Apps should offer creating the key right after the account is connected.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub async fn create_recovery_key(&self) -> Result<String> {
+        self.registry
+            .in_txn(|ctx, r| r.account.require_account_id(ctx))?;
+
+        let key = RecoveryKey::generate();
+        let kit = RecoveryKit::generate()?;
+        let payload = key.encrypt_kit(&kit)?;
+        self.client
+            .upload_recovery_kit(&key.kit_id()?, payload)
+            .await?;
+
+        self.registry.in_txn(|ctx, r| {
+            r.account.add_recovery_member(ctx, &kit)?;
+
+            // Backup key is derived from the mnemonic hence it rotates together with the key
+            if backup::find_settings(ctx)?.is_some() {
+                backup::enable(ctx, &key.backup_key()?)?;
+            }
+            Ok(())
+        })?;
+        self.sync();
+        key.to_mnemonic()
+    }
+
+    /// Connect this device to the account of the recovery key.
+    ///
+    /// Recovery member is temporarily set up in a separate database where it joins the account
+    /// and links this device. Once this device is connected the used recovery member is removed
+    /// from the account and a new recovery key is issued. Returns the mnemonic of the new key.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub async fn recover_account(&self, mnemonic: &str) -> Result<String> {
+        if self.get_account().is_some() {
+            bail!("Device is already connected to an account");
+        }
+
+        let key = RecoveryKey::from_mnemonic(mnemonic)?;
+        let payload = self.client.fetch_recovery_kit(&key.kit_id()?).await?;
+        let kit = key.decrypt_kit(&payload)?;
+
+        // Upload key packages before the recovery member links this device
+        let share = self.registry.in_txn(|ctx, r| r.account.get_share(ctx))?;
+        self.registry
+            .mailbox
+            .sync(&self.registry.db_ctx())
+            .await
+            .context("Upload key packages")?;
+
+        // Recovery database holds decrypted MLS keys hence it is kept next to the app database
+        let data_dir = Path::new(self.db_path.trim_start_matches("file:"))
+            .parent()
+            .ok_or(anyhow!("Unknown app data directory"))?;
+        let recovery_dir = data_dir.join(format!("recovery-{}", uuid::Uuid::new_v4()));
+        let res = self.join_from_recovery(&recovery_dir, kit, &share).await;
+        if let Err(err) = std::fs::remove_dir_all(&recovery_dir) {
+            tracing::warn!("Failed to remove recovery directory: {}", err);
+        }
+        res?;
+
+        self.create_recovery_key().await
+    }
+
+    /// Join the account of the recovery member and remove the used member.
+    async fn join_from_recovery(&self, dir: &Path, kit: RecoveryKit, share: &str) -> Result<()> {
+        let recovery = self.link_from_recovery(dir, kit, share).await?;
+
+        // Join the account and replace used recovery member
+        let ctx = self.registry.db_ctx();
+        self.registry.mailbox.sync(&ctx).await?;
+        self.registry.sync_docs.sync(&ctx).await?;
+        self.registry.in_txn(|ctx, r| {
+            r.account.remove_device(
+                ctx,
+                &recovery.registry.device.id,
+                Some("Recovery key was used"),
+            )
+        })?;
+        self.registry.mailbox.sync(&ctx).await?;
+
+        // Recovery database is about to be deleted. Ack what is left in the mailbox
+        // of the removed member so that messages don't pile up on the server.
+        if let Err(err) = recovery
+            .registry
+            .mailbox
+            .sync(&recovery.registry.db_ctx())
+            .await
+        {
+            tracing::warn!("Failed to clear recovery member mailbox: {}", err);
+        }
+        Ok(())
+    }
+
+    /// Link this device from the recovery member. Returns the recovery member.
+    async fn link_from_recovery(
+        &self,
+        dir: &Path,
+        kit: RecoveryKit,
+        share: &str,
+    ) -> Result<Sdk<C>> {
+        std::fs::create_dir_all(dir).context("Ensure recovery directory")?;
+        let db_path = format!("file:{}", dir.join("recovery.db").display());
+        let db_key = secrets::generate_key();
+        {
+            let mut conn = Connection::open(&db_path)?;
+            migrations::apply(&conn)?;
+            let db_cipher = DbCipher::new(&db_key);
+            let crypto = RustCrypto::default();
+            let txn = conn.transaction()?;
+            kit.restore(&SqliteCryptoProvider::new(&db_cipher, &txn, &crypto))?;
+            txn.commit()?;
+        }
+
+        // Recovery member is never run in the background hence we sync it manually.
+        let (background_tx, _) = tokio::sync::mpsc::channel(1);
+        let recovery: Sdk<C> = Sdk::new(
+            &db_path,
+            dir.join("files"),
+            RECOVERY_DEVICE_NAME,
+            db_key,
+            background_tx,
+            self.client_conf.clone(),
+        )?;
+        // Register recovery member credential on the server
+        recovery
+            .registry
+            .in_txn(|ctx, r| r.device.generate_key_packages(ctx, 1))?;
+
+        // Recovery member needs only the account doc to link this device
+        let ctx = recovery.registry.db_ctx();
+        recovery.registry.mailbox.sync(&ctx).await?;
+        recovery.registry.sync_docs.sync_account_doc(&ctx).await?;
+        if recovery.get_account().is_none() {
+            bail!("Recovery key is no longer a member of the account");
+        }
+
+        recovery.link_device(share).await?;
+        recovery.registry.mailbox.sync(&ctx).await?;
+        recovery.registry.sync_docs.sync_account_doc(&ctx).await?;
+        Ok(recovery)
+    }
+
+    /// Turn on daily encrypted backups. Backup key is derived from the recovery mnemonic
+    /// so that backups could be restored together with the account.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn enable_backups(&self, mnemonic: &str) -> Result<()> {
+        let key = RecoveryKey::from_mnemonic(mnemonic)?;
+        self.registry.in_txn(|ctx, r| {
+            r.account.require_account_id(ctx)?;
+            backup::enable(ctx, &key.backup_key()?)
+        })
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn disable_backups(&self) -> Result<()> {
+        self.registry.in_txn(|ctx, _r| backup::disable(ctx))
+    }
+
+    /// Create a backup right away instead of waiting for the scheduled one.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn backup_now(&self) -> Result<()> {
+        self.registry.in_txn(|ctx, r| {
+            let acc_id = r.account.require_account_id(ctx)?;
+            let settings = backup::find_settings(ctx)?.ok_or(anyhow!("Backups are disabled"))?;
+            backup::create_backup(ctx, &acc_id, &settings.key)
+        })?;
+        self.sync();
+        Ok(())
+    }
+
+    /// Download the latest backup of the account and merge it into local data.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub async fn restore_backup(&self, mnemonic: &str) -> Result<RestoreBackupResult> {
+        let key = RecoveryKey::from_mnemonic(mnemonic)?;
+        let acc_id = self
+            .registry
+            .in_txn(|ctx, r| r.account.require_account_id(ctx))?;
+        let manifest_id = BackupManifest::doc_id(&acc_id);
+
+        let find_manifest = || {
+            self.registry.in_txn(|ctx, r| {
+                let row = r.docs.find(ctx, &manifest_id)?;
+                Ok(row.map(|row| BackupManifest::from_db(row).0))
+            })
+        };
+        let manifest = match find_manifest()? {
+            Some(manifest) => manifest,
+            None => {
+                // Manifest might not have been fetched yet
+                let ctx = self.registry.db_ctx();
+                self.registry.mailbox.sync(&ctx).await?;
+                self.registry.sync_docs.sync(&ctx).await?;
+                find_manifest()?.ok_or(anyhow!("Backup not found"))?
+            }
+        };
+        let archive = manifest.archive.ok_or(anyhow!("Backup not found"))?;
+
+        let path = self
+            .registry
+            .blobs
+            .download_backup(&self.registry.db_ctx(), &manifest_id, &archive)
+            .await?;
+        let payload = std::fs::read(&path)?;
+        let snapshot = backup::decrypt_snapshot(&key.backup_key()?, &payload)?;
+
+        let result = self.registry.in_txn(|ctx, r| {
+            let (result, restored) = backup::restore_snapshot(ctx, &acc_id, snapshot)?;
+            for doc in restored {
+                r.sync_docs
+                    .queue_processing(ctx, &acc_id, &doc.doc_id, doc.schema, doc.is_new)?;
+            }
+            Ok(result)
+        })?;
+        self.sync();
+        Ok(result)
+    }
+
+    /// Remove a device from the account. Reason is visible on the other account devices.
     #[instrument(skip_all, fields(d = self.debug_name, remove_id))]
-    pub fn remove_device(&self, remove_id: &str) -> Result<AccView> {
+    pub fn remove_device(&self, remove_id: &str, reason: Option<&str>) -> Result<AccView> {
         let acc = self
             .registry
-            .in_txn(|ctx, r| r.account.remove_device(ctx, remove_id))?;
+            .in_txn(|ctx, r| r.account.remove_device(ctx, remove_id, reason))?;
         self.sync();
         Ok(acc)
     }
 
+    /// List account devices together with the time they were last seen by the server.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub async fn list_devices(&self) -> Result<Vec<DeviceSession>> {
+        let acc = self
+            .registry
+            .in_txn(|ctx, r| r.account.require_account(ctx))?;
+        let res = self.client.get_device_sessions(&acc.id).await?;
+
+        let mut devices: Vec<_> = acc
+            .devices
+            .into_iter()
+            .map(|device| {
+                let session = res.sessions.iter().find(|s| s.device_id == device.id);
+                DeviceSession {
+                    last_seen_at: session
+                        .and_then(|s| Utc.timestamp_opt(s.last_seen_at_sec, 0).earliest()),
+                    platform: session.and_then(|s| s.platform.clone()),
+                    app_version: session.and_then(|s| s.app_version.clone()),
+                    is_current: device.id == self.registry.device.id,
+                    device,
+                }
+            })
+            .collect();
+        devices.sort_by(|a, b| b.last_seen_at.cmp(&a.last_seen_at));
+        Ok(devices)
+    }
+
+    /// List every change of account devices together with chain verification results.
+    pub fn account_chain_history(&self) -> Result<Vec<ChainEvent>> {
+        self.registry.in_txn(|ctx, r| r.account.chain_history(ctx))
+    }
+
     pub fn create_card(&self) -> Result<CardView> {
         self.registry.in_txn(|ctx, r| {
             let acc_id = r.account.require_account_id(ctx)?;
@@ -195,7 +476,22 @@ where
     }
 
     pub fn get_card(&self, id: &str) -> Result<CardView> {
-        self.registry.in_txn(|ctx, r| r.timeline.get_card(ctx, id))
+        let (card, has_deferred) = self.registry.in_txn(|ctx, r| {
+            let card = r.timeline.get_card(ctx, id)?;
+            let has_deferred = r.sync_docs.has_deferred(ctx, id)?;
+            Ok((card, has_deferred))
+        })?;
+
+        if has_deferred {
+            // Card body hasn't been downloaded yet
+            tracing::debug!(card_id = id, "Schedule deferred card download");
+            let tx = self.background_tx.clone();
+            let doc_id = card.id.clone();
+            tokio::spawn(async move {
+                let _ = tx.send(BackgroundInput::FetchDeferredDoc { doc_id }).await;
+            });
+        }
+        Ok(card)
     }
 
     pub fn save_file(&self, card_id: &str, path: impl AsRef<Path>) -> Result<CardFile> {
@@ -290,6 +586,40 @@ where
         Ok(())
     }
 
+    pub fn list_comments(&self, card_id: &str) -> Result<Vec<CardComment>> {
+        self.registry
+            .in_txn(|ctx, r| r.timeline.list_comments(ctx, card_id))
+    }
+
+    /// Comment on a card. Any card member can comment, including readers.
+    #[instrument(skip_all, fields(d = self.debug_name, card_id))]
+    pub fn add_comment(&self, card_id: &str, text: String) -> Result<CardComment> {
+        let comment = self
+            .registry
+            .in_txn(|ctx, r| r.timeline.add_comment(ctx, card_id, text))?;
+        self.sync();
+        Ok(comment)
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name, card_id, comment_id))]
+    pub fn resolve_comment(&self, card_id: &str, comment_id: &str, resolved: bool) -> Result<()> {
+        self.registry.in_txn(|ctx, r| {
+            r.timeline
+                .resolve_comment(ctx, card_id, comment_id, resolved)
+        })?;
+        self.sync();
+        Ok(())
+    }
+
+    /// Delete own comment.
+    #[instrument(skip_all, fields(d = self.debug_name, card_id, comment_id))]
+    pub fn delete_comment(&self, card_id: &str, comment_id: &str) -> Result<()> {
+        self.registry
+            .in_txn(|ctx, r| r.timeline.delete_comment(ctx, card_id, comment_id))?;
+        self.sync();
+        Ok(())
+    }
+
     #[instrument(skip_all, fields(d = self.debug_name))]
     pub fn account_group(&self) -> Result<SecretGroupStatus> {
         self.registry.in_txn(|ctx, r| {
@@ -316,6 +646,99 @@ where
         })
     }
 
+    /// Update leaf keys of this device in all secret groups right away. Background task
+    /// does this periodically. Returns how many groups were updated.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn rotate_group_keys(&self) -> Result<u32> {
+        let updated = self.registry.in_txn(|ctx, r| {
+            r.account.require_account_id(ctx)?;
+            r.secret_group.update_own_leaves(ctx, None)
+        })?;
+        self.sync();
+        Ok(updated)
+    }
+
+    /// Re-create contact groups with a stronger ciphersuite when every member device
+    /// supports it. Returns how many groups were upgraded.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub async fn upgrade_group_ciphersuites(&self) -> Result<u32> {
+        let acc_id = self
+            .registry
+            .in_txn(|ctx, r| r.account.require_account_id(ctx))?;
+        let upgraded = self
+            .registry
+            .secret_group
+            .upgrade_contact_groups(&self.registry.db_ctx(), &acc_id)
+            .await?;
+        if upgraded > 0 {
+            self.sync();
+        }
+        Ok(upgraded)
+    }
+
+    /// Re-encrypt the local database with a new key and store the key with the provider.
+    /// All encrypted cells are updated in one transaction and progress is reported via
+    /// [OutputEvent::DbKeyRotationProgress]. Returns how many cells were re-encrypted.
+    ///
+    /// Between the commit and storing the new key the database holds the new key encrypted
+    /// with the previous one. If the app is interrupted in this window [crate::run] stores
+    /// the new key and confirms the rotation on the next start.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn rotate_db_key(&self, key_provider: &dyn KeyProvider) -> Result<u32> {
+        // Fail before re-encrypting anything if the provider cannot replace the key
+        key_provider.store(&key_provider.load_or_create()?)?;
+
+        let new_key = secrets::generate_key();
+        let rotated = self.reencrypt_with_key(new_key)?;
+        self.finish_db_key_rotation(key_provider)?;
+        Ok(rotated)
+    }
+
+    /// Re-encrypt the database with a new key. The key is pending until stored and confirmed.
+    pub(crate) fn reencrypt_with_key(&self, new_key: chacha20poly1305::Key) -> Result<u32> {
+        let db_cipher = &self.registry.db.db_cipher;
+        let mut conn = self.registry.db.conn.lock().unwrap();
+        let txn = conn.transaction()?;
+        let rotated = db::rotate_key(&txn, db_cipher, &new_key, |done, total| {
+            let _ = self
+                .registry
+                .broadcast
+                .send(OutputEvent::DbKeyRotationProgress { done, total });
+        })?;
+        txn.commit()?;
+
+        // Still holding the connection so nobody reads with the old key
+        db_cipher.replace_key(&new_key);
+        *self.pending_db_key.lock().unwrap() = Some(new_key);
+        tracing::info!(cells = rotated, "Rotated database key");
+        Ok(rotated)
+    }
+
+    /// Key the database is encrypted with if key rotation is not confirmed yet.
+    pub(crate) fn pending_db_key(&self) -> Option<chacha20poly1305::Key> {
+        *self.pending_db_key.lock().unwrap()
+    }
+
+    /// Store the pending database key (if any) and forget the previous key.
+    pub(crate) fn finish_db_key_rotation(&self, key_provider: &dyn KeyProvider) -> Result<()> {
+        if let Some(key) = self.pending_db_key() {
+            key_provider.store(&key)?;
+            self.confirm_db_key_rotation()?;
+        }
+        Ok(())
+    }
+
+    /// Forget the previous database key. Call this once the new key is stored.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    fn confirm_db_key_rotation(&self) -> Result<()> {
+        let conn = self.registry.db.conn.lock().unwrap();
+        if db::confirm_key_rotation(&conn)? {
+            tracing::info!("Confirmed database key rotation");
+        }
+        self.pending_db_key.lock().unwrap().take();
+        Ok(())
+    }
+
     pub fn move_card_to_bin(&self, card_id: &str, scope: MoveToBinScope) -> Result<()> {
         if let Err(_) = self.get_card(card_id) {
             // Card is not found --> no op
@@ -375,6 +798,125 @@ where
         Ok(view)
     }
 
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn edit_bio(&self, bio: String) -> Result<AccView> {
+        let view = self.registry.in_txn(|ctx, r| {
+            r.account.edit_profile(ctx, |doc| {
+                ProfileView::set_bio(doc, bio);
+                Ok(())
+            })
+        })?;
+        self.sync();
+        Ok(view)
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn edit_status(&self, status: String) -> Result<AccView> {
+        let view = self.registry.in_txn(|ctx, r| {
+            r.account.edit_profile(ctx, |doc| {
+                ProfileView::set_status(doc, status);
+                Ok(())
+            })
+        })?;
+        self.sync();
+        Ok(view)
+    }
+
+    /// Use an image as profile picture. Image is uploaded as an encrypted blob during sync.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn set_avatar(&self, path: impl AsRef<Path>) -> Result<AccView> {
+        let view = self.registry.in_txn(|ctx, r| {
+            let acc_id = r.account.require_account_id(ctx)?;
+            let profile_id = format!("{}/profile", acc_id);
+            let file = blobs::save_file(
+                ctx.txn(),
+                SaveFileParams {
+                    blob_dir: &r.device.blobs_dir,
+                    card_id: &profile_id,
+                    path: path.as_ref(),
+                    original_file_name: None,
+                    device_id: r.device.id.clone(),
+                },
+            )?;
+
+            let file_path = blobs::get_file_path(ctx.txn(), &file.blob_id)?
+                .ok_or(anyhow!("Saved avatar not found"))?;
+            let thumb_size = ProfileAvatar::THUMBNAIL_SIZE;
+            let thumb_res = timeline::create_thumbnail(&file_path, &file.checksum, thumb_size);
+            let thumbnail = match thumb_res {
+                Ok(timeline::ThumbnailResult::Created(thumb)) => Some(thumb),
+                Ok(timeline::ThumbnailResult::Skipped) => None,
+                Err(err) => {
+                    tracing::warn!("Failed to create avatar thumbnail: {}", err);
+                    None
+                }
+            };
+
+            let avatar = ProfileAvatar {
+                blob_id: file.blob_id,
+                device_id: file.device_id,
+                checksum: file.checksum,
+                size_bytes: file.size_bytes,
+                thumbnail,
+                secret: secrets::generate_key().to_vec(),
+            };
+            r.account.set_avatar(ctx, Some(avatar))
+        })?;
+        self.sync();
+        Ok(view)
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn remove_avatar(&self) -> Result<AccView> {
+        let view = self
+            .registry
+            .in_txn(|ctx, r| r.account.set_avatar(ctx, None))?;
+        self.sync();
+        Ok(view)
+    }
+
+    pub fn get_profile(&self, account_id: &str) -> Result<Option<ProfileView>> {
+        self.registry.in_txn(|ctx, r| {
+            let row = r.docs.find(ctx, &format!("{}/profile", account_id))?;
+            Ok(row.map(|row| ProfileView::from_db(row).0))
+        })
+    }
+
+    /// Get a path to profile picture of this or contact account. Picture is downloaded in the
+    /// background if it is missing locally.
+    pub fn download_avatar(&self, account_id: &str) -> Result<DownloadResult> {
+        let profile = self
+            .get_profile(account_id)?
+            .ok_or(anyhow!("Profile not found"))?;
+        let avatar = profile.avatar.ok_or(anyhow!("Profile has no avatar"))?;
+
+        // First, check locally
+        let blob = self
+            .registry
+            .in_txn(|ctx, _r| blobs::find_by_id(ctx.txn(), &avatar.blob_id, &avatar.device_id))?;
+        if let Some(b) = blob {
+            if Path::new(&b.path).exists() {
+                return Ok(DownloadResult {
+                    path: Some(b.path),
+                    download_started: false,
+                });
+            }
+        }
+
+        tracing::debug!(?avatar.blob_id, "Schedule avatar download");
+        let tx = self.background_tx.clone();
+        let account_id = account_id.to_string();
+        tokio::spawn(async move {
+            let _ = tx
+                .send(BackgroundInput::DownloadAvatar { account_id })
+                .await;
+        });
+        Ok(DownloadResult {
+            path: None,
+            download_started: true,
+        })
+    }
+
     pub fn create_acc_label(&self, name: String) -> Result<CreateAccLabelResult> {
         let label = AccLabel::new(name);
         let updated_acc = self.edit_account(|yrs_doc| {
@@ -404,6 +946,85 @@ where
         Ok(view)
     }
 
+    /// Create an invite code that lets other accounts add this account as a contact.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn create_contact_invite(&self, valid_for: chrono::Duration) -> Result<String> {
+        self.registry
+            .in_txn(|ctx, r| r.account.create_invite(ctx, valid_for))
+    }
+
+    /// Redeem an invite code. Inviting account is added to contacts and receives a contact request.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub async fn redeem_contact_invite(&self, code: &str) -> Result<AccView> {
+        let invite = ContactInvite::parse(code)?;
+        if invite.is_expired() {
+            bail!("Invite has expired");
+        }
+        let acc_id = self
+            .registry
+            .in_txn(|ctx, r| r.account.require_account_id(ctx))?;
+        if invite.account_id == acc_id {
+            bail!("Cannot redeem own invite");
+        }
+
+        let devices = self.client.get_account_devices(&invite.account_id).await?;
+        self.registry
+            .in_txn(|ctx, _r| invite.verify(&ctx.backend(), devices))?;
+
+        self.add_contact(AccContact {
+            account_id: invite.account_id,
+            name: invite.name,
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+    }
+
+    /// Remove contact from the account and revoke its access to cards.
+    #[instrument(skip_all, fields(d = self.debug_name, account_id))]
+    pub fn remove_contact(&self, account_id: &str) -> Result<AccView> {
+        let acc = self
+            .registry
+            .in_txn(|ctx, r| r.account.remove_contact(ctx, account_id, false))?;
+        self.sync();
+        Ok(acc)
+    }
+
+    /// Remove contact and ignore future contact requests and shares from the account.
+    #[instrument(skip_all, fields(d = self.debug_name, account_id))]
+    pub fn block_account(&self, account_id: &str) -> Result<AccView> {
+        let acc = self
+            .registry
+            .in_txn(|ctx, r| r.account.remove_contact(ctx, account_id, true))?;
+        self.sync();
+        Ok(acc)
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name, account_id))]
+    pub fn unblock_account(&self, account_id: &str) -> Result<AccView> {
+        self.edit_account(|yrs_doc| {
+            AccView::unblock_account(yrs_doc, account_id);
+            Ok(())
+        })
+    }
+
+    /// Safety number that both accounts can compare to verify each other's devices.
+    #[instrument(skip_all, fields(d = self.debug_name, account_id))]
+    pub fn contact_fingerprint(&self, account_id: &str) -> Result<ContactFingerprint> {
+        self.registry
+            .in_txn(|ctx, r| r.account.contact_fingerprint(ctx, account_id))
+    }
+
+    /// Mark contact as (un)verified after comparing safety numbers.
+    #[instrument(skip_all, fields(d = self.debug_name, account_id))]
+    pub fn set_contact_verified(&self, account_id: &str, verified: bool) -> Result<AccView> {
+        let acc = self
+            .registry
+            .in_txn(|ctx, r| r.account.set_contact_verified(ctx, account_id, verified))?;
+        self.sync();
+        Ok(acc)
+    }
+
     #[instrument(skip_all, fields(d = self.debug_name))]
     pub fn edit_contact_name(&self, account_id: &str, name: &str) -> Result<AccView> {
         let updated_acc = self.edit_account(|yrs_doc| {
@@ -433,6 +1054,8 @@ where
 
                     changes.push(AclChange::Add { account_id, rights });
                 } else {
+                    // Removed collaborator should not be able to read future changes
+                    r.docs.mark_secrets_obsolete(ctx, &account_id)?;
                     changes.push(AclChange::Remove { account_id });
                 }
             }
@@ -443,6 +1066,105 @@ where
         Ok(card)
     }
 
+    /// Set when collaborator's rights expire. Rights never expire when `expires_at` is None.
+    #[instrument(skip_all, fields(d = self.debug_name, card_id, account_id))]
+    pub fn edit_collaborator_expiry(
+        &self,
+        card_id: &str,
+        account_id: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<CardView> {
+        let card = self.registry.in_txn(|ctx, r| {
+            let acc_id = r.account.require_account_id(ctx)?;
+            if acc_id == account_id {
+                bail!("Cannot set expiry for own account");
+            }
+
+            let card = r.timeline.get_card(ctx, card_id)?;
+            if !card.acl.accounts.contains_key(account_id) {
+                bail!("Account is not a collaborator");
+            }
+
+            r.timeline.edit_card_acl(
+                ctx,
+                card_id,
+                vec![AclChange::SetExpiry {
+                    account_id: account_id.to_string(),
+                    expires_at,
+                }],
+            )
+        })?;
+        self.sync();
+        Ok(card)
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn create_shared_label(&self, name: String) -> Result<SharedLabelView> {
+        let label = self
+            .registry
+            .in_txn(|ctx, r| r.timeline.create_shared_label(ctx, name))?;
+        self.sync();
+        Ok(label)
+    }
+
+    pub fn list_shared_labels(&self) -> Result<Vec<SharedLabelView>> {
+        self.registry
+            .in_txn(|ctx, r| r.timeline.list_shared_labels(ctx))
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name, label_id))]
+    pub fn rename_shared_label(&self, label_id: &str, name: String) -> Result<SharedLabelView> {
+        let label = self.registry.in_txn(|ctx, r| {
+            r.timeline
+                .edit_shared_label(ctx, label_id, Some(name), vec![])
+        })?;
+        self.sync();
+        Ok(label)
+    }
+
+    /// Add, update or remove shared label members. Label cards are shared with the members.
+    #[instrument(skip_all, fields(d = self.debug_name, label_id))]
+    pub fn edit_shared_label_members(
+        &self,
+        label_id: &str,
+        changed: HashMap<String, Option<AclRights>>,
+    ) -> Result<SharedLabelView> {
+        let label = self.registry.in_txn(|ctx, r| {
+            let acc = r.account.require_account(ctx)?;
+            let mut changes = vec![];
+
+            for (account_id, rights) in changed.into_iter() {
+                // Members need to be contacts, so that we can deliver doc secrets.
+                if let Some(rights) = rights {
+                    let contact = acc.contacts.iter().find(|c| c.account_id == account_id);
+                    if contact.is_none() {
+                        bail!("Account is missing the contact");
+                    }
+
+                    changes.push(AclChange::Add { account_id, rights });
+                } else {
+                    // Removed member should not be able to read future changes
+                    r.docs.mark_secrets_obsolete(ctx, &account_id)?;
+                    changes.push(AclChange::Remove { account_id });
+                }
+            }
+
+            r.timeline.edit_shared_label(ctx, label_id, None, changes)
+        })?;
+        self.sync();
+        Ok(label)
+    }
+
+    /// Remove collaborators which rights have expired. Returns IDs of modified cards.
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn remove_expired_collaborators(&self) -> Result<Vec<String>> {
+        let card_ids = self
+            .registry
+            .in_txn(|ctx, r| r.timeline.remove_expired_collaborators(ctx))?;
+        self.sync();
+        Ok(card_ids)
+    }
+
     fn edit_account(&self, apply: impl FnOnce(&yrs::Doc) -> Result<()>) -> Result<AccView> {
         let view = self
             .registry
@@ -518,6 +1240,11 @@ where
         Ok(n)
     }
 
+    /// List pending notifications together with accepted and ignored ones.
+    pub fn list_inbox(&self) -> Result<Vec<InboxNotification>> {
+        self.registry.in_txn(|ctx, r| r.account.list_inbox(ctx))
+    }
+
     #[instrument(skip_all, fields(d = self.debug_name))]
     pub async fn accept_notification(&self, id: &str) -> Result<()> {
         let ctx = self.registry.db_ctx();
@@ -533,6 +1260,131 @@ where
         self.sync();
         Ok(())
     }
+
+    pub fn sync_diagnostics(&self) -> Result<SyncDiagnostics> {
+        let failed_docs = self
+            .registry
+            .in_txn(|ctx, r| r.sync_docs.list_failed_docs(ctx))?;
+        Ok(SyncDiagnostics { failed_docs })
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name, doc_id))]
+    pub fn retry_failed_doc(&self, doc_id: &str) -> Result<()> {
+        self.registry
+            .in_txn(|ctx, r| r.sync_docs.retry_failed_doc(ctx, doc_id))?;
+        self.sync();
+        Ok(())
+    }
+
+    /// Summarise local changes that haven't been sent yet.
+    pub fn pending_outbox(&self) -> Result<PendingOutbox> {
+        let mut outbox = self.registry.in_txn(|ctx, r| {
+            let mut docs = r.sync_docs.list_modified_docs(ctx)?;
+            docs.extend(r.docs.list_queued_docs(ctx)?);
+            let blobs = blobs::list_unsynced(ctx.txn())?;
+            let (mailbox_messages, mailbox_size_bytes) = mailbox::count_queued(ctx)?;
+            let mailbox_acks = mailbox::count_queued_acks(ctx)?;
+            Ok(PendingOutbox {
+                docs,
+                blobs,
+                mailbox_messages,
+                mailbox_size_bytes,
+                mailbox_acks,
+            })
+        })?;
+        blobs::read_sizes(&mut outbox.blobs);
+        Ok(outbox)
+    }
+
+    /// Cancel upload of a file attached to a card in the bin.
+    #[instrument(skip_all, fields(d = self.debug_name, blob_id))]
+    pub fn cancel_blob_upload(&self, blob_id: &str) -> Result<()> {
+        self.registry
+            .in_txn(|ctx, r| r.timeline.cancel_blob_upload(ctx, blob_id))?;
+        self.sync();
+        Ok(())
+    }
+
+    /// Cards created earlier than this window are downloaded only when opened.
+    /// None means that all cards are downloaded during sync.
+    pub fn sync_window(&self) -> Result<Option<chrono::Duration>> {
+        self.registry.in_txn(|ctx, r| r.sync_docs.sync_window(ctx))
+    }
+
+    #[instrument(skip_all, fields(d = self.debug_name))]
+    pub fn set_sync_window(&self, window: Option<chrono::Duration>) -> Result<()> {
+        self.registry
+            .in_txn(|ctx, r| r.sync_docs.set_sync_window(ctx, window))
+    }
+
+    /// Publish a read-only snapshot of the card. Anyone with the link can view it.
+    /// Publishing the same card again replaces the snapshot and keeps the link.
+    /// Only card admins can publish. Link is removed once the card is moved to the bin.
+    #[instrument(skip_all, fields(d = self.debug_name, card_id))]
+    pub async fn publish_card(&self, card_id: &str) -> Result<PublicLink> {
+        self.registry.in_txn(|ctx, r| {
+            let acc_id = r.account.require_account_id(ctx)?;
+            let card = r.timeline.get_card(ctx, card_id)?;
+            if !card.acl.allowed_to_admin(&acc_id) {
+                bail!("Only card admin can publish the card");
+            }
+            if card.labels.iter().any(|l| l.id == BIN_LABEL_ID) {
+                bail!("Cannot publish a card in the bin");
+            }
+            Ok(())
+        })?;
+
+        let ctx = self.registry.db_ctx();
+        let html = self.registry.export.export_card_html(&ctx, card_id).await?;
+        let existing = self
+            .registry
+            .in_txn(|ctx, _r| public_links::find_by_card_id(ctx, card_id))?;
+        let mut link = existing.unwrap_or_else(|| PublicLinkRow::new(card_id));
+
+        let payload = public_links::encrypt_snapshot(&link.key, &html)?;
+        self.client.publish_link(&link.id, payload).await?;
+
+        link.published_at = Utc::now();
+        self.registry
+            .in_txn(|ctx, _r| public_links::save(ctx, &link))?;
+        Ok(self.public_link_view(link))
+    }
+
+    /// Remove card snapshot from the server. Existing links will stop working.
+    #[instrument(skip_all, fields(d = self.debug_name, card_id))]
+    pub async fn unpublish_card(&self, card_id: &str) -> Result<()> {
+        let existing = self
+            .registry
+            .in_txn(|ctx, _r| public_links::find_by_card_id(ctx, card_id))?;
+        if let Some(link) = existing {
+            self.client.unpublish_link(&link.id).await?;
+            self.registry
+                .in_txn(|ctx, _r| public_links::delete(ctx, &link.id))?;
+        }
+        Ok(())
+    }
+
+    pub fn list_public_links(&self) -> Result<Vec<PublicLink>> {
+        let links = self.registry.in_txn(|ctx, _r| public_links::list(ctx))?;
+        Ok(links
+            .into_iter()
+            .map(|link| self.public_link_view(link))
+            .collect())
+    }
+
+    fn public_link_view(&self, link: PublicLinkRow) -> PublicLink {
+        let url = format!(
+            "{}#{}",
+            self.client.public_link_url(&link.id),
+            link.url_fragment()
+        );
+        PublicLink {
+            id: link.id,
+            card_id: link.card_id,
+            url,
+            published_at: link.published_at,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -549,6 +1401,25 @@ pub struct CreateAccLabelResult {
     pub label: AccLabel,
 }
 
+#[derive(Clone)]
+pub struct SyncDiagnostics {
+    /// Remote doc versions that failed to merge
+    pub failed_docs: Vec<FailedDoc>,
+}
+
+/// Local changes that haven't left the device yet.
+#[derive(Clone)]
+pub struct PendingOutbox {
+    /// Locally edited docs and queued doc messages (e.g. permanent deletions)
+    pub docs: Vec<PendingDoc>,
+    /// Files that haven't been uploaded yet
+    pub blobs: Vec<PendingBlob>,
+    pub mailbox_messages: u32,
+    pub mailbox_size_bytes: u64,
+    /// Received mailbox messages that haven't been acknowledged yet
+    pub mailbox_acks: u32,
+}
+
 pub enum MoveToBinScope {
     /// Move this card to bin only for this account.
     ThisAccount,
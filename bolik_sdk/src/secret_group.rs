@@ -8,6 +8,11 @@ use openmls::prelude::{Credential, KeyPackageRef, MlsGroup, OpenMlsCrypto};
 mod group_atom;
 pub use group_atom::{GroupApplyResult, SecretGroupAtom, SecretGroupCtx};
 
+/// How long a device keeps the same leaf keys in a group before issuing a self-update.
+pub fn leaf_update_interval() -> chrono::Duration {
+    chrono::Duration::days(7)
+}
+
 pub struct SecretGroup {
     pub mls: MlsGroup,
     pub chain: SignatureChain,
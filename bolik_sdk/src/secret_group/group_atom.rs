@@ -7,7 +7,7 @@ use crate::{
     documents::DocSecretRow,
     mailbox,
     registry::{WithBackend, WithDeviceAtom, WithInTxn, WithTxn},
-    secrets::{build_accounts_hash, CIPHERSUITES},
+    secrets::{self, build_accounts_hash, DEFAULT_CIPHERSUITE},
     signature_chain::SignatureChainStorage,
 };
 use anyhow::{anyhow, bail, Context, Result};
@@ -18,11 +18,11 @@ use bolik_proto::sync::{
     app_message::{doc_secrets_message, DocSecretsMessage, RemoveMe},
     request, response, AppMessage,
 };
+use chrono::{DateTime, Duration, Utc};
 use openmls::prelude::{
-    ApplicationMessage, GroupId, InnerState, KeyPackage, KeyPackageBundle, MlsGroup,
-    MlsGroupConfig, MlsGroupConfigBuilder, MlsMessageIn, MlsMessageOut, OpenMlsKeyStore,
-    ProcessedMessage, Sender, SenderRatchetConfiguration, TlsDeserializeTrait, TlsSerializeTrait,
-    Welcome,
+    ApplicationMessage, Ciphersuite, GroupId, InnerState, KeyPackage, MlsGroup, MlsGroupConfig,
+    MlsGroupConfigBuilder, MlsMessageIn, MlsMessageOut, OpenMlsKeyStore, ProcessedMessage, Sender,
+    SenderRatchetConfiguration, TlsDeserializeTrait, TlsSerializeTrait, Welcome,
 };
 use openmls_traits::OpenMlsCryptoProvider;
 use prost::Message;
@@ -47,13 +47,15 @@ impl<C: Client> SecretGroupAtom<C> {
 
     /// Create new secret group for a single account.
     pub fn create<'a>(&self, ctx: &impl SecretGroupCtx<'a>) -> Result<SecretGroup> {
-        self.do_create(ctx, None)
+        let ciphersuite = ctx.device().ciphersuites(ctx)[0];
+        self.do_create(ctx, None, ciphersuite)
     }
 
     fn do_create<'a>(
         &self,
         ctx: &impl SecretGroupCtx<'a>,
         account_ids: Option<Vec<String>>,
+        ciphersuite: Ciphersuite,
     ) -> Result<SecretGroup> {
         let backend = &ctx.backend();
         let chain_storage = SignatureChainStorage::new(
@@ -62,11 +64,10 @@ impl<C: Client> SecretGroupAtom<C> {
             },
             backend,
         );
-        let key_bundle = KeyPackageBundle::new(
-            &CIPHERSUITES,
+        let key_bundle = secrets::new_key_package_bundle(
+            ciphersuite,
             &chain_storage.author.bundle,
             &ctx.backend(),
-            vec![],
         )?;
         let key_ref = key_bundle.key_package().hash_ref(ctx.backend().crypto())?;
         ctx.backend()
@@ -82,10 +83,11 @@ impl<C: Client> SecretGroupAtom<C> {
             key_ref.as_slice(),
         )?;
         tracing::trace!(
-            "Group created epoch={:?} state={:?} account_ids={:?}",
+            "Group created epoch={:?} state={:?} account_ids={:?} ciphersuite={:?}",
             group.epoch(),
             group_state(&group),
             chain.account_ids(),
+            ciphersuite,
         );
 
         self.save(ctx, &mut group, &chain)?;
@@ -447,10 +449,18 @@ impl<C: Client> SecretGroupAtom<C> {
         ctx.txn()
             .execute(
                 r#"
-INSERT INTO mls_groups (id, chain_hash, epoch, encrypted_state, accounts_hash) VALUES (?1, ?2, ?3, ?4, ?5)
+INSERT INTO mls_groups (id, chain_hash, epoch, encrypted_state, accounts_hash, ciphersuite)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6)
   ON CONFLICT (id, chain_hash) DO UPDATE
      SET encrypted_state = excluded.encrypted_state"#,
-                params![id, chain_hash, epoch, nonce_ciphertext, accounts_hash],
+                params![
+                    id,
+                    chain_hash,
+                    epoch,
+                    nonce_ciphertext,
+                    accounts_hash,
+                    group.ciphersuite() as u16
+                ],
             )
             .context("Insert mls_group")?;
 
@@ -541,25 +551,37 @@ SELECT encrypted_state
         Ok(SecretGroup { mls, chain })
     }
 
+    /// Load the group for given accounts. When the group was re-created with a stronger
+    /// ciphersuite then the upgraded group is returned. If both sides upgraded at the same
+    /// time then the group with the smallest id is picked so that both sides agree.
     pub fn load_latest_for_accounts<'a>(
         &self,
         ctx: &impl WithTxn<'a>,
         account_ids: &mut [String],
     ) -> Result<SecretGroup> {
         let accounts_hash = build_accounts_hash(account_ids);
-        let mls = Self::query_row(
-            ctx,
-            r#"
-SELECT encrypted_state
-  FROM mls_groups
- WHERE accounts_hash = ?
- ORDER BY epoch DESC
- LIMIT 1"#,
-            params![accounts_hash],
-        )
-        .with_context(|| format!("Find latest MlsGroup accounts={:?}", account_ids))?
-        .ok_or(anyhow!("MlsGroup not found"))?;
-        let id = Self::group_id_str(mls.group_id());
+        let mut stmt = ctx.txn().prepare(
+            "SELECT id, max(ciphersuite) FROM mls_groups WHERE accounts_hash = ? GROUP BY id",
+        )?;
+        let groups = stmt
+            .query_map(params![accounts_hash], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Option<u16>>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Groups created before ciphersuites were stored use the default one
+        let (id, _) = groups
+            .into_iter()
+            .min_by_key(|(id, suite)| {
+                let ciphersuite = suite
+                    .and_then(secrets::ciphersuite_from_id)
+                    .unwrap_or(DEFAULT_CIPHERSUITE);
+                (secrets::ciphersuite_rank(ciphersuite), id.clone())
+            })
+            .ok_or(anyhow!("MlsGroup not found"))?;
+        let mls = self
+            .load_latest_mls(ctx, &id)
+            .with_context(|| format!("Find latest MlsGroup accounts={:?}", account_ids))?;
         let chain = SignatureChainStorage::load(ctx.txn(), &id)?.ok_or(anyhow!("Missing chain"))?;
         Ok(SecretGroup { mls, chain })
     }
@@ -694,7 +716,8 @@ SELECT encrypted_state
         created
     }
 
-    /// Create a new secret group for given account ids. Doesn't do anything if group already exists.
+    /// Create a new secret group for given account ids. If group already exists then only
+    /// add back devices of other account (in case they were removed).
     pub async fn create_for_accounts(
         &self,
         ctx: &impl SecretGroupAsyncCtx<C>,
@@ -707,7 +730,9 @@ SELECT encrypted_state
         })?;
 
         if group_exists {
-            return Ok(());
+            return self
+                .readd_account_devices(ctx, this_acc_id, other_acc_id)
+                .await;
         }
 
         // Fetch account devices (key packages)
@@ -716,83 +741,385 @@ SELECT encrypted_state
         let other_account = self.client.get_account_devices(other_acc_id).await?;
 
         ctx.in_txn(|tx_ctx| {
-            let backend = &tx_ctx.backend();
-            let mut group_members = HashMap::new();
+            let group_members = Self::contact_members(
+                tx_ctx,
+                [(this_acc_id, this_account), (other_acc_id, other_account)],
+            )?;
+            let ciphersuite = Self::common_ciphersuite(tx_ctx, &group_members)?;
+            self.create_with_members(
+                tx_ctx,
+                vec![this_acc_id.to_string(), other_acc_id.to_string()],
+                group_members,
+                ciphersuite,
+            )?;
+            Ok(())
+        })?;
+        Ok(())
+    }
 
-            // Build a list of new group members
-            for (devices, acc_id) in [this_account, other_account]
-                .into_iter()
-                .zip([this_acc_id, other_acc_id])
-            {
-                let chain = SignatureChain::decode(
-                    devices
+    /// Re-create contact groups with a stronger ciphersuite when every member device supports it.
+    /// Account groups cannot be upgraded this way because their id is bound to the account id.
+    /// Returns how many groups were upgraded.
+    pub async fn upgrade_contact_groups(
+        &self,
+        ctx: &impl SecretGroupAsyncCtx<C>,
+        this_acc_id: &str,
+    ) -> Result<u32> {
+        // Find contact groups that could use a stronger ciphersuite
+        let contacts: Vec<String> = ctx.in_txn(|tx_ctx| {
+            let mut contact_ids = vec![];
+            self.with_all_groups(tx_ctx, |group| {
+                contact_ids.extend(
+                    group
                         .chain
-                        .ok_or(anyhow!("AccountDevices is missing the chain"))?,
-                )?;
-                chain.verify(backend)?;
-                if chain.root() != acc_id {
-                    bail!(
-                        "Chain root doesn't match account id ({} != {acc_id})",
-                        chain.root()
-                    );
+                        .account_ids()
+                        .iter()
+                        .filter(|id| *id != this_acc_id)
+                        .cloned(),
+                );
+                Ok(())
+            })?;
+            contact_ids.sort();
+            contact_ids.dedup();
+
+            // Skip groups that already use the strongest suite of this device
+            let strongest = tx_ctx.device().ciphersuites(tx_ctx)[0];
+            let mut contacts = vec![];
+            for id in contact_ids {
+                let group = self
+                    .load_latest_for_accounts(tx_ctx, &mut [this_acc_id.to_string(), id.clone()])?;
+                if group.mls.ciphersuite() != strongest {
+                    contacts.push(id);
                 }
+            }
+            Ok(contacts)
+        })?;
 
-                let members = chain.members(backend.crypto())?;
-                for message in devices.key_packages {
-                    let package = KeyPackage::tls_deserialize(&mut message.data.as_slice())?;
-                    let device_id = get_device_id(package.credential())?;
-                    if members.find_by_id(&device_id).is_some() && device_id != ctx.device().id {
-                        // Include only devices that signature chain knows of
-                        group_members.insert(device_id, package);
-                    }
+        let mut upgraded = 0;
+        for contact_id in contacts {
+            match self
+                .upgrade_contact_group(ctx, this_acc_id, &contact_id)
+                .await
+            {
+                Ok(true) => {
+                    upgraded += 1;
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::warn!(
+                        "Failed to upgrade a group with acc_id={}: {}",
+                        contact_id,
+                        err
+                    );
                 }
             }
+        }
+        Ok(upgraded)
+    }
 
-            if group_members.is_empty() {
-                return Err(anyhow!("Devices list is empty"));
-            }
+    /// Create a new contact group if members share a stronger ciphersuite than the current one.
+    /// Old group is kept so that messages sent to it can still be read.
+    async fn upgrade_contact_group(
+        &self,
+        ctx: &impl SecretGroupAsyncCtx<C>,
+        this_acc_id: &str,
+        other_acc_id: &str,
+    ) -> Result<bool> {
+        let this_account = self.client.get_account_devices(this_acc_id).await?;
+        let other_account = self.client.get_account_devices(other_acc_id).await?;
 
-            // Create secret group
-            let mut group = self.do_create(
+        ctx.in_txn(|tx_ctx| {
+            let group_members = Self::contact_members(
                 tx_ctx,
-                Some(vec![this_acc_id.to_string(), other_acc_id.to_string()]),
+                [(this_acc_id, this_account), (other_acc_id, other_account)],
             )?;
+            let ciphersuite = Self::common_ciphersuite(tx_ctx, &group_members)?;
+            // Other side might have upgraded the group while we were fetching devices
+            let current = self
+                .load_latest_for_accounts(
+                    tx_ctx,
+                    &mut [this_acc_id.to_string(), other_acc_id.to_string()],
+                )?
+                .mls
+                .ciphersuite();
+            if secrets::ciphersuite_rank(ciphersuite) >= secrets::ciphersuite_rank(current) {
+                return Ok(false);
+            }
+
             tracing::info!(
-                group_id = group.id(),
-                chain_hash = group.chain.head(),
-                add_members = group_members.len(),
-                "Creating new MLS group for contact"
+                account_id = other_acc_id,
+                from = ?current,
+                to = ?ciphersuite,
+                "Upgrading contact group ciphersuite"
             );
+            self.create_with_members(
+                tx_ctx,
+                vec![this_acc_id.to_string(), other_acc_id.to_string()],
+                group_members,
+                ciphersuite,
+            )?;
+            Ok(true)
+        })
+    }
+
+    /// Verify account chains and collect key packages of their devices (except this device).
+    fn contact_members<'a>(
+        ctx: &impl SecretGroupCtx<'a>,
+        accounts: [(&str, response::AccountDevices); 2],
+    ) -> Result<HashMap<String, Vec<KeyPackage>>> {
+        let backend = &ctx.backend();
+        let mut group_members = HashMap::new();
+        for (acc_id, devices) in accounts {
+            group_members.extend(Self::verified_packages(
+                backend,
+                devices,
+                acc_id,
+                &ctx.device().id,
+            )?);
+        }
+
+        if group_members.is_empty() {
+            return Err(anyhow!("Devices list is empty"));
+        }
+        Ok(group_members)
+    }
+
+    /// Pick the strongest ciphersuite that this device and every member have key packages for.
+    fn common_ciphersuite<'a>(
+        ctx: &impl SecretGroupCtx<'a>,
+        members: &HashMap<String, Vec<KeyPackage>>,
+    ) -> Result<Ciphersuite> {
+        let member_suites: Vec<Vec<Ciphersuite>> = members
+            .values()
+            .map(|packages| packages.iter().map(|p| p.ciphersuite()).collect())
+            .collect();
+        secrets::strongest_common_ciphersuite(
+            &ctx.device().ciphersuites(ctx),
+            member_suites.iter().map(Vec::as_slice),
+        )
+        .ok_or(anyhow!("Group members have no common ciphersuite"))
+    }
+
+    /// Create a new contact group and add member devices to it.
+    fn create_with_members<'a>(
+        &self,
+        ctx: &impl SecretGroupCtx<'a>,
+        account_ids: Vec<String>,
+        members: HashMap<String, Vec<KeyPackage>>,
+        ciphersuite: Ciphersuite,
+    ) -> Result<()> {
+        let mut group = self.do_create(ctx, Some(account_ids), ciphersuite)?;
+        let packages = Self::packages_for_ciphersuite(members, ciphersuite);
+        tracing::info!(
+            group_id = group.id(),
+            chain_hash = group.chain.head(),
+            add_members = packages.len(),
+            ?ciphersuite,
+            "Creating new MLS group for contact"
+        );
+
+        let commit = self.add(ctx, &mut group, packages)?;
+        if let Some(c) = commit {
+            mailbox::queue_mls_commit(ctx, c)?;
+        }
+        Ok(())
+    }
 
-            let commit = self.add(
+    /// Pick one key package per device that matches the ciphersuite.
+    /// Devices without such package are skipped.
+    fn packages_for_ciphersuite(
+        members: HashMap<String, Vec<KeyPackage>>,
+        ciphersuite: Ciphersuite,
+    ) -> Vec<KeyPackage> {
+        members
+            .into_iter()
+            .filter_map(|(device_id, packages)| {
+                let package = packages
+                    .into_iter()
+                    .find(|p| p.ciphersuite() == ciphersuite);
+                if package.is_none() {
+                    tracing::warn!(device_id, ?ciphersuite, "Device has no matching KeyPackage");
+                }
+                package
+            })
+            .collect()
+    }
+
+    /// Add devices of other account back to the contact group if they were removed earlier.
+    async fn readd_account_devices(
+        &self,
+        ctx: &impl SecretGroupAsyncCtx<C>,
+        this_acc_id: &str,
+        other_acc_id: &str,
+    ) -> Result<()> {
+        let has_devices = ctx.in_txn(|tx_ctx| {
+            let group = self.load_latest_for_accounts(
                 tx_ctx,
-                &mut group,
-                group_members.into_iter().map(|(_, p)| p).collect(),
+                &mut [this_acc_id.to_string(), other_acc_id.to_string()],
             )?;
+            Ok(!self
+                .other_account_device_ids(tx_ctx, &group, this_acc_id)?
+                .is_empty())
+        })?;
+        if has_devices {
+            return Ok(());
+        }
+
+        let other_account = self.client.get_account_devices(other_acc_id).await?;
+        ctx.in_txn(|tx_ctx| {
+            let backend = &tx_ctx.backend();
+            let packages =
+                Self::verified_packages(backend, other_account, other_acc_id, &ctx.device().id)?;
+            if packages.is_empty() {
+                return Err(anyhow!("Devices list is empty"));
+            }
+
+            let mut group = self.load_latest_for_accounts(
+                tx_ctx,
+                &mut [this_acc_id.to_string(), other_acc_id.to_string()],
+            )?;
+            let packages = Self::packages_for_ciphersuite(packages, group.mls.ciphersuite());
+            tracing::info!(
+                group_id = group.id(),
+                add_members = packages.len(),
+                "Adding contact devices back to MLS group"
+            );
+            let commit = self.add(tx_ctx, &mut group, packages)?;
             if let Some(c) = commit {
                 mailbox::queue_mls_commit(tx_ctx, c)?;
             }
             Ok(())
-        })?;
+        })
+    }
+
+    /// Remove all devices of other account from the contact group.
+    pub fn remove_account_devices<'a>(
+        &self,
+        ctx: &impl SecretGroupCtx<'a>,
+        this_acc_id: &str,
+        other_acc_id: &str,
+    ) -> Result<()> {
+        let mut account_ids = vec![this_acc_id.to_string(), other_acc_id.to_string()];
+        if !self.exists_for_accounts(ctx, &mut account_ids)? {
+            return Ok(());
+        }
+
+        let mut group = self.load_latest_for_accounts(ctx, &mut account_ids)?;
+        let mut removals = vec![];
+        for device_id in self.other_account_device_ids(ctx, &group, this_acc_id)? {
+            if let Some(key_ref) = group.find_member_ref(&device_id, ctx.backend().crypto()) {
+                removals.push(DeviceRemovedOp {
+                    key_ref,
+                    last_counter: ctx.device().get_clock(ctx, &device_id)?,
+                });
+            }
+        }
+        if removals.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!(
+            group_id = group.id(),
+            account_id = other_acc_id,
+            "Removing {} contact devices from MLS group",
+            removals.len()
+        );
+        let commit = self.remove(ctx, &mut group, removals)?;
+        if let Some(c) = commit {
+            mailbox::queue_mls_commit(ctx, c)?;
+        }
         Ok(())
     }
 
+    /// List group devices that don't belong to this account.
+    fn other_account_device_ids<'a>(
+        &self,
+        ctx: &impl SecretGroupCtx<'a>,
+        group: &SecretGroup,
+        this_acc_id: &str,
+    ) -> Result<Vec<String>> {
+        let own_chain =
+            SignatureChainStorage::load(ctx.txn(), this_acc_id)?.ok_or(anyhow!("Missing chain"))?;
+        let own_members = own_chain.members(ctx.backend().crypto())?;
+        let ids = group
+            .device_ids()?
+            .into_iter()
+            .map(|(id, _)| id)
+            .filter(|id| {
+                own_members.find_by_id(id).is_none() && !own_members.removed.contains_key(id)
+            })
+            .collect();
+        Ok(ids)
+    }
+
+    /// Verify account chain and return key packages of chain devices (except this device).
+    fn verified_packages(
+        backend: &impl OpenMlsCryptoProvider,
+        devices: response::AccountDevices,
+        acc_id: &str,
+        this_device_id: &str,
+    ) -> Result<HashMap<String, Vec<KeyPackage>>> {
+        let chain = SignatureChain::decode(
+            devices
+                .chain
+                .ok_or(anyhow!("AccountDevices is missing the chain"))?,
+        )?;
+        chain.verify(backend)?;
+        if chain.root() != acc_id {
+            bail!(
+                "Chain root doesn't match account id ({} != {acc_id})",
+                chain.root()
+            );
+        }
+
+        let members = chain.members(backend.crypto())?;
+        let mut packages: HashMap<String, Vec<KeyPackage>> = HashMap::new();
+        for message in devices.key_packages {
+            let package = KeyPackage::tls_deserialize(&mut message.data.as_slice())?;
+            let device_id = get_device_id(package.credential())?;
+            if members.find_by_id(&device_id).is_some() && device_id != this_device_id {
+                // Include only devices that signature chain knows of
+                packages.entry(device_id).or_default().push(package);
+            }
+        }
+        Ok(packages)
+    }
+
     /// Add a device to all known groups.
     pub fn add_to_all_groups<'a>(
         &self,
         ctx: &impl SecretGroupCtx<'a>,
-        mut device_key_packages: Vec<KeyPackage>,
+        device_key_packages: Vec<KeyPackage>,
     ) -> Result<()> {
         if device_key_packages.is_empty() {
             bail!("New device has no available KeyPackages");
         }
 
+        // Each group needs a key package in its own ciphersuite
+        let mut packages_by_suite: HashMap<Ciphersuite, Vec<KeyPackage>> = HashMap::new();
+        for package in device_key_packages {
+            packages_by_suite
+                .entry(package.ciphersuite())
+                .or_default()
+                .push(package);
+        }
+
         self.with_all_groups(ctx, |group| {
+            let ciphersuite = group.mls.ciphersuite();
+            let Some(suite_packages) = packages_by_suite.get_mut(&ciphersuite) else {
+                tracing::warn!(
+                    group_id = group.id(),
+                    ?ciphersuite,
+                    "New device has no KeyPackage for group ciphersuite"
+                );
+                return Ok(());
+            };
+
             // Keep last key package for later use
-            let package = if device_key_packages.len() > 2 {
-                device_key_packages.pop().unwrap()
+            let package = if suite_packages.len() > 2 {
+                suite_packages.pop().unwrap()
             } else {
-                device_key_packages[0].clone()
+                suite_packages[0].clone()
             };
 
             let commit = self.add(ctx, group, vec![package])?;
@@ -850,6 +1177,68 @@ SELECT encrypted_state
         Ok(())
     }
 
+    /// Issue self-update commits for all known groups where leaf keys of this device are older
+    /// than `max_age` (or for every group if `max_age` is not provided). Returns how many
+    /// groups were updated.
+    pub fn update_own_leaves<'a>(
+        &self,
+        ctx: &impl SecretGroupCtx<'a>,
+        max_age: Option<Duration>,
+    ) -> Result<u32> {
+        let now = Utc::now();
+        let mut updated = 0;
+        self.with_all_groups(ctx, |group| {
+            let group_id = group.id();
+            let updated_at: Option<DateTime<Utc>> = ctx
+                .txn()
+                .query_row(
+                    "SELECT updated_at FROM mls_group_updates WHERE id = ?",
+                    params![group_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            if let Some(max_age) = max_age {
+                match updated_at {
+                    Some(at) if at + max_age > now => return Ok(()),
+                    None => {
+                        // Start counting from the moment we first see the group
+                        Self::mark_leaf_updated(ctx, &group_id, now)?;
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+            }
+
+            let kpb = ctx
+                .device()
+                .generate_update_bundle(ctx, group.mls.ciphersuite())?;
+            let commit = self.self_update(ctx, group, kpb)?;
+            if let Some(commit) = commit {
+                tracing::info!(group_id, "Updated own leaf in MLS group");
+                mailbox::queue_mls_commit(ctx, commit)?;
+                updated += 1;
+            }
+            Self::mark_leaf_updated(ctx, &group_id, now)?;
+            Ok(())
+        })?;
+        Ok(updated)
+    }
+
+    fn mark_leaf_updated<'a>(
+        ctx: &impl WithTxn<'a>,
+        group_id: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        ctx.txn().execute(
+            r#"
+INSERT INTO mls_group_updates (id, updated_at) VALUES (?1, ?2)
+    ON CONFLICT (id) DO UPDATE SET updated_at = excluded.updated_at"#,
+            params![group_id, at],
+        )?;
+        Ok(())
+    }
+
     /// Iterate over all known groups
     fn with_all_groups<'a>(
         &self,
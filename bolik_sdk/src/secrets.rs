@@ -1,4 +1,5 @@
 use std::ops::Sub;
+use std::sync::{Arc, RwLock};
 
 use anyhow::anyhow;
 use bolik_migrations::rusqlite::{self, Connection};
@@ -12,7 +13,9 @@ use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
 use chrono::{Duration, Utc};
 use multihash::{Blake3_256, Hasher};
 use openmls::prelude::{Ciphersuite, OpenMlsCryptoProvider};
+use openmls::prelude::{CredentialBundle, KeyPackageBundle};
 use openmls_rust_crypto::RustCrypto;
+use openmls_traits::crypto::OpenMlsCrypto;
 use openmls_traits::key_store::{FromKeyStoreValue, OpenMlsKeyStore, ToKeyStoreValue};
 use rand::RngCore;
 
@@ -23,21 +26,40 @@ pub(crate) const CIPHERSUITES: &[Ciphersuite] = &[
 ];
 pub(crate) const DEFAULT_CIPHERSUITE: Ciphersuite = CIPHERSUITES[0];
 
+/// Ciphersuites that secret groups can be created with, from the strongest to the weakest.
+/// New suites (e.g. post-quantum ones) should be added to the front of the list.
+///
+/// Both suites provide 128-bit security. ChaCha20 suite is preferred because it is fast and
+/// constant-time in software, on devices without AES hardware acceleration.
+///
+/// Device credentials are Ed25519 keys hence only Ed25519 suites are listed. Stronger suites
+/// (e.g. X448 with Ed448) need credentials with another signature scheme: until credentials
+/// are re-issued X25519 remains the only key exchange option.
+const CIPHERSUITE_PREFERENCE: &[Ciphersuite] = &[
+    Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519,
+    Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519,
+];
+
 const CHACHA20POLY1305_NONCE_SIZE: usize = 12;
 
-/// Cipher for encrypting/decrypting cells in the database
+/// Cipher for encrypting/decrypting cells in the database. Clones share the key.
 #[derive(Clone)]
 pub struct DbCipher {
-    cipher: ChaCha20Poly1305,
+    cipher: Arc<RwLock<ChaCha20Poly1305>>,
 }
 
 impl DbCipher {
     pub fn new(key: &chacha20poly1305::Key) -> Self {
         Self {
-            cipher: ChaCha20Poly1305::new(key),
+            cipher: Arc::new(RwLock::new(ChaCha20Poly1305::new(key))),
         }
     }
 
+    /// Use another key for all clones of this cipher.
+    pub fn replace_key(&self, key: &chacha20poly1305::Key) {
+        *self.cipher.write().unwrap() = ChaCha20Poly1305::new(key);
+    }
+
     fn generate_nonce() -> chacha20poly1305::Nonce {
         ChaCha20Poly1305::generate_nonce(&mut OsRng)
     }
@@ -61,6 +83,8 @@ impl DbCipher {
         let nonce = Self::generate_nonce();
         let ciphertext = self
             .cipher
+            .read()
+            .unwrap()
             .encrypt(&nonce, value)
             .map_err(|err| anyhow!("{:?}", err))?;
 
@@ -76,6 +100,8 @@ impl DbCipher {
         let (nonce, ciphertext) = Self::ciphertext_into_parts(&nonce_ciphertext)?;
         let value = self
             .cipher
+            .read()
+            .unwrap()
             .decrypt(nonce, ciphertext)
             .map_err(|err| anyhow!("{:?}", err))?;
         Ok(value)
@@ -266,3 +292,111 @@ fn accounts_hash_transform<T>(account_ids: &mut [String], transform: impl FnOnce
 pub(crate) fn build_accounts_hash(account_ids: &mut [String]) -> String {
     accounts_hash_transform(account_ids, |digest| id_from_key(digest))
 }
+
+/// Ciphersuites this device can create and join groups with, from the strongest to the weakest.
+///
+/// Group suite must use the same signature scheme as device credential (see [DEFAULT_CIPHERSUITE]).
+pub(crate) fn negotiable_ciphersuites(crypto: &impl OpenMlsCrypto) -> Vec<Ciphersuite> {
+    let suites: Vec<_> = CIPHERSUITE_PREFERENCE
+        .iter()
+        .copied()
+        .filter(|s| s.signature_algorithm() == DEFAULT_CIPHERSUITE.signature_algorithm())
+        .filter(|s| crypto.supports(*s).is_ok())
+        .collect();
+    if suites.is_empty() {
+        vec![DEFAULT_CIPHERSUITE]
+    } else {
+        suites
+    }
+}
+
+/// Pick the strongest suite from `own` (ordered by preference) that every member supports.
+pub(crate) fn strongest_common_ciphersuite<'a>(
+    own: &[Ciphersuite],
+    members: impl IntoIterator<Item = &'a [Ciphersuite]>,
+) -> Option<Ciphersuite> {
+    let mut common = own.to_vec();
+    for supported in members {
+        common.retain(|s| supported.contains(s));
+    }
+    common.into_iter().next()
+}
+
+/// Position of the suite in the preference list. Lower is stronger.
+pub(crate) fn ciphersuite_rank(ciphersuite: Ciphersuite) -> usize {
+    CIPHERSUITE_PREFERENCE
+        .iter()
+        .position(|s| *s == ciphersuite)
+        .unwrap_or(CIPHERSUITE_PREFERENCE.len())
+}
+
+/// Find a known ciphersuite by its MLS identifier.
+pub(crate) fn ciphersuite_from_id(id: u16) -> Option<Ciphersuite> {
+    CIPHERSUITES.iter().copied().find(|s| *s as u16 == id)
+}
+
+/// Build a KeyPackageBundle for given ciphersuite. Bundle advertises the rest of known suites
+/// in its capabilities.
+pub(crate) fn new_key_package_bundle(
+    ciphersuite: Ciphersuite,
+    credential_bundle: &CredentialBundle,
+    backend: &impl OpenMlsCryptoProvider,
+) -> anyhow::Result<KeyPackageBundle> {
+    let mut suites = vec![ciphersuite];
+    suites.extend(CIPHERSUITES.iter().filter(|s| **s != ciphersuite));
+    let bundle = KeyPackageBundle::new(&suites, credential_bundle, backend, vec![])?;
+    Ok(bundle)
+}
+
+#[cfg(test)]
+mod tests {
+    use openmls::prelude::Ciphersuite;
+    use openmls_rust_crypto::RustCrypto;
+
+    use super::{
+        ciphersuite_from_id, negotiable_ciphersuites, strongest_common_ciphersuite,
+        DEFAULT_CIPHERSUITE,
+    };
+
+    #[test]
+    fn test_negotiable_ciphersuites() {
+        let suites = negotiable_ciphersuites(&RustCrypto::default());
+        assert_eq!(
+            suites,
+            vec![
+                Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519,
+                DEFAULT_CIPHERSUITE,
+            ]
+        );
+        assert_eq!(
+            ciphersuite_from_id(suites[0] as u16),
+            Some(Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519)
+        );
+    }
+
+    #[test]
+    fn test_strongest_common_ciphersuite() {
+        let strong = Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+        let weak = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+        let own = [strong, weak];
+
+        let both: &[Ciphersuite] = &[weak, strong];
+        let only_weak: &[Ciphersuite] = &[weak];
+        let only_strong: &[Ciphersuite] = &[strong];
+        let no_members: [&[Ciphersuite]; 0] = [];
+
+        assert_eq!(
+            strongest_common_ciphersuite(&own, [both, both]),
+            Some(strong)
+        );
+        assert_eq!(
+            strongest_common_ciphersuite(&own, [both, only_weak]),
+            Some(weak)
+        );
+        assert_eq!(strongest_common_ciphersuite(&own, no_members), Some(strong));
+        assert_eq!(
+            strongest_common_ciphersuite(&own, [only_strong, only_weak]),
+            None
+        );
+    }
+}
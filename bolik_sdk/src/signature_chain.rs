@@ -817,6 +817,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_chain_audit() {
+        //
+        //   add A ─ add B ─ rm B
+        //
+
+        let a = get_device_a();
+        let b = get_device_b();
+        let a_id = get_device_id(&a.credential()).unwrap();
+        let b_id = get_device_id(&b.credential()).unwrap();
+
+        let backend_a = backend_of(&a);
+        let storage_a = storage_of(&a, &backend_a);
+
+        let mut chain_a = storage_a
+            .create_with_key(a.key_bundle.clone(), None)
+            .unwrap();
+        storage_a.add(&mut chain_a, vec![b.package()]).unwrap();
+        storage_a
+            .remove(
+                &mut chain_a,
+                vec![DeviceRemovedOp {
+                    key_ref: b.key_ref(),
+                    last_counter: 1,
+                }],
+            )
+            .unwrap();
+
+        let audits = chain_a.audit(&backend_a);
+        assert_eq!(audits.len(), 3);
+        assert!(audits.iter().all(|audit| audit.verified.is_ok()));
+        assert_eq!(audits[0].added, vec![a_id.clone()]);
+        assert_eq!(audits[1].added, vec![b_id.clone()]);
+        assert_eq!(audits[2].removed, vec![b_id.clone()]);
+        assert_eq!(audits[2].block.body.authored_by(), a_id);
+
+        // Tamper with the middle block
+        let mut chain_msg = chain_a.encode().unwrap();
+        chain_msg.blocks[1].body.as_mut().unwrap().account_ids = vec!["x".into(), "y".into()];
+        let tampered = SignatureChain::decode(chain_msg).unwrap();
+        assert!(tampered.verify(&backend_a).is_err());
+
+        let audits = tampered.audit(&backend_a);
+        assert!(audits[0].verified.is_ok());
+        assert!(audits[1].verified.is_err());
+        assert!(audits[2].verified.is_err());
+        // Removed device is still resolved
+        assert_eq!(audits[2].removed, vec![b_id]);
+    }
+
     fn db_conn() -> Result<(Connection, DbCipher)> {
         let conn_path = format!("file:mem{}?mode=memory", rand::random::<u16>());
         let conn = Connection::open(conn_path)?;
@@ -8,10 +8,12 @@ use crate::documents::{
     BIN_LABEL_ID,
 };
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct AclDoc {
     /// Account ID to rights mapping
     pub accounts: HashMap<String, AclRights>,
+    /// Account ID to the time when its rights expire
+    pub expires: HashMap<String, DateTime<Utc>>,
     /// Present when doc was moved to bin
     pub bolik_bin: Option<DateTime<Utc>>,
     /// Define how the doc should be sent to accounts it is shared with
@@ -50,7 +52,7 @@ impl PartialOrd for AclRights {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum AclOperationMode {
     /// Build list of participants from accounts map.
     Normal,
@@ -79,6 +81,7 @@ impl AclOperationMode {
 impl AclDoc {
     const FIELDS: &'static str = "fields";
     const ACCOUNTS: &'static str = "accounts";
+    const EXPIRES: &'static str = "expires";
     const MODE: &'static str = "mode";
 
     pub fn new(admin_id: impl Into<String>) -> Self {
@@ -86,6 +89,7 @@ impl AclDoc {
         accounts.insert(admin_id.into(), AclRights::Admin);
         Self {
             accounts,
+            expires: HashMap::new(),
             bolik_bin: None,
             mode: AclOperationMode::Normal,
         }
@@ -112,9 +116,11 @@ impl AclDoc {
 
     pub fn from_doc(doc: &yrs::Doc) -> Self {
         let accounts = Self::read_accounts(doc);
+        let expires = Self::read_expires(doc);
 
         let mut acl = Self {
             accounts,
+            expires,
             bolik_bin: None,
             mode: AclOperationMode::Normal,
         };
@@ -136,22 +142,50 @@ impl AclDoc {
         acl
     }
 
+    /// Check if given account id has any rights
+    pub fn allowed_to_read(&self, account_id: &str) -> bool {
+        self.rights(account_id).is_some()
+    }
+
     /// Check if given account id has rights to edit
     pub fn allowed_to_edit(&self, account_id: &str) -> bool {
-        self.accounts
-            .get(account_id)
+        self.rights(account_id)
             .map(|p| p >= &AclRights::Write)
             .unwrap_or(false)
     }
 
     /// Check if given account id has rights to admin
     pub fn allowed_to_admin(&self, account_id: &str) -> bool {
-        self.accounts
-            .get(account_id)
+        self.rights(account_id)
             .map(|p| p >= &AclRights::Admin)
             .unwrap_or(false)
     }
 
+    /// Return account rights unless they have expired.
+    fn rights(&self, account_id: &str) -> Option<&AclRights> {
+        if self.is_expired(account_id, Utc::now()) {
+            return None;
+        }
+        self.accounts.get(account_id)
+    }
+
+    /// Check if account rights have expired by given time.
+    pub fn is_expired(&self, account_id: &str, now: DateTime<Utc>) -> bool {
+        self.expires
+            .get(account_id)
+            .map(|expires_at| expires_at <= &now)
+            .unwrap_or(false)
+    }
+
+    /// List accounts which rights have expired by given time.
+    pub fn expired_accounts(&self, now: DateTime<Utc>) -> Vec<String> {
+        self.accounts
+            .keys()
+            .filter(|account_id| self.is_expired(account_id, now))
+            .cloned()
+            .collect()
+    }
+
     pub fn add(doc: &yrs::Doc, account_id: String, rights: AclRights) {
         let accounts = doc.get_or_insert_map(Self::ACCOUNTS);
         let txn = &mut doc.transact_mut();
@@ -160,8 +194,24 @@ impl AclDoc {
 
     pub fn remove(doc: &yrs::Doc, account_id: &str) {
         let accounts = doc.get_or_insert_map(Self::ACCOUNTS);
+        let expires = doc.get_or_insert_map(Self::EXPIRES);
         let txn = &mut doc.transact_mut();
         accounts.remove(txn, account_id);
+        expires.remove(txn, account_id);
+    }
+
+    /// Set when account rights expire. Rights never expire when `expires_at` is None.
+    pub fn set_expiry(doc: &yrs::Doc, account_id: &str, expires_at: Option<DateTime<Utc>>) {
+        let expires = doc.get_or_insert_map(Self::EXPIRES);
+        let txn = &mut doc.transact_mut();
+        match expires_at {
+            Some(at) => {
+                expires.insert(txn, account_id.to_string(), at.timestamp());
+            }
+            None => {
+                expires.remove(txn, account_id);
+            }
+        }
     }
 
     pub fn set_mode(doc: &yrs::Doc, mode: AclOperationMode) {
@@ -200,9 +250,30 @@ impl AclDoc {
         accounts
     }
 
+    fn read_expires(doc: &yrs::Doc) -> HashMap<String, DateTime<Utc>> {
+        let txn = &doc.transact();
+        let mut expires = HashMap::new();
+        if let Some(expires_map) = txn.get_map(Self::EXPIRES) {
+            for (account_id, v) in expires_map.iter(txn) {
+                if let Some(at) =
+                    int64_from_yrs(v).and_then(|secs| Utc.timestamp_opt(secs, 0).earliest())
+                {
+                    expires.insert(account_id.to_string(), at);
+                }
+            }
+        }
+        expires
+    }
+
     /// Build a list of participants (account ids the doc should be delivered to).
+    /// Accounts which rights have expired are excluded.
     pub fn participants(self) -> Vec<String> {
-        self.accounts.into_iter().map(|(k, _)| k).collect()
+        let now = Utc::now();
+        self.accounts
+            .keys()
+            .filter(|account_id| !self.is_expired(account_id, now))
+            .cloned()
+            .collect()
     }
 }
 
@@ -215,5 +286,9 @@ pub enum AclChange {
     Remove {
         account_id: String,
     },
+    SetExpiry {
+        account_id: String,
+        expires_at: Option<DateTime<Utc>>,
+    },
     MoveToBin,
 }
@@ -38,6 +38,7 @@ impl CardView {
     const CONTENT: &'static str = "content";
     const THUMBNAIL: &'static str = "thumbnail";
     const SECRETS: &'static str = "secrets";
+    const TITLE_MAX_CHARS: usize = 100;
 
     pub fn empty(account_id: impl Into<String>) -> Self {
         let now = Utc::now();
@@ -206,11 +207,7 @@ impl CardView {
                 CardChange::SetThumbnail(thumb) => {
                     thumb_map.clear(txn);
                     if let Some(thumb) = thumb {
-                        thumb_map.insert(txn, FileThumbnail::MIME_TYPE, thumb.mime_type);
-                        thumb_map.insert(txn, FileThumbnail::WIDTH, thumb.width);
-                        thumb_map.insert(txn, FileThumbnail::HEIGHT, thumb.height);
-                        thumb_map.insert(txn, FileThumbnail::DATA, thumb.data);
-                        thumb_map.insert(txn, FileThumbnail::FROM_CHECKSUM, thumb.from_checksum);
+                        thumb.insert_into(txn, &thumb_map);
                     }
                 }
                 CardChange::Format {
@@ -266,6 +263,22 @@ impl CardView {
 
         None
     }
+
+    /// First non-empty line of card text.
+    pub fn title(&self) -> Option<String> {
+        let mut text = String::new();
+        for block in &self.blocks {
+            match &block.view {
+                ContentView::Text(t) => text.push_str(&t.value),
+                ContentView::File(_) => text.push('\n'),
+            }
+        }
+
+        text.lines()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+            .map(|line| line.chars().take(Self::TITLE_MAX_CHARS).collect())
+    }
 }
 
 pub struct CleanupResult {
@@ -589,7 +602,7 @@ impl CardFile {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct FileThumbnail {
     pub mime_type: String,
     pub width: u32,
@@ -605,7 +618,7 @@ impl FileThumbnail {
     const DATA: &'static str = "data";
     const FROM_CHECKSUM: &'static str = "from_checksum";
 
-    fn from_map(txn: &yrs::Transaction, map: impl yrs::Map) -> Option<Self> {
+    pub(crate) fn from_map(txn: &yrs::Transaction, map: impl yrs::Map) -> Option<Self> {
         Some(Self {
             mime_type: map.get(txn, Self::MIME_TYPE)?.to_string(txn),
             width: uint_from_yrs(map.get(txn, Self::WIDTH)?)?,
@@ -614,6 +627,14 @@ impl FileThumbnail {
             from_checksum: map.get(txn, Self::FROM_CHECKSUM)?.to_string(txn),
         })
     }
+
+    pub(crate) fn insert_into(self, txn: &mut yrs::TransactionMut, map: &impl yrs::Map) {
+        map.insert(txn, Self::MIME_TYPE, self.mime_type);
+        map.insert(txn, Self::WIDTH, self.width);
+        map.insert(txn, Self::HEIGHT, self.height);
+        map.insert(txn, Self::DATA, self.data);
+        map.insert(txn, Self::FROM_CHECKSUM, self.from_checksum);
+    }
 }
 
 pub struct CardSecret {
@@ -878,6 +899,19 @@ mod tests {
         assert_eq!(vec!["Hi world!"], get_texts(&view));
     }
 
+    #[test]
+    fn test_card_title() {
+        let doc = CardView::init(1);
+        let (view, doc) = CardView::from_db(build_row(doc), None);
+        assert_eq!(view.title(), None);
+
+        append_text(&doc, "\n  \n");
+        CardView::edit(&doc, CardChange::append_text_heading("Shopping", 1));
+        append_text(&doc, "Milk\nBread");
+        let (view, _) = CardView::from_db(build_row(doc), None);
+        assert_eq!(view.title(), Some("Shopping".to_string()));
+    }
+
     #[test]
     fn test_card_text_edit_ru() {
         // Verify doc works with Russian and Emojis
@@ -0,0 +1,225 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, TimeZone, Utc};
+use lib0::any::Any;
+use uuid::Uuid;
+use yrs::{Array, Map, MapPrelim, ReadTxn, Transact};
+
+use crate::documents::{yrs_util::int64_from_yrs, DbDocRow};
+
+/// Comments thread of a card. Comments doc uses the same ACL as the card
+/// so that every card member (including readers) can comment.
+pub struct CardComments {
+    pub card_id: String,
+    pub comments: Vec<CardComment>,
+}
+
+impl CardComments {
+    const COMMENTS: &'static str = "comments";
+    const SUFFIX: &'static str = "/comments";
+
+    pub fn init(client_id: yrs::block::ClientID) -> yrs::Doc {
+        yrs::Doc::with_options(yrs::Options {
+            client_id,
+            offset_kind: yrs::OffsetKind::Utf32,
+            ..Default::default()
+        })
+    }
+
+    /// Build comments doc id from card id.
+    pub fn doc_id(card_id: &str) -> String {
+        format!("{}{}", card_id, Self::SUFFIX)
+    }
+
+    /// Extract card id from comments doc id.
+    pub fn card_id(doc_id: &str) -> Option<&str> {
+        doc_id.strip_suffix(Self::SUFFIX)
+    }
+
+    pub fn from_db(row: DbDocRow) -> Self {
+        let comments = Self::read_comments(&row.yrs);
+        Self {
+            card_id: Self::card_id(&row.meta.id)
+                .unwrap_or(&row.meta.id)
+                .to_string(),
+            comments,
+        }
+    }
+
+    pub fn read_comments(doc: &yrs::Doc) -> Vec<CardComment> {
+        let mut comments = vec![];
+        let txn = &doc.transact();
+        if let Some(array) = txn.get_array(Self::COMMENTS) {
+            for value in array.iter(txn) {
+                if let Some(comment) = CardComment::from_value(txn, value) {
+                    comments.push(comment);
+                }
+            }
+        }
+        comments
+    }
+
+    /// Compare comments with `before` and collect authors of changed comments.
+    pub fn changes(before: &[CardComment], doc: &yrs::Doc) -> CommentChanges {
+        let after = Self::read_comments(doc);
+        let mut changes = CommentChanges::default();
+        for comment in &after {
+            match before.iter().find(|c| c.id == comment.id) {
+                None => {
+                    changes.added.insert(comment.author_id.clone());
+                }
+                Some(old) if old == comment => {}
+                Some(old) => {
+                    let only_resolved = CardComment {
+                        resolved_at: comment.resolved_at,
+                        ..old.clone()
+                    } == *comment;
+                    if only_resolved {
+                        changes.resolved.insert(old.author_id.clone());
+                    } else {
+                        // Author field could have been changed too
+                        changes.edited.insert(old.author_id.clone());
+                        changes.edited.insert(comment.author_id.clone());
+                    }
+                }
+            }
+        }
+        for comment in before {
+            if !after.iter().any(|c| c.id == comment.id) {
+                changes.edited.insert(comment.author_id.clone());
+            }
+        }
+        changes
+    }
+
+    pub fn add(doc: &yrs::Doc, comment: &CardComment) {
+        let comment_prelim: MapPrelim<Any> = MapPrelim::from(HashMap::from([
+            (CardComment::ID.to_string(), comment.id.clone().into()),
+            (
+                CardComment::AUTHOR.to_string(),
+                comment.author_id.clone().into(),
+            ),
+            (CardComment::TEXT.to_string(), comment.text.clone().into()),
+            (
+                CardComment::CREATED_AT.to_string(),
+                comment.created_at.timestamp().into(),
+            ),
+        ]));
+        let comments = doc.get_or_insert_array(Self::COMMENTS);
+        let txn = &mut doc.transact_mut();
+        comments.push_back(txn, comment_prelim);
+    }
+
+    /// Mark comment as resolved or reopen it. Returns false if comment wasn't found.
+    pub fn set_resolved(doc: &yrs::Doc, comment_id: &str, resolved: bool) -> bool {
+        let comments = doc.get_or_insert_array(Self::COMMENTS);
+        let txn = &mut doc.transact_mut();
+        let Some(index) = Self::find_index(txn, &comments, comment_id) else {
+            return false;
+        };
+
+        if let Some(comment) = comments.get(txn, index).and_then(|v| v.to_ymap()) {
+            if resolved {
+                comment.insert(txn, CardComment::RESOLVED_AT, Utc::now().timestamp());
+            } else {
+                comment.remove(txn, CardComment::RESOLVED_AT);
+            }
+        }
+        true
+    }
+
+    /// Remove comment from the thread. Returns false if comment wasn't found.
+    pub fn remove(doc: &yrs::Doc, comment_id: &str) -> bool {
+        let comments = doc.get_or_insert_array(Self::COMMENTS);
+        let txn = &mut doc.transact_mut();
+        match Self::find_index(txn, &comments, comment_id) {
+            Some(index) => {
+                comments.remove(txn, index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Find the author of the comment.
+    pub fn author_id(doc: &yrs::Doc, comment_id: &str) -> Option<String> {
+        let txn = &doc.transact();
+        let comments = txn.get_array(Self::COMMENTS)?;
+        let index = Self::find_index(txn, &comments, comment_id)?;
+        comments
+            .get(txn, index)?
+            .to_ymap()?
+            .get(txn, CardComment::AUTHOR)
+            .map(|v| v.to_string(txn))
+    }
+
+    fn find_index(txn: &impl ReadTxn, comments: &yrs::ArrayRef, comment_id: &str) -> Option<u32> {
+        comments
+            .iter(txn)
+            .position(|v| {
+                v.to_ymap()
+                    .and_then(|m| m.get(txn, CardComment::ID))
+                    .map(|id| id.to_string(txn) == comment_id)
+                    .unwrap_or(false)
+            })
+            .map(|i| i as u32)
+    }
+}
+
+/// Authors of comments that were changed.
+#[derive(Default)]
+pub struct CommentChanges {
+    /// Comments were added
+    pub added: HashSet<String>,
+    /// Comments were edited or removed
+    pub edited: HashSet<String>,
+    /// Comments were only resolved or reopened
+    pub resolved: HashSet<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CardComment {
+    pub id: String,
+    pub author_id: String,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+    /// Present when comment was resolved
+    pub resolved_at: Option<DateTime<Utc>>,
+}
+
+impl CardComment {
+    const ID: &'static str = "id";
+    const AUTHOR: &'static str = "author";
+    const TEXT: &'static str = "text";
+    const CREATED_AT: &'static str = "created_at";
+    const RESOLVED_AT: &'static str = "resolved_at";
+
+    pub fn new(author_id: String, text: String) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            author_id,
+            text,
+            created_at: Utc::now(),
+            resolved_at: None,
+        }
+    }
+
+    fn from_value(txn: &impl ReadTxn, value: yrs::types::Value) -> Option<Self> {
+        let ymap = value.to_ymap()?;
+        let timestamp = |key: &str| {
+            ymap.get(txn, key)
+                .and_then(int64_from_yrs)
+                .and_then(|secs| Utc.timestamp_opt(secs, 0).earliest())
+        };
+        Some(Self {
+            id: ymap.get(txn, Self::ID)?.to_string(txn),
+            author_id: ymap.get(txn, Self::AUTHOR)?.to_string(txn),
+            text: ymap
+                .get(txn, Self::TEXT)
+                .map(|v| v.to_string(txn))
+                .unwrap_or_default(),
+            created_at: timestamp(Self::CREATED_AT)?,
+            resolved_at: timestamp(Self::RESOLVED_AT),
+        })
+    }
+}
@@ -8,8 +8,11 @@ use self::card::CardView;
 
 pub mod acl_doc;
 pub mod card;
+pub mod comments;
+pub mod shared_label;
 mod timeline_atom;
 
+pub(crate) use timeline_atom::{create_thumbnail, ThumbnailResult};
 pub use timeline_atom::{EditCardOpts, PermanentDeleteOpts, TimelineAtom, TimelineCtx};
 
 pub fn timeline_days(conn: &Connection, label_ids: Vec<String>) -> Result<Vec<String>> {
@@ -34,6 +37,7 @@ SELECT strftime('%Y-%m-%d', created_at) AS created_day
 
 pub fn timeline_by_day(
     conn: &Connection,
+    yrs_client_id: yrs::block::ClientID,
     day: &str,
     label_ids: Vec<String>,
 ) -> Result<TimelineDay> {
@@ -55,15 +59,18 @@ pub fn timeline_by_day(
         day: day.to_string(),
         cards: vec![],
     };
-    let yrs_client_id = 1; // Doesn't matter in this case
 
     while let Some(row) = rows.next()? {
         let id: String = row.get(0)?;
-        let data: Vec<u8> = row.get(1)?;
+        let data: Option<Vec<u8>> = row.get(1)?;
         let acl_data: Vec<u8> = row.get(2)?;
         let created_at = row.get(3)?;
         let edited_at = row.get(4)?;
-        let doc = documents::build_yrs_doc(yrs_client_id, &data)?;
+        let doc = match data {
+            Some(data) => documents::build_yrs_doc(yrs_client_id, &data)?,
+            // Card body hasn't been downloaded yet
+            None => yrs::Doc::new(),
+        };
         let acl = documents::build_yrs_doc(yrs_client_id, &acl_data)?;
 
         let labels_id: Option<String> = row.get(6)?;
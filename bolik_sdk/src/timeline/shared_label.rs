@@ -0,0 +1,103 @@
+use chrono::{DateTime, TimeZone, Utc};
+use yrs::{Map, ReadTxn, Transact};
+
+use crate::documents::{yrs_util::int64_from_yrs, DbDocRow};
+
+use super::acl_doc::AclDoc;
+
+/// A label that is shared with other accounts. Cards added to the label are shared with all label members.
+#[derive(Debug)]
+pub struct SharedLabelView {
+    pub id: String,
+    pub name: String,
+    /// Account that created the label
+    pub owner_id: String,
+    pub card_ids: Vec<String>,
+    pub acl: AclDoc,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SharedLabelView {
+    const FIELDS: &'static str = "fields";
+    const NAME: &'static str = "name";
+    const OWNER: &'static str = "owner";
+    const CARDS: &'static str = "cards";
+
+    pub fn init(client_id: yrs::block::ClientID, owner_id: &str) -> yrs::Doc {
+        let doc = yrs::Doc::with_options(yrs::Options {
+            client_id,
+            offset_kind: yrs::OffsetKind::Utf32,
+            ..Default::default()
+        });
+        let fields = doc.get_or_insert_map(Self::FIELDS);
+        {
+            let txn = &mut doc.transact_mut();
+            fields.insert(txn, Self::OWNER, owner_id);
+        }
+        doc
+    }
+
+    pub fn from_db(row: DbDocRow) -> Self {
+        let mut name = String::new();
+        let mut owner_id = String::new();
+        let mut card_ids = vec![];
+
+        {
+            let txn = &row.yrs.transact();
+            if let Some(fields) = txn.get_map(Self::FIELDS) {
+                if let Some(v) = fields.get(txn, Self::NAME) {
+                    name = v.to_string(txn);
+                }
+                if let Some(v) = fields.get(txn, Self::OWNER) {
+                    owner_id = v.to_string(txn);
+                }
+            }
+
+            if let Some(cards) = txn.get_map(Self::CARDS) {
+                let mut entries: Vec<_> = cards
+                    .iter(txn)
+                    .map(|(card_id, v)| {
+                        let added_at = int64_from_yrs(v)
+                            .and_then(|secs| Utc.timestamp_opt(secs, 0).earliest());
+                        (card_id.to_string(), added_at)
+                    })
+                    .collect();
+                entries.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+                card_ids = entries.into_iter().map(|(id, _)| id).collect();
+            }
+        }
+
+        Self {
+            id: row.meta.id,
+            name,
+            owner_id,
+            card_ids,
+            acl: AclDoc::from_doc(&row.acl),
+            created_at: row.meta.created_at,
+        }
+    }
+
+    pub fn set_name(doc: &yrs::Doc, name: String) {
+        let fields = doc.get_or_insert_map(Self::FIELDS);
+        let txn = &mut doc.transact_mut();
+        fields.insert(txn, Self::NAME, name);
+    }
+
+    pub fn add_card(doc: &yrs::Doc, card_id: String) {
+        let cards = doc.get_or_insert_map(Self::CARDS);
+        let txn = &mut doc.transact_mut();
+        if cards.get(txn, &card_id).is_none() {
+            cards.insert(txn, card_id, Utc::now().timestamp());
+        }
+    }
+
+    pub fn remove_card(doc: &yrs::Doc, card_id: &str) {
+        let cards = doc.get_or_insert_map(Self::CARDS);
+        let txn = &mut doc.transact_mut();
+        cards.remove(txn, card_id);
+    }
+
+    pub fn contains_card(&self, card_id: &str) -> bool {
+        self.card_ids.iter().any(|id| id == card_id)
+    }
+}
@@ -11,6 +11,7 @@ use image::{
 use uuid::Uuid;
 
 use crate::{
+    account::{AccNotification, NotificationStatus},
     blobs,
     documents::{self, DbDocRow, DbDocRowMeta},
     registry::{WithAccountAtom, WithBackend, WithDeviceAtom, WithDocsAtom, WithTxn},
@@ -19,9 +20,9 @@ use crate::{
 
 use super::{
     acl_doc::{AclChange, AclDoc},
-    card::{
-        CardChange, CardFile, CardLabels, CardLabelsChange, CardView, ContentView, FileThumbnail,
-    },
+    card::{CardChange, CardLabels, CardLabelsChange, CardView, ContentView, FileThumbnail},
+    comments::{CardComment, CardComments},
+    shared_label::SharedLabelView,
 };
 
 pub trait TimelineCtx<'a>: WithTxn<'a> + WithAccountAtom + WithDocsAtom + WithDeviceAtom {}
@@ -42,9 +43,12 @@ impl TimelineAtom {
         self.find_card(ctx, id)?.ok_or(anyhow!("Card not found"))
     }
 
-    fn find_card<'a>(&self, ctx: &impl TimelineCtx<'a>, id: &str) -> Result<Option<CardView>> {
-        // Read doc and doc labels
-        let row = ctx.docs().find(ctx, id)?;
+    pub fn find_card<'a>(&self, ctx: &impl TimelineCtx<'a>, id: &str) -> Result<Option<CardView>> {
+        // Read doc and doc labels. Fallback to a placeholder if card body hasn't been downloaded yet.
+        let row = match ctx.docs().find(ctx, id)? {
+            Some(row) => Some(row),
+            None => ctx.docs().find_placeholder(ctx, id)?,
+        };
         if let Some(row) = row {
             let labels_row = ctx.docs().find(ctx, &format!("{}/labels", id))?;
             Ok(Some(CardView::from_db(row, labels_row).0))
@@ -107,6 +111,9 @@ impl TimelineAtom {
         let mut doc_row = match ctx.docs().find(ctx, id)? {
             Some(row) => row,
             None => {
+                // Editing a placeholder would replace the card and its ACL
+                self.ensure_not_placeholder(ctx, id)?;
+
                 // If not found create a new one
                 let timeline_doc = CardView::init(ctx.device().yrs_client_id);
                 let created_at = opts.created_at.unwrap_or(Utc::now());
@@ -136,6 +143,7 @@ impl TimelineAtom {
         }
 
         CardView::edit(yrs_doc, opts.changes);
+        let acl_changed = !opts.acl_changes.is_empty();
 
         if acl_view.allowed_to_admin(&acc_id) {
             for change in opts.acl_changes.into_iter() {
@@ -145,6 +153,10 @@ impl TimelineAtom {
                         AclDoc::add(acl_doc, account_id, rights)
                     }
                     AclChange::Remove { account_id } => AclDoc::remove(acl_doc, &account_id),
+                    AclChange::SetExpiry {
+                        account_id,
+                        expires_at,
+                    } => AclDoc::set_expiry(acl_doc, &account_id, expires_at),
                     AclChange::MoveToBin => AclDoc::move_to_bin(acl_doc),
                 }
             }
@@ -160,6 +172,13 @@ impl TimelineAtom {
         doc_row.meta.edited_at = Utc::now();
         ctx.docs().save(ctx, &doc_row)?;
 
+        // Comments should reach the same accounts as the card
+        if acl_changed {
+            if let Some(comments_row) = ctx.docs().find(ctx, &CardComments::doc_id(id))? {
+                Self::save_comments(ctx, comments_row, &doc_row.acl)?;
+            }
+        }
+
         let labels_row = ctx.docs().find(ctx, &format!("{}/labels", id))?;
 
         // Index the card
@@ -179,6 +198,39 @@ impl TimelineAtom {
         changes: Vec<CardLabelsChange>,
     ) -> Result<CardView> {
         let acc_id = ctx.account().require_account_id(ctx)?;
+        self.ensure_not_placeholder(ctx, card_id)?;
+
+        // Keep shared labels in sync
+        for change in &changes {
+            match change {
+                CardLabelsChange::AddLabel { label_id } => {
+                    self.add_card_to_shared_label(ctx, &acc_id, label_id, card_id)?
+                }
+                CardLabelsChange::RemoveLabel { label_id } => {
+                    self.remove_card_from_shared_label(ctx, &acc_id, label_id, card_id)?
+                }
+            }
+        }
+
+        self.apply_card_labels(ctx, &acc_id, card_id, changes)
+    }
+
+    /// Fail if only a placeholder of the card is present (its body is still being fetched).
+    fn ensure_not_placeholder<'a>(&self, ctx: &impl TimelineCtx<'a>, card_id: &str) -> Result<()> {
+        if ctx.docs().find_placeholder(ctx, card_id)?.is_some() {
+            bail!("Card not synced yet");
+        }
+        Ok(())
+    }
+
+    /// Edit card labels doc of this account.
+    fn apply_card_labels<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        acc_id: &str,
+        card_id: &str,
+        changes: Vec<CardLabelsChange>,
+    ) -> Result<CardView> {
         let card_row = ctx
             .docs()
             .find(ctx, card_id)?
@@ -202,7 +254,7 @@ impl TimelineAtom {
                         counter: 0,
                     },
                     yrs: labels_doc,
-                    acl: AclDoc::init(ctx.device().yrs_client_id, &acc_id),
+                    acl: AclDoc::init(ctx.device().yrs_client_id, acc_id),
                 };
                 row
             }
@@ -213,7 +265,7 @@ impl TimelineAtom {
         let acl_doc = &doc_row.acl;
         let acl_view = AclDoc::from_doc(acl_doc);
 
-        if !acl_view.allowed_to_edit(&acc_id) {
+        if !acl_view.allowed_to_edit(acc_id) {
             bail!("This account is not allowed to edit");
         }
 
@@ -242,6 +294,334 @@ impl TimelineAtom {
         Ok(view)
     }
 
+    pub fn create_shared_label<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        name: String,
+    ) -> Result<SharedLabelView> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let yrs_doc = SharedLabelView::init(ctx.device().yrs_client_id, &acc_id);
+        SharedLabelView::set_name(&yrs_doc, name);
+
+        let now = Utc::now();
+        let row = DbDocRow {
+            meta: DbDocRowMeta {
+                id: Uuid::new_v4().to_string(),
+                created_at: now,
+                edited_at: now,
+                schema: DocSchema::SharedLabelV1 as i32,
+                author_device_id: ctx.device().id.clone(),
+                counter: ctx.device().increment_clock(ctx)?,
+            },
+            yrs: yrs_doc,
+            acl: AclDoc::init(ctx.device().yrs_client_id, &acc_id),
+        };
+        ctx.docs().save(ctx, &row)?;
+        Ok(SharedLabelView::from_db(row))
+    }
+
+    fn find_shared_label_row<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        id: &str,
+    ) -> Result<Option<DbDocRow>> {
+        match ctx.docs().find(ctx, id)? {
+            Some(row) if row.meta.schema == DocSchema::SharedLabelV1 as i32 => Ok(Some(row)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn find_shared_label<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        id: &str,
+    ) -> Result<Option<SharedLabelView>> {
+        Ok(self
+            .find_shared_label_row(ctx, id)?
+            .map(SharedLabelView::from_db))
+    }
+
+    /// List shared labels that were created by this account or accepted from other accounts.
+    pub fn list_shared_labels<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+    ) -> Result<Vec<SharedLabelView>> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let mut labels = vec![];
+        for row in ctx.docs().list_by_schema(ctx, DocSchema::SharedLabelV1)? {
+            let label = SharedLabelView::from_db(row);
+            if label.owner_id == acc_id {
+                labels.push(label);
+                continue;
+            }
+
+            let notification = AccNotification::LabelShare {
+                doc_id: label.id.clone(),
+            };
+            if ctx.account().notification_status(ctx, &notification.id())?
+                == NotificationStatus::Accepted
+            {
+                labels.push(label);
+            }
+        }
+        labels.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(labels)
+    }
+
+    /// Edit shared label name and members. Cards of the label are shared with new members
+    /// and removed members lose access to them.
+    pub fn edit_shared_label<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        id: &str,
+        name: Option<String>,
+        acl_changes: Vec<AclChange>,
+    ) -> Result<SharedLabelView> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let mut row = self
+            .find_shared_label_row(ctx, id)?
+            .ok_or(anyhow!("Shared label not found"))?;
+
+        let acl_view = AclDoc::from_doc(&row.acl);
+        if !acl_view.allowed_to_edit(&acc_id) {
+            bail!("This account is not allowed to edit");
+        }
+
+        if let Some(name) = name {
+            SharedLabelView::set_name(&row.yrs, name);
+        }
+
+        if !acl_changes.is_empty() && !acl_view.allowed_to_admin(&acc_id) {
+            bail!("This account not allowed to edit ACL");
+        }
+        for change in &acl_changes {
+            tracing::debug!(?change);
+            match change {
+                AclChange::Add { account_id, rights } => {
+                    AclDoc::add(&row.acl, account_id.clone(), rights.clone())
+                }
+                AclChange::Remove { account_id } => AclDoc::remove(&row.acl, account_id),
+                AclChange::SetExpiry {
+                    account_id,
+                    expires_at,
+                } => AclDoc::set_expiry(&row.acl, account_id, *expires_at),
+                AclChange::MoveToBin => {}
+            }
+        }
+
+        // Save doc
+        row.meta.author_device_id = ctx.device().id.clone();
+        row.meta.counter = ctx.device().increment_clock(ctx)?;
+        row.meta.edited_at = Utc::now();
+        ctx.docs().save(ctx, &row)?;
+        let label = SharedLabelView::from_db(row);
+
+        if acl_changes.is_empty() {
+            return Ok(label);
+        }
+
+        // Apply member changes to label cards
+        for card_id in &label.card_ids {
+            let card = match self.find_card(ctx, card_id)? {
+                Some(card) if card.acl.allowed_to_admin(&acc_id) => card,
+                _ => {
+                    tracing::debug!(card_id, "Skipping shared label card that we cannot admin");
+                    continue;
+                }
+            };
+
+            let mut card_changes = Self::label_acl_changes(&acc_id, &card, &label.acl);
+            for change in &acl_changes {
+                match change {
+                    AclChange::Remove { account_id }
+                        if account_id != &acc_id && card.acl.accounts.contains_key(account_id) =>
+                    {
+                        card_changes.push(AclChange::Remove {
+                            account_id: account_id.clone(),
+                        });
+                    }
+                    AclChange::SetExpiry {
+                        account_id,
+                        expires_at,
+                    } if account_id != &acc_id && card.acl.accounts.contains_key(account_id) => {
+                        card_changes.push(AclChange::SetExpiry {
+                            account_id: account_id.clone(),
+                            expires_at: *expires_at,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            if !card_changes.is_empty() {
+                self.edit_card_acl(ctx, card_id, card_changes)?;
+            }
+        }
+
+        Ok(label)
+    }
+
+    /// Build ACL changes that grant shared label members access to the card.
+    fn label_acl_changes(acc_id: &str, card: &CardView, label_acl: &AclDoc) -> Vec<AclChange> {
+        let now = Utc::now();
+        let mut changes = vec![];
+        for (account_id, rights) in &label_acl.accounts {
+            if account_id == acc_id || label_acl.is_expired(account_id, now) {
+                continue;
+            }
+
+            let has_rights = card
+                .acl
+                .accounts
+                .get(account_id)
+                .map(|r| r >= rights)
+                .unwrap_or(false);
+            if has_rights {
+                continue;
+            }
+
+            changes.push(AclChange::Add {
+                account_id: account_id.clone(),
+                rights: rights.clone(),
+            });
+            if let Some(expires_at) = label_acl.expires.get(account_id) {
+                changes.push(AclChange::SetExpiry {
+                    account_id: account_id.clone(),
+                    expires_at: Some(*expires_at),
+                });
+            }
+        }
+        changes
+    }
+
+    fn add_card_to_shared_label<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        acc_id: &str,
+        label_id: &str,
+        card_id: &str,
+    ) -> Result<()> {
+        let mut row = match self.find_shared_label_row(ctx, label_id)? {
+            Some(row) => row,
+            // Regular label
+            None => return Ok(()),
+        };
+
+        if !AclDoc::from_doc(&row.acl).allowed_to_edit(acc_id) {
+            bail!("This account is not allowed to edit the shared label");
+        }
+
+        let card = self.get_card(ctx, card_id)?;
+        if !card.acl.allowed_to_admin(acc_id) {
+            bail!("Only card admin can add the card to a shared label");
+        }
+
+        SharedLabelView::add_card(&row.yrs, card_id.to_string());
+        row.meta.author_device_id = ctx.device().id.clone();
+        row.meta.counter = ctx.device().increment_clock(ctx)?;
+        row.meta.edited_at = Utc::now();
+        ctx.docs().save(ctx, &row)?;
+
+        // Share the card with label members
+        let label = SharedLabelView::from_db(row);
+        let changes = Self::label_acl_changes(acc_id, &card, &label.acl);
+        if !changes.is_empty() {
+            self.edit_card_acl(ctx, card_id, changes)?;
+        }
+        Ok(())
+    }
+
+    fn remove_card_from_shared_label<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        acc_id: &str,
+        label_id: &str,
+        card_id: &str,
+    ) -> Result<()> {
+        let mut row = match self.find_shared_label_row(ctx, label_id)? {
+            Some(row) => row,
+            // Regular label
+            None => return Ok(()),
+        };
+
+        if !AclDoc::from_doc(&row.acl).allowed_to_edit(acc_id) {
+            bail!("This account is not allowed to edit the shared label");
+        }
+
+        // Card stays shared with label members. Collaborators could be edited separately.
+        SharedLabelView::remove_card(&row.yrs, card_id);
+        row.meta.author_device_id = ctx.device().id.clone();
+        row.meta.counter = ctx.device().increment_clock(ctx)?;
+        row.meta.edited_at = Utc::now();
+        ctx.docs().save(ctx, &row)?;
+        Ok(())
+    }
+
+    /// Find an active shared label that contains the card.
+    pub fn find_shared_label_for_card<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        card_id: &str,
+    ) -> Result<Option<SharedLabelView>> {
+        let label = self
+            .list_shared_labels(ctx)?
+            .into_iter()
+            .find(|label| label.contains_card(card_id));
+        Ok(label)
+    }
+
+    /// Add received cards of the shared label to the timeline. Return ids of added cards.
+    pub fn accept_shared_label_cards<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        label: &SharedLabelView,
+    ) -> Result<Vec<String>> {
+        let mut accepted = vec![];
+        for card_id in &label.card_ids {
+            if self.accept_shared_label_card(ctx, &label.id, card_id)? {
+                accepted.push(card_id.clone());
+            }
+        }
+        Ok(accepted)
+    }
+
+    /// Add a card from the shared label to the timeline. Cards shared via accepted labels
+    /// don't require a separate notification. Return false if card hasn't been received yet.
+    pub fn accept_shared_label_card<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        label_id: &str,
+        card_id: &str,
+    ) -> Result<bool> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let card = match ctx.docs().find(ctx, card_id)? {
+            Some(row) => {
+                let labels_row = ctx.docs().find(ctx, &format!("{}/labels", card_id))?;
+                CardView::from_db(row, labels_row).0
+            }
+            None => return Ok(false),
+        };
+        if card.labels.iter().any(|l| l.id == label_id) && self.is_indexed(ctx, card_id)? {
+            return Ok(false);
+        }
+
+        let notification = AccNotification::CardShare {
+            doc_id: card_id.to_string(),
+        };
+        ctx.account()
+            .delete_local_notification(ctx, &notification.id())?;
+
+        self.apply_card_labels(
+            ctx,
+            &acc_id,
+            card_id,
+            vec![CardLabelsChange::AddLabel {
+                label_id: label_id.to_string(),
+            }],
+        )?;
+        Ok(true)
+    }
+
     /// Permanently delete all cards that were moved to been earlier than `till`.
     pub fn empty_bin<'a>(
         &self,
@@ -254,14 +634,15 @@ impl TimelineAtom {
         // Go through all cards in the bin
         let mut stmt = ctx.txn().prepare(
             r#"
-    SELECT d.id, d.acl_data, d.created_at, d2.id, d2.data, d2.created_at
+    SELECT d.id, d.acl_data, d.created_at, d2.id, d2.data, d2.created_at, d3.id, d3.created_at
       FROM documents d
       JOIN card_index i ON d.id = i.id
       LEFT JOIN documents d2 ON d.id || '/labels' = d2.id
+      LEFT JOIN documents d3 ON d.id || '/comments' = d3.id
      WHERE d.schema = ? AND i.label_ids MATCH ?
      ORDER BY d.created_at DESC"#,
         )?;
-        let yrs_client_id = 1; // Doesn't matter in this case
+        let yrs_client_id = ctx.device().yrs_client_id;
 
         let delete_single = |row: &Row| -> Result<()> {
             // Find when card was added to the bin
@@ -273,6 +654,8 @@ impl TimelineAtom {
             let labels_id: Option<String> = row.get(3)?;
             let labels_bytes: Option<Vec<u8>> = row.get(4)?;
             let labels_created_at: Option<DateTime<Utc>> = row.get(5)?;
+            let comments_id: Option<String> = row.get(6)?;
+            let comments_created_at: Option<DateTime<Utc>> = row.get(7)?;
 
             // Check ACL doc
             let added_to_bin_at = if let Some(added_at) = AclDoc::in_bin_since(&acl) {
@@ -315,6 +698,15 @@ impl TimelineAtom {
                             deleted_at,
                         )?;
                     }
+                    if let Some(id) = comments_id {
+                        ctx.docs().add_to_deleted_queue(
+                            ctx,
+                            &acc_id,
+                            &id,
+                            comments_created_at.unwrap_or(deleted_at),
+                            deleted_at,
+                        )?;
+                    }
                 }
                 _ => {}
             }
@@ -334,6 +726,184 @@ impl TimelineAtom {
         Ok(())
     }
 
+    /// Remove collaborators which rights have expired from the cards this account administers.
+    /// Doc secrets shared with removed accounts are rotated. Returns IDs of modified cards.
+    /// Placeholders are checked once their bodies are fetched (see [`Self::apply_postponed_acl_changes`]).
+    pub fn remove_expired_collaborators<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+    ) -> Result<Vec<String>> {
+        let now = Utc::now();
+        let mut expired_cards = vec![];
+        for (card_id, acl) in self.admin_card_acls(ctx)? {
+            let expired = acl.expired_accounts(now);
+            if !expired.is_empty() {
+                expired_cards.push((card_id, expired));
+            }
+        }
+
+        let mut card_ids = vec![];
+        for (card_id, expired) in expired_cards {
+            tracing::info!(card_id, "Removing {} expired collaborators", expired.len());
+            for account_id in &expired {
+                ctx.docs().mark_secrets_obsolete(ctx, account_id)?;
+            }
+
+            let changes = expired
+                .into_iter()
+                .map(|account_id| AclChange::Remove { account_id })
+                .collect();
+            self.edit_card_acl(ctx, &card_id, changes)?;
+            card_ids.push(card_id);
+        }
+
+        Ok(card_ids)
+    }
+
+    /// Remove account from all cards and shared labels that this account can admin.
+    /// Returns IDs of modified cards.
+    pub fn remove_account_access<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        account_id: &str,
+    ) -> Result<Vec<String>> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        ctx.docs().mark_secrets_obsolete(ctx, account_id)?;
+
+        for label in self.list_shared_labels(ctx)? {
+            if label.acl.accounts.contains_key(account_id) && label.acl.allowed_to_admin(&acc_id) {
+                let change = AclChange::Remove {
+                    account_id: account_id.to_string(),
+                };
+                self.edit_shared_label(ctx, &label.id, None, vec![change])?;
+            }
+        }
+
+        // ACL of a placeholder is unknown until its body is fetched
+        for card_id in self.placeholder_card_ids(ctx)? {
+            ctx.txn().execute(
+                "INSERT OR IGNORE INTO postponed_acl_removals (doc_id, account_id) VALUES (?1, ?2)",
+                params![card_id, account_id],
+            )?;
+        }
+
+        let mut card_ids = vec![];
+        for (card_id, acl) in self.admin_card_acls(ctx)? {
+            if !acl.accounts.contains_key(account_id) {
+                continue;
+            }
+
+            tracing::info!(card_id, account_id, "Removing account from card");
+            let change = AclChange::Remove {
+                account_id: account_id.to_string(),
+            };
+            self.edit_card_acl(ctx, &card_id, vec![change])?;
+            card_ids.push(card_id);
+        }
+
+        Ok(card_ids)
+    }
+
+    /// List ACLs of cards that this account can admin.
+    fn admin_card_acls<'a>(&self, ctx: &impl TimelineCtx<'a>) -> Result<Vec<(String, AclDoc)>> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let mut stmt = ctx.txn().prepare(
+            r#"
+    SELECT id, acl_data
+      FROM documents
+     WHERE schema = ? AND data IS NOT NULL"#,
+        )?;
+        let mut rows = stmt.query([DocSchema::CardV1 as i32])?;
+
+        let mut acls = vec![];
+        while let Some(row) = rows.next()? {
+            let card_id: String = row.get(0)?;
+            let acl_bytes: Vec<u8> = row.get(1)?;
+            let acl = AclDoc::from_doc(&documents::build_yrs_doc(
+                ctx.device().yrs_client_id,
+                &acl_bytes,
+            )?);
+            if acl.allowed_to_admin(&acc_id) {
+                acls.push((card_id, acl));
+            }
+        }
+        Ok(acls)
+    }
+
+    /// List cards which bodies haven't been downloaded yet.
+    fn placeholder_card_ids<'a>(&self, ctx: &impl TimelineCtx<'a>) -> Result<Vec<String>> {
+        let mut stmt = ctx
+            .txn()
+            .prepare("SELECT id FROM documents WHERE schema = ? AND data IS NULL")?;
+        let mut rows = stmt.query([DocSchema::CardV1 as i32])?;
+        let mut ids = vec![];
+        while let Some(row) = rows.next()? {
+            ids.push(row.get(0)?);
+        }
+        Ok(ids)
+    }
+
+    /// Apply ACL changes that were postponed until the card body was downloaded:
+    /// remove accounts that were removed while the card was a placeholder and
+    /// collaborators which rights have expired. Returns true if ACL was modified.
+    pub fn apply_postponed_acl_changes<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        card_id: &str,
+    ) -> Result<bool> {
+        let Some(row) = ctx.docs().find(ctx, card_id)? else {
+            return Ok(false);
+        };
+        if row.meta.schema != DocSchema::CardV1 as i32 {
+            return Ok(false);
+        }
+
+        let mut removed: Vec<String> = {
+            let mut stmt = ctx
+                .txn()
+                .prepare("SELECT account_id FROM postponed_acl_removals WHERE doc_id = ?")?;
+            let mut rows = stmt.query([card_id])?;
+            let mut ids = vec![];
+            while let Some(row) = rows.next()? {
+                ids.push(row.get(0)?);
+            }
+            ids
+        };
+        ctx.txn().execute(
+            "DELETE FROM postponed_acl_removals WHERE doc_id = ?",
+            [card_id],
+        )?;
+
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let acl = AclDoc::from_doc(&row.acl);
+        if !acl.allowed_to_admin(&acc_id) {
+            return Ok(false);
+        }
+
+        removed.retain(|account_id| acl.accounts.contains_key(account_id));
+        for account_id in acl.expired_accounts(Utc::now()) {
+            ctx.docs().mark_secrets_obsolete(ctx, &account_id)?;
+            if !removed.contains(&account_id) {
+                removed.push(account_id);
+            }
+        }
+        if removed.is_empty() {
+            return Ok(false);
+        }
+
+        tracing::info!(
+            card_id,
+            "Removing {} accounts from fetched card",
+            removed.len()
+        );
+        let changes = removed
+            .into_iter()
+            .map(|account_id| AclChange::Remove { account_id })
+            .collect();
+        self.edit_card_acl(ctx, card_id, changes)?;
+        Ok(true)
+    }
+
     pub fn permanently_delete<'a>(
         &self,
         ctx: &impl TimelineCtx<'a>,
@@ -364,12 +934,187 @@ impl TimelineAtom {
 
         // Remove labels doc
         documents::delete_row(ctx.txn(), &format!("{}/labels", card_id))?;
+        // Remove comments doc
+        documents::delete_row(ctx.txn(), &CardComments::doc_id(card_id))?;
         // Remove card doc
         documents::delete_row(ctx.txn(), card_id)?;
 
         Ok(())
     }
 
+    pub fn list_comments<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        card_id: &str,
+    ) -> Result<Vec<CardComment>> {
+        match ctx.docs().find(ctx, &CardComments::doc_id(card_id))? {
+            Some(row) => Ok(CardComments::from_db(row).comments),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn add_comment<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        card_id: &str,
+        text: String,
+    ) -> Result<CardComment> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let comment = CardComment::new(acc_id.clone(), text);
+        self.edit_comments(ctx, &acc_id, card_id, |doc| {
+            CardComments::add(doc, &comment);
+            Ok(())
+        })?;
+        Ok(comment)
+    }
+
+    /// Resolve or reopen a comment. Allowed to comment author and card admins.
+    pub fn resolve_comment<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        card_id: &str,
+        comment_id: &str,
+        resolved: bool,
+    ) -> Result<()> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        let is_admin = ctx
+            .docs()
+            .find(ctx, card_id)?
+            .map(|row| AclDoc::from_doc(&row.acl).allowed_to_admin(&acc_id))
+            .unwrap_or(false);
+        self.edit_comments(ctx, &acc_id, card_id, |doc| {
+            match CardComments::author_id(doc, comment_id) {
+                Some(author_id) if author_id == acc_id || is_admin => {
+                    CardComments::set_resolved(doc, comment_id, resolved);
+                    Ok(())
+                }
+                Some(_) => bail!("Only the author or card admin can resolve a comment"),
+                None => bail!("Comment not found"),
+            }
+        })
+    }
+
+    /// Delete a comment. Only comment author is allowed to do that.
+    pub fn delete_comment<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        card_id: &str,
+        comment_id: &str,
+    ) -> Result<()> {
+        let acc_id = ctx.account().require_account_id(ctx)?;
+        self.edit_comments(ctx, &acc_id, card_id, |doc| {
+            match CardComments::author_id(doc, comment_id) {
+                Some(author_id) if author_id == acc_id => {
+                    CardComments::remove(doc, comment_id);
+                    Ok(())
+                }
+                Some(_) => bail!("Only the author can delete a comment"),
+                None => bail!("Comment not found"),
+            }
+        })
+    }
+
+    /// Apply changes to card comments. Any card member (including readers) is allowed to comment.
+    fn edit_comments<'a>(
+        &self,
+        ctx: &impl TimelineCtx<'a>,
+        acc_id: &str,
+        card_id: &str,
+        apply: impl FnOnce(&yrs::Doc) -> Result<()>,
+    ) -> Result<()> {
+        let card_row = ctx
+            .docs()
+            .find(ctx, card_id)?
+            .ok_or(anyhow!("Card not found"))?;
+        if !AclDoc::from_doc(&card_row.acl).allowed_to_read(acc_id) {
+            bail!("This account is not allowed to comment");
+        }
+
+        // Read from the database
+        let id = CardComments::doc_id(card_id);
+        let row = match ctx.docs().find(ctx, &id)? {
+            Some(row) => row,
+            None => {
+                // If not found create a new one
+                let now = Utc::now();
+                DbDocRow {
+                    meta: DbDocRowMeta {
+                        id,
+                        created_at: now,
+                        edited_at: now,
+                        schema: DocSchema::CardCommentsV1 as i32,
+                        author_device_id: "".into(),
+                        counter: 0,
+                    },
+                    yrs: CardComments::init(ctx.device().yrs_client_id),
+                    acl: AclDoc::init(ctx.device().yrs_client_id, acc_id),
+                }
+            }
+        };
+
+        apply(&row.yrs)?;
+        Self::save_comments(ctx, row, &card_row.acl)
+    }
+
+    /// Save comments doc with a copy of card ACL. Same participants mean that
+    /// comments are encrypted with the same secret as the card.
+    fn save_comments<'a>(
+        ctx: &impl TimelineCtx<'a>,
+        mut row: DbDocRow,
+        card_acl: &yrs::Doc,
+    ) -> Result<()> {
+        row.acl = documents::build_yrs_doc(
+            ctx.device().yrs_client_id,
+            &documents::encode_yrs_doc(card_acl),
+        )?;
+        row.meta.author_device_id = ctx.device().id.clone();
+        row.meta.counter = ctx.device().increment_clock(ctx)?;
+        row.meta.edited_at = Utc::now();
+        ctx.docs().save(ctx, &row)
+    }
+
+    /// Cancel upload of a file that is attached to a card in the bin.
+    /// The file is removed from the card and from the disk.
+    pub fn cancel_blob_upload<'a>(&self, ctx: &impl TimelineCtx<'a>, blob_id: &str) -> Result<()> {
+        let Some(blob) = blobs::find_by_id(ctx.txn(), blob_id, &ctx.device().id)? else {
+            bail!("Blob not found id={}", blob_id);
+        };
+        if blob.synced {
+            bail!("Blob has been uploaded already id={}", blob_id);
+        }
+        let Some(card_id) = &blob.card_id else {
+            bail!("Unknown card for blob id={}", blob_id);
+        };
+
+        let card = self.get_card(ctx, card_id)?;
+        if !card.labels.iter().any(|l| l.id == BIN_LABEL_ID) {
+            bail!("Card is not in the bin id={}", card_id);
+        }
+
+        // Remove from the end so that positions stay valid
+        let changes: Vec<_> = card
+            .blocks
+            .iter()
+            .rev()
+            .filter_map(|block| match &block.view {
+                ContentView::File(file) if file.blob_id == blob_id => Some(CardChange::Remove {
+                    position: block.position,
+                    len: 1,
+                }),
+                _ => None,
+            })
+            .collect();
+        if !changes.is_empty() {
+            self.edit_card(ctx, card_id, changes)?;
+        }
+
+        if let Err(err) = std::fs::remove_file(&blob.path) {
+            tracing::warn!("Failed to remove a file: {}", err);
+        }
+        blobs::rm_row(ctx.txn(), blob_id)?;
+        Ok(())
+    }
+
     /// Find first card after offset
     pub fn find_first<'a>(
         &self,
@@ -398,6 +1143,7 @@ impl TimelineAtom {
             .find(ctx, card_id)?
             .ok_or(anyhow!("Card not found"))?;
         let mut labels_row = ctx.docs().find(ctx, &format!("{}/labels", card_id))?;
+        let comments_row = ctx.docs().find(ctx, &CardComments::doc_id(card_id))?;
         let created_at = row.meta.created_at;
 
         // Generate new ID and reset ACL
@@ -425,7 +1171,17 @@ impl TimelineAtom {
             .permanently_delete(ctx, card_id, PermanentDeleteOpts { keep_blobs: true })
             .and_then(|_| {
                 ctx.docs()
-                    .add_to_deleted_queue(ctx, &acc_id, card_id, created_at, Utc::now())
+                    .add_to_deleted_queue(ctx, &acc_id, card_id, created_at, Utc::now())?;
+                if let Some(comments) = &comments_row {
+                    ctx.docs().add_to_deleted_queue(
+                        ctx,
+                        &acc_id,
+                        &comments.meta.id,
+                        comments.meta.created_at,
+                        Utc::now(),
+                    )?;
+                }
+                Ok(())
             })
         {
             tracing::warn!("Cannot permanently delete after restoring a copy: {}", err);
@@ -465,7 +1221,7 @@ impl TimelineAtom {
                     }
                 };
 
-                match create_thumbnail(&blob.path, &f) {
+                match create_thumbnail(&blob.path, &f.checksum, CARD_THUMBNAIL_SIZE) {
                     Ok(ThumbnailResult::Created(thumb)) => {
                         has_thumbnail = true;
                         changes.push(CardChange::SetThumbnail(Some(thumb)));
@@ -530,7 +1286,15 @@ pub struct EditCardOpts<'a> {
     pub skip_counter: bool,
 }
 
-fn create_thumbnail(file_path: &str, card_file: &CardFile) -> Result<ThumbnailResult> {
+/// Max width and height of card thumbnails.
+const CARD_THUMBNAIL_SIZE: u32 = 400;
+
+/// Create a square thumbnail that is at most `max_size` wide and high.
+pub(crate) fn create_thumbnail(
+    file_path: &str,
+    checksum: &str,
+    max_size: u32,
+) -> Result<ThumbnailResult> {
     // Read the file
     let reader = ImageReader::open(file_path)?;
     let img = match reader.decode() {
@@ -543,8 +1307,8 @@ fn create_thumbnail(file_path: &str, card_file: &CardFile) -> Result<ThumbnailRe
         }
     };
 
-    let max_width = 400;
-    let max_height = 400;
+    let max_width = max_size;
+    let max_height = max_size;
     if img.width() < max_width && img.height() < max_height {
         // Image is too small for thumbnails
         tracing::debug!("Not creating a thumbnail: Image is too small");
@@ -553,7 +1317,7 @@ fn create_thumbnail(file_path: &str, card_file: &CardFile) -> Result<ThumbnailRe
 
     // Generate a thumbnail
     tracing::debug!(
-        blob_id = card_file.blob_id,
+        checksum,
         "Generating thumbnail from {}x{}",
         img.width(),
         img.height()
@@ -588,7 +1352,7 @@ fn create_thumbnail(file_path: &str, card_file: &CardFile) -> Result<ThumbnailRe
         width: thumb.width(),
         height: thumb.height(),
         data: bytes,
-        from_checksum: card_file.checksum.clone(),
+        from_checksum: checksum.to_string(),
     }))
 }
 
@@ -631,7 +1395,7 @@ fn thumb_rotate(file_path: &str, thumb: &DynamicImage) -> Result<Option<DynamicI
     Ok(Some(thumb))
 }
 
-enum ThumbnailResult {
+pub(crate) enum ThumbnailResult {
     /// File is not an image or image is too small
     Skipped,
     /// Create new thumbnail
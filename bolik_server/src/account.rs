@@ -6,6 +6,7 @@ use axum::{
 use bolik_chain::SignatureChain;
 use bolik_migrations::rusqlite::{params, Connection, OptionalExtension};
 use bolik_proto::sync::response;
+use chrono::{DateTime, Utc};
 use hyper::StatusCode;
 use tracing::instrument;
 
@@ -45,6 +46,53 @@ pub async fn list_devices(
     Ok((StatusCode::OK, Protobuf(response)))
 }
 
+/// List when each account device was last seen. Only account devices can see this.
+#[axum::debug_handler]
+#[instrument(skip(app, current_device))]
+pub async fn list_sessions(
+    State(app): State<AppState>,
+    Extension(current_device): Extension<CurrentDevice>,
+    Path(account_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = app.conn.lock().unwrap();
+    let txn = conn.transaction().db_txn()?;
+    if find_account_id(&txn, &current_device.device_id)? != account_id {
+        return Err(UserError::NotFound("Account".into()).into());
+    }
+
+    let chain = get_account_chain(&txn, &account_id)?;
+    let members = chain
+        .members(&CryptoProvider::default())
+        .map_err(ServerError::SignatureChain)?;
+
+    let mut sessions = vec![];
+    for device_id in members.device_ids() {
+        let session = txn
+            .query_row(
+                r#"
+SELECT last_seen_at, platform, app_version
+  FROM device_sessions
+ WHERE device_id = ?"#,
+                params![device_id],
+                |row| {
+                    let last_seen_at: DateTime<Utc> = row.get(0)?;
+                    Ok(response::device_sessions::Session {
+                        device_id: device_id.to_string(),
+                        last_seen_at_sec: last_seen_at.timestamp(),
+                        platform: row.get(1)?,
+                        app_version: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .db_context("Find device session")?;
+        sessions.extend(session);
+    }
+
+    let response = response::DeviceSessions { sessions };
+    Ok((StatusCode::OK, Protobuf(response)))
+}
+
 pub fn find_account_id(conn: &Connection, device_id: &str) -> Result<String, AppError> {
     let account_id = conn
         .query_row(
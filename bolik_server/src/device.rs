@@ -5,6 +5,7 @@ use axum::{
 };
 use bolik_migrations::rusqlite::{params, Connection};
 use bolik_proto::sync::{response, KeyPackageMessage};
+use chrono::Utc;
 use hyper::StatusCode;
 use openmls::prelude::{KeyPackage, TlsDeserializeTrait, TlsSerializeTrait};
 use tracing::instrument;
@@ -92,3 +93,23 @@ pub fn db_device_packages(
     }
     Ok(packages)
 }
+
+/// Remember when the device made its last authenticated request.
+pub fn record_session(
+    conn: &Connection,
+    device_id: &str,
+    platform: Option<&str>,
+    app_version: Option<&str>,
+) -> Result<(), AppError> {
+    conn.execute(
+        r#"
+INSERT INTO device_sessions (device_id, last_seen_at, platform, app_version) VALUES (?1, ?2, ?3, ?4)
+    ON CONFLICT (device_id) DO UPDATE
+       SET last_seen_at = excluded.last_seen_at,
+           platform = coalesce(excluded.platform, platform),
+           app_version = coalesce(excluded.app_version, app_version)"#,
+        params![device_id, Utc::now(), platform, app_version],
+    )
+    .db_context("Record device session")?;
+    Ok(())
+}
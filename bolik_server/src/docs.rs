@@ -1,12 +1,13 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     Extension,
 };
 use bolik_migrations::rusqlite::{params, OptionalExtension, Row, ToSql};
-use bolik_proto::sync::{request, response, DeviceVectorClock};
+use bolik_proto::sync::{doc_payload::DocSchema, request, response, DeviceVectorClock};
 use chrono::{DateTime, TimeZone, Utc};
 use hyper::StatusCode;
+use serde::Deserialize;
 use tracing::instrument;
 
 use crate::{
@@ -125,12 +126,12 @@ DELETE
             .db_context("Delete account_docs (other account)")?;
         }
 
-        match &doc.body {
+        let inserted = match &doc.body {
             Some(request::doc_message::Body::Encrypted(body)) => {
-                txn.execute(
+                let inserted = txn.execute(
             r#"
-INSERT INTO account_docs (account_id, doc_id, author_device_id, counter, secret_id, payload, payload_signature, created_at)
-  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+INSERT INTO account_docs (account_id, doc_id, author_device_id, counter, secret_id, payload, payload_signature, created_at, schema)
+  VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
   ON CONFLICT (account_id, doc_id, author_device_id) DO NOTHING"#,
                     params![
                         to_acc_id,
@@ -141,6 +142,7 @@ INSERT INTO account_docs (account_id, doc_id, author_device_id, counter, secret_
                         body.payload,
                         doc.payload_signature,
                         created_at,
+                        doc.schema,
                     ],
                 )
                    .db_context("Insert account_doc")?;
@@ -162,6 +164,7 @@ INSERT INTO doc_blobs (blob_id, device_id, account_id, doc_id, author_device_id)
                     )
                     .db_context("Insert doc_blob")?;
                 }
+                inserted
             }
             Some(request::doc_message::Body::Deleted(body)) => {
                 let deleted_at = Utc
@@ -184,7 +187,7 @@ INSERT INTO account_docs (account_id, doc_id, author_device_id, counter, payload
                         deleted_at,
                     ],
                 )
-                   .db_context("Insert deleted account_doc")?;
+                   .db_context("Insert deleted account_doc")?
             }
             None => {
                 return Err(UserError::MissingField {
@@ -192,20 +195,65 @@ INSERT INTO account_docs (account_id, doc_id, author_device_id, counter, payload
                 }
                 .into());
             }
+        };
+
+        if inserted > 0 {
+            // Remember which versions this version covers (used for compaction)
+            for (device_id, counter) in &device_clock.vector {
+                txn.execute(
+                    r#"
+INSERT INTO account_doc_clocks (account_id, doc_id, author_device_id, clock_device_id, counter)
+  VALUES (?1, ?2, ?3, ?4, ?5)"#,
+                    params![
+                        to_acc_id,
+                        doc.id,
+                        current_device.device_id,
+                        device_id,
+                        counter
+                    ],
+                )
+                .db_context("Insert account_doc_clock")?;
+            }
         }
     }
 
+    // Author has seen its own version (used for compaction)
+    txn.execute(
+        r#"
+INSERT INTO device_doc_clocks (device_id, author_device_id, counter) VALUES (?1, ?1, ?2)
+  ON CONFLICT (device_id, author_device_id) DO UPDATE
+     SET counter = MAX(counter, excluded.counter)"#,
+        params![current_device.device_id, doc.counter],
+    )
+    .db_context("Upsert device_doc_clock")?;
+
     txn.commit().db_txn()?;
     Ok(StatusCode::CREATED)
 }
 
+#[derive(Deserialize)]
+pub struct ListParams {
+    /// Return only headers for cards created before this time.
+    headers_before_sec: Option<i64>,
+}
+
 #[axum::debug_handler]
 #[instrument(skip_all, fields(account_id))]
 pub async fn list(
     State(app): State<AppState>,
     Extension(current_device): Extension<CurrentDevice>,
+    Query(list_params): Query<ListParams>,
     Protobuf(clock): Protobuf<DeviceVectorClock>,
 ) -> Result<impl IntoResponse, AppError> {
+    let headers_before = match list_params.headers_before_sec {
+        Some(sec) => Some(
+            Utc.timestamp_opt(sec, 0)
+                .earliest()
+                .ok_or(UserError::InvalidCreatedAt(sec, 0))?,
+        ),
+        None => None,
+    };
+
     let mut conn = app.conn.lock().unwrap();
     let txn = conn.transaction().db_txn()?;
     let account_id = find_account_id(&txn, &current_device.device_id)?;
@@ -241,17 +289,30 @@ pub async fn list(
         )
     };
 
+    // Old cards are returned without the payload (client will fetch them on demand).
+    let card_schema = DocSchema::CardV1 as i32;
+    let payload_column = if headers_before.is_some() {
+        "CASE WHEN deleted_at IS NULL AND schema = ? AND created_at <= ? THEN NULL ELSE payload END"
+    } else {
+        "payload"
+    };
+
     // Fetch docs that are in the clock with higher counters and docs that are not in the clock.
     let query = format!(
         r#"
-SELECT doc_id, author_device_id, counter, secret_id, payload, payload_signature, created_at, deleted_at
+SELECT doc_id, author_device_id, counter, secret_id, {}, payload_signature, created_at, deleted_at, schema, LENGTH(payload)
   FROM account_docs
  WHERE account_id = ? {}
  ORDER BY counter
  LIMIT {}"#,
-        case_clause, LIMIT
+        payload_column, case_clause, LIMIT
     );
-    let mut query_params: Vec<&dyn ToSql> = vec![&account_id];
+    let mut query_params: Vec<&dyn ToSql> = vec![];
+    if let Some(before) = &headers_before {
+        query_params.push(&card_schema);
+        query_params.push(before);
+    }
+    query_params.push(&account_id);
     for (device_id, counter) in &clock.vector {
         query_params.push(device_id);
         query_params.push(counter);
@@ -276,6 +337,32 @@ SELECT doc_id, author_device_id, counter, secret_id, payload, payload_signature,
     Ok((StatusCode::OK, Protobuf(res)))
 }
 
+#[axum::debug_handler]
+#[instrument(skip_all)]
+pub async fn ack(
+    State(app): State<AppState>,
+    Extension(current_device): Extension<CurrentDevice>,
+    Protobuf(clock): Protobuf<DeviceVectorClock>,
+) -> Result<impl IntoResponse, AppError> {
+    let mut conn = app.conn.lock().unwrap();
+    let txn = conn.transaction().db_txn()?;
+
+    // Remember which versions this device has already processed (used for compaction)
+    for (author_device_id, counter) in &clock.vector {
+        txn.execute(
+            r#"
+INSERT INTO device_doc_clocks (device_id, author_device_id, counter) VALUES (?1, ?2, ?3)
+  ON CONFLICT (device_id, author_device_id) DO UPDATE
+     SET counter = MAX(counter, excluded.counter)"#,
+            params![current_device.device_id, author_device_id, counter],
+        )
+        .db_context("Upsert device_doc_clock")?;
+    }
+    txn.commit().db_commit()?;
+
+    Ok(StatusCode::OK)
+}
+
 #[axum::debug_handler]
 #[instrument(skip_all, fields(account_id, doc_id))]
 pub async fn get_version(
@@ -290,7 +377,7 @@ pub async fn get_version(
 
     // Find doc from author device.
     let query = r#"
-SELECT doc_id, author_device_id, counter, secret_id, payload, payload_signature, created_at, deleted_at
+SELECT doc_id, author_device_id, counter, secret_id, payload, payload_signature, created_at, deleted_at, schema, LENGTH(payload)
   FROM account_docs
  WHERE account_id = ?1 AND doc_id = ?2 AND author_device_id = ?3
  LIMIT 1"#;
@@ -328,11 +415,20 @@ fn read_account_docs_row(row: &Row) -> Result<response::DocVersion, AppError> {
                 deleted_at_sec: deleted_at.timestamp(),
             },
         ));
-    } else {
+    } else if let Some(payload) = row.get(4).db_context("Read payload")? {
         doc.body = Some(response::doc_version::Body::Encrypted(
             response::doc_version::EncryptedBody {
                 secret_id: row.get(3).db_context("Read secret_id")?,
-                payload: row.get(4).db_context("Read payload")?,
+                payload,
+            },
+        ));
+    } else {
+        let schema: Option<i32> = row.get(8).db_context("Read schema")?;
+        let size_bytes: Option<u64> = row.get(9).db_context("Read size_bytes")?;
+        doc.body = Some(response::doc_version::Body::Header(
+            response::doc_version::HeaderBody {
+                schema: schema.unwrap_or_default(),
+                size_bytes: size_bytes.unwrap_or(0),
             },
         ));
     }
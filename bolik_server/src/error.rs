@@ -39,8 +39,16 @@ pub enum AuthError {
     MissingDeviceIdHeader,
     #[error("Missing timestamp header")]
     MissingTimestampHeader,
+    #[error("Missing nonce header")]
+    MissingNonceHeader,
     #[error("Missing signature header")]
     MissingSignatureHeader,
+    #[error("Timestamp is not a number")]
+    MalformedTimestamp,
+    #[error("Timestamp is outside of allowed window (age={0}s)")]
+    ExpiredTimestamp(i64),
+    #[error("Request was already processed")]
+    ReplayedRequest,
     #[error("Bad signature")]
     BadSignature,
     #[error("Unknown device")]
@@ -70,6 +78,14 @@ pub enum UserError {
     InvalidBlobId,
     #[error("Blob is too big")]
     BlobTooBig,
+    #[error("Public link ID is in invalid format")]
+    InvalidLinkId,
+    #[error("Public link payload is too big")]
+    LinkTooBig,
+    #[error("Recovery kit ID is in invalid format")]
+    InvalidRecoveryKitId,
+    #[error("Recovery kit payload is too big")]
+    RecoveryKitTooBig,
     #[error("Blob is not uploaded blob_id={blob_id} device_id={device_id}")]
     MissingBlob { blob_id: String, device_id: String },
     #[error("{0} not found")]
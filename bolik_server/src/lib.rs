@@ -15,6 +15,8 @@ pub mod error;
 mod mailbox;
 mod migration;
 mod mls;
+mod public_links;
+mod recovery_kits;
 pub mod router;
 pub mod state;
 
@@ -43,10 +45,23 @@ async fn batch_jobs_task(state: AppState) {
     loop {
         tracing::info!("Running batch jobs");
 
+        match state.compact_account_docs() {
+            Ok(info) => {
+                tracing::info!("Compaction info: {:?}", info);
+            }
+            Err(err) => {
+                tracing::warn!("Cannot compact account docs: {}", err);
+            }
+        }
+
         if let Err(err) = state.mark_unused_blobs() {
             tracing::warn!("Cannot mark unused blobs: {}", err);
         }
 
+        if let Err(err) = state.cleanup_seen_requests() {
+            tracing::warn!("Cannot cleanup seen requests: {}", err);
+        }
+
         match state.cleanup_blobs(None).await {
             Ok(info) => {
                 tracing::info!("Cleanup info: {:?}", info);
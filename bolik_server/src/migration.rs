@@ -1,8 +1,9 @@
 use bolik_migrations::{rusqlite::Connection, MigrationError};
 
-const CHANGELOG: [(&str, &str); 1] = [(
-    "20220807",
-    r#"
+const CHANGELOG: [(&str, &str); 7] = [
+    (
+        "20220807",
+        r#"
 CREATE TABLE credentials (
   device_id TEXT PRIMARY KEY,
   data BLOB NOT NULL
@@ -79,7 +80,95 @@ CREATE TABLE mailbox_ack_stats (
   failed INT DEFAULT 0
 ) WITHOUT ROWID;
 "#,
-)];
+    ),
+    (
+        "20230215",
+        r#"
+-- Last doc counters (per author device) that each device has acknowledged
+CREATE TABLE device_doc_clocks (
+  device_id TEXT NOT NULL,
+  author_device_id TEXT NOT NULL,
+  counter INT NOT NULL,
+  PRIMARY KEY (device_id, author_device_id)
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230301",
+        r#"
+-- Doc schema (as reported by the client). Used to send only headers for large docs.
+ALTER TABLE account_docs ADD COLUMN schema INT;
+"#,
+    ),
+    (
+        "20230312",
+        r#"
+-- Public read-only card snapshots. Payload is encrypted with a key that only the link holders know.
+CREATE TABLE public_links (
+  id TEXT PRIMARY KEY,
+  account_id TEXT NOT NULL,
+  payload BLOB NOT NULL,
+  created_at TEXT NOT NULL,
+  updated_at TEXT NOT NULL
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230401",
+        r#"
+-- Account recovery kits. Payload is encrypted with a key derived from the recovery mnemonic.
+CREATE TABLE recovery_kits (
+  id TEXT PRIMARY KEY,
+  account_id TEXT NOT NULL,
+  payload BLOB NOT NULL,
+  created_at TEXT NOT NULL
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230408",
+        r#"
+-- Last authenticated request per device. Platform and app version are reported by the client.
+CREATE TABLE device_sessions (
+  device_id TEXT PRIMARY KEY,
+  last_seen_at TEXT NOT NULL,
+  platform TEXT,
+  app_version TEXT
+) WITHOUT ROWID;
+"#,
+    ),
+    (
+        "20230415",
+        r#"
+-- Recently authenticated requests. Used to reject replayed requests.
+CREATE TABLE seen_requests (
+  device_id TEXT NOT NULL,
+  timestamp INTEGER NOT NULL,
+  signature TEXT NOT NULL,
+  PRIMARY KEY (device_id, timestamp, signature)
+) WITHOUT ROWID;
+
+CREATE INDEX seen_requests_timestamp_idx ON seen_requests (timestamp);
+"#,
+    ),
+    (
+        "20230423",
+        r#"
+-- Vector clock of each doc version. Lets us find versions that newer versions cover.
+CREATE TABLE account_doc_clocks (
+  account_id TEXT NOT NULL,
+  doc_id TEXT NOT NULL,
+  author_device_id TEXT NOT NULL,
+  clock_device_id TEXT NOT NULL,
+  counter INT NOT NULL,
+  PRIMARY KEY (account_id, doc_id, author_device_id, clock_device_id),
+  FOREIGN KEY(account_id, doc_id, author_device_id)
+      REFERENCES account_docs(account_id, doc_id, author_device_id)
+      ON DELETE CASCADE
+) WITHOUT ROWID;
+"#,
+    ),
+];
 
 pub fn apply(conn: &Connection) -> Result<(), MigrationError> {
     bolik_migrations::apply(conn, &CHANGELOG)?;
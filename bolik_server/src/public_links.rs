@@ -0,0 +1,159 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension,
+};
+use bolik_migrations::rusqlite::{params, OptionalExtension};
+use bolik_proto::sync::request;
+use chrono::Utc;
+use hyper::{header, StatusCode};
+use tracing::instrument;
+
+use crate::{
+    account::find_account_id,
+    error::{AppError, DbContext, UserError},
+    router::CurrentDevice,
+    state::{AppState, Protobuf},
+};
+
+/// Snapshots contain only card text so they should be small.
+const MAX_PAYLOAD_SIZE: usize = 1024 * 1024;
+
+/// Page that fetches the snapshot and decrypts it with the key from URL fragment.
+const PAGE_HTML: &str = include_str!("../static/public_link.html");
+const PAGE_SCRIPT: &str = include_str!("../static/public_link.js");
+
+/// Only our script is allowed to run on public pages.
+const PAGE_CSP: &str = "default-src 'none'; script-src 'self'; style-src 'unsafe-inline'; connect-src 'self'; base-uri 'none'; form-action 'none'; frame-ancestors 'none'";
+
+#[axum::debug_handler]
+#[instrument(skip(app, current_device, payload))]
+pub async fn publish(
+    State(app): State<AppState>,
+    Extension(current_device): Extension<CurrentDevice>,
+    Path(link_id): Path<String>,
+    Protobuf(payload): Protobuf<request::PublishLink>,
+) -> Result<impl IntoResponse, AppError> {
+    verify_link_id(&link_id)?;
+    if payload.payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(UserError::LinkTooBig.into());
+    }
+
+    let conn = app.conn.lock().unwrap();
+    let account_id = find_account_id(&conn, &current_device.device_id)?;
+
+    // Only the account that created the link can replace the snapshot
+    let now = Utc::now();
+    let changed = conn
+        .execute(
+            r#"
+INSERT INTO public_links (id, account_id, payload, created_at, updated_at)
+  VALUES (?1, ?2, ?3, ?4, ?4)
+  ON CONFLICT (id) DO UPDATE
+     SET payload = excluded.payload,
+         updated_at = excluded.updated_at
+   WHERE account_id = excluded.account_id"#,
+            params![link_id, account_id, payload.payload, now],
+        )
+        .db_context("Save public link")?;
+
+    if changed == 0 {
+        return Err(UserError::NotFound("Public link".into()).into());
+    }
+    Ok(StatusCode::OK)
+}
+
+#[axum::debug_handler]
+#[instrument(skip(app, current_device))]
+pub async fn unpublish(
+    State(app): State<AppState>,
+    Extension(current_device): Extension<CurrentDevice>,
+    Path(link_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let conn = app.conn.lock().unwrap();
+    let account_id = find_account_id(&conn, &current_device.device_id)?;
+    conn.execute(
+        "DELETE FROM public_links WHERE id = ?1 AND account_id = ?2",
+        params![link_id, account_id],
+    )
+    .db_context("Delete public link")?;
+    Ok(StatusCode::OK)
+}
+
+/// Public (unauthenticated) page of the link.
+#[axum::debug_handler]
+#[instrument(skip(app))]
+pub async fn page(
+    State(app): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    {
+        let conn = app.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT 1 FROM public_links WHERE id = ?",
+            params![link_id],
+            |_row| Ok(()),
+        )
+        .optional()
+        .db_context("Find public link")?
+        .ok_or(UserError::NotFound("Public link".into()))?;
+    }
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/html; charset=utf-8"),
+            (header::CONTENT_SECURITY_POLICY, PAGE_CSP),
+            (header::REFERRER_POLICY, "no-referrer"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        PAGE_HTML,
+    ))
+}
+
+/// Public (unauthenticated) encrypted snapshot of the link.
+#[axum::debug_handler]
+#[instrument(skip(app))]
+pub async fn payload(
+    State(app): State<AppState>,
+    Path(link_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let payload: Vec<u8> = {
+        let conn = app.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT payload FROM public_links WHERE id = ?",
+            params![link_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .db_context("Find public link")?
+        .ok_or(UserError::NotFound("Public link".into()))?
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        payload,
+    ))
+}
+
+pub async fn script() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript; charset=utf-8")],
+        PAGE_SCRIPT,
+    )
+}
+
+fn verify_link_id(link_id: &str) -> Result<(), UserError> {
+    // Link ID is a UUID
+    if link_id.is_empty()
+        || link_id.len() > 64
+        || !link_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    {
+        return Err(UserError::InvalidLinkId);
+    }
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use axum::{
+    extract::{Path, State},
+    response::IntoResponse,
+    Extension,
+};
+use bolik_migrations::rusqlite::{params, OptionalExtension};
+use bolik_proto::sync::request;
+use chrono::Utc;
+use hyper::{header, StatusCode};
+use tracing::instrument;
+
+use crate::{
+    account::find_account_id,
+    error::{AppError, DbContext, UserError},
+    router::CurrentDevice,
+    state::{AppState, Protobuf},
+};
+
+/// Kit contains only a credential and a key package.
+const MAX_PAYLOAD_SIZE: usize = 64 * 1024;
+
+#[axum::debug_handler]
+#[instrument(skip(app, current_device, payload))]
+pub async fn upload(
+    State(app): State<AppState>,
+    Extension(current_device): Extension<CurrentDevice>,
+    Path(kit_id): Path<String>,
+    Protobuf(payload): Protobuf<request::UploadRecoveryKit>,
+) -> Result<impl IntoResponse, AppError> {
+    verify_kit_id(&kit_id)?;
+    if payload.payload.len() > MAX_PAYLOAD_SIZE {
+        return Err(UserError::RecoveryKitTooBig.into());
+    }
+
+    let mut conn = app.conn.lock().unwrap();
+    let account_id = find_account_id(&conn, &current_device.device_id)?;
+    let txn = conn.transaction().db_txn()?;
+
+    // Only the account that created the kit can replace it
+    let now = Utc::now();
+    let changed = txn
+        .execute(
+            r#"
+INSERT INTO recovery_kits (id, account_id, payload, created_at)
+  VALUES (?1, ?2, ?3, ?4)
+  ON CONFLICT (id) DO UPDATE
+     SET payload = excluded.payload
+   WHERE account_id = excluded.account_id"#,
+            params![kit_id, account_id, payload.payload, now],
+        )
+        .db_context("Save recovery kit")?;
+
+    if changed == 0 {
+        return Err(UserError::NotFound("Recovery kit".into()).into());
+    }
+
+    // New kit supersedes previous ones
+    txn.execute(
+        "DELETE FROM recovery_kits WHERE account_id = ? AND id != ?",
+        params![account_id, kit_id],
+    )
+    .db_context("Delete superseded recovery kits")?;
+    txn.commit().db_commit()?;
+    Ok(StatusCode::OK)
+}
+
+/// Public (unauthenticated) encrypted recovery kit. Device that fetches the kit
+/// doesn't belong to any account yet.
+#[axum::debug_handler]
+#[instrument(skip(app))]
+pub async fn fetch(
+    State(app): State<AppState>,
+    Path(kit_id): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    verify_kit_id(&kit_id)?;
+    let payload: Vec<u8> = {
+        let conn = app.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT payload FROM recovery_kits WHERE id = ?",
+            params![kit_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .db_context("Find recovery kit")?
+        .ok_or(UserError::NotFound("Recovery kit".into()))?
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream"),
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        payload,
+    ))
+}
+
+fn verify_kit_id(kit_id: &str) -> Result<(), UserError> {
+    // Kit ID is base58 encoded
+    if kit_id.is_empty() || kit_id.len() > 64 || !kit_id.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(UserError::InvalidRecoveryKitId);
+    }
+    Ok(())
+}
@@ -2,6 +2,7 @@ use std::time::Duration;
 
 use axum::{
     error_handling::HandleErrorLayer,
+    extract::DefaultBodyLimit,
     middleware::{self, Next},
     response::{IntoResponse, Response},
     routing::{delete, get, post, put},
@@ -9,7 +10,8 @@ use axum::{
 };
 use bolik_migrations::rusqlite::{params, OptionalExtension};
 use chrono::Utc;
-use hyper::{Body, Request, StatusCode};
+use hyper::{body::HttpBody, Body, Request, StatusCode};
+use multihash::{Blake3_256, Hasher};
 use openmls::prelude::{Credential, TlsDeserializeTrait};
 use openmls_rust_crypto::OpenMlsRustCrypto;
 use tower::{timeout::TimeoutLayer, ServiceBuilder};
@@ -21,9 +23,14 @@ use crate::{
     error::{AppError, AuthError, DbError, ServerError},
     mailbox,
     mls::read_signature,
+    public_links, recovery_kits,
     state::AppState,
 };
 
+/// Max size of request body. Authentication reads the body before the handlers
+/// hence the limit is enforced there as well.
+const MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
 pub fn router(state: AppState) -> Router {
     let state2 = state.clone();
     let api = Router::new()
@@ -32,18 +39,32 @@ pub fn router(state: AppState) -> Router {
         .route("/mailbox/ack/:message_id", delete(mailbox::ack_message))
         .route("/docs", post(docs::save))
         .route("/docs/list", post(docs::list))
+        .route("/docs/ack", post(docs::ack))
         .route("/docs/version/:id/:device_id", get(docs::get_version))
         .route("/blobs/upload", put(blobs::presign_upload))
         .route("/blobs/download", put(blobs::presign_download))
         .route("/account/:id/devices", get(account::list_devices))
+        .route("/account/:id/sessions", get(account::list_sessions))
         .route("/device/:id/packages", get(device::list_packages))
+        .route(
+            "/public-links/:id",
+            put(public_links::publish).delete(public_links::unpublish),
+        )
+        .route("/recovery-kits/:id", put(recovery_kits::upload))
         .route_layer(middleware::from_fn(move |req, next| {
             auth(req, next, state2.clone())
-        }));
+        }))
+        .layer(DefaultBodyLimit::max(MAX_BODY_SIZE));
 
     let app = Router::new()
         .nest("/api", api)
         .route("/", get(server_status))
+        // Public read-only links (no authentication)
+        .route("/p/:id", get(public_links::page))
+        .route("/p/:id/data", get(public_links::payload))
+        .route("/public-link.js", get(public_links::script))
+        // Recovery kits are fetched by devices without an account (no authentication)
+        .route("/recovery-kits/:id", get(recovery_kits::fetch))
         .with_state(state)
         .layer(
             ServiceBuilder::new()
@@ -112,14 +133,34 @@ pub struct CurrentDevice {
     pub device_id: String,
 }
 
-async fn auth<B>(
-    mut req: Request<B>,
-    next: Next<B>,
-    app: AppState,
-) -> Result<Response, StatusCode> {
+async fn auth(req: Request<Body>, next: Next<Body>, app: AppState) -> Result<Response, StatusCode> {
+    // Body is part of the signature
+    let (parts, body) = req.into_parts();
+    let body = read_body(body, MAX_BODY_SIZE).await?;
+    let mut req = Request::from_parts(parts, Body::from(body.clone()));
+
     // Verify signature
-    match verify_signature(&app, &req) {
+    match verify_signature(&app, &req, &body) {
         Ok(device_id) => {
+            let header = |name: &str| {
+                req.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(|s| s.to_string())
+            };
+            let (platform, app_version) = (header("platform"), header("app-version"));
+            if app.session_record_due(&device_id) {
+                let conn = app.conn.lock().unwrap();
+                if let Err(err) = device::record_session(
+                    &conn,
+                    &device_id,
+                    platform.as_deref(),
+                    app_version.as_deref(),
+                ) {
+                    tracing::warn!("Failed to record device session: {}", err);
+                }
+            }
+
             req.extensions_mut().insert(CurrentDevice { device_id });
             Ok(next.run(req).await)
         }
@@ -130,7 +171,27 @@ async fn auth<B>(
     }
 }
 
-fn verify_signature<B>(app: &AppState, req: &Request<B>) -> Result<String, AppError> {
+/// Read the whole body. Fail as soon as the body exceeds the limit.
+async fn read_body(mut body: Body, limit: usize) -> Result<hyper::body::Bytes, StatusCode> {
+    if body.size_hint().lower() > limit as u64 {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| {
+            tracing::debug!("Cannot read request body: {}", err);
+            StatusCode::BAD_REQUEST
+        })?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes.into())
+}
+
+fn verify_signature<B>(app: &AppState, req: &Request<B>, body: &[u8]) -> Result<String, AppError> {
     let headers = req.headers();
     let device_id = req
         .headers()
@@ -143,13 +204,24 @@ fn verify_signature<B>(app: &AppState, req: &Request<B>) -> Result<String, AppEr
         .get("timestamp")
         .and_then(|header| header.to_str().ok())
         .ok_or(AuthError::MissingTimestampHeader)?;
-    let signature = headers
+    // Clients without a nonce use the previous scheme where the body is not signed.
+    let nonce = headers.get("nonce").and_then(|header| header.to_str().ok());
+    if nonce.is_none() && !app.allow_legacy_signatures {
+        return Err(AuthError::MissingNonceHeader.into());
+    }
+    let signature_str = headers
         .get("signature")
         .and_then(|header| header.to_str().ok())
-        .and_then(|id| read_signature(id).ok())
         .ok_or(AuthError::MissingSignatureHeader)?;
+    let signature = read_signature(signature_str).map_err(|_| AuthError::MissingSignatureHeader)?;
 
-    // TODO: verify timestamp is not old (parse timestamp as unix seconds)
+    let timestamp_sec: i64 = timestamp
+        .parse()
+        .map_err(|_| AuthError::MalformedTimestamp)?;
+    let age = Utc::now().timestamp() - timestamp_sec;
+    if age.unsigned_abs() > app.request_max_age.as_secs() {
+        return Err(AuthError::ExpiredTimestamp(age).into());
+    }
 
     let req_path = format!("/api{}", req.uri().path());
     let credential_data: Option<Vec<u8>> = {
@@ -167,13 +239,22 @@ fn verify_signature<B>(app: &AppState, req: &Request<B>) -> Result<String, AppEr
         Some(data) => {
             let credential = Credential::tls_deserialize(&mut data.as_slice())
                 .map_err(|err| ServerError::MalformedCredential(format!("{}", err)))?;
+
             let mut payload = vec![];
             payload.extend(timestamp.as_bytes());
+            if let Some(nonce) = nonce {
+                payload.extend(nonce.as_bytes());
+            }
             payload.extend(req.method().as_str().as_bytes());
             payload.extend(req_path.as_bytes());
             if let Some(query) = req.uri().query() {
                 payload.extend(query.as_bytes());
             }
+            if nonce.is_some() {
+                let mut hasher = Blake3_256::default();
+                hasher.update(body);
+                payload.extend(hasher.finalize());
+            }
 
             let backend = &OpenMlsRustCrypto::default();
             credential
@@ -188,6 +269,24 @@ fn verify_signature<B>(app: &AppState, req: &Request<B>) -> Result<String, AppEr
         }
     }
 
+    // Reject exact replays of recent requests. Identical requests of clients without
+    // a nonce have identical signatures hence they cannot be told apart from replays.
+    if nonce.is_some() {
+        let inserted = {
+            let conn = app.conn.lock().unwrap();
+            conn.execute(
+                r#"
+INSERT INTO seen_requests (device_id, timestamp, signature) VALUES (?, ?, ?)
+    ON CONFLICT DO NOTHING"#,
+                params![device_id, timestamp_sec, signature_str],
+            )
+            .map_err(|err| DbError::new("Insert seen request", err))?
+        };
+        if inserted == 0 {
+            return Err(AuthError::ReplayedRequest.into());
+        }
+    }
+
     Ok(device_id)
 }
 
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     net::SocketAddr,
     sync::{Arc, Mutex}, time::Duration,
 };
@@ -31,6 +32,11 @@ pub struct AppConfig {
     pub s3_region: String,
     pub s3_endpoint: String,
     pub addr: SocketAddr,
+    /// Signed requests older than this are rejected
+    pub request_max_age: Duration,
+    /// Accept requests signed without a nonce and body hash (clients before request body
+    /// signing). Such requests can't be protected from replays hence this is off by default.
+    pub allow_legacy_signatures: bool,
 }
 
 impl AppConfig {
@@ -55,6 +61,17 @@ impl AppConfig {
             Err(_) => SocketAddr::from(([127, 0, 0, 1], 5030)),
         };
 
+        let max_age_str = Self::optional_env("REQUEST_MAX_AGE_SECS", "300");
+        let request_max_age = max_age_str.parse().map(Duration::from_secs).map_err(|_| {
+            SetupError::invalid_env_var(format!(
+                "REQUEST_MAX_AGE_SECS must be a number: REQUEST_MAX_AGE_SECS={}",
+                max_age_str
+            ))
+        })?;
+
+        let allow_legacy_signatures =
+            Self::optional_env("ALLOW_LEGACY_SIGNATURES", "false") == "true";
+
         Ok(Self {
             db_path,
             s3_creds: s3::creds::Credentials::new(
@@ -68,6 +85,8 @@ impl AppConfig {
             s3_region,
             s3_endpoint,
             addr,
+            request_max_age,
+            allow_legacy_signatures,
         })
     }
 
@@ -86,19 +105,58 @@ impl AppConfig {
     }
 }
 
+/// Device sessions are written to the database at most this often.
+const SESSION_RECORD_INTERVAL_SECS: i64 = 5 * 60;
+
 pub struct State {
     pub conn: Mutex<Connection>,
     pub bucket: Bucket,
+    pub request_max_age: Duration,
+    pub allow_legacy_signatures: bool,
+    /// When device sessions were last written to the database
+    recorded_sessions: Mutex<HashMap<String, DateTime<Utc>>>,
 }
 
 impl State {
-    pub fn new(conn: Connection, bucket: Bucket) -> Self {
+    pub fn new(
+        conn: Connection,
+        bucket: Bucket,
+        request_max_age: Duration,
+        allow_legacy_signatures: bool,
+    ) -> Self {
         Self {
             conn: Mutex::new(conn),
             bucket,
+            request_max_age,
+            allow_legacy_signatures,
+            recorded_sessions: Mutex::new(HashMap::new()),
         }
     }
 
+    /// Check if device session should be written to the database. Sessions are recorded
+    /// at most once per interval to avoid a database write on every request.
+    pub fn session_record_due(&self, device_id: &str) -> bool {
+        let now = Utc::now();
+        let mut recorded = self.recorded_sessions.lock().unwrap();
+        match recorded.get(device_id) {
+            Some(at) if (now - *at).num_seconds() < SESSION_RECORD_INTERVAL_SECS => false,
+            _ => {
+                recorded.insert(device_id.to_string(), now);
+                true
+            }
+        }
+    }
+
+    /// Forget requests that are too old to be replayed.
+    pub fn cleanup_seen_requests(&self) -> Result<usize, JobError> {
+        let oldest = Utc::now().timestamp() - self.request_max_age.as_secs() as i64;
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn
+            .execute("DELETE FROM seen_requests WHERE timestamp < ?", [oldest])
+            .db_context("Delete seen requests")?;
+        Ok(deleted)
+    }
+
     /// Mark unused blobs, blobs that are not referenced by doc_blobs table.
     pub fn mark_unused_blobs(&self) -> Result<(), JobError> {
         let now = Utc::now();
@@ -118,6 +176,74 @@ UPDATE blobs AS b
         Ok(())
     }
 
+    /// Delete doc versions authored by devices that were removed from their account.
+    ///
+    /// Removed device's version could hold edits that no other version has. Hence a version
+    /// is removed only when a version from a current device covers it (see account_doc_clocks)
+    /// and every remaining device of the account has acknowledged that newer version
+    /// (see device_doc_clocks). Doc blob references are cascaded, so this job should run
+    /// before marking unused blobs.
+    pub fn compact_account_docs(&self) -> Result<DocsCompactionInfo, JobError> {
+        let conn = self.conn.lock().unwrap();
+        let removed = conn
+            .execute(
+                r#"
+DELETE FROM account_docs
+ WHERE deleted_at IS NULL
+   AND NOT EXISTS (
+         -- Author device is no longer part of any account
+         SELECT 1
+           FROM signature_chain_members m
+           JOIN signature_chains c ON c.id = m.chain_id
+          WHERE c.is_account = 1 AND m.device_id = account_docs.author_device_id)
+   AND EXISTS (
+         SELECT 1
+           FROM signature_chain_members m
+          WHERE m.chain_id = account_docs.account_id)
+   AND EXISTS (
+         -- There is a newer version from a current device that covers this version
+         SELECT 1
+           FROM account_docs other
+           JOIN account_doc_clocks oc
+                ON oc.account_id = other.account_id
+               AND oc.doc_id = other.doc_id
+               AND oc.author_device_id = other.author_device_id
+           JOIN signature_chain_members m ON m.device_id = other.author_device_id
+           JOIN signature_chains c ON c.id = m.chain_id
+          WHERE c.is_account = 1
+            AND other.account_id = account_docs.account_id
+            AND other.doc_id = account_docs.doc_id
+            AND oc.clock_device_id = account_docs.author_device_id
+            AND oc.counter >= account_docs.counter
+            AND NOT EXISTS (
+                  -- Every account device has seen the newer version
+                  SELECT 1
+                    FROM signature_chain_members am
+                    LEFT JOIN device_doc_clocks k
+                           ON k.device_id = am.device_id
+                          AND k.author_device_id = other.author_device_id
+                   WHERE am.chain_id = account_docs.account_id
+                     AND (k.counter IS NULL OR k.counter < other.counter)))"#,
+                [],
+            )
+            .db_context("Compact account_docs")?;
+
+        // Forget clocks of devices that are no longer part of any account
+        conn.execute(
+            r#"
+DELETE FROM device_doc_clocks
+ WHERE device_id NOT IN (
+         SELECT m.device_id
+           FROM signature_chain_members m
+           JOIN signature_chains c ON c.id = m.chain_id
+          WHERE c.is_account = 1)"#,
+            [],
+        )
+        .db_context("Delete stale device_doc_clocks")?;
+
+        Ok(DocsCompactionInfo { removed })
+    }
+
     /// Delete blobs that have been unused for some time.
     #[instrument(skip_all, fields(since))]
     pub async fn cleanup_blobs(
@@ -239,7 +365,12 @@ pub async fn build_app_state(conf: AppConfig) -> Result<AppState, SetupError> {
     conn.pragma_update(None, "foreign_keys", "ON")?;
     migration::apply(&conn)?;
 
-    let state = Arc::new(State::new(conn, bucket));
+    let state = Arc::new(State::new(
+        conn,
+        bucket,
+        conf.request_max_age,
+        conf.allow_legacy_signatures,
+    ));
     Ok(state)
 }
 
@@ -297,3 +428,9 @@ pub struct BlobCleanupInfo {
     /// Amount of blobs that were restored, a doc_blob reference was found
     pub restored: u32,
 }
+
+#[derive(Default, Debug)]
+pub struct DocsCompactionInfo {
+    /// Amount of doc versions that were removed
+    pub removed: usize,
+}
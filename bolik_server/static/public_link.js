@@ -0,0 +1,70 @@
+// Fetch an encrypted card snapshot and decrypt it with the key from URL fragment.
+// Payload format: 12 bytes of nonce followed by AES-256-GCM ciphertext.
+(function () {
+  const NONCE_SIZE = 12;
+  const container = document.getElementById("card");
+
+  function showStatus(text) {
+    const p = document.createElement("p");
+    p.className = "status";
+    p.textContent = text;
+    container.replaceChildren(p);
+  }
+
+  function hexToBytes(hex) {
+    if (!/^([0-9a-f]{2})+$/i.test(hex)) {
+      return null;
+    }
+    const bytes = new Uint8Array(hex.length / 2);
+    for (let i = 0; i < bytes.length; i++) {
+      bytes[i] = parseInt(hex.substr(i * 2, 2), 16);
+    }
+    return bytes;
+  }
+
+  async function load() {
+    const keyBytes = hexToBytes(window.location.hash.slice(1));
+    if (!keyBytes) {
+      showStatus("This link is incomplete. Ask for the full link.");
+      return;
+    }
+
+    const res = await fetch(window.location.pathname + "/data", {
+      cache: "no-store",
+      referrerPolicy: "no-referrer",
+    });
+    if (res.status === 404) {
+      showStatus("This card is no longer shared.");
+      return;
+    }
+    if (!res.ok) {
+      showStatus("Failed to load the card.");
+      return;
+    }
+
+    const payload = new Uint8Array(await res.arrayBuffer());
+    const key = await crypto.subtle.importKey("raw", keyBytes, "AES-GCM", false, [
+      "decrypt",
+    ]);
+    let plaintext;
+    try {
+      plaintext = await crypto.subtle.decrypt(
+        { name: "AES-GCM", iv: payload.slice(0, NONCE_SIZE) },
+        key,
+        payload.slice(NONCE_SIZE)
+      );
+    } catch (err) {
+      showStatus("Failed to decrypt the card. The link might be wrong.");
+      return;
+    }
+
+    // HTML is produced by the app from card content. It contains no scripts
+    // and page CSP blocks everything except this file anyway.
+    container.innerHTML = new TextDecoder().decode(plaintext);
+  }
+
+  load().catch(function (err) {
+    console.error(err);
+    showStatus("Failed to load the card.");
+  });
+})();
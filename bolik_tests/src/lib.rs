@@ -86,6 +86,8 @@ pub async fn start_server() -> Result<TestServerResult> {
         s3_endpoint: format!("http://{}", s3_addr),
         s3_region: "eu-local".into(),
         addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+        request_max_age: Duration::from_secs(300),
+        allow_legacy_signatures: false,
     })
     .await?;
     let listener = TcpListener::bind("127.0.0.1:0".parse::<SocketAddr>()?)?;
@@ -109,13 +111,17 @@ pub async fn start_server() -> Result<TestServerResult> {
 }
 
 pub struct TestSdk {
-    #[allow(unused)]
     temp_dir: TempDir,
     pub sdk: DefaultSdk,
     pub output_rx: tokio::sync::broadcast::Receiver<OutputEvent>,
 }
 
 impl TestSdk {
+    /// Connect to the device database (bypassing the SDK).
+    pub fn get_conn(&self) -> Result<Connection> {
+        Ok(Connection::open(self.temp_dir.path().join("app.db"))?)
+    }
+
     pub async fn expect_synced(&mut self) -> Result<()> {
         match self.output().await? {
             OutputEvent::Synced => Ok(()),
@@ -173,9 +179,40 @@ impl TestSdk {
     }
 
     pub async fn output(&mut self) -> Result<OutputEvent> {
-        let event =
-            tokio::time::timeout(Duration::from_millis(1000), self.output_rx.recv()).await??;
-        Ok(event)
+        loop {
+            let event =
+                tokio::time::timeout(Duration::from_millis(1000), self.output_rx.recv()).await??;
+            // Progress events are not deterministic, skip them
+            if !matches!(event, OutputEvent::SyncProgress { .. }) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Wait for the next sync to complete and return all notifications that were emitted.
+    pub async fn collect_notifications(&mut self) -> Result<Vec<AccNotification>> {
+        let mut notifications = vec![];
+        loop {
+            match self.output().await? {
+                OutputEvent::Notification(n) => notifications.push(n),
+                OutputEvent::Synced => return Ok(notifications),
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for the next sync to complete and return all progress events that were emitted.
+    pub async fn collect_sync_progress(&mut self) -> Result<Vec<OutputEvent>> {
+        let mut progress = vec![];
+        loop {
+            let event =
+                tokio::time::timeout(Duration::from_millis(1000), self.output_rx.recv()).await??;
+            match event {
+                OutputEvent::SyncProgress { .. } => progress.push(event),
+                OutputEvent::Synced => return Ok(progress),
+                _ => {}
+            }
+        }
     }
 
     pub fn create_sample_card(&mut self, text: impl Into<String>) -> Result<CardView> {
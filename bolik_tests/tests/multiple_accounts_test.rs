@@ -4,18 +4,20 @@ use std::{
     path::Path,
 };
 
+use bolik_migrations::rusqlite::params;
 use bolik_proto::sync::DeviceVectorClock;
 use bolik_sdk::{
-    account::AccContact,
+    account::{AccContact, AccNotification, NotificationStatus},
     client::Client,
     output::OutputEvent,
     timeline::{
         acl_doc::AclRights,
-        card::{CardBlock, CardChange, CardText, ContentView},
+        card::{CardBlock, CardChange, CardLabelsChange, CardText, ContentView},
     },
     MoveToBinScope, BIN_LABEL_ID,
 };
 use chrono::Utc;
+use openmls::prelude::{Ciphersuite, KeyPackage, TlsDeserializeTrait};
 
 use bolik_tests as common;
 
@@ -45,6 +47,8 @@ async fn test_multiple_accounts_add_contact() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -123,6 +127,8 @@ async fn test_multiple_accounts_ignore_contact_request() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -183,6 +189,8 @@ async fn test_multiple_accounts_share_card() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -332,6 +340,8 @@ async fn test_multiple_accounts_ignore_card_share() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -509,6 +519,8 @@ async fn test_multiple_accounts_multiple_cards() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -627,6 +639,8 @@ async fn test_multiple_accounts_share_file() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -684,6 +698,8 @@ async fn test_multiple_accounts_concurrent_add() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -825,6 +841,8 @@ async fn test_multiple_accounts_concurrent_card_edit() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -933,13 +951,13 @@ async fn test_multiple_accounts_concurrent_card_edit() {
     // Now we have an account, two concurrent docs on the server side and two profiles
     let docs_a = sdk_a
         .client
-        .fetch_docs(&DeviceVectorClock::default())
+        .fetch_docs(&DeviceVectorClock::default(), None)
         .await
         .unwrap();
     assert_eq!(docs_a.docs.len(), 5);
     let docs_c = sdk_a
         .client
-        .fetch_docs(&DeviceVectorClock::default())
+        .fetch_docs(&DeviceVectorClock::default(), None)
         .await
         .unwrap();
     assert_eq!(docs_c.docs.len(), 5);
@@ -953,14 +971,14 @@ async fn test_multiple_accounts_concurrent_card_edit() {
 
     let docs_a = sdk_a
         .client
-        .fetch_docs(&DeviceVectorClock::default())
+        .fetch_docs(&DeviceVectorClock::default(), None)
         .await
         .unwrap();
     assert_eq!(docs_a.docs.len(), 4);
 
     let docs_c = sdk_a
         .client
-        .fetch_docs(&DeviceVectorClock::default())
+        .fetch_docs(&DeviceVectorClock::default(), None)
         .await
         .unwrap();
     assert_eq!(docs_c.docs.len(), 4);
@@ -992,6 +1010,8 @@ async fn test_multiple_accounts_permanent_deletion() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1165,6 +1185,8 @@ async fn test_multiple_accounts_blobs_cleanup() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1278,6 +1300,8 @@ async fn test_multiple_accounts_remove_collaborator() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1286,6 +1310,8 @@ async fn test_multiple_accounts_remove_collaborator() {
         .add_contact(AccContact {
             account_id: acc_3.id.clone(),
             name: "Custom Account 3".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1387,6 +1413,8 @@ async fn test_multiple_accounts_edit_card_by_non_contact() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1395,6 +1423,8 @@ async fn test_multiple_accounts_edit_card_by_non_contact() {
         .add_contact(AccContact {
             account_id: acc_3.id.clone(),
             name: "Custom Account 3".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1449,6 +1479,8 @@ async fn test_multiple_accounts_edit_card_by_non_contact() {
         .add_contact(AccContact {
             account_id: acc_4.id.clone(),
             name: "Custom Account 4".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1539,6 +1571,8 @@ async fn test_multiple_accounts_logout() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1612,6 +1646,8 @@ async fn test_multiple_accounts_local_notification_deleted() {
         .add_contact(AccContact {
             account_id: acc_2.id.clone(),
             name: "Custom Account 2".into(),
+            bio: String::new(),
+            avatar: None,
         })
         .await
         .unwrap();
@@ -1664,6 +1700,1194 @@ async fn test_multiple_accounts_local_notification_deleted() {
     sdk_d.expect_timeline_days(0).unwrap();
 }
 
+#[tokio::test]
+async fn test_multiple_accounts_card_rights() {
+    // Account 1: Device A (card owner)
+    // Account 2: Device B (collaborator)
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(None).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Share card with Write rights
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_1 = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_1 = sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let n1 = sdk_b.expect_notification().await.unwrap();
+    let n2 = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_b.accept_notification(&n1.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_b.accept_notification(&n2.id()).await.unwrap();
+    sdk_b.expect_doc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let card_text = |sdk: &common::TestSdk| match &sdk.get_card(&card_1.id).unwrap().blocks[0].view
+    {
+        ContentView::Text(t) => t.value.clone(),
+        _ => panic!("Expected Text content"),
+    };
+
+    // Write: edits are merged
+    sdk_b
+        .edit_card(&card_1.id, vec![CardChange::append_text(" write")])
+        .unwrap();
+    sdk_b.close_card(&card_1.id).unwrap();
+    assert_eq!(sdk_b.output().await.unwrap(), OutputEvent::TimelineUpdated);
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a.sync();
+    assert_eq!(sdk_a.output().await.unwrap(), OutputEvent::TimelineUpdated);
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(card_text(&sdk_a), "Hello write");
+    assert!(sdk_a.sync_diagnostics().unwrap().failed_docs.is_empty());
+
+    // Read: B edits the card before learning that it has lost Write rights
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b
+        .edit_card(&card_1.id, vec![CardChange::append_text(" read")])
+        .unwrap();
+    sdk_b.close_card(&card_1.id).unwrap();
+    assert_eq!(sdk_b.output().await.unwrap(), OutputEvent::TimelineUpdated);
+    sdk_b.expect_synced().await.unwrap();
+
+    // A should reject the edit
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(card_text(&sdk_a), "Hello write");
+
+    let failed_docs = sdk_a.sync_diagnostics().unwrap().failed_docs;
+    assert_eq!(failed_docs.len(), 1);
+    assert_eq!(failed_docs[0].doc_id, card_1.id);
+    assert_eq!(
+        failed_docs[0].reason.as_deref(),
+        Some(format!("Account={} has read-only access", acc_2.id).as_ref())
+    );
+
+    // Admin: B changes the ACL after A has revoked Admin rights
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Admin))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    sdk_b.expect_timeline_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_1.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_b.expect_timeline_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // A should reject the ACL change
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+
+    let acl = sdk_a.get_card(&card_1.id).unwrap().acl;
+    assert_eq!(Some(&AclRights::Admin), acl.accounts.get(&acc_1.id));
+    assert_eq!(Some(&AclRights::Write), acl.accounts.get(&acc_2.id));
+
+    let failed_docs = sdk_a.sync_diagnostics().unwrap().failed_docs;
+    assert_eq!(failed_docs.len(), 1);
+    assert_eq!(
+        failed_docs[0].reason.as_deref(),
+        Some(format!("Account={} is not allowed to change ACL", acc_2.id).as_ref())
+    );
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_deferred_card_rights() {
+    // Account 1: Device A (card owner), Device C (downloads only card headers)
+    // Account 2: Device B (collaborator)
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(None).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_1 = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Admin))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let n1 = sdk_b.expect_notification().await.unwrap();
+    let n2 = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&n1.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&n2.id()).await.unwrap();
+    sdk_b.expect_doc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // B changes the ACL after A has revoked Admin rights
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_1.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_b.expect_timeline_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // C defers the card body
+    let mut sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    sdk_c
+        .set_sync_window(Some(chrono::Duration::zero()))
+        .unwrap();
+    sdk_a.link_devices(&mut sdk_c).await.unwrap();
+    while sdk_c.output().await.unwrap() != OutputEvent::Synced {}
+    assert!(sdk_c.get_card(&card_1.id).unwrap().blocks.is_empty());
+
+    // Opening the card downloads all versions and rejects the ACL change
+    loop {
+        match sdk_c.output().await.unwrap() {
+            OutputEvent::DocUpdated { doc_id } if doc_id == card_1.id => break,
+            _ => {}
+        }
+    }
+
+    let acl = sdk_c.get_card(&card_1.id).unwrap().acl;
+    assert_eq!(Some(&AclRights::Admin), acl.accounts.get(&acc_1.id));
+    assert_eq!(Some(&AclRights::Write), acl.accounts.get(&acc_2.id));
+
+    let failed_docs = sdk_c.sync_diagnostics().unwrap().failed_docs;
+    assert_eq!(failed_docs.len(), 1);
+    assert_eq!(
+        failed_docs[0].reason.as_deref(),
+        Some(format!("Account={} is not allowed to change ACL", acc_2.id).as_ref())
+    );
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_remove_contact_deferred_card() {
+    // Account 1: Device A (card owner), Device C (downloads only card headers)
+    // Account 2: Device B (collaborator)
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(None).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_1 = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // C defers the card body
+    let mut sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    sdk_c
+        .set_sync_window(Some(chrono::Duration::zero()))
+        .unwrap();
+    sdk_a.link_devices(&mut sdk_c).await.unwrap();
+    while sdk_c.output().await.unwrap() != OutputEvent::Synced {}
+
+    // C removes the contact while the card is a placeholder
+    sdk_c.remove_contact(&acc_2.id).unwrap();
+    assert!(sdk_c.get_card(&card_1.id).unwrap().blocks.is_empty());
+
+    // Removal is applied once the card body is downloaded
+    loop {
+        match sdk_c.output().await.unwrap() {
+            OutputEvent::DocUpdated { doc_id } if doc_id == card_1.id => break,
+            _ => {}
+        }
+    }
+    let acl = sdk_c.get_card(&card_1.id).unwrap().acl;
+    assert!(!acl.accounts.contains_key(&acc_2.id));
+
+    // Owner device receives the change
+    sdk_c.sync();
+    sdk_c.expect_synced().await.unwrap();
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+    let acl = sdk_a.get_card(&card_1.id).unwrap().acl;
+    assert!(!acl.accounts.contains_key(&acc_2.id));
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_card_share_expiry() {
+    // Account 1: Device A (card owner)
+    // Account 2: Device B (collaborator)
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(None).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Share card with Write rights
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_1 = sdk_a.create_sample_card("Trip notes").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Own rights cannot expire
+    assert!(sdk_a
+        .edit_collaborator_expiry(&card_1.id, &acc_1.id, Some(Utc::now()))
+        .is_err());
+
+    // Share expires soon
+    let expires_at = Utc::now() + chrono::Duration::seconds(3);
+    let card_1 = sdk_a
+        .edit_collaborator_expiry(&card_1.id, &acc_2.id, Some(expires_at))
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert!(card_1.acl.expires.contains_key(&acc_2.id));
+
+    sdk_b.sync();
+    let n1 = sdk_b.expect_notification().await.unwrap();
+    let n2 = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_b.accept_notification(&n1.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_b.accept_notification(&n2.id()).await.unwrap();
+    sdk_b.expect_doc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let acl = sdk_b.get_card(&card_1.id).unwrap().acl;
+    assert_eq!(Some(&AclRights::Write), acl.accounts.get(&acc_2.id));
+    assert_eq!(
+        Some(expires_at.timestamp()),
+        acl.expires.get(&acc_2.id).map(|at| at.timestamp())
+    );
+
+    // Wait until the share expires
+    let wait = expires_at - Utc::now() + chrono::Duration::milliseconds(500);
+    if let Ok(wait) = wait.to_std() {
+        tokio::time::sleep(wait).await;
+    }
+
+    // B is no longer allowed to edit the card
+    assert!(sdk_b
+        .edit_card(&card_1.id, vec![CardChange::append_text(" late")])
+        .is_err());
+
+    // A removes expired collaborator
+    let card_ids = sdk_a.remove_expired_collaborators().unwrap();
+    assert_eq!(card_ids, vec![card_1.id.clone()]);
+    sdk_a.expect_synced().await.unwrap();
+
+    let acl = sdk_a.get_card(&card_1.id).unwrap().acl;
+    assert_eq!(acl.accounts.len(), 1);
+    assert!(acl.accounts.contains_key(&acc_1.id));
+    assert!(acl.expires.is_empty());
+
+    // Nothing else to remove
+    assert!(sdk_a.remove_expired_collaborators().unwrap().is_empty());
+    sdk_a.expect_synced().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_shared_label() {
+    // Account 1: Device A (label owner)
+    // Account 2: Device B (label member)
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(None).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Create a shared label with a card
+    let label = sdk_a.create_shared_label("Household".into()).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(label.owner_id, acc_1.id);
+
+    let card_1 = sdk_a.create_sample_card("Groceries").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_card_labels(
+            &card_1.id,
+            vec![CardLabelsChange::AddLabel {
+                label_id: label.id.clone(),
+            }],
+        )
+        .unwrap();
+
+    // Members need to be contacts
+    assert!(sdk_a
+        .edit_shared_label_members(
+            &label.id,
+            HashMap::from([("unknown".to_string(), Some(AclRights::Write))]),
+        )
+        .is_err());
+
+    // Adding a member shares existing cards
+    let label = sdk_a
+        .edit_shared_label_members(
+            &label.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(label.card_ids, vec![card_1.id.clone()]);
+    let acl = sdk_a.get_card(&card_1.id).unwrap().acl;
+    assert_eq!(Some(&AclRights::Write), acl.accounts.get(&acc_2.id));
+
+    // Adding a card to the label shares it with members
+    let card_2 = sdk_a.create_sample_card("Chores").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_2 = sdk_a
+        .edit_card_labels(
+            &card_2.id,
+            vec![CardLabelsChange::AddLabel {
+                label_id: label.id.clone(),
+            }],
+        )
+        .unwrap();
+    assert_eq!(Some(&AclRights::Write), card_2.acl.accounts.get(&acc_2.id));
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+
+    // B receives the label
+    sdk_b.sync();
+    let notifications = sdk_b.collect_notifications().await.unwrap();
+    let label_share = AccNotification::LabelShare {
+        doc_id: label.id.clone(),
+    };
+    let contact_request = AccNotification::ContactRequest {
+        account_id: acc_1.id.clone(),
+    };
+    assert!(notifications.contains(&label_share));
+    assert!(notifications.contains(&contact_request));
+    assert!(sdk_b.list_shared_labels().unwrap().is_empty());
+
+    sdk_b
+        .accept_notification(&contact_request.id())
+        .await
+        .unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Accepting the label accepts its cards
+    sdk_b.accept_notification(&label_share.id()).await.unwrap();
+    sdk_b.expect_timeline_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let labels = sdk_b.list_shared_labels().unwrap();
+    assert_eq!(labels.len(), 1);
+    assert_eq!(labels[0].name, "Household");
+    assert_eq!(sdk_b.list_notification_ids().unwrap(), Vec::<String>::new());
+    for card_id in [&card_1.id, &card_2.id] {
+        let card = sdk_b.get_card(card_id).unwrap();
+        assert!(card.labels.iter().any(|l| l.id == label.id));
+    }
+
+    // New cards in the label are accepted without a confirmation
+    let card_3 = sdk_a.create_sample_card("Recipes").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_card_labels(
+            &card_3.id,
+            vec![CardLabelsChange::AddLabel {
+                label_id: label.id.clone(),
+            }],
+        )
+        .unwrap();
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let notifications = sdk_b.collect_notifications().await.unwrap();
+    assert!(notifications.is_empty());
+    let card = sdk_b.get_card(&card_3.id).unwrap();
+    assert!(card.labels.iter().any(|l| l.id == label.id));
+
+    // Inbox tells about the added card
+    let inbox = sdk_b.list_inbox().unwrap();
+    let added = inbox
+        .iter()
+        .find(|n| {
+            n.notification
+                == AccNotification::LabelCardAdded {
+                    doc_id: card_3.id.clone(),
+                }
+        })
+        .unwrap();
+    assert_eq!(added.status, NotificationStatus::Missing);
+    assert_eq!(added.sender.as_ref().unwrap().account_id, acc_1.id);
+
+    // Removing a member revokes access to label cards
+    let label = sdk_a
+        .edit_shared_label_members(&label.id, HashMap::from([(acc_2.id.clone(), None)]))
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(label.acl.accounts.len(), 1);
+    for card_id in [&card_1.id, &card_2.id, &card_3.id] {
+        let acl = sdk_a.get_card(card_id).unwrap().acl;
+        assert!(!acl.accounts.contains_key(&acc_2.id));
+    }
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_contact_invite() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(Some("Account 1".into())).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let _acc_2 = sdk_b.create_account(Some("Account 2".into())).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let code = sdk_a
+        .create_contact_invite(chrono::Duration::days(1))
+        .unwrap();
+
+    // Invalid, expired and own invites are rejected
+    assert!(sdk_b.redeem_contact_invite("not-a-code").await.is_err());
+    assert!(sdk_a.redeem_contact_invite(&code).await.is_err());
+    let expired = sdk_a
+        .create_contact_invite(chrono::Duration::days(-1))
+        .unwrap();
+    assert!(sdk_b.redeem_contact_invite(&expired).await.is_err());
+
+    // Redeeming the invite adds a contact
+    let acc_2 = sdk_b.redeem_contact_invite(&code).await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    assert_eq!(acc_2.contacts.len(), 1);
+    assert_eq!(acc_2.contacts[0].account_id, acc_1.id);
+    assert_eq!(acc_2.contacts[0].name, "Account 1");
+
+    // Inviting account receives a contact request
+    sdk_a.sync();
+    let notification = sdk_a.expect_notification().await.unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(notification.id(), format!("contact-request/{}", acc_2.id));
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_contact_fingerprint() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(Some("Account 1".into())).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(Some("Account 2".into())).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Connect accounts
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "Account 2".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let notification = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&notification.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Both accounts see the same safety number
+    let fingerprint_a = sdk_a.contact_fingerprint(&acc_2.id).unwrap();
+    let fingerprint_b = sdk_b.contact_fingerprint(&acc_1.id).unwrap();
+    assert_eq!(fingerprint_a.safety_number, fingerprint_b.safety_number);
+    assert!(!fingerprint_a.verified);
+    assert!(sdk_a.contact_fingerprint("unknown").is_err());
+
+    // Verify contact
+    sdk_a.set_contact_verified(&acc_2.id, true).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert!(sdk_a.contact_fingerprint(&acc_2.id).unwrap().verified);
+
+    // Contact adds a device
+    let mut sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    sdk_b.link_devices(&mut sdk_c).await.unwrap();
+    sdk_c.expect_synced().await.unwrap();
+
+    sdk_a.sync();
+    let mut changed = vec![];
+    loop {
+        match sdk_a.output().await.unwrap() {
+            OutputEvent::VerifiedContactChanged { account_id } => changed.push(account_id),
+            OutputEvent::Synced => break,
+            _ => {}
+        }
+    }
+    assert_eq!(changed, vec![acc_2.id.clone()]);
+
+    let fingerprint = sdk_a.contact_fingerprint(&acc_2.id).unwrap();
+    assert!(!fingerprint.verified);
+    assert_ne!(fingerprint.safety_number, fingerprint_a.safety_number);
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_remove_and_block_contact() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(Some("Account 1".into())).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(Some("Account 2".into())).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let mut sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    let acc_3 = sdk_c.create_account(Some("Account 3".into())).unwrap();
+    sdk_c.expect_synced().await.unwrap();
+
+    // Share a card with second account
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "Account 2".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Only contacts can be removed
+    assert!(sdk_a.remove_contact(&acc_3.id).is_err());
+    assert!(sdk_a.remove_contact(&acc_1.id).is_err());
+
+    // Removing a contact revokes access to cards
+    let acc_1 = sdk_a.remove_contact(&acc_2.id).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert!(acc_1.contacts.is_empty());
+    assert!(acc_1.blocked.is_empty());
+    let card = sdk_a.get_card(&card.id).unwrap();
+    assert!(!card.acl.accounts.contains_key(&acc_2.id));
+
+    // Block third account before it reaches out
+    let acc_1 = sdk_a.block_account(&acc_3.id).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(acc_1.blocked, vec![acc_3.id.clone()]);
+
+    // Third account adds a contact and shares a card
+    sdk_c
+        .add_contact(AccContact {
+            account_id: acc_1.id.clone(),
+            name: "Account 1".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_c.expect_synced().await.unwrap();
+    let card_3 = sdk_c.create_sample_card("Spam").unwrap();
+    sdk_c.expect_synced().await.unwrap();
+    sdk_c
+        .edit_collaborators(
+            &card_3.id,
+            HashMap::from([(acc_1.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_c.expect_synced().await.unwrap();
+
+    // Blocked account requests and shares are ignored
+    sdk_a.sync();
+    let notifications = sdk_a.collect_notifications().await.unwrap();
+    assert!(notifications.is_empty());
+    assert!(sdk_a.get_card(&card_3.id).is_err());
+
+    // Unblocking
+    let acc_1 = sdk_a.unblock_account(&acc_3.id).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert!(acc_1.blocked.is_empty());
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_profile_avatar() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(Some("Account 1".into())).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(Some("Account 2".into())).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Connect accounts
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "Account 2".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let notification = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&notification.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Edit profile
+    sdk_a.edit_bio("Writes things down".into()).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a.edit_status("On vacation".into()).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut tmp_avatar = tempfile::NamedTempFile::new().unwrap();
+    tmp_avatar.write(&[1, 2, 3, 4, 5]).unwrap();
+    let tmp_avatar_path = tmp_avatar.into_temp_path();
+    sdk_a.set_avatar(&tmp_avatar_path).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let profile = sdk_a.get_profile(&acc_1.id).unwrap().unwrap();
+    let avatar = profile.avatar.unwrap();
+    assert_eq!(avatar.size_bytes, 5);
+    // Not an image
+    assert!(avatar.thumbnail.is_none());
+
+    // Contact sees the changes
+    sdk_b.sync();
+    sdk_b.expect_synced().await.unwrap();
+    let profile = sdk_b.get_profile(&acc_1.id).unwrap().unwrap();
+    assert_eq!(profile.name, "Account 1");
+    assert_eq!(profile.bio, "Writes things down");
+    assert_eq!(profile.status, "On vacation");
+    assert_eq!(profile.avatar.unwrap().blob_id, avatar.blob_id);
+
+    let acc_2 = sdk_b.get_account().unwrap();
+    let contact = acc_2
+        .contacts
+        .iter()
+        .find(|c| c.account_id == acc_1.id)
+        .unwrap();
+    assert_eq!(contact.bio, "Writes things down");
+    assert_eq!(contact.avatar.as_ref().unwrap().blob_id, avatar.blob_id);
+
+    // Download avatar
+    let res = sdk_b.download_avatar(&acc_1.id).unwrap();
+    assert!(res.path.is_none());
+    assert!(res.download_started);
+
+    let event = sdk_b.output().await.unwrap();
+    let downloaded_path = if let OutputEvent::DownloadCompleted { path, blob_id, .. } = event {
+        assert_eq!(blob_id, avatar.blob_id);
+        let file = std::fs::File::open(&path).unwrap();
+        assert_eq!(file.metadata().unwrap().len(), 5);
+        path
+    } else {
+        panic!("Expected DownloadCompleted but received {:?}", event);
+    };
+    let local_path = sdk_a.download_avatar(&acc_1.id).unwrap().path.unwrap();
+
+    // Remove avatar
+    sdk_a.remove_avatar().unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    assert!(!std::path::Path::new(&local_path).exists());
+    sdk_b.sync();
+    sdk_b.expect_synced().await.unwrap();
+    let profile = sdk_b.get_profile(&acc_1.id).unwrap().unwrap();
+    assert!(profile.avatar.is_none());
+    assert!(sdk_b.download_avatar(&acc_1.id).is_err());
+    assert!(!std::path::Path::new(&downloaded_path).exists());
+
+    let acc_2 = sdk_b.get_account().unwrap();
+    let contact = acc_2
+        .contacts
+        .iter()
+        .find(|c| c.account_id == acc_1.id)
+        .unwrap();
+    assert!(contact.avatar.is_none());
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_notification_inbox() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(Some("Account 1".into())).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(Some("Account 2".into())).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Contact request
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "Account 2".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let contact_request = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let inbox = sdk_b.list_inbox().unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].notification, contact_request);
+    assert_eq!(inbox[0].status, NotificationStatus::Missing);
+    assert_eq!(inbox[0].sender.as_ref().unwrap().name, "Account 1");
+
+    sdk_b
+        .accept_notification(&contact_request.id())
+        .await
+        .unwrap();
+    sdk_b.collect_notifications().await.unwrap();
+
+    // Card share
+    let card = sdk_a.create_sample_card("Groceries\nMilk").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let card_share = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&card_share.id()).await.unwrap();
+    sdk_b.collect_notifications().await.unwrap();
+
+    // Inbox keeps the history
+    let inbox = sdk_b.list_inbox().unwrap();
+    assert_eq!(inbox.len(), 2);
+    let shared = inbox.iter().find(|n| n.notification == card_share).unwrap();
+    assert_eq!(shared.status, NotificationStatus::Accepted);
+    assert_eq!(shared.title.as_deref(), Some("Groceries"));
+    assert_eq!(shared.sender.as_ref().unwrap().name, "Account 1");
+    let requested = inbox
+        .iter()
+        .find(|n| n.notification == contact_request)
+        .unwrap();
+    assert_eq!(requested.status, NotificationStatus::Accepted);
+
+    // Collaborator is allowed to edit
+    sdk_a
+        .edit_collaborators(
+            &card.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Write))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    sdk_b.collect_notifications().await.unwrap();
+    let inbox = sdk_b.list_inbox().unwrap();
+    assert_eq!(inbox.len(), 3);
+    assert_eq!(
+        inbox[0].notification,
+        AccNotification::EditorAdded {
+            doc_id: card.id.clone()
+        }
+    );
+    assert_eq!(inbox[0].status, NotificationStatus::Missing);
+
+    // Collaborator edits the card
+    sdk_b
+        .edit_card(&card.id, vec![CardChange::append_text("Bread")])
+        .unwrap();
+    sdk_b.close_card(&card.id).unwrap();
+    sdk_b.collect_notifications().await.unwrap();
+
+    sdk_a.sync();
+    sdk_a.collect_notifications().await.unwrap();
+    let inbox = sdk_a.list_inbox().unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(
+        inbox[0].notification,
+        AccNotification::CardEdited {
+            doc_id: card.id.clone()
+        }
+    );
+    assert_eq!(inbox[0].title.as_deref(), Some("Groceries"));
+    assert_eq!(inbox[0].sender.as_ref().unwrap().account_id, acc_2.id);
+
+    // Dismissing moves the notification to history
+    sdk_a
+        .ignore_notification(&inbox[0].notification.id())
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let inbox = sdk_a.list_inbox().unwrap();
+    assert_eq!(inbox.len(), 1);
+    assert_eq!(inbox[0].status, NotificationStatus::Ignored);
+    assert_eq!(inbox[0].sender.as_ref().unwrap().account_id, acc_2.id);
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_card_comments() {
+    // Account 1: Device A (card owner)
+    // Account 2: Device B (reader)
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(None).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Share card with Read rights
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card_1 = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card_1.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let n1 = sdk_b.expect_notification().await.unwrap();
+    let n2 = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_b.accept_notification(&n1.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_b.accept_notification(&n2.id()).await.unwrap();
+    sdk_b.expect_doc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Reader can comment
+    let comment_b = sdk_b.add_comment(&card_1.id, "Looks good".into()).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a.sync();
+    assert_eq!(sdk_a.expect_doc_updated().await.unwrap(), card_1.id);
+    sdk_a.expect_synced().await.unwrap();
+
+    let comments = sdk_a.list_comments(&card_1.id).unwrap();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].id, comment_b.id);
+    assert_eq!(comments[0].author_id, acc_2.id);
+    assert_eq!(comments[0].text, "Looks good");
+    assert_eq!(comments[0].resolved_at, None);
+    assert!(sdk_a.sync_diagnostics().unwrap().failed_docs.is_empty());
+
+    // Owner replies and resolves the comment
+    let comment_a = sdk_a.add_comment(&card_1.id, "Thanks".into()).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .resolve_comment(&card_1.id, &comment_b.id, true)
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Only the author can delete a comment
+    assert!(sdk_a.delete_comment(&card_1.id, &comment_b.id).is_err());
+
+    sdk_b.sync();
+    assert_eq!(sdk_b.expect_doc_updated().await.unwrap(), card_1.id);
+    sdk_b.expect_synced().await.unwrap();
+
+    let comments = sdk_b.list_comments(&card_1.id).unwrap();
+    assert_eq!(comments.len(), 2);
+    assert_eq!(comments[0].id, comment_b.id);
+    assert!(comments[0].resolved_at.is_some());
+    assert_eq!(comments[1].id, comment_a.id);
+    assert_eq!(comments[1].author_id, acc_1.id);
+
+    // Author deletes own comment
+    sdk_b.delete_comment(&card_1.id, &comment_b.id).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a.sync();
+    assert_eq!(sdk_a.expect_doc_updated().await.unwrap(), card_1.id);
+    sdk_a.expect_synced().await.unwrap();
+
+    let comments = sdk_a.list_comments(&card_1.id).unwrap();
+    assert_eq!(comments.len(), 1);
+    assert_eq!(comments[0].id, comment_a.id);
+}
+
+#[tokio::test]
+async fn test_multiple_accounts_upgrade_group_ciphersuites() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_1 = sdk_a.create_account(Some("Account 1".into())).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let acc_2 = sdk_b.create_account(Some("Account 2".into())).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Pretend that B is an older device that supports only the default ciphersuite
+    let conn = server.get_conn().unwrap();
+    let mut hidden_packages = vec![];
+    {
+        let mut stmt = conn
+            .prepare("SELECT ref, data FROM unused_key_packages WHERE device_id = ?")
+            .unwrap();
+        let mut rows = stmt.query([sdk_b.get_device_id()]).unwrap();
+        while let Some(row) = rows.next().unwrap() {
+            let key_ref: String = row.get(0).unwrap();
+            let data: Vec<u8> = row.get(1).unwrap();
+            let package = KeyPackage::tls_deserialize(&mut data.as_slice()).unwrap();
+            if package.ciphersuite() != Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519 {
+                hidden_packages.push((key_ref, data));
+            }
+        }
+    }
+    assert!(!hidden_packages.is_empty());
+    for (key_ref, _) in &hidden_packages {
+        conn.execute("DELETE FROM unused_key_packages WHERE ref = ?", [key_ref])
+            .unwrap();
+    }
+
+    // Connect accounts
+    sdk_a
+        .add_contact(AccContact {
+            account_id: acc_2.id.clone(),
+            name: "Account 2".into(),
+            bio: String::new(),
+            avatar: None,
+        })
+        .await
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let notification = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&notification.id()).await.unwrap();
+    sdk_b.expect_acc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let old_group = sdk_a.contact_group(&acc_2.id).unwrap();
+    assert_eq!(
+        sdk_b.contact_group(&acc_1.id).unwrap().group_id,
+        old_group.group_id
+    );
+
+    // B doesn't support a stronger ciphersuite yet
+    assert_eq!(sdk_a.upgrade_group_ciphersuites().await.unwrap(), 0);
+
+    // B was updated and published key packages for the stronger ciphersuite
+    for (key_ref, data) in &hidden_packages {
+        conn.execute(
+            "INSERT INTO unused_key_packages (ref, data, device_id) VALUES (?, ?, ?)",
+            params![key_ref, data, sdk_b.get_device_id()],
+        )
+        .unwrap();
+    }
+
+    // Both sides upgrade at the same time
+    assert_eq!(sdk_a.upgrade_group_ciphersuites().await.unwrap(), 1);
+    sdk_a.expect_synced().await.unwrap();
+    assert_eq!(sdk_b.upgrade_group_ciphersuites().await.unwrap(), 1);
+    sdk_b.expect_synced().await.unwrap();
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Both sides pick the same upgraded group
+    let group_a = sdk_a.contact_group(&acc_2.id).unwrap();
+    let group_b = sdk_b.contact_group(&acc_1.id).unwrap();
+    assert_ne!(group_a.group_id, old_group.group_id);
+    assert_eq!(group_a.group_id, group_b.group_id);
+    assert_eq!(group_a.authentication_secret, group_b.authentication_secret);
+
+    // Nothing left to upgrade
+    assert_eq!(sdk_a.upgrade_group_ciphersuites().await.unwrap(), 0);
+    assert_eq!(sdk_b.upgrade_group_ciphersuites().await.unwrap(), 0);
+
+    // Secrets are shared over the upgraded group
+    let card = sdk_a.create_sample_card("Upgraded").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a
+        .edit_collaborators(
+            &card.id,
+            HashMap::from([(acc_2.id.clone(), Some(AclRights::Read))]),
+        )
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    sdk_b.sync();
+    let notification = sdk_b.expect_notification().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b.accept_notification(&notification.id()).await.unwrap();
+    sdk_b.expect_doc_updated().await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    let card_b = sdk_b.get_card(&card.id).unwrap();
+    assert_eq!(card_b.id, card.id);
+}
+
 // TODO: after joining a group verify that all account devices are present (maybe one device was added/removed in the meantime)
 // TODO: ACL
 // TODO: share an account doc (should not mix different accounts)
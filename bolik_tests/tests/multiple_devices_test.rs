@@ -2,9 +2,10 @@ use std::{collections::HashSet, io::Write, path::Path};
 
 use bolik_proto::sync::DeviceVectorClock;
 use bolik_sdk::{
+    account::ChainEventKind,
     client::Client,
     output::OutputEvent,
-    timeline::card::{CardBlock, CardChange, CardText, ContentView},
+    timeline::card::{CardBlock, CardChange, CardLabelsChange, CardText, ContentView},
     MoveToBinScope, BIN_LABEL_ID,
 };
 use bolik_server::get_device_id;
@@ -294,7 +295,7 @@ async fn test_multiple_devices_concurrent_card_edit() {
     // Now we have an account, a profile and a doc on the server side
     let docs_a = sdk_a
         .client
-        .fetch_docs(&DeviceVectorClock::default())
+        .fetch_docs(&DeviceVectorClock::default(), None)
         .await
         .unwrap();
     assert_eq!(docs_a.docs.len(), 3);
@@ -444,7 +445,7 @@ async fn test_multiple_devices_remove_device() {
     sdk_b.expect_synced().await.unwrap();
 
     // Remove B
-    sdk_a.remove_device(sdk_b.get_device_id()).unwrap();
+    sdk_a.remove_device(sdk_b.get_device_id(), None).unwrap();
     sdk_a.expect_synced().await.unwrap();
     sdk_b.sync();
     assert_eq!(OutputEvent::LogOut, sdk_b.output().await.unwrap());
@@ -452,7 +453,423 @@ async fn test_multiple_devices_remove_device() {
     assert_eq!(None, sdk_b.get_account());
 }
 
+#[tokio::test]
+async fn test_multiple_devices_remove_device_missed_commit() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let _acc_a = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_b).await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    let card = sdk_b.create_sample_card("Secret").unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Remove B
+    sdk_a.remove_device(sdk_b.get_device_id(), None).unwrap();
+    while sdk_a.output().await.unwrap() != OutputEvent::Synced {}
+
+    // Pretend that B has never received the removal commit
+    let conn = server.get_conn().unwrap();
+    conn.execute(
+        "DELETE FROM device_mailbox WHERE device_id = ?",
+        [sdk_b.get_device_id()],
+    )
+    .unwrap();
+
+    // B finds out about the removal from the account chain once the periodic check is due
+    sdk_b
+        .get_conn()
+        .unwrap()
+        .execute(
+            "UPDATE device_settings SET membership_checked_at = NULL",
+            [],
+        )
+        .unwrap();
+    sdk_b.sync();
+    while sdk_b.output().await.unwrap() != OutputEvent::LogOut {}
+    sdk_b.expect_synced().await.unwrap();
+    assert_eq!(None, sdk_b.get_account());
+    assert!(sdk_b.get_card(&card.id).is_err());
+}
+
+#[tokio::test]
+async fn test_multiple_devices_chain_history() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_b).await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    sdk_a.remove_device(sdk_b.get_device_id(), None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let history = sdk_a.account_chain_history().unwrap();
+    let changes: Vec<_> = history
+        .iter()
+        .map(|e| {
+            (
+                e.kind,
+                e.device_name.as_deref(),
+                e.authored_by_name.as_deref(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        changes,
+        vec![
+            (ChainEventKind::DeviceAdded, Some("A"), Some("A")),
+            (ChainEventKind::DeviceAdded, Some("B"), Some("A")),
+            (ChainEventKind::DeviceRemoved, Some("B"), Some("A")),
+        ]
+    );
+    assert!(history.iter().all(|e| e.verified));
+    assert!(history[0].happened_at.is_some());
+    assert!(history[2].happened_at.is_some());
+}
+
+#[tokio::test]
+async fn test_multiple_devices_sessions_and_removal_reason() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_b).await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    let mut sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_c).await.unwrap();
+    sdk_c.expect_synced().await.unwrap();
+
+    // Server knows when each device was active
+    let devices = sdk_a.list_devices().await.unwrap();
+    assert_eq!(devices.len(), 3);
+    assert!(devices.iter().all(|d| d.last_seen_at.is_some()));
+    assert!(devices.iter().all(|d| d.platform.is_some()));
+    let current: Vec<_> = devices.iter().filter(|d| d.is_current).collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0].device.id, sdk_a.get_device_id());
+
+    // A removes B with a reason
+    sdk_a
+        .remove_device(sdk_b.get_device_id(), Some("Lost phone"))
+        .unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // C sees who removed B and why
+    sdk_c.sync();
+    while sdk_c.output().await.unwrap() != OutputEvent::Synced {}
+    let acc_c = sdk_c.get_account().unwrap();
+    assert_eq!(acc_c.devices.len(), 2);
+    assert_eq!(acc_c.removed_devices.len(), 1);
+    let removed = &acc_c.removed_devices[0];
+    assert_eq!(removed.id, sdk_b.get_device_id());
+    assert_eq!(removed.name.as_deref(), Some("B"));
+    assert_eq!(removed.removed_by, sdk_a.get_device_id());
+    assert_eq!(removed.reason.as_deref(), Some("Lost phone"));
+}
+
+#[tokio::test]
+async fn test_multiple_devices_compact_removed_device_docs() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let _acc_a = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_b).await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Create a card on A
+    let card = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Edit the card on B
+    sdk_b.sync();
+    assert_eq!(sdk_b.output().await.unwrap(), OutputEvent::TimelineUpdated);
+    sdk_b.expect_synced().await.unwrap();
+    sdk_b
+        .edit_card(&card.id, vec![CardChange::append_text(" from B")])
+        .unwrap();
+    sdk_b.close_card(&card.id).unwrap();
+    sdk_b.expect_synced().await.unwrap();
+
+    // Concurrently edit the card on A
+    sdk_a
+        .edit_card(&card.id, vec![CardChange::append_text(" from A")])
+        .unwrap();
+    sdk_a.close_card(&card.id).unwrap();
+    while sdk_a.output().await.unwrap() != OutputEvent::Synced {}
+
+    // Remove B
+    sdk_a.remove_device(sdk_b.get_device_id(), None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Acknowledge all versions
+    sdk_a.sync();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Merged state is not pushed by A hence B's version is the only one with B's edit
+    server.app.compact_account_docs().unwrap();
+
+    let expect_text = |sdk: &common::TestSdk| {
+        let card = sdk.get_card(&card.id).unwrap();
+        let ContentView::Text(text) = &card.blocks[0].view else {
+            panic!("Expected Text but got {:?}", card.blocks[0].view);
+        };
+        assert!(text.value.contains(" from A"), "text={}", text.value);
+        assert!(text.value.contains(" from B"), "text={}", text.value);
+    };
+
+    let mut sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_c).await.unwrap();
+    while sdk_c.output().await.unwrap() != OutputEvent::Synced {}
+    expect_text(&sdk_c);
+
+    // New version from A covers B's version
+    sdk_a
+        .edit_card(&card.id, vec![CardChange::append_text("!")])
+        .unwrap();
+    sdk_a.close_card(&card.id).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_c.sync();
+    while sdk_c.output().await.unwrap() != OutputEvent::Synced {}
+
+    server.app.compact_account_docs().unwrap();
+    let conn = server.get_conn().unwrap();
+    let count_b: u32 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM account_docs WHERE doc_id = ? AND author_device_id = ?",
+            [card.id.as_str(), sdk_b.get_device_id()],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(count_b, 0);
+
+    let mut sdk_d = common::run_sdk("D", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_d).await.unwrap();
+    while sdk_d.output().await.unwrap() != OutputEvent::Synced {}
+    expect_text(&sdk_c);
+    expect_text(&sdk_d);
+}
+
+#[tokio::test]
+async fn test_multiple_devices_deferred_card_bodies() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let card = sdk_a.create_sample_card("Old card").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Download only card headers on B
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_b
+        .set_sync_window(Some(chrono::Duration::zero()))
+        .unwrap();
+    sdk_a.link_devices(&mut sdk_b).await.unwrap();
+    while sdk_b.output().await.unwrap() != OutputEvent::Synced {}
+
+    // Card is present in the timeline but its body is missing
+    sdk_b.expect_timeline_cards(&[&card.id]).unwrap();
+
+    // Placeholder can't be edited until its body is fetched
+    let err = sdk_b
+        .edit_card(&card.id, vec![CardChange::append_text("!")])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Card not synced yet");
+    let err = sdk_b
+        .edit_card_labels(
+            &card.id,
+            vec![CardLabelsChange::AddLabel {
+                label_id: BIN_LABEL_ID.to_string(),
+            }],
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Card not synced yet");
+
+    let placeholder = sdk_b.get_card(&card.id).unwrap();
+    assert!(placeholder.blocks.is_empty());
+
+    // Opening the card downloads it
+    loop {
+        match sdk_b.output().await.unwrap() {
+            OutputEvent::DocUpdated { doc_id } if doc_id == card.id => break,
+            _ => {}
+        }
+    }
+    let card_b = sdk_b.get_card(&card.id).unwrap();
+    assert_eq!(card_b.blocks.len(), 1);
+    let ContentView::Text(text) = &card_b.blocks[0].view else {
+        panic!("Expected Text but got {:?}", card_b.blocks[0].view);
+    };
+    assert_eq!(text.value, "Old card");
+
+    // Nothing has failed
+    let diagnostics = sdk_b.sync_diagnostics().unwrap();
+    assert!(diagnostics.failed_docs.is_empty());
+}
+
+#[tokio::test]
+async fn test_multiple_devices_recover_account() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc_a = sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // A creates a recovery key
+    let mnemonic = sdk_a.create_recovery_key().await.unwrap();
+    assert_eq!(mnemonic.split_whitespace().count(), 24);
+    sdk_a.expect_synced().await.unwrap();
+    let acc_a = sdk_a.get_account().unwrap();
+    assert_eq!(acc_a.devices.len(), 2);
+    let recovery_a = acc_a
+        .devices
+        .iter()
+        .find(|d| d.name == "Recovery key")
+        .unwrap();
+
+    // All devices are lost. New device B recovers the account.
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    let new_mnemonic = sdk_b.recover_account(&mnemonic).await.unwrap();
+    assert_ne!(new_mnemonic, mnemonic);
+    sdk_b.expect_connected_account().await.unwrap();
+    while sdk_b.output().await.unwrap() != OutputEvent::Synced {}
+
+    // Used recovery member is replaced with a new one
+    let acc_b = sdk_b.get_account().unwrap();
+    assert_eq!(acc_b.id, acc_a.id);
+    assert_eq!(acc_b.devices.len(), 3);
+    assert!(acc_b.devices.iter().all(|d| d.id != recovery_a.id));
+    assert!(acc_b.devices.iter().any(|d| d.name == "Recovery key"));
+    let card_b = sdk_b.get_card(&card.id).unwrap();
+    assert_eq!(card_b.blocks.len(), 1);
+
+    // Mailbox of used recovery member is cleared and only the new kit is kept
+    let server_conn = server.get_conn().unwrap();
+    let mailbox_len: u32 = server_conn
+        .query_row(
+            "SELECT count(*) FROM device_mailbox WHERE device_id = ?",
+            [&recovery_a.id],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(mailbox_len, 0);
+    let kits: u32 = server_conn
+        .query_row("SELECT count(*) FROM recovery_kits", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(kits, 1);
+
+    // Recovery key can be used only once
+    let sdk_c = common::run_sdk("C", &server.addr).await.unwrap();
+    assert!(sdk_c.recover_account(&mnemonic).await.is_err());
+    assert!(sdk_c.get_account().is_none());
+
+    // New recovery key works
+    sdk_c.recover_account(&new_mnemonic).await.unwrap();
+    assert_eq!(sdk_c.get_account().unwrap().id, acc_a.id);
+}
+
+#[tokio::test]
+async fn test_multiple_devices_restore_backup() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    let card = sdk_a.create_sample_card("Hello").unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // Backups are opt-in
+    assert!(sdk_a.backup_now().is_err());
+
+    let mnemonic = sdk_a.create_recovery_key().await.unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a.enable_backups(&mnemonic).unwrap();
+    sdk_a.backup_now().unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    // New device B recovers the account without downloading card bodies
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_b
+        .set_sync_window(Some(chrono::Duration::zero()))
+        .unwrap();
+    let new_mnemonic = sdk_b.recover_account(&mnemonic).await.unwrap();
+    sdk_b.expect_connected_account().await.unwrap();
+    while sdk_b.output().await.unwrap() != OutputEvent::Synced {}
+
+    // Backup key is derived from the mnemonic that was used when creating the backup
+    assert!(sdk_b.restore_backup(&new_mnemonic).await.is_err());
+
+    // Card body comes from the backup
+    let res = sdk_b.restore_backup(&mnemonic).await.unwrap();
+    assert!(res.restored_docs > 0);
+    assert_eq!(res.missing_files, 0);
+    let card_b = sdk_b.get_card(&card.id).unwrap();
+    assert_eq!(card_b.blocks.len(), 1);
+    let diagnostics = sdk_b.sync_diagnostics().unwrap();
+    assert!(diagnostics.failed_docs.is_empty());
+
+    // Issuing a new key re-keys backups
+    let newer_mnemonic = sdk_a.create_recovery_key().await.unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_a.backup_now().unwrap();
+    sdk_a.expect_synced().await.unwrap();
+    sdk_b.sync();
+    while sdk_b.output().await.unwrap() != OutputEvent::Synced {}
+    assert!(sdk_b.restore_backup(&mnemonic).await.is_err());
+    sdk_b.restore_backup(&newer_mnemonic).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_multiple_devices_rotate_group_keys() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk_a = common::run_sdk("A", &server.addr).await.unwrap();
+    sdk_a.create_account(None).unwrap();
+    sdk_a.expect_synced().await.unwrap();
+
+    let mut sdk_b = common::run_sdk("B", &server.addr).await.unwrap();
+    sdk_a.link_devices(&mut sdk_b).await.unwrap();
+    sdk_b.expect_synced().await.unwrap();
+    let secret_before = sdk_a.account_group().unwrap().authentication_secret;
+
+    // A updates its leaf keys
+    assert_eq!(sdk_a.rotate_group_keys().unwrap(), 1);
+    sdk_a.expect_synced().await.unwrap();
+    let info_a = sdk_a.account_group().unwrap();
+    assert_ne!(info_a.authentication_secret, secret_before);
+
+    // B applies the commit
+    sdk_b.sync();
+    while sdk_b.output().await.unwrap() != OutputEvent::Synced {}
+    let info_b = sdk_b.account_group().unwrap();
+    assert_eq!(info_a.authentication_secret, info_b.authentication_secret);
+    assert_eq!(info_b.devices.len(), 2);
+
+    // Devices can still exchange docs
+    let card = sdk_b.create_sample_card("Hello").unwrap();
+    while sdk_b.output().await.unwrap() != OutputEvent::Synced {}
+    sdk_a.sync();
+    while sdk_a.output().await.unwrap() != OutputEvent::Synced {}
+    let card_a = sdk_a.get_card(&card.id).unwrap();
+    assert_eq!(card_a.blocks.len(), 1);
+}
+
 // TODO: concurrent file modification
 // TODO: test removing files on single device but not on the other (should keep blob refs to docs)
 // TODO: link a device, create a card, remove a device that created a card, link new device (new device should be able to decrypt the card)
-// TODO: self update package
@@ -1,13 +1,25 @@
 use std::{io::Write, path::Path};
 
-use bolik_proto::sync::{request, DeviceVectorClock};
+use bolik_migrations::rusqlite::params;
+use bolik_proto::{
+    prost::Message,
+    sync::{request, DeviceVectorClock, KeyPackageMessage},
+};
 use bolik_sdk::{
     client::Client,
-    output::OutputEvent,
+    output::{OutputEvent, SyncPhase},
     timeline::card::{CardChange, ContentView},
     MoveToBinScope, BIN_LABEL_ID,
 };
 use chrono::Utc;
+use hyper::{Body, Request, StatusCode};
+use multihash::{Blake3_256, Hasher};
+use openmls::prelude::{
+    Ciphersuite, CredentialBundle, CredentialType, KeyPackageBundle, SignaturePrivateKey,
+    TlsSerializeTrait,
+};
+use openmls_rust_crypto::OpenMlsRustCrypto;
+use tower::ServiceExt;
 
 use bolik_tests as common;
 
@@ -44,7 +56,7 @@ async fn test_single_device_create_account() {
 
     let docs = sdk
         .client
-        .fetch_docs(&DeviceVectorClock::default())
+        .fetch_docs(&DeviceVectorClock::default(), None)
         .await
         .unwrap();
     // Account and profile docs
@@ -205,6 +217,88 @@ async fn test_single_device_blob_server_cleanup() {
     assert_eq!(info.removed, 1);
 }
 
+#[tokio::test]
+async fn test_single_device_sync_progress() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk = common::run_sdk("A", &server.addr).await.unwrap();
+    let _acc = sdk.create_account(None).unwrap();
+    sdk.expect_synced().await.unwrap();
+
+    // Create a temporary file
+    let mut tmp_attachment = tempfile::NamedTempFile::new().unwrap();
+    tmp_attachment.write(&[1, 2, 3, 4, 5]).unwrap();
+    let tmp_attachment_path = tmp_attachment.into_temp_path();
+
+    // Create a card and attach a file
+    let card = sdk.create_card().unwrap();
+    let card = sdk.attach_file(&card.id, &tmp_attachment_path).unwrap();
+    sdk.close_card(&card.id).unwrap();
+
+    let progress = sdk.collect_sync_progress().await.unwrap();
+    let phases: Vec<_> = progress
+        .iter()
+        .filter_map(|e| match e {
+            OutputEvent::SyncProgress { phase, .. } => Some(*phase),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(phases.first(), Some(&SyncPhase::Mailbox));
+    assert!(phases.contains(&SyncPhase::FetchDocs));
+    assert!(phases.contains(&SyncPhase::UploadBlobs));
+    assert_eq!(phases.last(), Some(&SyncPhase::ProcessDocs));
+
+    // File was uploaded
+    match progress.last() {
+        Some(OutputEvent::SyncProgress { blobs_pending, .. }) => assert_eq!(*blobs_pending, 0),
+        _ => panic!("Expected SyncProgress"),
+    }
+}
+
+#[tokio::test]
+async fn test_single_device_sync_diagnostics() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk = common::run_sdk("A", &server.addr).await.unwrap();
+    let acc = sdk.create_account(None).unwrap();
+    sdk.expect_synced().await.unwrap();
+    assert!(sdk.sync_diagnostics().unwrap().failed_docs.is_empty());
+
+    // Insert a doc from unknown device
+    {
+        let conn = server.get_conn().unwrap();
+        conn.execute(
+            r#"
+INSERT INTO account_docs (account_id, doc_id, author_device_id, counter, secret_id, payload, payload_signature, created_at)
+  VALUES (?1, 'broken-doc', 'unknown-device', 1, 'secret', ?2, 'signature', ?3)"#,
+            params![acc.id, vec![1u8, 2, 3], Utc::now()],
+        )
+        .unwrap();
+    }
+
+    sdk.sync();
+    sdk.expect_synced().await.unwrap();
+
+    let diagnostics = sdk.sync_diagnostics().unwrap();
+    assert_eq!(diagnostics.failed_docs.len(), 1);
+    let failed = &diagnostics.failed_docs[0];
+    assert_eq!(failed.doc_id, "broken-doc");
+    assert_eq!(failed.author_device_id, "unknown-device");
+    assert_eq!(failed.tries, 1);
+    assert!(failed.reason.is_some());
+    assert!(failed.next_retry_at.unwrap() > Utc::now());
+
+    // Force a retry
+    sdk.retry_failed_doc("broken-doc").unwrap();
+    sdk.expect_synced().await.unwrap();
+
+    let diagnostics = sdk.sync_diagnostics().unwrap();
+    assert_eq!(diagnostics.failed_docs.len(), 1);
+    assert_eq!(diagnostics.failed_docs[0].tries, 1);
+    assert!(diagnostics.failed_docs[0].next_retry_at.unwrap() > Utc::now());
+    assert!(sdk.retry_failed_doc("unknown-doc").is_err());
+}
+
 #[tokio::test]
 async fn test_single_device_restore_from_bin() {
     common::setup();
@@ -240,3 +334,236 @@ async fn test_single_device_restore_from_bin() {
 }
 
 // TODO: Test blobs ACL: I fail to download some blobs due to `Error Db: 'Find doc_payload_blob': Query returned no rows`
+
+#[tokio::test]
+async fn test_single_device_pending_outbox() {
+    common::setup();
+    // Nothing is listening on this address so nothing leaves the device
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let sdk = common::run_sdk("A", &addr).await.unwrap();
+    let _acc = sdk.create_account(None).unwrap();
+
+    // Create a temporary file
+    let mut tmp_attachment = tempfile::NamedTempFile::new().unwrap();
+    for _ in 0..1000 {
+        tmp_attachment.write(&[1]).unwrap();
+    }
+    let tmp_attachment_path = tmp_attachment.into_temp_path();
+
+    // Create a card and attach a file
+    let card = sdk.create_card().unwrap();
+    let card = sdk.attach_file(&card.id, &tmp_attachment_path).unwrap();
+    let ContentView::File(file) = &card.blocks[0].view else {
+        panic!("Expected File but got {:?}", card.blocks[0].view)
+    };
+
+    let outbox = sdk.pending_outbox().unwrap();
+    // Edited card is waiting to be uploaded
+    assert!(outbox.docs.iter().any(|doc| doc.doc_id == card.id));
+    assert_eq!(outbox.blobs.len(), 1);
+    assert_eq!(outbox.blobs[0].blob_id, file.blob_id);
+    assert_eq!(outbox.blobs[0].card_id.as_deref(), Some(card.id.as_str()));
+    assert_eq!(outbox.blobs[0].size_bytes, 1000);
+
+    // Only uploads of deleted cards can be cancelled
+    assert!(sdk.cancel_blob_upload(&file.blob_id).is_err());
+
+    sdk.move_card_to_bin(&card.id, MoveToBinScope::ThisAccount)
+        .unwrap();
+    sdk.cancel_blob_upload(&file.blob_id).unwrap();
+
+    let outbox = sdk.pending_outbox().unwrap();
+    assert!(outbox.blobs.is_empty());
+    let card = sdk.get_card(&card.id).unwrap();
+    assert!(card.blocks.is_empty());
+}
+
+#[tokio::test]
+async fn test_single_device_public_link() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+    let mut sdk = common::run_sdk("A", &server.addr).await.unwrap();
+    let _acc = sdk.create_account(None).unwrap();
+    sdk.expect_synced().await.unwrap();
+
+    let card = sdk.create_sample_card("Secret recipe").unwrap();
+    let link = sdk.publish_card(&card.id).await.unwrap();
+    assert!(link
+        .url
+        .starts_with(&format!("http://{}/p/{}#", server.addr, link.id)));
+
+    // Server stores only encrypted snapshot
+    let read_payload = || -> Option<Vec<u8>> {
+        let conn = server.get_conn().unwrap();
+        conn.query_row(
+            "SELECT payload FROM public_links WHERE id = ?",
+            [&link.id],
+            |row| row.get(0),
+        )
+        .ok()
+    };
+    let payload = read_payload().unwrap();
+    assert!(!payload.is_empty());
+    assert!(!String::from_utf8_lossy(&payload).contains("Secret recipe"));
+
+    // Republish replaces the snapshot
+    sdk.edit_card(&card.id, vec![CardChange::append_text(" updated")])
+        .unwrap();
+    let link_2 = sdk.publish_card(&card.id).await.unwrap();
+    assert_eq!(link_2.id, link.id);
+    assert_ne!(read_payload().unwrap(), payload);
+
+    // Unpublish
+    sdk.unpublish_card(&card.id).await.unwrap();
+    assert!(read_payload().is_none());
+    assert!(sdk.list_public_links().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_single_device_replayed_request() {
+    common::setup();
+    let server = common::start_server().await.unwrap();
+
+    // Register a device by uploading its key package
+    let backend = &OpenMlsRustCrypto::default();
+    let ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_AES128GCM_SHA256_Ed25519;
+    let credential_bundle = CredentialBundle::new(
+        b"replay".to_vec(),
+        CredentialType::Basic,
+        ciphersuite.signature_algorithm(),
+        backend,
+    )
+    .unwrap();
+    let key_bundle =
+        KeyPackageBundle::new(&[ciphersuite], &credential_bundle, backend, vec![]).unwrap();
+    let device_id = bolik_server::get_device_id(key_bundle.key_package().credential()).unwrap();
+    let signer = Signer {
+        device_id: device_id.clone(),
+        key: credential_bundle.into_parts().1,
+    };
+
+    let now = Utc::now().timestamp();
+    let package = KeyPackageMessage {
+        data: key_bundle.key_package().tls_serialize_detached().unwrap(),
+    };
+    let req = signer.request(
+        "POST",
+        "/api/key-package",
+        package.encode_to_vec(),
+        now,
+        "n1",
+    );
+    assert_eq!(send(&server, req).await, StatusCode::CREATED);
+
+    // Request is accepted only once
+    let packages_path = format!("/api/device/{}/packages", device_id);
+    let req = signer.request("GET", &packages_path, vec![], now, "n2");
+    assert_eq!(send(&server, req).await, StatusCode::OK);
+    let req = signer.request("GET", &packages_path, vec![], now, "n2");
+    assert_eq!(send(&server, req).await, StatusCode::UNAUTHORIZED);
+
+    // Old requests are rejected
+    let req = signer.request("GET", &packages_path, vec![], now - 3600, "n3");
+    assert_eq!(send(&server, req).await, StatusCode::UNAUTHORIZED);
+
+    // Body cannot be swapped
+    let req = signer.request(
+        "POST",
+        "/api/key-package",
+        package.encode_to_vec(),
+        now,
+        "n4",
+    );
+    let (parts, _) = req.into_parts();
+    let req = Request::from_parts(parts, Body::from(vec![1, 2, 3]));
+    assert_eq!(send(&server, req).await, StatusCode::UNAUTHORIZED);
+
+    // Fresh request still works
+    let req = signer.request("GET", &packages_path, vec![], now, "n5");
+    assert_eq!(send(&server, req).await, StatusCode::OK);
+
+    // Clients without a nonce are rejected unless explicitly allowed
+    let req = signer.legacy_request("GET", &packages_path, now);
+    assert_eq!(send(&server, req).await, StatusCode::UNAUTHORIZED);
+
+    // Too large bodies are rejected
+    let req = signer.request(
+        "POST",
+        "/api/key-package",
+        vec![0; 3 * 1024 * 1024],
+        now,
+        "n6",
+    );
+    assert_eq!(send(&server, req).await, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+struct Signer {
+    device_id: String,
+    key: SignaturePrivateKey,
+}
+
+impl Signer {
+    fn request(
+        &self,
+        method: &str,
+        path: &str,
+        body: Vec<u8>,
+        timestamp: i64,
+        nonce: &str,
+    ) -> Request<Body> {
+        let timestamp = timestamp.to_string();
+        let mut hasher = Blake3_256::default();
+        hasher.update(&body);
+
+        let mut payload = vec![];
+        payload.extend(timestamp.as_bytes());
+        payload.extend(nonce.as_bytes());
+        payload.extend(method.as_bytes());
+        payload.extend(path.as_bytes());
+        payload.extend(hasher.finalize());
+
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header("device-id", &self.device_id)
+            .header("timestamp", timestamp)
+            .header("nonce", nonce)
+            .header("signature", self.sign(&payload))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    /// Request signed without a nonce and body (previous scheme).
+    fn legacy_request(&self, method: &str, path: &str, timestamp: i64) -> Request<Body> {
+        let timestamp = timestamp.to_string();
+        let mut payload = vec![];
+        payload.extend(timestamp.as_bytes());
+        payload.extend(method.as_bytes());
+        payload.extend(path.as_bytes());
+
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header("device-id", &self.device_id)
+            .header("timestamp", timestamp)
+            .header("signature", self.sign(&payload))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn sign(&self, payload: &[u8]) -> String {
+        let signature = self
+            .key
+            .sign(&OpenMlsRustCrypto::default(), payload)
+            .unwrap();
+        bs58::encode(signature.tls_serialize_detached().unwrap()).into_string()
+    }
+}
+
+async fn send(server: &common::TestServerResult, req: Request<Body>) -> StatusCode {
+    let router = bolik_server::router::router(server.app.clone());
+    router.oneshot(req).await.unwrap().status()
+}